@@ -1,3 +1,4 @@
+import BN from "bn.js";
 import { PublicKey } from "@solana/web3.js";
 
 export const DEFAULT_TOKEN_DECIMALS = 6;
@@ -7,3 +8,27 @@ export const TOKEN_PROGRAM_ID = new PublicKey(
 );
 
 export const ZERO_TS = 0;
+
+export const RATE_PRECISION = new BN("1000000000000000000");
+
+/**
+ * Rate provider program of swaps without a rate provider
+ */
+export const NO_RATE_PROVIDER = new PublicKey(0);
+
+/**
+ * Curve a pool prices its trades with
+ */
+export enum CurveType {
+  StableSwap = 0,
+  ConstantProduct = 1,
+  ConstantPrice = 2,
+}
+
+/**
+ * Schedule the amplification coefficient follows while ramping
+ */
+export enum RampType {
+  Linear = 0,
+  Geometric = 1,
+}
@@ -0,0 +1,23 @@
+import BN from "bn.js";
+
+export type SwapCounters = {
+  totalVolumeA: BN;
+  totalVolumeB: BN;
+  totalLpFeesA: BN;
+  totalLpFeesB: BN;
+  totalAdminFeesA: BN;
+  totalAdminFeesB: BN;
+  swapCount: number;
+  lastTradeTimestamp: number;
+};
+
+export const NO_COUNTERS: SwapCounters = {
+  totalVolumeA: new BN(0),
+  totalVolumeB: new BN(0),
+  totalLpFeesA: new BN(0),
+  totalLpFeesB: new BN(0),
+  totalAdminFeesA: new BN(0),
+  totalAdminFeesB: new BN(0),
+  swapCount: 0,
+  lastTradeTimestamp: 0,
+};
@@ -7,10 +7,14 @@ export type Fees = {
   tradeFeeDenominator: number;
   withdrawFeeNumerator: number;
   withdrawFeeDenominator: number;
+  offpegFeeMultiplier: number;
+  hostFeeNumerator: number;
+  hostFeeDenominator: number;
 };
 
 export const DEFAULT_FEE_NUMERATOR = 0;
 export const DEFAULT_FEE_DENOMINATOR = 1000;
+export const OFFPEG_FEE_MULTIPLIER_DENOMINATOR = 10_000_000_000;
 export const DEFAULT_FEES: Fees = {
   adminTradeFeeNumerator: DEFAULT_FEE_NUMERATOR,
   adminTradeFeeDenominator: DEFAULT_FEE_DENOMINATOR,
@@ -20,4 +24,21 @@ export const DEFAULT_FEES: Fees = {
   tradeFeeDenominator: DEFAULT_FEE_DENOMINATOR,
   withdrawFeeNumerator: DEFAULT_FEE_NUMERATOR,
   withdrawFeeDenominator: DEFAULT_FEE_DENOMINATOR,
+  offpegFeeMultiplier: OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+  hostFeeNumerator: DEFAULT_FEE_NUMERATOR,
+  hostFeeDenominator: DEFAULT_FEE_DENOMINATOR,
+};
+
+export const NO_FEES: Fees = {
+  adminTradeFeeNumerator: 0,
+  adminTradeFeeDenominator: 0,
+  adminWithdrawFeeNumerator: 0,
+  adminWithdrawFeeDenominator: 0,
+  tradeFeeNumerator: 0,
+  tradeFeeDenominator: 0,
+  withdrawFeeNumerator: 0,
+  withdrawFeeDenominator: 0,
+  offpegFeeMultiplier: 0,
+  hostFeeNumerator: 0,
+  hostFeeDenominator: 0,
 };
@@ -1,3 +1,4 @@
+import BN from "bn.js";
 import * as BufferLayout from "buffer-layout";
 import {
   Account,
@@ -6,8 +7,9 @@ import {
   SYSVAR_CLOCK_PUBKEY,
 } from "@solana/web3.js";
 
+import { CurveType } from "./constants";
 import { NumberU64 } from "./util/u64";
-import { Uint64Layout } from "./layout";
+import { PublicKeyLayout, Uint64Layout } from "./layout";
 import { Fees } from "./fees";
 
 export const createInitSwapInstruction = (
@@ -26,7 +28,12 @@ export const createInitSwapInstruction = (
   tokenProgramId: PublicKey,
   nonce: number,
   ampFactor: number | NumberU64,
-  fees: Fees
+  fees: Fees,
+  rateProviderProgram: PublicKey,
+  maxRateAge: number,
+  curveType: CurveType,
+  tokenBPrice: BN,
+  rateProvider?: PublicKey
 ): TransactionInstruction => {
   const keys = [
     { pubkey: tokenSwapAccount.publicKey, isSigner: false, isWritable: true },
@@ -42,6 +49,9 @@ export const createInitSwapInstruction = (
     { pubkey: poolTokenAccount, isSigner: false, isWritable: true },
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
   ];
+  if (rateProvider) {
+    keys.push({ pubkey: rateProvider, isSigner: false, isWritable: false });
+  }
   const dataLayout = BufferLayout.struct([
     BufferLayout.u8("instruction"),
     BufferLayout.u8("nonce"),
@@ -54,6 +64,13 @@ export const createInitSwapInstruction = (
     Uint64Layout("tradeFeeDenominator"),
     Uint64Layout("withdrawFeeNumerator"),
     Uint64Layout("withdrawFeeDenominator"),
+    Uint64Layout("offpegFeeMultiplier"),
+    Uint64Layout("hostFeeNumerator"),
+    Uint64Layout("hostFeeDenominator"),
+    PublicKeyLayout("rateProviderProgram"),
+    BufferLayout.ns64("maxRateAge"),
+    BufferLayout.u8("curveType"),
+    Uint64Layout("tokenBPrice"),
   ]);
   let data = Buffer.alloc(dataLayout.span);
   {
@@ -82,6 +99,15 @@ export const createInitSwapInstruction = (
         withdrawFeeDenominator: new NumberU64(
           fees.withdrawFeeDenominator
         ).toBuffer(),
+        offpegFeeMultiplier: new NumberU64(
+          fees.offpegFeeMultiplier
+        ).toBuffer(),
+        hostFeeNumerator: new NumberU64(fees.hostFeeNumerator).toBuffer(),
+        hostFeeDenominator: new NumberU64(fees.hostFeeDenominator).toBuffer(),
+        rateProviderProgram: rateProviderProgram.toBuffer(),
+        maxRateAge,
+        curveType,
+        tokenBPrice: tokenBPrice.toArrayLike(Buffer, "le", 8),
       },
       data
     );
@@ -101,11 +127,12 @@ export const swapInstruction = (
   poolSource: PublicKey,
   poolDestination: PublicKey,
   userDestination: PublicKey,
-  adminDestination: PublicKey,
   swapProgramId: PublicKey,
   tokenProgramId: PublicKey,
   amountIn: number | NumberU64,
-  minimumAmountOut: number | NumberU64
+  minimumAmountOut: number | NumberU64,
+  rateProvider?: PublicKey,
+  hostFeeAccount?: PublicKey
 ): TransactionInstruction => {
   const dataLayout = BufferLayout.struct([
     BufferLayout.u8("instruction"),
@@ -124,16 +151,21 @@ export const swapInstruction = (
   );
 
   const keys = [
-    { pubkey: tokenSwap, isSigner: false, isWritable: false },
+    { pubkey: tokenSwap, isSigner: false, isWritable: true },
     { pubkey: authority, isSigner: false, isWritable: false },
     { pubkey: userSource, isSigner: false, isWritable: true },
     { pubkey: poolSource, isSigner: false, isWritable: true },
     { pubkey: poolDestination, isSigner: false, isWritable: true },
     { pubkey: userDestination, isSigner: false, isWritable: true },
-    { pubkey: adminDestination, isSigner: false, isWritable: true },
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
   ];
+  if (rateProvider) {
+    keys.push({ pubkey: rateProvider, isSigner: false, isWritable: false });
+  }
+  if (hostFeeAccount) {
+    keys.push({ pubkey: hostFeeAccount, isSigner: false, isWritable: true });
+  }
   return new TransactionInstruction({
     keys,
     programId: swapProgramId,
@@ -154,7 +186,8 @@ export const depositInstruction = (
   tokenProgramId: PublicKey,
   tokenAmountA: number | NumberU64,
   tokenAmountB: number | NumberU64,
-  minimumPoolTokenAmount: number | NumberU64
+  minimumPoolTokenAmount: number | NumberU64,
+  rateProvider?: PublicKey
 ): TransactionInstruction => {
   const dataLayout = BufferLayout.struct([
     BufferLayout.u8("instruction"),
@@ -185,6 +218,9 @@ export const depositInstruction = (
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
   ];
+  if (rateProvider) {
+    keys.push({ pubkey: rateProvider, isSigner: false, isWritable: false });
+  }
   return new TransactionInstruction({
     keys,
     programId: swapProgramId,
@@ -201,8 +237,6 @@ export const withdrawInstruction = (
   fromB: PublicKey,
   userAccountA: PublicKey,
   userAccountB: PublicKey,
-  adminFeeAccountA: PublicKey,
-  adminFeeAccountB: PublicKey,
   swapProgramId: PublicKey,
   tokenProgramId: PublicKey,
   poolTokenAmount: number | NumberU64,
@@ -228,7 +262,7 @@ export const withdrawInstruction = (
   );
 
   const keys = [
-    { pubkey: tokenSwap, isSigner: false, isWritable: false },
+    { pubkey: tokenSwap, isSigner: false, isWritable: true },
     { pubkey: authority, isSigner: false, isWritable: false },
     { pubkey: poolMint, isSigner: false, isWritable: true },
     { pubkey: sourcePoolAccount, isSigner: false, isWritable: true },
@@ -236,8 +270,6 @@ export const withdrawInstruction = (
     { pubkey: fromB, isSigner: false, isWritable: true },
     { pubkey: userAccountA, isSigner: false, isWritable: true },
     { pubkey: userAccountB, isSigner: false, isWritable: true },
-    { pubkey: adminFeeAccountA, isSigner: false, isWritable: true },
-    { pubkey: adminFeeAccountB, isSigner: false, isWritable: true },
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
   ];
   return new TransactionInstruction({
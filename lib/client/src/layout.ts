@@ -14,6 +14,13 @@ export const Uint64Layout = (property: string = "uint64"): object => {
   return BufferLayout.blob(8, property);
 };
 
+/**
+ * Layout for a 128bit unsigned value
+ */
+export const Uint128Layout = (property: string = "uint128"): object => {
+  return BufferLayout.blob(16, property);
+};
+
 /**
  * Layout for stable swap state
  */
@@ -44,5 +51,49 @@ export const StableSwapLayout: typeof BufferLayout.Structure = BufferLayout.stru
     BufferLayout.nu64("tradeFeeDenominator"),
     BufferLayout.nu64("withdrawFeeNumerator"),
     BufferLayout.nu64("withdrawFeeDenominator"),
+    BufferLayout.nu64("offpegFeeMultiplier"),
+    BufferLayout.nu64("hostFeeNumerator"),
+    BufferLayout.nu64("hostFeeDenominator"),
+    BufferLayout.nu64("tokenAPrecisionMultiplier"),
+    BufferLayout.nu64("tokenBPrecisionMultiplier"),
+    PublicKeyLayout("rateProvider"),
+    PublicKeyLayout("rateProviderProgram"),
+    BufferLayout.ns64("maxRateAge"),
+    BufferLayout.u8("curveType"),
+    Uint64Layout("tokenBPrice"),
+    BufferLayout.u8("rampType"),
+    BufferLayout.nu64("futureAdminTradeFeeNumerator"),
+    BufferLayout.nu64("futureAdminTradeFeeDenominator"),
+    BufferLayout.nu64("futureAdminWithdrawFeeNumerator"),
+    BufferLayout.nu64("futureAdminWithdrawFeeDenominator"),
+    BufferLayout.nu64("futureTradeFeeNumerator"),
+    BufferLayout.nu64("futureTradeFeeDenominator"),
+    BufferLayout.nu64("futureWithdrawFeeNumerator"),
+    BufferLayout.nu64("futureWithdrawFeeDenominator"),
+    BufferLayout.nu64("futureOffpegFeeMultiplier"),
+    BufferLayout.nu64("futureHostFeeNumerator"),
+    BufferLayout.nu64("futureHostFeeDenominator"),
+    BufferLayout.ns64("futureFeesTs"),
+    BufferLayout.nu64("adminFeesA"),
+    BufferLayout.nu64("adminFeesB"),
+    Uint128Layout("totalVolumeA"),
+    Uint128Layout("totalVolumeB"),
+    Uint128Layout("totalLpFeesA"),
+    Uint128Layout("totalLpFeesB"),
+    Uint128Layout("totalAdminFeesA"),
+    Uint128Layout("totalAdminFeesB"),
+    BufferLayout.nu64("swapCount"),
+    BufferLayout.ns64("lastTradeTs"),
+  ]
+);
+
+/**
+ * Layout for the rate provider account of a swap
+ */
+export const RateInfoLayout: typeof BufferLayout.Structure = BufferLayout.struct(
+  [
+    BufferLayout.u8("isInitialized"),
+    Uint64Layout("rate"),
+    BufferLayout.ns64("lastUpdateTs"),
   ]
 );
@@ -0,0 +1,11 @@
+import { RateInfoLayout, StableSwapLayout } from "./layout";
+
+describe("Layout tests", () => {
+  it("StableSwapLayout matches SwapInfo::LEN", () => {
+    expect(StableSwapLayout.span).toBe(741);
+  });
+
+  it("RateInfoLayout matches RateInfo::LEN", () => {
+    expect(RateInfoLayout.span).toBe(17);
+  });
+});
@@ -8,12 +8,19 @@ import {
 } from "@solana/web3.js";
 import { AccountLayout, MintLayout } from "@solana/spl-token";
 
-import { TOKEN_PROGRAM_ID, ZERO_TS } from "./constants";
-import { DEFAULT_FEES, Fees } from "./fees";
+import {
+  CurveType,
+  NO_RATE_PROVIDER,
+  RampType,
+  RATE_PRECISION,
+  TOKEN_PROGRAM_ID,
+} from "./constants";
+import { NO_COUNTERS, SwapCounters } from "./counters";
+import { DEFAULT_FEES, Fees, NO_FEES } from "./fees";
 import * as instructions from "./instructions";
 import * as layout from "./layout";
 import { loadAccount } from "./util/account";
-import { computeD } from "./util/calculator";
+import { computeConstantProductD, computeD } from "./util/calculator";
 import { sendAndConfirmTransaction } from "./util/send-and-confirm-transaction";
 import { NumberU64 } from "./util/u64";
 
@@ -106,11 +113,82 @@ export class StableSwap {
    */
   stopRampTimestamp: number;
 
+  /**
+   * Schedule A follows between the ramp timestamps
+   */
+  rampType: RampType;
+
+  /**
+   * Multiplier normalizing token A amounts to the pool token precision
+   */
+  tokenAPrecisionMultiplier: number;
+
+  /**
+   * Multiplier normalizing token B amounts to the pool token precision
+   */
+  tokenBPrecisionMultiplier: number;
+
+  /**
+   * Account publishing the rate of token B in token A, or `NO_RATE_PROVIDER`
+   * if the tokens trade 1:1
+   */
+  rateProvider: PublicKey;
+
+  /**
+   * Program owning the rate provider account
+   */
+  rateProviderProgram: PublicKey;
+
+  /**
+   * Maximum age of the rate provider's rate in seconds
+   */
+  maxRateAge: number;
+
+  /**
+   * Curve the pool prices trades with
+   */
+  curveType: CurveType;
+
+  /**
+   * Price of one token B in token A for constant price pools, scaled by
+   * `RATE_PRECISION`
+   */
+  tokenBPrice: BN;
+
   /**
    * Fees
    */
   fees: Fees;
 
+  /**
+   * Fees committed by the admin, applicable from `futureFeesTimestamp`
+   */
+  futureFees: Fees;
+
+  /**
+   * Timestamp from which `futureFees` can be applied, or 0 if no fee change
+   * is committed
+   */
+  futureFeesTimestamp: number;
+
+  /**
+   * Admin fees accrued in the token A swap account, not part of the pool
+   * balance until swept by the admin
+   */
+  adminFeesA: number;
+
+  /**
+   * Admin fees accrued in the token B swap account, not part of the pool
+   * balance until swept by the admin
+   */
+  adminFeesB: number;
+
+  /**
+   * Cumulative volume and fee counters, to compute the fee APR from two
+   * snapshots of the pool
+   */
+  counters: SwapCounters;
+
   /**
    * Constructor for new StableSwap client object
    * @param connection
@@ -130,7 +208,20 @@ export class StableSwap {
    * @param targetAmpFactor
    * @param startRampTimestamp
    * @param stopRampTimeStamp
+   * @param tokenAPrecisionMultiplier
+   * @param tokenBPrecisionMultiplier
+   * @param rateProvider
+   * @param rateProviderProgram
+   * @param maxRateAge
+   * @param curveType
+   * @param tokenBPrice
+   * @param rampType
    * @param fees
+   * @param futureFees
+   * @param futureFeesTimestamp
+   * @param adminFeesA
+   * @param adminFeesB
+   * @param counters
    */
   constructor(
     connection: Connection,
@@ -150,7 +241,20 @@ export class StableSwap {
     targetAmpFactor: number,
     startRampTimestamp: number,
     stopRampTimeStamp: number,
-    fees: Fees = DEFAULT_FEES
+    tokenAPrecisionMultiplier: number,
+    tokenBPrecisionMultiplier: number,
+    rateProvider: PublicKey,
+    rateProviderProgram: PublicKey,
+    maxRateAge: number,
+    curveType: CurveType,
+    tokenBPrice: BN,
+    rampType: RampType,
+    fees: Fees = DEFAULT_FEES,
+    futureFees: Fees = NO_FEES,
+    futureFeesTimestamp: number = 0,
+    adminFeesA: number = 0,
+    adminFeesB: number = 0,
+    counters: SwapCounters = NO_COUNTERS
   ) {
     this.connection = connection;
     this.stableSwap = stableSwap;
@@ -169,7 +273,20 @@ export class StableSwap {
     this.targetAmpFactor = targetAmpFactor;
     this.startRampTimestamp = startRampTimestamp;
     this.stopRampTimestamp = stopRampTimeStamp;
+    this.tokenAPrecisionMultiplier = tokenAPrecisionMultiplier;
+    this.tokenBPrecisionMultiplier = tokenBPrecisionMultiplier;
+    this.rateProvider = rateProvider;
+    this.rateProviderProgram = rateProviderProgram;
+    this.maxRateAge = maxRateAge;
+    this.curveType = curveType;
+    this.tokenBPrice = tokenBPrice;
+    this.rampType = rampType;
     this.fees = fees;
+    this.futureFees = futureFees;
+    this.futureFeesTimestamp = futureFeesTimestamp;
+    this.adminFeesA = adminFeesA;
+    this.adminFeesB = adminFeesB;
+    this.counters = counters;
   }
 
   /**
@@ -220,6 +337,16 @@ export class StableSwap {
     const targetAmpFactor = stableSwapData.targetAmpFactor;
     const startRampTimestamp = stableSwapData.startRampTs;
     const stopRampTimeStamp = stableSwapData.stopRampTs;
+    const tokenAPrecisionMultiplier = stableSwapData.tokenAPrecisionMultiplier;
+    const tokenBPrecisionMultiplier = stableSwapData.tokenBPrecisionMultiplier;
+    const rateProvider = new PublicKey(stableSwapData.rateProvider);
+    const rateProviderProgram = new PublicKey(
+      stableSwapData.rateProviderProgram
+    );
+    const maxRateAge = stableSwapData.maxRateAge;
+    const curveType = stableSwapData.curveType;
+    const tokenBPrice = NumberU64.fromBuffer(stableSwapData.tokenBPrice);
+    const rampType = stableSwapData.rampType;
     const fees = {
       adminTradeFeeNumerator: stableSwapData.adminTradeFeeNumerator as number,
       adminTradeFeeDenominator: stableSwapData.adminTradeFeeDenominator as number,
@@ -229,6 +356,36 @@ export class StableSwap {
       tradeFeeDenominator: stableSwapData.tradeFeeDenominator as number,
       withdrawFeeNumerator: stableSwapData.withdrawFeeNumerator as number,
       withdrawFeeDenominator: stableSwapData.withdrawFeeDenominator as number,
+      offpegFeeMultiplier: stableSwapData.offpegFeeMultiplier as number,
+      hostFeeNumerator: stableSwapData.hostFeeNumerator as number,
+      hostFeeDenominator: stableSwapData.hostFeeDenominator as number,
+    };
+    const futureFees = {
+      adminTradeFeeNumerator: stableSwapData.futureAdminTradeFeeNumerator as number,
+      adminTradeFeeDenominator: stableSwapData.futureAdminTradeFeeDenominator as number,
+      adminWithdrawFeeNumerator: stableSwapData.futureAdminWithdrawFeeNumerator as number,
+      adminWithdrawFeeDenominator: stableSwapData.futureAdminWithdrawFeeDenominator as number,
+      tradeFeeNumerator: stableSwapData.futureTradeFeeNumerator as number,
+      tradeFeeDenominator: stableSwapData.futureTradeFeeDenominator as number,
+      withdrawFeeNumerator: stableSwapData.futureWithdrawFeeNumerator as number,
+      withdrawFeeDenominator: stableSwapData.futureWithdrawFeeDenominator as number,
+      offpegFeeMultiplier: stableSwapData.futureOffpegFeeMultiplier as number,
+      hostFeeNumerator: stableSwapData.futureHostFeeNumerator as number,
+      hostFeeDenominator: stableSwapData.futureHostFeeDenominator as number,
+    };
+    const futureFeesTimestamp = stableSwapData.futureFeesTs;
+    const adminFeesA = stableSwapData.adminFeesA;
+    const adminFeesB = stableSwapData.adminFeesB;
+    const u128 = (buffer: Buffer): BN => new BN(buffer, 10, "le");
+    const counters = {
+      totalVolumeA: u128(stableSwapData.totalVolumeA),
+      totalVolumeB: u128(stableSwapData.totalVolumeB),
+      totalLpFeesA: u128(stableSwapData.totalLpFeesA),
+      totalLpFeesB: u128(stableSwapData.totalLpFeesB),
+      totalAdminFeesA: u128(stableSwapData.totalAdminFeesA),
+      totalAdminFeesB: u128(stableSwapData.totalAdminFeesB),
+      swapCount: stableSwapData.swapCount as number,
+      lastTradeTimestamp: stableSwapData.lastTradeTs as number,
     };
 
     return new StableSwap(
@@ -249,7 +406,20 @@ export class StableSwap {
       targetAmpFactor,
       startRampTimestamp,
       stopRampTimeStamp,
-      fees
+      tokenAPrecisionMultiplier,
+      tokenBPrecisionMultiplier,
+      rateProvider,
+      rateProviderProgram,
+      maxRateAge,
+      curveType,
+      tokenBPrice,
+      rampType,
+      fees,
+      futureFees,
+      futureFeesTimestamp,
+      adminFeesA,
+      adminFeesB,
+      counters
     );
   }
 
@@ -273,6 +443,11 @@ export class StableSwap {
    * @param nonce
    * @param ampFactor
    * @param fees
+   * @param rateProvider Account publishing the rate of token B, if any
+   * @param rateProviderProgram Program trusted to own the rate provider account
+   * @param maxRateAge Maximum age of the rate provider's rate in seconds
+   * @param curveType Curve the pool prices trades with
+   * @param tokenBPrice Price of token B for constant price pools
    */
   static async createStableSwap(
     connection: Connection,
@@ -294,7 +469,12 @@ export class StableSwap {
     tokenProgramId: PublicKey,
     nonce: number,
     ampFactor: number,
-    fees: Fees = DEFAULT_FEES
+    fees: Fees = DEFAULT_FEES,
+    rateProvider: PublicKey | null = null,
+    rateProviderProgram: PublicKey = NO_RATE_PROVIDER,
+    maxRateAge: number = 0,
+    curveType: CurveType = CurveType.StableSwap,
+    tokenBPrice: BN = RATE_PRECISION
   ): Promise<StableSwap> {
     // Allocate memory for the account
     const balanceNeeded = await StableSwap.getMinBalanceRentForExemptStableSwap(
@@ -326,7 +506,12 @@ export class StableSwap {
       tokenProgramId,
       nonce,
       ampFactor,
-      fees
+      fees,
+      rateProviderProgram,
+      maxRateAge,
+      curveType,
+      tokenBPrice,
+      rateProvider || undefined
     );
     transaction.add(instruction);
 
@@ -338,36 +523,54 @@ export class StableSwap {
       stableSwapAccount
     );
 
-    return new StableSwap(
+    // The program derives the precision multipliers from the mints and the
+    // rate provider from the accounts
+    return await StableSwap.loadStableSwap(
       connection,
       stableSwapAccount.publicKey,
-      swapProgramId,
-      tokenProgramId,
-      poolTokenMint,
-      authority,
-      adminAccount,
-      adminFeeAccountA,
-      adminFeeAccountB,
-      tokenAccountA,
-      tokenAccountB,
-      mintA,
-      mintB,
-      ampFactor,
-      ampFactor,
-      ZERO_TS,
-      ZERO_TS,
-      fees
+      swapProgramId
     );
   }
 
+  /**
+   * Whether the pool reads the rate of token B from a rate provider
+   */
+  hasRateProvider(): boolean {
+    return !this.rateProvider.equals(NO_RATE_PROVIDER);
+  }
+
+  /**
+   * Get the rate of token B in token A, scaled by `RATE_PRECISION`
+   */
+  async getTokenBRate(): Promise<BN> {
+    if (!this.hasRateProvider()) {
+      return this.curveType === CurveType.ConstantPrice
+        ? this.tokenBPrice
+        : RATE_PRECISION;
+    }
+    const rateInfoData = await loadAccount(
+      this.connection,
+      this.rateProvider,
+      this.rateProviderProgram
+    );
+    const rateInfo = layout.RateInfoLayout.decode(rateInfoData);
+    return NumberU64.fromBuffer(rateInfo.rate);
+  }
+
   /**
    * Get the virtual price of the pool.
    */
   async getVirtualPrice(): Promise<number> {
+    let stableSwapData;
     let tokenAData;
     let tokenBData;
     let poolMintData;
     try {
+      stableSwapData = await loadAccount(
+        this.connection,
+        this.stableSwap,
+        this.swapProgramId
+      );
       tokenAData = await loadAccount(
         this.connection,
         this.tokenAccountA,
@@ -387,11 +590,30 @@ export class StableSwap {
       throw new Error(e);
     }
 
+    const stableSwap = layout.StableSwapLayout.decode(stableSwapData);
     const tokenA = AccountLayout.decode(tokenAData);
     const tokenB = AccountLayout.decode(tokenBData);
-    const amountA = NumberU64.fromBuffer(tokenA.amount);
-    const amountB = NumberU64.fromBuffer(tokenB.amount);
-    const D = computeD(new BN(this.initialAmpFactor), amountA, amountB);
+    // Exclude the accrued admin fees and normalize the balances to the pool
+    // token precision
+    const amountA = NumberU64.fromBuffer(tokenA.amount)
+      .sub(new BN(stableSwap.adminFeesA))
+      .mul(new BN(this.tokenAPrecisionMultiplier));
+    const amountB = NumberU64.fromBuffer(tokenB.amount)
+      .sub(new BN(stableSwap.adminFeesB))
+      .mul(new BN(this.tokenBPrecisionMultiplier))
+      .mul(await this.getTokenBRate())
+      .div(RATE_PRECISION);
+    let D: BN;
+    switch (this.curveType) {
+      case CurveType.ConstantProduct:
+        D = computeConstantProductD(amountA, amountB);
+        break;
+      case CurveType.ConstantPrice:
+        D = amountA.add(amountB);
+        break;
+      default:
+        D = computeD(new BN(this.initialAmpFactor), amountA, amountB);
+    }
 
     const poolMint = MintLayout.decode(poolMintData);
     const poolSupply = NumberU64.fromBuffer(poolMint.supply);
@@ -407,6 +629,7 @@ export class StableSwap {
    * @param userDestination
    * @param amountIn
    * @param minimumAmountOut
+   * @param hostFeeAccount Optional host fee account of the destination token
    */
   swap(
     userSource: PublicKey,
@@ -414,12 +637,9 @@ export class StableSwap {
     poolDestination: PublicKey,
     userDestination: PublicKey,
     amountIn: number,
-    minimumAmountOut: number
+    minimumAmountOut: number,
+    hostFeeAccount?: PublicKey
   ): Transaction {
-    const adminDestination =
-      poolDestination === this.tokenAccountA
-        ? this.adminFeeAccountA
-        : this.adminFeeAccountB;
     return new Transaction().add(
       instructions.swapInstruction(
         this.stableSwap,
@@ -428,11 +648,12 @@ export class StableSwap {
         poolSource,
         poolDestination,
         userDestination,
-        adminDestination,
         this.swapProgramId,
         this.tokenProgramId,
         amountIn,
-        minimumAmountOut
+        minimumAmountOut,
+        this.optionalRateProvider(),
+        hostFeeAccount
       )
     );
   }
@@ -468,7 +689,8 @@ export class StableSwap {
         this.tokenProgramId,
         tokenAmountA,
         tokenAmountB,
-        minimumPoolTokenAmount
+        minimumPoolTokenAmount,
+        this.optionalRateProvider()
       )
     );
   }
@@ -500,8 +722,6 @@ export class StableSwap {
         this.tokenAccountB,
         userAccountA,
         userAccountB,
-        this.adminFeeAccountA,
-        this.adminFeeAccountB,
         this.swapProgramId,
         this.tokenProgramId,
         poolTokenAmount,
@@ -510,4 +730,11 @@ export class StableSwap {
       )
     );
   }
+
+  /**
+   * Rate provider account to append to instructions pricing token B, if any
+   */
+  private optionalRateProvider(): PublicKey | undefined {
+    return this.hasRateProvider() ? this.rateProvider : undefined;
+  }
 }
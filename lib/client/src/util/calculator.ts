@@ -36,6 +36,27 @@ export const computeD = (ampFactor: BN, amountA: BN, amountB: BN): BN => {
   return d;
 };
 
+/**
+ * Compute the constant product invariant, reported as `2 * sqrt(x * y)` to
+ * match the StableSwap invariant of a balanced pool
+ * @param amountA Swap balance of token A
+ * @param amountB Swap balance of token B
+ */
+export const computeConstantProductD = (amountA: BN, amountB: BN): BN => {
+  const product = amountA.mul(amountB);
+  if (product.lt(new BN(2))) {
+    return product.mul(N_COINS);
+  }
+  // Integer square root by Newton's method
+  let x = product;
+  let y = product.divn(2).add(ONE);
+  while (y.lt(x)) {
+    x = y;
+    y = x.add(product.div(x)).divn(2);
+  }
+  return x.mul(N_COINS);
+};
+
 /**
  * Compute Y amount in respect to X on the StableSwap curve
  * @param ampFactor Amplification coefficient (A)
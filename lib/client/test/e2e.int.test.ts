@@ -9,11 +9,20 @@ import {
 } from "@solana/web3.js";
 
 import { StableSwap } from "../src";
-import { DEFAULT_TOKEN_DECIMALS, TOKEN_PROGRAM_ID } from "../src/constants";
+import {
+  CurveType,
+  DEFAULT_TOKEN_DECIMALS,
+  NO_RATE_PROVIDER,
+  RampType,
+  RATE_PRECISION,
+  TOKEN_PROGRAM_ID,
+} from "../src/constants";
 import {
   DEFAULT_FEE_DENOMINATOR,
   DEFAULT_FEE_NUMERATOR,
   Fees,
+  NO_FEES,
+  OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
 } from "../src/fees";
 import { sendAndConfirmTransaction } from "../src/util/send-and-confirm-transaction";
 import { getDeploymentInfo, newAccountWithLamports, sleep } from "./helpers";
@@ -32,6 +41,9 @@ const FEES: Fees = {
   tradeFeeDenominator: 4,
   withdrawFeeNumerator: DEFAULT_FEE_NUMERATOR,
   withdrawFeeDenominator: DEFAULT_FEE_DENOMINATOR,
+  offpegFeeMultiplier: OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+  hostFeeNumerator: DEFAULT_FEE_NUMERATOR,
+  hostFeeDenominator: DEFAULT_FEE_DENOMINATOR,
 };
 // Initial amount in each swap token
 const INITIAL_TOKEN_A_AMOUNT = LAMPORTS_PER_SOL;
@@ -203,7 +215,21 @@ describe("e2e test", () => {
     expect(fetchedStableSwap.mintB).toEqual(mintB.publicKey);
     expect(fetchedStableSwap.poolTokenMint).toEqual(tokenPool.publicKey);
     expect(fetchedStableSwap.initialAmpFactor).toEqual(AMP_FACTOR);
+    expect(fetchedStableSwap.tokenAPrecisionMultiplier).toEqual(1);
+    expect(fetchedStableSwap.tokenBPrecisionMultiplier).toEqual(1);
+    expect(fetchedStableSwap.rateProvider).toEqual(NO_RATE_PROVIDER);
+    expect(fetchedStableSwap.hasRateProvider()).toBe(false);
+    expect(fetchedStableSwap.maxRateAge).toEqual(0);
+    expect(fetchedStableSwap.curveType).toEqual(CurveType.StableSwap);
+    expect(fetchedStableSwap.tokenBPrice.eq(RATE_PRECISION)).toBe(true);
+    expect(fetchedStableSwap.rampType).toEqual(RampType.Linear);
     expect(fetchedStableSwap.fees).toEqual(FEES);
+    expect(fetchedStableSwap.futureFees).toEqual(NO_FEES);
+    expect(fetchedStableSwap.futureFeesTimestamp).toEqual(0);
+    expect(fetchedStableSwap.adminFeesA).toEqual(0);
+    expect(fetchedStableSwap.adminFeesB).toEqual(0);
+    expect(fetchedStableSwap.counters.swapCount).toEqual(0);
+    expect(fetchedStableSwap.counters.lastTradeTimestamp).toEqual(0);
   });
 
   it("getVirtualPrice", async () => {
@@ -362,6 +388,13 @@ describe("e2e test", () => {
     expect(info.amount.toNumber()).toBe(
       oldSwapTokenB.amount.toNumber() - EXPECTED_AMOUNT_OUT
     );
+    // The admin fee accrues in the pool, FEES charge no admin trade fee
+    const fetchedStableSwap = await StableSwap.loadStableSwap(
+      connection,
+      stableSwapAccount.publicKey,
+      stableSwapProgramId
+    );
+    expect(fetchedStableSwap.adminFeesB).toEqual(0);
   });
 
   it("swap B->A", async () => {
@@ -410,5 +443,15 @@ describe("e2e test", () => {
     expect(info.amount.toNumber()).toBe(
       oldSwapTokenA.amount.toNumber() - EXPECTED_AMOUNT_OUT
     );
+    // Both swaps are counted
+    const { counters } = await StableSwap.loadStableSwap(
+      connection,
+      stableSwapAccount.publicKey,
+      stableSwapProgramId
+    );
+    expect(counters.swapCount).toEqual(2);
+    expect(counters.totalVolumeA.toNumber()).toEqual(SWAP_AMOUNT_IN);
+    expect(counters.totalVolumeB.toNumber()).toEqual(SWAP_AMOUNT_IN);
+    expect(counters.lastTradeTimestamp).toBeGreaterThan(0);
   });
 });
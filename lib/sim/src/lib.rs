@@ -39,7 +39,7 @@ impl Model {
             amp_factor,
             balances,
             n_coins,
-            target_prices: vec![DEFAULT_TARGET_PRICE, DEFAULT_TARGET_PRICE],
+            target_prices: vec![DEFAULT_TARGET_PRICE; n_coins as usize],
             pool_tokens: DEFAULT_POOL_TOKENS,
         }
     }
@@ -66,7 +66,7 @@ impl Model {
             amp_factor,
             balances,
             n_coins,
-            target_prices: vec![DEFAULT_TARGET_PRICE, DEFAULT_TARGET_PRICE],
+            target_prices: vec![DEFAULT_TARGET_PRICE; n_coins as usize],
             pool_tokens: pool_token_amount,
         }
     }
@@ -131,7 +131,7 @@ impl Model {
             .call1(
                 gil.python(),
                 "remove_liquidity_imbalance",
-                PyTuple::new(gil.python(), amounts.to_vec()),
+                (amounts.to_vec(),),
             )
             .unwrap()
             .extract(gil.python())
@@ -2,64 +2,233 @@
 
 use crate::{
     bn::U256,
-    curve::{StableSwap, MAX_AMP, MIN_AMP, MIN_RAMP_DURATION, ZERO_TS},
+    curve::{
+        CurveType, RampType, StableSwap, MAX_AMP, MAX_N_COINS, MIN_AMP, MIN_RAMP_DURATION, ZERO_TS,
+    },
     error::SwapError,
     fees::Fees,
     instruction::{AdminInstruction, RampAData},
-    state::SwapInfo,
+    processor::Processor,
+    state::{MultiSwapInfo, SwapInfo},
     utils,
 };
 use solana_program::{
-    account_info::{next_account_info, AccountInfo},
+    account_info::{next_account_info, next_account_infos, AccountInfo},
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
-    program_pack::Pack,
+    program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
     sysvar::{clock::Clock, Sysvar},
 };
 
+/// Delay between committing new fees and applying them
+pub const FEE_CHANGE_DELAY: i64 = 259200;
+
+/// Admin settings shared by two-token and multi-token swaps, with the
+/// per-token keys and balances in pool order
+#[derive(Clone, Copy, Debug)]
+struct AdminState {
+    nonce: u8,
+    n_coins: usize,
+    curve_type: CurveType,
+    is_paused: bool,
+    initial_amp_factor: u64,
+    target_amp_factor: u64,
+    start_ramp_ts: i64,
+    stop_ramp_ts: i64,
+    ramp_type: RampType,
+    future_admin_deadline: i64,
+    future_admin_key: Pubkey,
+    admin_key: Pubkey,
+    fees: Fees,
+    future_fees: Fees,
+    future_fees_ts: i64,
+    tokens: [Pubkey; MAX_N_COINS],
+    token_mints: [Pubkey; MAX_N_COINS],
+    admin_fee_keys: [Pubkey; MAX_N_COINS],
+    admin_fees: [u64; MAX_N_COINS],
+}
+
+/// Swap states admin instructions apply to
+trait AdminSwap: Pack + IsInitialized {
+    /// Admin settings of the swap
+    fn admin_state(&self) -> AdminState;
+    /// Write back the admin settings of the swap
+    fn set_admin_state(&mut self, state: &AdminState);
+}
+
+impl AdminSwap for SwapInfo {
+    fn admin_state(&self) -> AdminState {
+        AdminState {
+            nonce: self.nonce,
+            n_coins: 2,
+            curve_type: self.curve_type,
+            is_paused: self.is_paused,
+            initial_amp_factor: self.initial_amp_factor,
+            target_amp_factor: self.target_amp_factor,
+            start_ramp_ts: self.start_ramp_ts,
+            stop_ramp_ts: self.stop_ramp_ts,
+            ramp_type: self.ramp_type,
+            future_admin_deadline: self.future_admin_deadline,
+            future_admin_key: self.future_admin_key,
+            admin_key: self.admin_key,
+            fees: self.fees,
+            future_fees: self.future_fees,
+            future_fees_ts: self.future_fees_ts,
+            tokens: [
+                self.token_a,
+                self.token_b,
+                Pubkey::default(),
+                Pubkey::default(),
+            ],
+            token_mints: [
+                self.token_a_mint,
+                self.token_b_mint,
+                Pubkey::default(),
+                Pubkey::default(),
+            ],
+            admin_fee_keys: [
+                self.admin_fee_key_a,
+                self.admin_fee_key_b,
+                Pubkey::default(),
+                Pubkey::default(),
+            ],
+            admin_fees: [self.admin_fees_a, self.admin_fees_b, 0, 0],
+        }
+    }
+
+    fn set_admin_state(&mut self, state: &AdminState) {
+        self.is_paused = state.is_paused;
+        self.initial_amp_factor = state.initial_amp_factor;
+        self.target_amp_factor = state.target_amp_factor;
+        self.start_ramp_ts = state.start_ramp_ts;
+        self.stop_ramp_ts = state.stop_ramp_ts;
+        self.ramp_type = state.ramp_type;
+        self.future_admin_deadline = state.future_admin_deadline;
+        self.future_admin_key = state.future_admin_key;
+        self.admin_key = state.admin_key;
+        self.fees = state.fees;
+        self.future_fees = state.future_fees;
+        self.future_fees_ts = state.future_fees_ts;
+        self.admin_fee_key_a = state.admin_fee_keys[0];
+        self.admin_fee_key_b = state.admin_fee_keys[1];
+        self.admin_fees_a = state.admin_fees[0];
+        self.admin_fees_b = state.admin_fees[1];
+    }
+}
+
+impl AdminSwap for MultiSwapInfo {
+    fn admin_state(&self) -> AdminState {
+        AdminState {
+            nonce: self.nonce,
+            n_coins: self.n_coins as usize,
+            curve_type: CurveType::StableSwap,
+            is_paused: self.is_paused,
+            initial_amp_factor: self.initial_amp_factor,
+            target_amp_factor: self.target_amp_factor,
+            start_ramp_ts: self.start_ramp_ts,
+            stop_ramp_ts: self.stop_ramp_ts,
+            ramp_type: self.ramp_type,
+            future_admin_deadline: self.future_admin_deadline,
+            future_admin_key: self.future_admin_key,
+            admin_key: self.admin_key,
+            fees: self.fees,
+            future_fees: self.future_fees,
+            future_fees_ts: self.future_fees_ts,
+            tokens: self.tokens,
+            token_mints: self.token_mints,
+            admin_fee_keys: self.admin_fee_keys,
+            admin_fees: self.admin_fees,
+        }
+    }
+
+    fn set_admin_state(&mut self, state: &AdminState) {
+        self.is_paused = state.is_paused;
+        self.initial_amp_factor = state.initial_amp_factor;
+        self.target_amp_factor = state.target_amp_factor;
+        self.start_ramp_ts = state.start_ramp_ts;
+        self.stop_ramp_ts = state.stop_ramp_ts;
+        self.ramp_type = state.ramp_type;
+        self.future_admin_deadline = state.future_admin_deadline;
+        self.future_admin_key = state.future_admin_key;
+        self.admin_key = state.admin_key;
+        self.fees = state.fees;
+        self.future_fees = state.future_fees;
+        self.future_fees_ts = state.future_fees_ts;
+        self.admin_fee_keys = state.admin_fee_keys;
+        self.admin_fees = state.admin_fees;
+    }
+}
+
 /// Process admin instruction
 pub fn process_admin_instruction(
     instruction: &AdminInstruction,
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+) -> ProgramResult {
+    let swap_info = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    // Multi-token swaps are told apart by the size of their state
+    if swap_info.data_len() == MultiSwapInfo::LEN {
+        process_swap_admin_instruction::<MultiSwapInfo>(instruction, program_id, accounts)
+    } else {
+        process_swap_admin_instruction::<SwapInfo>(instruction, program_id, accounts)
+    }
+}
+
+fn process_swap_admin_instruction<T: AdminSwap>(
+    instruction: &AdminInstruction,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
     match *instruction {
         AdminInstruction::RampA(RampAData {
             target_amp,
             stop_ramp_ts,
+            ramp_type,
         }) => {
             msg!("Instruction : RampA");
-            ramp_a(program_id, target_amp, stop_ramp_ts, accounts)
+            ramp_a::<T>(program_id, target_amp, stop_ramp_ts, ramp_type, accounts)
         }
         AdminInstruction::StopRampA => {
             msg!("Instruction: StopRampA");
-            stop_ramp_a(program_id, accounts)
+            stop_ramp_a::<T>(program_id, accounts)
         }
         AdminInstruction::Pause => {
             msg!("Instruction: Pause");
-            pause(program_id, accounts)
+            pause::<T>(program_id, accounts)
         }
         AdminInstruction::Unpause => {
             msg!("Instruction: Unpause");
-            unpause(program_id, accounts)
+            unpause::<T>(program_id, accounts)
         }
         AdminInstruction::SetFeeAccount => {
             msg!("Instruction: SetFeeAccount");
-            set_fee_account(program_id, accounts)
+            set_fee_account::<T>(program_id, accounts)
         }
         AdminInstruction::ApplyNewAdmin => {
             msg!("Instruction: ApplyNewAdmin");
-            apply_new_admin(program_id, accounts)
+            apply_new_admin::<T>(program_id, accounts)
         }
         AdminInstruction::CommitNewAdmin => {
             msg!("Instruction: CommitNewAdmin");
-            commit_new_admin(program_id, accounts)
+            commit_new_admin::<T>(program_id, accounts)
+        }
+        AdminInstruction::CommitNewFees(new_fees) => {
+            msg!("Instruction: CommitNewFees");
+            commit_new_fees::<T>(program_id, &new_fees, accounts)
+        }
+        AdminInstruction::ApplyNewFees => {
+            msg!("Instruction: ApplyNewFees");
+            apply_new_fees::<T>(program_id, accounts)
         }
-        AdminInstruction::SetNewFees(new_fees) => {
-            msg!("Instruction: SetNewFees");
-            set_new_fees(program_id, &new_fees, accounts)
+        AdminInstruction::RevertNewFees => {
+            msg!("Instruction: RevertNewFees");
+            revert_new_fees::<T>(program_id, accounts)
+        }
+        AdminInstruction::WithdrawAdminFees => {
+            msg!("Instruction: WithdrawAdminFees");
+            withdraw_admin_fees::<T>(program_id, accounts)
         }
     }
 }
@@ -76,10 +245,11 @@ fn is_admin(expected_admin_key: &Pubkey, admin_account_info: &AccountInfo) -> Pr
 }
 
 /// Ramp to future a
-fn ramp_a(
+fn ramp_a<T: AdminSwap>(
     program_id: &Pubkey,
     target_amp: u64,
     stop_ramp_ts: i64,
+    ramp_type: RampType,
     accounts: &[AccountInfo],
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
@@ -91,14 +261,15 @@ fn ramp_a(
     if !(MIN_AMP..=MAX_AMP).contains(&target_amp) {
         return Err(SwapError::InvalidInput.into());
     }
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
 
     let clock = Clock::from_account_info(clock_sysvar_info)?;
-    let ramp_lock_ts = token_swap
+    let ramp_lock_ts = state
         .start_ramp_ts
         .checked_add(MIN_RAMP_DURATION)
         .ok_or(SwapError::CalculationFailure)?;
@@ -115,17 +286,14 @@ fn ramp_a(
 
     const MAX_A_CHANGE: u64 = 10;
     let invariant = StableSwap::new(
-        token_swap.initial_amp_factor,
-        token_swap.target_amp_factor,
+        state.initial_amp_factor,
+        state.target_amp_factor,
         clock.unix_timestamp,
-        token_swap.start_ramp_ts,
-        token_swap.stop_ramp_ts,
-    );
-    let current_amp = U256::to_u64(
-        invariant
-            .compute_amp_factor()
-            .ok_or(SwapError::CalculationFailure)?,
-    )?;
+        state.start_ramp_ts,
+        state.stop_ramp_ts,
+    )
+    .with_ramp_type(state.ramp_type);
+    let current_amp = U256::to_u64(invariant.compute_amp_factor()?)?;
     if target_amp < current_amp {
         if current_amp > target_amp * MAX_A_CHANGE {
             // target_amp too low
@@ -136,143 +304,150 @@ fn ramp_a(
         return Err(SwapError::InvalidInput.into());
     }
 
-    token_swap.initial_amp_factor = current_amp;
-    token_swap.target_amp_factor = target_amp;
-    token_swap.start_ramp_ts = clock.unix_timestamp;
-    token_swap.stop_ramp_ts = stop_ramp_ts;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    state.initial_amp_factor = current_amp;
+    state.target_amp_factor = target_amp;
+    state.start_ramp_ts = clock.unix_timestamp;
+    state.stop_ramp_ts = stop_ramp_ts;
+    state.ramp_type = ramp_type;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Stop ramp a
-fn stop_ramp_a(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn stop_ramp_a<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
     let clock_sysvar_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
 
     let clock = Clock::from_account_info(clock_sysvar_info)?;
     let invariant = StableSwap::new(
-        token_swap.initial_amp_factor,
-        token_swap.target_amp_factor,
+        state.initial_amp_factor,
+        state.target_amp_factor,
         clock.unix_timestamp,
-        token_swap.start_ramp_ts,
-        token_swap.stop_ramp_ts,
-    );
-    let current_amp = U256::to_u64(
-        invariant
-            .compute_amp_factor()
-            .ok_or(SwapError::CalculationFailure)?,
-    )?;
-
-    token_swap.initial_amp_factor = current_amp;
-    token_swap.target_amp_factor = current_amp;
-    token_swap.start_ramp_ts = clock.unix_timestamp;
-    token_swap.stop_ramp_ts = clock.unix_timestamp;
+        state.start_ramp_ts,
+        state.stop_ramp_ts,
+    )
+    .with_ramp_type(state.ramp_type);
+    let current_amp = U256::to_u64(invariant.compute_amp_factor()?)?;
+
+    state.initial_amp_factor = current_amp;
+    state.target_amp_factor = current_amp;
+    state.start_ramp_ts = clock.unix_timestamp;
+    state.stop_ramp_ts = clock.unix_timestamp;
     // now (current_ts < stop_ramp_ts) is always False, compute_amp_factor should return target_amp
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Pause swap
-fn pause(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn pause<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
 
-    token_swap.is_paused = true;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    state.is_paused = true;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Unpause swap
-fn unpause(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn unpause<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
 
-    token_swap.is_paused = false;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    state.is_paused = false;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Set fee account
-fn set_fee_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn set_fee_account<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
     let new_fee_account_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
     let new_admin_fee_account = utils::unpack_token_account(&new_fee_account_info.data.borrow())?;
-    if new_admin_fee_account.mint == token_swap.token_a_mint {
-        token_swap.admin_fee_key_a = *new_fee_account_info.key;
-    } else if new_admin_fee_account.mint == token_swap.token_b_mint {
-        token_swap.admin_fee_key_b = *new_fee_account_info.key;
-    } else {
-        return Err(SwapError::InvalidAdmin.into());
-    }
-
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    let i = state.token_mints[..state.n_coins]
+        .iter()
+        .position(|mint| *mint == new_admin_fee_account.mint)
+        .ok_or(SwapError::InvalidAdmin)?;
+    state.admin_fee_keys[i] = *new_fee_account_info.key;
+
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Apply new admin (finalize admin transfer)
-fn apply_new_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn apply_new_admin<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
     let clock_sysvar_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
-    if token_swap.future_admin_deadline == ZERO_TS {
+    if state.future_admin_deadline == ZERO_TS {
         return Err(SwapError::NoActiveTransfer.into());
     }
     let clock = Clock::from_account_info(clock_sysvar_info)?;
-    if clock.unix_timestamp > token_swap.future_admin_deadline {
+    if clock.unix_timestamp > state.future_admin_deadline {
         return Err(SwapError::AdminDeadlineExceeded.into());
     }
 
-    token_swap.admin_key = token_swap.future_admin_key;
-    token_swap.future_admin_key = Pubkey::default();
-    token_swap.future_admin_deadline = ZERO_TS;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    state.admin_key = state.future_admin_key;
+    state.future_admin_key = Pubkey::default();
+    state.future_admin_deadline = ZERO_TS;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 /// Commit new admin (initiate admin transfer)
-fn commit_new_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn commit_new_admin<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
@@ -280,50 +455,174 @@ fn commit_new_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramRes
     let new_admin_info = next_account_info(account_info_iter)?;
     let clock_sysvar_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
 
     let clock = Clock::from_account_info(clock_sysvar_info)?;
     const ADMIN_TRANSFER_DELAY: i64 = 259200;
-    if clock.unix_timestamp < token_swap.future_admin_deadline {
+    if clock.unix_timestamp < state.future_admin_deadline {
         return Err(SwapError::ActiveTransfer.into());
     }
 
-    token_swap.future_admin_key = *new_admin_info.key;
-    token_swap.future_admin_deadline = clock
+    state.future_admin_key = *new_admin_info.key;
+    state.future_admin_deadline = clock
         .unix_timestamp
         .checked_add(ADMIN_TRANSFER_DELAY)
         .ok_or(SwapError::CalculationFailure)?;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    Ok(())
+}
+
+/// Commit new fees (initiate fee change)
+fn commit_new_fees<T: AdminSwap>(
+    program_id: &Pubkey,
+    new_fees: &Fees,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let swap_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    let clock_sysvar_info = next_account_info(account_info_iter)?;
+
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
+        return Err(SwapError::InvalidProgramAddress.into());
+    }
+    if state.future_fees_ts != ZERO_TS {
+        return Err(SwapError::ActiveFeeChange.into());
+    }
+
+    if state.curve_type == CurveType::ConstantProduct && new_fees.has_offpeg_fees() {
+        return Err(SwapError::InvalidInput.into());
+    }
+    new_fees.validate()?;
+    let clock = Clock::from_account_info(clock_sysvar_info)?;
+    state.future_fees = *new_fees;
+    state.future_fees_ts = clock
+        .unix_timestamp
+        .checked_add(FEE_CHANGE_DELAY)
+        .ok_or(SwapError::CalculationFailure)?;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    Ok(())
+}
+
+/// Apply new fees (finalize fee change)
+fn apply_new_fees<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let swap_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+    let clock_sysvar_info = next_account_info(account_info_iter)?;
+
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
+        return Err(SwapError::InvalidProgramAddress.into());
+    }
+    if state.future_fees_ts == ZERO_TS {
+        return Err(SwapError::NoActiveFeeChange.into());
+    }
+    let clock = Clock::from_account_info(clock_sysvar_info)?;
+    if clock.unix_timestamp < state.future_fees_ts {
+        return Err(SwapError::FeeChangeLocked.into());
+    }
+
+    state.fees = state.future_fees;
+    state.future_fees = Fees::default();
+    state.future_fees_ts = ZERO_TS;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    Ok(())
+}
+
+/// Revert new fees (cancel fee change)
+fn revert_new_fees<T: AdminSwap>(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let swap_info = next_account_info(account_info_iter)?;
+    let authority_info = next_account_info(account_info_iter)?;
+    let admin_info = next_account_info(account_info_iter)?;
+
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
+        return Err(SwapError::InvalidProgramAddress.into());
+    }
+    if state.future_fees_ts == ZERO_TS {
+        return Err(SwapError::NoActiveFeeChange.into());
+    }
+
+    state.future_fees = Fees::default();
+    state.future_fees_ts = ZERO_TS;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
-/// Set new fees
-fn set_new_fees(program_id: &Pubkey, new_fees: &Fees, accounts: &[AccountInfo]) -> ProgramResult {
+/// Withdraw admin fees accrued in the pool
+fn withdraw_admin_fees<T: AdminSwap>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let swap_info = next_account_info(account_info_iter)?;
     let authority_info = next_account_info(account_info_iter)?;
     let admin_info = next_account_info(account_info_iter)?;
 
-    let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
-    is_admin(&token_swap.admin_key, admin_info)?;
-    if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)? {
+    let mut token_swap = T::unpack(&swap_info.data.borrow())?;
+    let mut state = token_swap.admin_state();
+    let n = state.n_coins;
+    let swap_token_infos = next_account_infos(account_info_iter, n)?;
+    let admin_fee_infos = next_account_infos(account_info_iter, n)?;
+    let token_program_info = next_account_info(account_info_iter)?;
+    is_admin(&state.admin_key, admin_info)?;
+    if *authority_info.key != utils::authority_id(program_id, swap_info.key, state.nonce)? {
         return Err(SwapError::InvalidProgramAddress.into());
     }
+    for (swap_token_info, token) in swap_token_infos.iter().zip(state.tokens.iter()) {
+        if swap_token_info.key != token {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+    }
+    for (admin_fee_info, admin_fee_key) in admin_fee_infos.iter().zip(state.admin_fee_keys.iter()) {
+        if admin_fee_info.key != admin_fee_key {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+    }
+
+    for i in 0..n {
+        Processor::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_infos[i].clone(),
+            admin_fee_infos[i].clone(),
+            authority_info.clone(),
+            state.nonce,
+            state.admin_fees[i],
+        )?;
+        state.admin_fees[i] = 0;
+    }
 
-    token_swap.fees = *new_fees;
-    SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+    token_swap.set_admin_state(&state);
+    T::pack(token_swap, &mut swap_info.data.borrow_mut())?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{curve::ZERO_TS, utils::test_utils::*};
-    use solana_sdk::clock::Epoch;
+    use crate::{curve::ZERO_TS, fees::OFFPEG_FEE_MULTIPLIER_DENOMINATOR, utils::test_utils::*};
+    use solana_sdk::{account::Account, clock::Epoch};
 
     const DEFAULT_TOKEN_A_AMOUNT: u64 = 1_000_000_000;
     const DEFAULT_TOKEN_B_AMOUNT: u64 = 1_000_000_000;
@@ -382,7 +681,7 @@ mod tests {
         {
             assert_eq!(
                 Err(ProgramError::UninitializedAccount),
-                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION)
+                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION, RampType::Linear)
             );
         }
 
@@ -394,12 +693,12 @@ mod tests {
             let target_amp = 0;
             assert_eq!(
                 Err(SwapError::InvalidInput.into()),
-                accounts.ramp_a(target_amp, ZERO_TS, stop_ramp_ts)
+                accounts.ramp_a(target_amp, ZERO_TS, stop_ramp_ts, RampType::Linear)
             );
             let target_amp = MAX_AMP + 1;
             assert_eq!(
                 Err(SwapError::InvalidInput.into()),
-                accounts.ramp_a(target_amp, ZERO_TS, stop_ramp_ts)
+                accounts.ramp_a(target_amp, ZERO_TS, stop_ramp_ts, RampType::Linear)
             );
         }
 
@@ -413,7 +712,7 @@ mod tests {
             accounts.authority_key = bad_authority_key;
             assert_eq!(
                 Err(SwapError::InvalidProgramAddress.into()),
-                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION)
+                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION, RampType::Linear)
             );
             accounts.authority_key = old_authority;
         }
@@ -425,7 +724,7 @@ mod tests {
             accounts.admin_key = fake_admin_key;
             assert_eq!(
                 Err(SwapError::Unauthorized.into()),
-                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION)
+                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION, RampType::Linear)
             );
             accounts.admin_key = old_admin_key;
         }
@@ -434,7 +733,7 @@ mod tests {
         {
             assert_eq!(
                 Err(SwapError::RampLocked.into()),
-                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION / 2)
+                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION / 2, RampType::Linear)
             );
         }
 
@@ -442,7 +741,7 @@ mod tests {
         {
             assert_eq!(
                 Err(SwapError::InsufficientRampTime.into()),
-                accounts.ramp_a(amp_factor, MIN_RAMP_DURATION, ZERO_TS)
+                accounts.ramp_a(amp_factor, MIN_RAMP_DURATION, ZERO_TS, RampType::Linear)
             );
         }
 
@@ -451,12 +750,22 @@ mod tests {
             // amp target too low
             assert_eq!(
                 Err(SwapError::InvalidInput.into()),
-                accounts.ramp_a(MIN_AMP, MIN_RAMP_DURATION, MIN_RAMP_DURATION * 2)
+                accounts.ramp_a(
+                    MIN_AMP,
+                    MIN_RAMP_DURATION,
+                    MIN_RAMP_DURATION * 2,
+                    RampType::Linear
+                )
             );
             // amp target too high
             assert_eq!(
                 Err(SwapError::InvalidInput.into()),
-                accounts.ramp_a(MAX_AMP, MIN_RAMP_DURATION, MIN_RAMP_DURATION * 2)
+                accounts.ramp_a(
+                    MAX_AMP,
+                    MIN_RAMP_DURATION,
+                    MIN_RAMP_DURATION * 2,
+                    RampType::Linear
+                )
             );
         }
 
@@ -466,7 +775,7 @@ mod tests {
             let current_ts = MIN_RAMP_DURATION;
             let stop_ramp_ts = MIN_RAMP_DURATION * 2;
             accounts
-                .ramp_a(target_amp, current_ts, stop_ramp_ts)
+                .ramp_a(target_amp, current_ts, stop_ramp_ts, RampType::Linear)
                 .unwrap();
 
             let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
@@ -474,6 +783,27 @@ mod tests {
             assert_eq!(swap_info.target_amp_factor, target_amp);
             assert_eq!(swap_info.start_ramp_ts, current_ts);
             assert_eq!(swap_info.stop_ramp_ts, stop_ramp_ts);
+            assert_eq!(swap_info.ramp_type, RampType::Linear);
+        }
+
+        // valid geometric ramp
+        {
+            let target_amp = MIN_AMP * 2_000;
+            let current_ts = MIN_RAMP_DURATION * 3;
+            let stop_ramp_ts = MIN_RAMP_DURATION * 5;
+            accounts
+                .ramp_a(target_amp, current_ts, stop_ramp_ts, RampType::Geometric)
+                .unwrap();
+
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.initial_amp_factor, MIN_AMP * 200);
+            assert_eq!(swap_info.target_amp_factor, target_amp);
+            assert_eq!(swap_info.ramp_type, RampType::Geometric);
+
+            // halfway through, A is the geometric mean of the ramp ends
+            accounts.stop_ramp_a(MIN_RAMP_DURATION * 4).unwrap();
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.target_amp_factor, MIN_AMP * 632);
         }
     }
 
@@ -493,7 +823,7 @@ mod tests {
         {
             assert_eq!(
                 Err(ProgramError::UninitializedAccount),
-                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION)
+                accounts.ramp_a(MIN_AMP, ZERO_TS, MIN_RAMP_DURATION, RampType::Linear)
             );
         }
 
@@ -901,8 +1231,9 @@ mod tests {
     }
 
     #[test]
-    fn test_set_new_fees() {
+    fn test_commit_new_fees() {
         let user_key = pubkey_rand();
+        let current_ts = MIN_RAMP_DURATION;
         let amp_factor = MIN_AMP * 100;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
@@ -911,28 +1242,150 @@ mod tests {
             DEFAULT_TOKEN_B_AMOUNT,
             DEFAULT_TEST_FEES,
         );
+        let new_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1_000,
+            ..DEFAULT_TEST_FEES
+        };
+
+        // swap not initialized
+        {
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.commit_new_fees(new_fees, current_ts)
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong nonce for authority_key
+        {
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &TOKEN_PROGRAM_ID,
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.commit_new_fees(new_fees, current_ts)
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // unauthorized account
+        {
+            let old_admin_key = accounts.admin_key;
+            let fake_admin_key = pubkey_rand();
+            accounts.admin_key = fake_admin_key;
+            assert_eq!(
+                Err(SwapError::Unauthorized.into()),
+                accounts.commit_new_fees(new_fees, current_ts)
+            );
+            accounts.admin_key = old_admin_key;
+        }
+
+        // invalid fees
+        {
+            let invalid_fees = Fees {
+                trade_fee_denominator: 0,
+                ..new_fees
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.commit_new_fees(invalid_fees, current_ts)
+            );
+            let above_maximum = Fees {
+                trade_fee_numerator: 3,
+                trade_fee_denominator: 4,
+                ..new_fees
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.commit_new_fees(above_maximum, current_ts)
+            );
+        }
+
+        // commit new fees
+        {
+            accounts.commit_new_fees(new_fees, current_ts).unwrap();
+
+            // current fees stay in place until the new ones are applied
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
+            assert_eq!(swap_info.future_fees, new_fees);
+            assert_eq!(swap_info.future_fees_ts, current_ts + FEE_CHANGE_DELAY);
+        }
+
+        // constant product pools have no peg
+        {
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                amp_factor,
+                DEFAULT_TOKEN_A_AMOUNT,
+                DEFAULT_TOKEN_B_AMOUNT,
+                DEFAULT_TEST_FEES,
+            );
+            accounts.curve_type = CurveType::ConstantProduct;
+            accounts.initialize_swap().unwrap();
+            let offpeg_fees = Fees {
+                offpeg_fee_multiplier: 2 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+                ..new_fees
+            };
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.commit_new_fees(offpeg_fees, current_ts)
+            );
+        }
 
-        let new_fees: Fees = Fees {
-            admin_trade_fee_numerator: 0,
-            admin_trade_fee_denominator: 0,
-            admin_withdraw_fee_numerator: 0,
-            admin_withdraw_fee_denominator: 0,
-            trade_fee_numerator: 0,
-            trade_fee_denominator: 0,
-            withdraw_fee_numerator: 0,
-            withdraw_fee_denominator: 0,
+        // active fee change
+        {
+            assert_eq!(
+                Err(SwapError::ActiveFeeChange.into()),
+                accounts.commit_new_fees(new_fees, current_ts + FEE_CHANGE_DELAY)
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_new_fees() {
+        let user_key = pubkey_rand();
+        let current_ts = MIN_RAMP_DURATION;
+        let amp_factor = MIN_AMP * 100;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            DEFAULT_TOKEN_A_AMOUNT,
+            DEFAULT_TOKEN_B_AMOUNT,
+            DEFAULT_TEST_FEES,
+        );
+        let new_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1_000,
+            ..DEFAULT_TEST_FEES
         };
 
         // swap not initialized
         {
             assert_eq!(
                 Err(ProgramError::UninitializedAccount),
-                accounts.set_new_fees(new_fees)
+                accounts.apply_new_fees(current_ts)
             );
         }
 
         accounts.initialize_swap().unwrap();
 
+        // no active fee change
+        {
+            assert_eq!(
+                Err(SwapError::NoActiveFeeChange.into()),
+                accounts.apply_new_fees(current_ts)
+            );
+        }
+
+        accounts.commit_new_fees(new_fees, current_ts).unwrap();
+        let apply_ts = current_ts + FEE_CHANGE_DELAY;
+
         // wrong nonce for authority_key
         {
             let old_authority = accounts.authority_key;
@@ -943,7 +1396,7 @@ mod tests {
             accounts.authority_key = bad_authority_key;
             assert_eq!(
                 Err(SwapError::InvalidProgramAddress.into()),
-                accounts.set_new_fees(new_fees)
+                accounts.apply_new_fees(apply_ts)
             );
             accounts.authority_key = old_authority;
         }
@@ -955,17 +1408,652 @@ mod tests {
             accounts.admin_key = fake_admin_key;
             assert_eq!(
                 Err(SwapError::Unauthorized.into()),
-                accounts.set_new_fees(new_fees)
+                accounts.apply_new_fees(apply_ts)
             );
             accounts.admin_key = old_admin_key;
         }
 
-        // valid call
+        // apply before the delay
+        {
+            assert_eq!(
+                Err(SwapError::FeeChangeLocked.into()),
+                accounts.apply_new_fees(apply_ts - 1)
+            );
+        }
+
+        // apply new fees
         {
-            accounts.set_new_fees(new_fees).unwrap();
+            accounts.apply_new_fees(apply_ts).unwrap();
 
             let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
             assert_eq!(swap_info.fees, new_fees);
+            assert_eq!(swap_info.future_fees, Fees::default());
+            assert_eq!(swap_info.future_fees_ts, ZERO_TS);
+        }
+    }
+
+    #[test]
+    fn test_revert_new_fees() {
+        let user_key = pubkey_rand();
+        let current_ts = MIN_RAMP_DURATION;
+        let amp_factor = MIN_AMP * 100;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            DEFAULT_TOKEN_A_AMOUNT,
+            DEFAULT_TOKEN_B_AMOUNT,
+            DEFAULT_TEST_FEES,
+        );
+        let new_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1_000,
+            ..DEFAULT_TEST_FEES
+        };
+
+        // swap not initialized
+        {
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.revert_new_fees()
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // no active fee change
+        {
+            assert_eq!(
+                Err(SwapError::NoActiveFeeChange.into()),
+                accounts.revert_new_fees()
+            );
+        }
+
+        accounts.commit_new_fees(new_fees, current_ts).unwrap();
+
+        // unauthorized account
+        {
+            let old_admin_key = accounts.admin_key;
+            let fake_admin_key = pubkey_rand();
+            accounts.admin_key = fake_admin_key;
+            assert_eq!(
+                Err(SwapError::Unauthorized.into()),
+                accounts.revert_new_fees()
+            );
+            accounts.admin_key = old_admin_key;
+        }
+
+        // revert new fees
+        {
+            accounts.revert_new_fees().unwrap();
+
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
+            assert_eq!(swap_info.future_fees, Fees::default());
+            assert_eq!(swap_info.future_fees_ts, ZERO_TS);
+            assert_eq!(
+                Err(SwapError::NoActiveFeeChange.into()),
+                accounts.apply_new_fees(current_ts + FEE_CHANGE_DELAY)
+            );
+
+            // a new change can be committed
+            accounts.commit_new_fees(new_fees, current_ts).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fee_change_delay() {
+        let user_key = pubkey_rand();
+        let current_ts = MIN_RAMP_DURATION;
+        let amp_factor = MIN_AMP * 100;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            DEFAULT_TOKEN_A_AMOUNT,
+            DEFAULT_TOKEN_B_AMOUNT,
+            DEFAULT_TEST_FEES,
+        );
+        let new_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1_000,
+            ..DEFAULT_TEST_FEES
+        };
+        accounts.initialize_swap().unwrap();
+        accounts.commit_new_fees(new_fees, current_ts).unwrap();
+        let apply_ts = current_ts + FEE_CHANGE_DELAY;
+
+        // the removed SetNewFees tag no longer sets fees instantly
+        {
+            let mut data = vec![107];
+            let mut new_fees_slice = [0u8; Fees::LEN];
+            new_fees.pack_into_slice(&mut new_fees_slice[..]);
+            data.extend_from_slice(&new_fees_slice);
+            let mut instruction = crate::instruction::commit_new_fees(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.admin_key,
+                new_fees,
+            )
+            .unwrap();
+            instruction.data = data;
+            assert_eq!(
+                Err(SwapError::InvalidInstruction.into()),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.admin_account,
+                        &mut clock_account(current_ts),
+                    ],
+                )
+            );
+        }
+
+        // neither applying early nor committing again changes the fees
+        {
+            assert_eq!(
+                Err(SwapError::FeeChangeLocked.into()),
+                accounts.apply_new_fees(apply_ts - 1)
+            );
+            assert_eq!(
+                Err(SwapError::ActiveFeeChange.into()),
+                accounts.commit_new_fees(new_fees, apply_ts - 1)
+            );
+        }
+
+        // reverting and committing again restarts the delay
+        {
+            accounts.revert_new_fees().unwrap();
+            accounts.commit_new_fees(new_fees, apply_ts - 1).unwrap();
+            assert_eq!(
+                Err(SwapError::FeeChangeLocked.into()),
+                accounts.apply_new_fees(apply_ts)
+            );
+        }
+
+        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
+
+        accounts
+            .apply_new_fees(apply_ts - 1 + FEE_CHANGE_DELAY)
+            .unwrap();
+        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.fees, new_fees);
+    }
+
+    #[test]
+    fn test_swap_info_lengths_differ() {
+        // Admin instructions tell the pool types apart by account size
+        assert_ne!(SwapInfo::LEN, MultiSwapInfo::LEN);
+    }
+
+    fn new_multi_swap(user_key: &Pubkey) -> MultiSwapAccountInfo {
+        MultiSwapAccountInfo::new(
+            user_key,
+            MIN_AMP * 100,
+            &[
+                DEFAULT_TOKEN_A_AMOUNT,
+                DEFAULT_TOKEN_B_AMOUNT,
+                DEFAULT_TOKEN_A_AMOUNT,
+            ],
+            DEFAULT_TEST_FEES,
+        )
+    }
+
+    fn swap_multi(
+        accounts: &mut MultiSwapAccountInfo,
+        user_key: &Pubkey,
+        token_index_in: u8,
+        token_index_out: u8,
+        amount_in: u64,
+    ) -> ProgramResult {
+        let trader_key = pubkey_rand();
+        let mut amounts = [0; 3];
+        amounts[token_index_in as usize] = amount_in;
+        let (token_keys, mut token_accounts, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(user_key, &trader_key, &amounts, 0);
+        let (i, j) = (token_index_in as usize, token_index_out as usize);
+        let mut source_account = token_accounts[i].clone();
+        accounts.swap(
+            &trader_key,
+            &token_keys[i],
+            &mut source_account,
+            &token_keys[j],
+            &mut token_accounts[j],
+            token_index_in,
+            token_index_out,
+            amount_in,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_multi_pause() {
+        let user_key = pubkey_rand();
+        let mut accounts = new_multi_swap(&user_key);
+
+        // swap not initialized
+        {
+            assert_eq!(Err(ProgramError::UninitializedAccount), accounts.pause());
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // unauthorized account
+        {
+            let old_admin_key = accounts.admin_key;
+            accounts.admin_key = pubkey_rand();
+            assert_eq!(Err(SwapError::Unauthorized.into()), accounts.pause());
+            assert_eq!(Err(SwapError::Unauthorized.into()), accounts.unpause());
+            accounts.admin_key = old_admin_key;
+        }
+
+        // paused swaps reject trades
+        {
+            accounts.pause().unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert!(swap_info.is_paused);
+            assert_eq!(
+                Err(SwapError::IsPaused.into()),
+                swap_multi(&mut accounts, &user_key, 0, 2, 1_000)
+            );
+        }
+
+        // unpaused swaps trade again
+        {
+            accounts.unpause().unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert!(!swap_info.is_paused);
+            swap_multi(&mut accounts, &user_key, 0, 2, 1_000).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_multi_ramp_a() {
+        let user_key = pubkey_rand();
+        let amp_factor = MIN_AMP * 100;
+        let mut accounts = new_multi_swap(&user_key);
+        accounts.initialize_swap().unwrap();
+
+        // too large a change
+        {
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.ramp_a(
+                    amp_factor * 11,
+                    MIN_RAMP_DURATION,
+                    MIN_RAMP_DURATION * 2,
+                    RampType::Linear
+                )
+            );
+        }
+
+        // ramp geometrically and stop halfway
+        {
+            let target_amp = amp_factor * 2;
+            let stop_ramp_ts = MIN_RAMP_DURATION * 3;
+            accounts
+                .ramp_a(
+                    target_amp,
+                    MIN_RAMP_DURATION,
+                    stop_ramp_ts,
+                    RampType::Geometric,
+                )
+                .unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.initial_amp_factor, amp_factor);
+            assert_eq!(swap_info.target_amp_factor, target_amp);
+            assert_eq!(swap_info.start_ramp_ts, MIN_RAMP_DURATION);
+            assert_eq!(swap_info.stop_ramp_ts, stop_ramp_ts);
+            assert_eq!(swap_info.ramp_type, RampType::Geometric);
+
+            let stop_ts = MIN_RAMP_DURATION * 2;
+            let expected_amp = StableSwap::new(
+                swap_info.initial_amp_factor,
+                swap_info.target_amp_factor,
+                stop_ts,
+                swap_info.start_ramp_ts,
+                swap_info.stop_ramp_ts,
+            )
+            .with_ramp_type(swap_info.ramp_type)
+            .compute_amp_factor()
+            .unwrap();
+            accounts.stop_ramp_a(stop_ts).unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(U256::from(swap_info.target_amp_factor), expected_amp);
+            assert_eq!(swap_info.initial_amp_factor, swap_info.target_amp_factor);
+            assert_eq!(swap_info.stop_ramp_ts, stop_ts);
+        }
+    }
+
+    #[test]
+    fn test_multi_admin_transfer() {
+        let user_key = pubkey_rand();
+        let new_admin_key = pubkey_rand();
+        let mut accounts = new_multi_swap(&user_key);
+        accounts.initialize_swap().unwrap();
+
+        assert_eq!(
+            Err(SwapError::NoActiveTransfer.into()),
+            accounts.apply_new_admin(ZERO_TS)
+        );
+        accounts.commit_new_admin(&new_admin_key, ZERO_TS).unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.future_admin_key, new_admin_key);
+        accounts.apply_new_admin(ZERO_TS + 1).unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.admin_key, new_admin_key);
+        assert_eq!(swap_info.future_admin_key, Pubkey::default());
+        assert_eq!(swap_info.future_admin_deadline, ZERO_TS);
+
+        // the previous admin lost control
+        assert_eq!(Err(SwapError::Unauthorized.into()), accounts.pause());
+    }
+
+    #[test]
+    fn test_multi_set_fee_account() {
+        let user_key = pubkey_rand();
+        let owner_key = pubkey_rand();
+        let mut accounts = new_multi_swap(&user_key);
+        accounts.initialize_swap().unwrap();
+        let (admin_fee_keys, admin_fee_accounts, wrong_admin_fee_key, wrong_admin_fee_account) =
+            accounts.setup_token_accounts(&user_key, &owner_key, &[0, 0, 0], 0);
+
+        // wrong admin account
+        {
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                accounts.set_admin_fee_account(&wrong_admin_fee_key, &wrong_admin_fee_account)
+            );
+        }
+
+        // the fee account replaces the one of its mint
+        {
+            accounts
+                .set_admin_fee_account(&admin_fee_keys[2], &admin_fee_accounts[2])
+                .unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.admin_fee_keys[0], accounts.admin_fee_keys[0]);
+            assert_eq!(swap_info.admin_fee_keys[1], accounts.admin_fee_keys[1]);
+            assert_eq!(swap_info.admin_fee_keys[2], admin_fee_keys[2]);
+        }
+    }
+
+    #[test]
+    fn test_multi_fee_change_delay() {
+        let user_key = pubkey_rand();
+        let current_ts = MIN_RAMP_DURATION;
+        let mut accounts = new_multi_swap(&user_key);
+        let new_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1_000,
+            ..DEFAULT_TEST_FEES
+        };
+        accounts.initialize_swap().unwrap();
+        let apply_ts = current_ts + FEE_CHANGE_DELAY;
+
+        // invalid fees
+        {
+            let invalid_fees = Fees {
+                trade_fee_numerator: 2,
+                trade_fee_denominator: 1,
+                ..DEFAULT_TEST_FEES
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.commit_new_fees(invalid_fees, current_ts)
+            );
+        }
+
+        // reverting cancels the change
+        {
+            accounts.commit_new_fees(new_fees, current_ts).unwrap();
+            accounts.revert_new_fees().unwrap();
+            assert_eq!(
+                Err(SwapError::NoActiveFeeChange.into()),
+                accounts.apply_new_fees(apply_ts)
+            );
+        }
+
+        accounts.commit_new_fees(new_fees, current_ts).unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.future_fees, new_fees);
+        assert_eq!(swap_info.future_fees_ts, apply_ts);
+        assert_eq!(
+            Err(SwapError::FeeChangeLocked.into()),
+            accounts.apply_new_fees(apply_ts - 1)
+        );
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
+
+        accounts.apply_new_fees(apply_ts).unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.fees, new_fees);
+        assert_eq!(swap_info.future_fees, Fees::default());
+        assert_eq!(swap_info.future_fees_ts, ZERO_TS);
+    }
+
+    #[test]
+    fn test_withdraw_admin_fees() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = MIN_AMP * 100;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            DEFAULT_TOKEN_A_AMOUNT,
+            DEFAULT_TOKEN_B_AMOUNT,
+            DEFAULT_TEST_FEES,
+        );
+        let amount_in = DEFAULT_TOKEN_A_AMOUNT / 100;
+
+        // swap not initialized
+        {
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_admin_fees()
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // swaps both ways accrue admin fees in both tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, amount_in, amount_in, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let swap_token_b_key = accounts.token_b_key;
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
+
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert!(swap_info.admin_fees_a > 0);
+            assert!(swap_info.admin_fees_b > 0);
+            let admin_fee_account_a =
+                utils::unpack_token_account(&accounts.admin_fee_a_account.data).unwrap();
+            assert_eq!(admin_fee_account_a.amount, 0);
+        }
+
+        // wrong nonce for authority_key
+        {
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &TOKEN_PROGRAM_ID,
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // unauthorized account
+        {
+            let old_admin_key = accounts.admin_key;
+            let fake_admin_key = pubkey_rand();
+            accounts.admin_key = fake_admin_key;
+            assert_eq!(
+                Err(SwapError::Unauthorized.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.admin_key = old_admin_key;
+        }
+
+        // wrong admin fee account
+        {
+            let old_admin_fee_key_b = accounts.admin_fee_b_key;
+            accounts.admin_fee_b_key = pubkey_rand();
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.admin_fee_b_key = old_admin_fee_key_b;
+        }
+
+        // wrong swap token account
+        {
+            let old_token_a_key = accounts.token_a_key;
+            accounts.token_a_key = pubkey_rand();
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.token_a_key = old_token_a_key;
+        }
+
+        // withdraw admin fees
+        {
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let pool_amounts = accounts.pool_amounts();
+            accounts.withdraw_admin_fees().unwrap();
+
+            let admin_fee_account_a =
+                utils::unpack_token_account(&accounts.admin_fee_a_account.data).unwrap();
+            assert_eq!(admin_fee_account_a.amount, swap_info.admin_fees_a);
+            let admin_fee_account_b =
+                utils::unpack_token_account(&accounts.admin_fee_b_account.data).unwrap();
+            assert_eq!(admin_fee_account_b.amount, swap_info.admin_fees_b);
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.admin_fees_a, 0);
+            assert_eq!(swap_info.admin_fees_b, 0);
+            // the pool balances are left as they were
+            assert_eq!(accounts.pool_amounts(), pool_amounts);
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, pool_amounts.0);
+
+            // nothing is left to withdraw
+            accounts.withdraw_admin_fees().unwrap();
+            let admin_fee_account_b_after =
+                utils::unpack_token_account(&accounts.admin_fee_b_account.data).unwrap();
+            assert_eq!(admin_fee_account_b_after.amount, admin_fee_account_b.amount);
+        }
+    }
+
+    #[test]
+    fn test_multi_withdraw_admin_fees() {
+        let user_key = pubkey_rand();
+        let mut accounts = new_multi_swap(&user_key);
+
+        // swap not initialized
+        {
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_admin_fees()
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // swaps accrue admin fees in the destination tokens
+        {
+            swap_multi(&mut accounts, &user_key, 0, 2, DEFAULT_TOKEN_A_AMOUNT / 100).unwrap();
+            swap_multi(&mut accounts, &user_key, 2, 1, DEFAULT_TOKEN_A_AMOUNT / 100).unwrap();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.admin_fees[0], 0);
+            assert!(swap_info.admin_fees[1] > 0);
+            assert!(swap_info.admin_fees[2] > 0);
+        }
+
+        // unauthorized account
+        {
+            let old_admin_key = accounts.admin_key;
+            accounts.admin_key = pubkey_rand();
+            assert_eq!(
+                Err(SwapError::Unauthorized.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.admin_key = old_admin_key;
+        }
+
+        // wrong admin fee account
+        {
+            let old_admin_fee_key = accounts.admin_fee_keys[2];
+            accounts.admin_fee_keys[2] = pubkey_rand();
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.admin_fee_keys[2] = old_admin_fee_key;
+        }
+
+        // wrong swap token account
+        {
+            let old_token_key = accounts.token_keys[1];
+            accounts.token_keys[1] = pubkey_rand();
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_admin_fees()
+            );
+            accounts.token_keys[1] = old_token_key;
+        }
+
+        // withdraw admin fees
+        {
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let pool_amounts = accounts.pool_amounts();
+            accounts.withdraw_admin_fees().unwrap();
+
+            for (i, admin_fee_account) in accounts.admin_fee_accounts.iter().enumerate() {
+                let admin_fee = utils::unpack_token_account(&admin_fee_account.data).unwrap();
+                assert_eq!(admin_fee.amount, swap_info.admin_fees[i]);
+            }
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.admin_fees, [0; MAX_N_COINS]);
+            // the pool balances are left as they were
+            assert_eq!(accounts.pool_amounts(), pool_amounts);
         }
     }
 }
@@ -0,0 +1,169 @@
+//! Constant price invariant implementation
+
+use crate::{
+    bn::U256,
+    curve::{CurveCalculator, MAX_N_COINS, MIN_N_COINS, RATE_PRECISION},
+    error::CurveError,
+    math::TryMath,
+};
+
+/// The constant price invariant calculator, `D = sum(x_i)` of normalized
+/// amounts, for pools of tokens redeemable for each other at a fixed price.
+/// Coins trade at their rates, 1:1 by default, regardless of the balances.
+pub struct ConstantPrice {
+    /// Multipliers normalizing each coin's amounts to a common precision
+    precision_multipliers: [U256; MAX_N_COINS],
+    /// Prices of each coin in a common unit of account, scaled by `RATE_PRECISION`
+    rates: [U256; MAX_N_COINS],
+}
+
+impl ConstantPrice {
+    /// New constant price calculator, with the multipliers normalizing each
+    /// coin's amounts to a common precision and the prices of each coin,
+    /// scaled by `RATE_PRECISION`
+    pub fn new(precision_multipliers: &[u64], rates: &[u64]) -> Self {
+        let mut calculator = Self {
+            precision_multipliers: [U256::one(); MAX_N_COINS],
+            rates: [U256::from(RATE_PRECISION); MAX_N_COINS],
+        };
+        for (multiplier, precision_multiplier) in calculator
+            .precision_multipliers
+            .iter_mut()
+            .zip(precision_multipliers.iter())
+        {
+            *multiplier = U256::from(*precision_multiplier);
+        }
+        for (rate, new_rate) in calculator.rates.iter_mut().zip(rates.iter()) {
+            *rate = U256::from(*new_rate);
+        }
+        calculator
+    }
+}
+
+impl CurveCalculator for ConstantPrice {
+    fn scale(&self, i: usize) -> Result<U256, CurveError> {
+        self.precision_multipliers
+            .get(i)
+            .ok_or(CurveError::InvalidInput)?
+            .try_mul(*self.rates.get(i).ok_or(CurveError::InvalidInput)?)
+    }
+
+    fn compute_d_n(&self, amounts: &[U256]) -> Result<U256, CurveError> {
+        if amounts.len() < MIN_N_COINS {
+            return Err(CurveError::InvalidInput);
+        }
+        let mut d = U256::zero();
+        for amount in amounts {
+            d = d.try_add(*amount)?;
+        }
+        Ok(d)
+    }
+
+    fn compute_y_n(
+        &self,
+        i: usize,
+        j: usize,
+        x: U256,
+        balances: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError> {
+        if i == j || i >= balances.len() || j >= balances.len() {
+            return Err(CurveError::InvalidInput);
+        }
+        let mut sum_others = x;
+        for (k, balance) in balances.iter().enumerate() {
+            if k != i && k != j {
+                sum_others = sum_others.try_add(*balance)?;
+            }
+        }
+        if sum_others > d {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        d.try_sub(sum_others)
+    }
+
+    fn compute_marginal_rate_n(
+        &self,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+    ) -> Result<U256, CurveError> {
+        if i == j || i >= swap_amounts.len() || j >= swap_amounts.len() {
+            return Err(CurveError::InvalidInput);
+        }
+        self.scale(i)?
+            .try_mul(RATE_PRECISION.into())?
+            .try_div(self.scale(j)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::Fees;
+
+    const FEES: Fees = Fees {
+        admin_trade_fee_numerator: 1,
+        admin_trade_fee_denominator: 2,
+        admin_withdraw_fee_numerator: 1,
+        admin_withdraw_fee_denominator: 2,
+        trade_fee_numerator: 1,
+        trade_fee_denominator: 1000,
+        withdraw_fee_numerator: 0,
+        withdraw_fee_denominator: 1,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
+    };
+
+    #[test]
+    fn test_constant_price() {
+        // Token B has 3 decimals less than token A and is worth 2 token A
+        let curve = ConstantPrice::new(&[1, 1_000], &[RATE_PRECISION, 2 * RATE_PRECISION]);
+        let xp = curve.xp(&[1_000_000.into(), 500.into()]).unwrap();
+        assert_eq!(xp, vec![1_000_000.into(), 1_000_000.into()]);
+        assert_eq!(curve.compute_d_n(&xp), Ok(2_000_000.into()));
+        assert_eq!(curve.compute_d(0.into(), 0.into()), Ok(0.into()));
+
+        // Swaps trade at the price, whatever the balances
+        let result = curve
+            .swap_to_n(1, 0, 100.into(), &[1_000_000.into(), 500.into()], &FEES)
+            .unwrap();
+        let dy = U256::from(200_000);
+        assert_eq!(result.amount_swapped, dy - FEES.trade_fee(dy).unwrap());
+        let result = curve
+            .swap_to(200_000.into(), 1_000_000.into(), 500.into(), &FEES)
+            .unwrap();
+        assert_eq!(result.amount_swapped, 100.into());
+
+        // Prices do not move with the balances
+        let quote = curve
+            .quote_swap_n(1, 0, 100.into(), &[1_000_000.into(), 500.into()], &FEES)
+            .unwrap();
+        let price = U256::from(RATE_PRECISION) * 2_000;
+        assert_eq!(quote.spot_price_before, price);
+        assert_eq!(quote.spot_price_after, price);
+        assert_eq!(quote.price_impact, 0.into());
+
+        // Swaps cannot take more than the pool holds
+        assert_eq!(
+            curve
+                .swap_to_n(1, 0, 600.into(), &[1_000_000.into(), 500.into()], &FEES)
+                .err(),
+            Some(CurveError::ZeroLiquidity)
+        );
+
+        // Single sided withdrawals are valued at the price too
+        let result = curve
+            .compute_withdraw_one_n(
+                1_000_000.into(),
+                2_000_000.into(),
+                0,
+                &[1_000_000.into(), 500.into()],
+                &FEES,
+            )
+            .unwrap();
+        assert!(result.amount_before_fee <= 1_000_000.into());
+        assert!(result.amount_before_fee >= 999_999.into());
+    }
+}
@@ -0,0 +1,376 @@
+//! Constant product invariant implementation
+
+use crate::{
+    bn::U256,
+    curve::{CurveCalculator, SwapResult, WithdrawOneResult, RATE_PRECISION},
+    error::CurveError,
+    fees::Fees,
+    math::{sqrt, TryMath},
+};
+
+/// Number of coins of a constant product pool
+const N_COINS: usize = 2;
+
+/// The constant product invariant calculator, `x * y = k`, for pools of two
+/// uncorrelated tokens.
+/// The invariant is reported as `D = 2 * sqrt(x * y)` of normalized amounts,
+/// so that a balanced pool mints the same pool tokens as a StableSwap pool.
+/// Swaps and single sided withdrawals work on `x * y` directly to avoid the
+/// rounding of the square root. Uncorrelated tokens have no peg, so pools
+/// cannot set an off-peg fee multiplier.
+pub struct ConstantProduct {
+    /// Multipliers normalizing each coin's amounts to a common precision
+    precision_multipliers: [U256; N_COINS],
+}
+
+impl ConstantProduct {
+    /// New constant product calculator, with the multipliers normalizing each
+    /// coin's amounts to a common precision
+    pub fn new(precision_multipliers: &[u64]) -> Self {
+        let mut multipliers = [U256::one(); N_COINS];
+        for (multiplier, precision_multiplier) in
+            multipliers.iter_mut().zip(precision_multipliers.iter())
+        {
+            *multiplier = U256::from(*precision_multiplier);
+        }
+        Self {
+            precision_multipliers: multipliers,
+        }
+    }
+
+    /// Balances of coin `i` and `j`, which must be the two distinct coins
+    /// of the pool
+    fn balances(i: usize, j: usize, balances: &[U256]) -> Result<(U256, U256), CurveError> {
+        if i == j || balances.len() != N_COINS {
+            return Err(CurveError::InvalidInput);
+        }
+        let balance_i = *balances.get(i).ok_or(CurveError::InvalidInput)?;
+        let balance_j = *balances.get(j).ok_or(CurveError::InvalidInput)?;
+        if balance_i.is_zero() || balance_j.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        Ok((balance_i, balance_j))
+    }
+}
+
+impl CurveCalculator for ConstantProduct {
+    fn scale(&self, i: usize) -> Result<U256, CurveError> {
+        self.precision_multipliers
+            .get(i)
+            .ok_or(CurveError::InvalidInput)?
+            .try_mul(RATE_PRECISION.into())
+    }
+
+    fn compute_d_n(&self, amounts: &[U256]) -> Result<U256, CurveError> {
+        if amounts.len() != N_COINS {
+            return Err(CurveError::InvalidInput);
+        }
+        if amounts.iter().all(|amount| amount.is_zero()) {
+            Ok(0.into())
+        } else if amounts.iter().any(|amount| amount.is_zero()) {
+            Err(CurveError::ZeroLiquidity)
+        } else {
+            sqrt(amounts[0].try_mul(amounts[1])?)?.try_mul(2.into())
+        }
+    }
+
+    fn compute_y_n(
+        &self,
+        i: usize,
+        j: usize,
+        x: U256,
+        balances: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError> {
+        if i == j || i >= N_COINS || j >= N_COINS || balances.len() != N_COINS {
+            return Err(CurveError::InvalidInput);
+        }
+        if x.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        // y = ceil((D / 2) ** 2 / x)
+        ceil_div(d.try_mul(d)?, x.try_mul(4.into())?)
+    }
+
+    fn compute_marginal_rate_n(
+        &self,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+    ) -> Result<U256, CurveError> {
+        // Normalization cancels out of x * y = k, so -dy/dx = y / x
+        let (swap_source_amount, swap_destination_amount) = Self::balances(i, j, swap_amounts)?;
+        swap_destination_amount
+            .try_mul(RATE_PRECISION.into())?
+            .try_div(swap_source_amount)
+    }
+
+    fn compute_withdraw_one_n(
+        &self,
+        pool_token_amount: U256,
+        pool_token_supply: U256,
+        i: usize,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<WithdrawOneResult, CurveError> {
+        if pool_token_supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        let (swap_base_amount, _) = Self::balances(i, if i == 0 { 1 } else { 0 }, swap_amounts)?;
+        // Burning a share of the pool scales x * y by (1 - share) ** 2. The
+        // other coin keeps its balance, so coin `i` drops by that factor,
+        // rounded up in the pool's favor.
+        let remaining_supply = pool_token_supply.try_sub(pool_token_amount)?;
+        let new_base_amount = ceil_div(
+            swap_base_amount
+                .try_mul(remaining_supply)?
+                .try_mul(remaining_supply)?,
+            pool_token_supply.try_mul(pool_token_supply)?,
+        )?;
+        let dy_0 = swap_base_amount.try_sub(new_base_amount)?;
+        // Anything beyond the proportional share is swapped from the other
+        // coin and pays the trade fee
+        let proportional_amount = swap_base_amount
+            .try_mul(pool_token_amount)?
+            .try_div(pool_token_supply)?;
+        let dy_fee = fees.trade_fee(dy_0.try_sub(proportional_amount)?)?;
+
+        WithdrawOneResult::new(dy_0.try_sub(dy_fee)?, dy_0, fees)
+    }
+
+    fn swap_to_n(
+        &self,
+        i: usize,
+        j: usize,
+        source_amount: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<SwapResult, CurveError> {
+        let (swap_source_amount, swap_destination_amount) = Self::balances(i, j, swap_amounts)?;
+        // Normalization cancels out of x * y = k, so swap raw amounts and
+        // round the destination balance up in the pool's favor
+        let new_source_amount = swap_source_amount.try_add(source_amount)?;
+        let destination_amount = ceil_div(
+            swap_source_amount.try_mul(swap_destination_amount)?,
+            new_source_amount,
+        )?;
+        let dy = swap_destination_amount.try_sub(destination_amount)?;
+        let dy_fee = fees.trade_fee(dy)?;
+        let admin_fee = fees.admin_trade_fee(dy_fee)?;
+
+        let amount_swapped = dy.try_sub(dy_fee)?;
+        let new_destination_amount = swap_destination_amount
+            .try_sub(amount_swapped)?
+            .try_sub(admin_fee)?;
+
+        Ok(SwapResult {
+            new_source_amount,
+            new_destination_amount,
+            amount_swapped,
+            amount_before_fee: dy,
+            fee: dy_fee,
+            admin_fee,
+            host_fee: fees.host_fee(admin_fee)?,
+            lp_fee: dy_fee.try_sub(admin_fee)?,
+        })
+    }
+
+    fn swap_to_exact_out_n(
+        &self,
+        i: usize,
+        j: usize,
+        amount_out: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<(U256, SwapResult), CurveError> {
+        let (swap_source_amount, swap_destination_amount) = Self::balances(i, j, swap_amounts)?;
+        // dy = ceil(amount_out * fee_denominator / (fee_denominator - fee_numerator))
+        let fee_denominator = U256::from(fees.trade_fee_denominator);
+        let net_denominator = fee_denominator.try_sub(fees.trade_fee_numerator.into())?;
+        let dy = ceil_div(amount_out.try_mul(fee_denominator)?, net_denominator)?;
+        if dy >= swap_destination_amount {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        let source_amount = ceil_div(
+            swap_source_amount.try_mul(swap_destination_amount)?,
+            swap_destination_amount.try_sub(dy)?,
+        )?;
+        let amount_in = source_amount.try_sub(swap_source_amount)?;
+        let dy_fee = dy.try_sub(amount_out)?;
+        let admin_fee = fees.admin_trade_fee(dy_fee)?;
+
+        let new_destination_amount = swap_destination_amount
+            .try_sub(amount_out)?
+            .try_sub(admin_fee)?;
+
+        Ok((
+            amount_in,
+            SwapResult {
+                new_source_amount: source_amount,
+                new_destination_amount,
+                amount_swapped: amount_out,
+                amount_before_fee: dy,
+                fee: dy_fee,
+                admin_fee,
+                host_fee: fees.host_fee(admin_fee)?,
+                lp_fee: dy_fee.try_sub(admin_fee)?,
+            },
+        ))
+    }
+}
+
+/// Division rounding up
+fn ceil_div(numerator: U256, denominator: U256) -> Result<U256, CurveError> {
+    if denominator.is_zero() {
+        return Err(CurveError::DivisionByZero);
+    }
+    numerator
+        .try_add(denominator.try_sub(1.into())?)?
+        .try_div(denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const FEES: Fees = Fees {
+        admin_trade_fee_numerator: 1,
+        admin_trade_fee_denominator: 2,
+        admin_withdraw_fee_numerator: 1,
+        admin_withdraw_fee_denominator: 2,
+        trade_fee_numerator: 3,
+        trade_fee_denominator: 1000,
+        withdraw_fee_numerator: 0,
+        withdraw_fee_denominator: 1,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
+    };
+
+    #[test]
+    fn test_constant_product() {
+        let curve = ConstantProduct::new(&[1, 1]);
+        assert_eq!(
+            curve.compute_d(1_000_000.into(), 1_000_000.into()),
+            Ok(2_000_000.into())
+        );
+        assert_eq!(curve.compute_d(0.into(), 0.into()), Ok(0.into()));
+        assert_eq!(
+            curve.compute_d(0.into(), 1.into()),
+            Err(CurveError::ZeroLiquidity)
+        );
+        assert_eq!(
+            curve.compute_d_n(&[1.into(), 1.into(), 1.into()]),
+            Err(CurveError::InvalidInput)
+        );
+
+        // 1_000 in at 1_000_000 / 1_000_000 leaves ceil(1e12 / 1_001_000) = 999_001
+        let result = curve
+            .swap_to(1_000.into(), 1_000_000.into(), 1_000_000.into(), &FEES)
+            .unwrap();
+        let dy = U256::from(999);
+        let dy_fee = FEES.trade_fee(dy).unwrap();
+        assert_eq!(result.amount_swapped, dy - dy_fee);
+        assert_eq!(result.new_source_amount, 1_001_000.into());
+
+        // Buying it back exactly costs at least what was paid
+        let (amount_in, result) = curve
+            .swap_to_exact_out(
+                1_000.into(),
+                result.new_destination_amount,
+                result.new_source_amount,
+                &FEES,
+            )
+            .unwrap();
+        assert!(amount_in >= 999.into());
+        assert_eq!(result.amount_swapped, 1_000.into());
+
+        assert!(matches!(
+            curve.swap_to_exact_out(1_000_000.into(), 1_000_000.into(), 1_000_000.into(), &FEES),
+            Err(CurveError::ZeroLiquidity)
+        ));
+        assert!(matches!(
+            curve.swap_to(1.into(), 0.into(), 1.into(), &FEES),
+            Err(CurveError::ZeroLiquidity)
+        ));
+
+        // Spot prices follow the ratio of the balances
+        assert_eq!(
+            curve.compute_marginal_rate(1_000_000.into(), 4_000_000.into()),
+            Ok((4 * RATE_PRECISION).into())
+        );
+        let quote = curve
+            .quote_swap(100_000.into(), 1_000_000.into(), 1_000_000.into(), &FEES)
+            .unwrap();
+        assert_eq!(quote.spot_price_before, RATE_PRECISION.into());
+        assert!(quote.spot_price_after < quote.spot_price_before);
+        assert!(quote.price_impact > 0.into());
+
+        // Withdrawing a quarter of the pool in a single coin halves x * y
+        let result = curve
+            .compute_withdraw_one(
+                1.into(),
+                4.into(),
+                1_000_000.into(),
+                1_000_000.into(),
+                &FEES,
+            )
+            .unwrap();
+        assert_eq!(result.amount_before_fee, 437_500.into());
+        assert_eq!(result.fee, FEES.trade_fee(187_500.into()).unwrap());
+        assert_eq!(result.admin_fee + result.lp_fee, result.fee);
+    }
+
+    proptest! {
+        #[test]
+        fn test_swap_keeps_product(
+            source_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            let curve = ConstantProduct::new(&[1, 1]);
+            let result = curve
+                .swap_to(
+                    source_amount.into(),
+                    swap_source_amount.into(),
+                    swap_destination_amount.into(),
+                    &FEES,
+                )
+                .unwrap();
+            let k = U256::from(swap_source_amount) * U256::from(swap_destination_amount);
+            assert!(result.new_source_amount * result.new_destination_amount >= k);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_withdraw_one_keeps_product_per_share(
+            pool_token_amount in 1..u64::from(u32::MAX),
+            pool_token_supply in 1..u64::from(u32::MAX),
+            swap_base_amount in 1..u64::MAX,
+            swap_quote_amount in 1..u64::MAX,
+        ) {
+            prop_assume!(pool_token_amount < pool_token_supply);
+            let curve = ConstantProduct::new(&[1, 1]);
+            let dy = curve
+                .compute_withdraw_one(
+                    pool_token_amount.into(),
+                    pool_token_supply.into(),
+                    swap_base_amount.into(),
+                    swap_quote_amount.into(),
+                    &FEES,
+                )
+                .unwrap()
+                .amount_withdrawn;
+            // sqrt(x * y) per pool token never decreases
+            let remaining_supply = U256::from(pool_token_supply - pool_token_amount);
+            let k = U256::from(swap_base_amount) * U256::from(swap_quote_amount);
+            let new_k = (U256::from(swap_base_amount) - dy) * U256::from(swap_quote_amount);
+            assert!(
+                new_k * U256::from(pool_token_supply) * U256::from(pool_token_supply)
+                    >= k * remaining_supply * remaining_supply
+            );
+        }
+    }
+}
@@ -1,9 +1,18 @@
 //! Swap calculations and curve invariant implementation
 
-use crate::{bn::U256, fees::Fees};
+use crate::{
+    bn::U256,
+    error::CurveError,
+    fees::Fees,
+    math::{sqrt, TryMath, UnsignedInt},
+};
+use num_derive::FromPrimitive;
+use std::{cmp, convert::TryFrom};
 
-/// Number of coins
-const N_COINS: u64 = 2;
+/// Minimum number of coins in a pool
+pub const MIN_N_COINS: usize = 2;
+/// Maximum number of coins in a pool
+pub const MAX_N_COINS: usize = 4;
 /// Timestamp at 0
 pub const ZERO_TS: i64 = 0;
 /// Minimum ramp duration
@@ -12,6 +21,39 @@ pub const MIN_RAMP_DURATION: i64 = 86400;
 pub const MIN_AMP: u64 = 1;
 /// Max amplification coefficient
 pub const MAX_AMP: u64 = 1_000_000;
+/// Maximum number of iterations approximating the invariant
+pub const MAX_ITERATIONS: u64 = 256;
+/// Fixed-point precision of marginal exchange rates and virtual prices
+pub const RATE_PRECISION: u64 = 1_000_000_000_000_000_000;
+
+/// Curve a pool prices its trades with
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, FromPrimitive, PartialEq)]
+pub enum CurveType {
+    /// StableSwap invariant for pegged tokens, see `StableSwap`
+    #[default]
+    StableSwap,
+    /// Constant product invariant for uncorrelated tokens, see
+    /// `constant_product::ConstantProduct`
+    ConstantProduct,
+    /// Constant price invariant for redeemable tokens, see
+    /// `constant_price::ConstantPrice`
+    ConstantPrice,
+}
+
+/// Schedule the amplification coefficient follows while ramping
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, FromPrimitive, PartialEq)]
+pub enum RampType {
+    /// A changes by the same amount every second
+    #[default]
+    Linear,
+    /// A changes by the same percentage every second
+    Geometric,
+}
+
+/// Bits of precision of the elapsed fraction of a geometric ramp
+const RAMP_FRACTION_BITS: usize = 32;
 
 /// Encodes all results of swapping from a source token to a destination token
 pub struct SwapResult {
@@ -21,125 +63,189 @@ pub struct SwapResult {
     pub new_destination_amount: U256,
     /// Amount of destination token swapped
     pub amount_swapped: U256,
+    /// Amount of destination token the swap would pay without fees
+    pub amount_before_fee: U256,
+    /// Trade fee for the swap, after the off-peg multiplier, in destination token
+    pub fee: U256,
     /// Admin fee for the swap
     pub admin_fee: U256,
+    /// Part of the admin fee paid to the host fee account, if the swap has one
+    pub host_fee: U256,
+    /// Part of the trade fee left in the pool for liquidity providers
+    pub lp_fee: U256,
 }
 
-/// The StableSwap invariant calculator.
-pub struct StableSwap {
-    /// Initial amplification coefficient (A)
-    initial_amp_factor: U256,
-    /// Target amplificaiton coeffiecient (A)
-    target_amp_factor: U256,
-    /// Current unix timestamp
-    current_ts: i64,
-    /// Ramp A start timestamp
-    start_ramp_ts: i64,
-    /// Ramp A stop timestamp
-    stop_ramp_ts: i64,
+/// Encodes the result of a swap along with the prices it trades at, all
+/// scaled by `RATE_PRECISION` and in destination token per source token
+pub struct SwapQuote {
+    /// Result of the swap
+    pub result: SwapResult,
+    /// Marginal rate of the pool before the swap
+    pub spot_price_before: U256,
+    /// Marginal rate of the pool after the swap
+    pub spot_price_after: U256,
+    /// Average rate of the swap, net of fees
+    pub execution_price: U256,
+    /// Shortfall of the execution price from the spot price net of the trade
+    /// fee, relative to the latter
+    pub price_impact: U256,
 }
 
-impl StableSwap {
-    /// New StableSwap calculator
+/// Encodes all results of depositing tokens into a pool
+pub struct DepositResult {
+    /// Amount of pool tokens to mint
+    pub mint_amount: U256,
+    /// Amount of pool tokens the deposit would mint without imbalance fees
+    pub mint_amount_before_fee: U256,
+}
+
+/// Encodes the result of a deposit along with the imbalance fees it pays and
+/// the invariants it goes through
+pub struct DepositPreview {
+    /// Result of the deposit
+    pub result: DepositResult,
+    /// Imbalance fee paid in each token
+    pub fees: Vec<U256>,
+    /// Invariant before the deposit
+    pub d_0: U256,
+    /// Invariant after the deposit, before imbalance fees
+    pub d_1: U256,
+    /// Invariant after the deposit and imbalance fees
+    pub d_2: U256,
+    /// Share of the pool the minted pool tokens hold after the deposit,
+    /// scaled by `RATE_PRECISION`
+    pub pool_share: U256,
+}
+
+/// Encodes the result of a withdrawal of exact token amounts along with the
+/// imbalance fees it pays
+pub struct WithdrawImbalancePreview {
+    /// Amount of pool tokens to burn
+    pub burn_amount: U256,
+    /// Imbalance fee paid in each token
+    pub fees: Vec<U256>,
+}
+
+/// Encodes a quote at one point of a forecast over an amplification ramp
+pub struct ForecastPoint<T> {
+    /// Unix timestamp of the quote
+    pub timestamp: i64,
+    /// Amplification coefficient (A) at the timestamp
+    pub amp_factor: U256,
+    /// Quote at the timestamp
+    pub quote: T,
+}
+
+/// Encodes all results of withdrawing a single token from a pool
+pub struct WithdrawOneResult {
+    /// Amount of token withdrawn, before the withdraw fee
+    pub amount_withdrawn: U256,
+    /// Amount of token the withdrawal would pay without the imbalance fee
+    pub amount_before_fee: U256,
+    /// Imbalance fee for the withdrawal, charged like a trade fee
+    pub fee: U256,
+    /// Admin fee for the withdrawal
+    pub admin_fee: U256,
+    /// Part of the admin fee paid to the host fee account, if the withdrawal
+    /// has one
+    pub host_fee: U256,
+    /// Part of the imbalance fee left in the pool for liquidity providers
+    pub lp_fee: U256,
+}
+
+impl WithdrawOneResult {
+    /// Split the imbalance fee between the withdrawn amount and the amount
+    /// before the fee into the admin, host and liquidity provider parts
     pub fn new(
-        initial_amp_factor: u64,
-        target_amp_factor: u64,
-        current_ts: i64,
-        start_ramp_ts: i64,
-        stop_ramp_ts: i64,
-    ) -> Self {
-        Self {
-            initial_amp_factor: U256::from(initial_amp_factor),
-            target_amp_factor: U256::from(target_amp_factor),
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
-        }
+        amount_withdrawn: U256,
+        amount_before_fee: U256,
+        fees: &Fees,
+    ) -> Result<Self, CurveError> {
+        let fee = amount_before_fee.try_sub(amount_withdrawn)?;
+        let admin_fee = fees.admin_trade_fee(fee)?;
+        Ok(Self {
+            amount_withdrawn,
+            amount_before_fee,
+            fee,
+            admin_fee,
+            host_fee: fees.host_fee(admin_fee)?,
+            lp_fee: fee.try_sub(admin_fee)?,
+        })
     }
+}
+
+/// Swap and liquidity math of a pool curve. Implementors define the invariant
+/// (D) of normalized amounts and how to solve it for a single balance; swaps,
+/// deposits and withdrawals are derived from those. The two-coin helpers take
+/// the source, or the base token of a withdrawal, first.
+pub trait CurveCalculator {
+    /// Scale of coin `i` amounts in normalized amounts, times `RATE_PRECISION`
+    fn scale(&self, i: usize) -> Result<U256, CurveError>;
 
-    fn compute_next_d(
+    /// Compute the invariant (D) of normalized amounts for a pool holding
+    /// `amounts.len()` coins
+    fn compute_d_n(&self, amounts: &[U256]) -> Result<U256, CurveError>;
+
+    /// Compute the new balance of coin `j` after the balance of coin `i` is
+    /// set to `x`, keeping the invariant `d` constant. All amounts are normalized.
+    fn compute_y_n(
         &self,
-        amp_factor: U256,
-        d_init: U256,
-        d_prod: U256,
-        sum_x: U256,
-    ) -> Option<U256> {
-        let ann = amp_factor.checked_mul(N_COINS.into())?;
-        let leverage = ann.checked_mul(sum_x)?;
-        // d = (ann * sum_x + d_prod * n_coins) * d / ((ann - 1) * d + (n_coins + 1) * d_prod)
-        let numerator =
-            d_init.checked_mul(d_prod.checked_mul(N_COINS.into())?.checked_add(leverage)?)?;
-        let denominator = d_init
-            .checked_mul(ann.checked_sub(1.into())?)?
-            .checked_add(d_prod.checked_mul((N_COINS + 1).into())?)?;
-        numerator.checked_div(denominator)
-    }
+        i: usize,
+        j: usize,
+        x: U256,
+        balances: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError>;
 
-    /// Compute the amplification coefficient (A)
-    pub fn compute_amp_factor(&self) -> Option<U256> {
-        if self.current_ts < self.stop_ramp_ts {
-            let time_range = U256::from(self.stop_ramp_ts.checked_sub(self.start_ramp_ts)?);
-            let time_delta = U256::from(self.current_ts.checked_sub(self.start_ramp_ts)?);
+    /// Compute the marginal exchange rate of coin `i` in coin `j`, i.e. -dx_j/dx_i
+    /// of the invariant, scaled by `RATE_PRECISION`
+    fn compute_marginal_rate_n(
+        &self,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+    ) -> Result<U256, CurveError>;
 
-            // Compute amp factor based on ramp time
-            if self.target_amp_factor >= self.initial_amp_factor {
-                // Ramp up
-                let amp_range = self
-                    .target_amp_factor
-                    .checked_sub(self.initial_amp_factor)?;
-                let amp_delta = amp_range.checked_mul(time_delta)?.checked_div(time_range)?;
-                self.initial_amp_factor.checked_add(amp_delta)
-            } else {
-                // Ramp down
-                let amp_range = self
-                    .initial_amp_factor
-                    .checked_sub(self.target_amp_factor)?;
-                let amp_delta = amp_range.checked_mul(time_delta)?.checked_div(time_range)?;
-                self.initial_amp_factor.checked_sub(amp_delta)
-            }
-        } else {
-            // when stop_ramp_ts == 0 or current_ts >= stop_ramp_ts
-            Some(self.target_amp_factor)
+    /// Normalize token amounts to the common precision and unit of account used
+    /// by the invariant
+    fn xp(&self, amounts: &[U256]) -> Result<Vec<U256>, CurveError> {
+        let mut xp = Vec::with_capacity(amounts.len());
+        for (i, amount) in amounts.iter().enumerate() {
+            xp.push(self.normalize(i, *amount)?);
         }
+        Ok(xp)
     }
 
-    /// Compute stable swap invariant (D)
-    /// Equation:
-    /// A * sum(x_i) * n**n + D = A * D * n**n + D**(n+1) / (n**n * prod(x_i))
-    pub fn compute_d(&self, amount_a: U256, amount_b: U256) -> Option<U256> {
-        let sum_x = amount_a.checked_add(amount_b)?; // sum(x_i), a.k.a S
-        if sum_x == 0.into() {
-            Some(0.into())
-        } else {
-            let amp_factor = self.compute_amp_factor()?;
-            let amount_a_times_coins = amount_a.checked_mul(N_COINS.into())?;
-            let amount_b_times_coins = amount_b.checked_mul(N_COINS.into())?;
-
-            // Newton's method to approximate D
-            let mut d_prev: U256;
-            let mut d = sum_x;
-            for _ in 0..256 {
-                let mut d_prod = d;
-                d_prod = d_prod.checked_mul(d)?.checked_div(amount_a_times_coins)?;
-                d_prod = d_prod.checked_mul(d)?.checked_div(amount_b_times_coins)?;
-                d_prev = d;
-                d = self.compute_next_d(amp_factor, d, d_prod, sum_x)?;
-                // Equality with the precision of 1
-                if d > d_prev {
-                    if d.checked_sub(d_prev)? <= 1.into() {
-                        break;
-                    }
-                } else if d_prev.checked_sub(d)? <= 1.into() {
-                    break;
-                }
-            }
+    /// Normalize an amount of coin `i`, rounding down
+    fn normalize(&self, i: usize, amount: U256) -> Result<U256, CurveError> {
+        amount
+            .try_mul(self.scale(i)?)?
+            .try_div(RATE_PRECISION.into())
+    }
 
-            Some(d)
-        }
+    /// Denormalize an amount of coin `i`, rounding down
+    fn denormalize(&self, i: usize, amount: U256) -> Result<U256, CurveError> {
+        amount
+            .try_mul(RATE_PRECISION.into())?
+            .try_div(self.scale(i)?)
+    }
+
+    /// Denormalize an amount of coin `i`, rounding up
+    fn denormalize_up(&self, i: usize, amount: U256) -> Result<U256, CurveError> {
+        let scale = self.scale(i)?;
+        amount
+            .try_mul(RATE_PRECISION.into())?
+            .try_add(scale.try_sub(1.into())?)?
+            .try_div(scale)
+    }
+
+    /// Compute the invariant (D) of normalized amounts of a two-coin pool
+    fn compute_d(&self, amount_a: U256, amount_b: U256) -> Result<U256, CurveError> {
+        self.compute_d_n(&[amount_a, amount_b])
     }
 
     /// Compute the amount of pool tokens to mint after a deposit
-    pub fn compute_mint_amount_for_deposit(
+    fn compute_mint_amount_for_deposit(
         &self,
         deposit_amount_a: U256,
         deposit_amount_b: U256,
@@ -147,530 +253,3309 @@ impl StableSwap {
         swap_amount_b: U256,
         pool_token_supply: U256,
         fees: &Fees,
-    ) -> Option<U256> {
+    ) -> Result<DepositResult, CurveError> {
+        self.compute_mint_amount_for_deposit_n(
+            &[deposit_amount_a, deposit_amount_b],
+            &[swap_amount_a, swap_amount_b],
+            pool_token_supply,
+            fees,
+        )
+    }
+
+    /// Compute the amount of pool tokens to mint after a deposit into a pool
+    /// holding `swap_amounts.len()` coins
+    fn compute_mint_amount_for_deposit_n(
+        &self,
+        deposit_amounts: &[U256],
+        swap_amounts: &[U256],
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<DepositResult, CurveError> {
+        Ok(self
+            .preview_deposit_n(deposit_amounts, swap_amounts, pool_token_supply, fees)?
+            .result)
+    }
+
+    /// Compute DepositPreview of a deposit
+    fn preview_deposit(
+        &self,
+        deposit_amount_a: U256,
+        deposit_amount_b: U256,
+        swap_amount_a: U256,
+        swap_amount_b: U256,
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<DepositPreview, CurveError> {
+        self.preview_deposit_n(
+            &[deposit_amount_a, deposit_amount_b],
+            &[swap_amount_a, swap_amount_b],
+            pool_token_supply,
+            fees,
+        )
+    }
+
+    /// Compute DepositPreview of a deposit into a pool holding
+    /// `swap_amounts.len()` coins
+    fn preview_deposit_n(
+        &self,
+        deposit_amounts: &[U256],
+        swap_amounts: &[U256],
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<DepositPreview, CurveError> {
+        if deposit_amounts.len() != swap_amounts.len() {
+            return Err(CurveError::InvalidInput);
+        }
+        let n_coins = swap_amounts.len() as u64;
+        let deposit_amounts = self.xp(deposit_amounts)?;
+        let swap_amounts = self.xp(swap_amounts)?;
         // Initial invariant
-        let d_0 = self.compute_d(swap_amount_a, swap_amount_b)?;
-        let old_balances = [swap_amount_a, swap_amount_b];
-        let mut new_balances = [
-            swap_amount_a.checked_add(deposit_amount_a)?,
-            swap_amount_b.checked_add(deposit_amount_b)?,
-        ];
+        let d_0 = self.compute_d_n(&swap_amounts)?;
+        let old_balances = &swap_amounts;
+        let mut new_balances = Vec::with_capacity(swap_amounts.len());
+        for (swap_amount, deposit_amount) in swap_amounts.iter().zip(deposit_amounts.iter()) {
+            new_balances.push(swap_amount.try_add(*deposit_amount)?);
+        }
         // Invariant after change
-        let d_1 = self.compute_d(new_balances[0], new_balances[1])?;
+        let d_1 = self.compute_d_n(&new_balances)?;
         if d_1 <= d_0 {
-            None
-        } else {
-            // Recalculate the invariant accounting for fees
-            for i in 0..new_balances.len() {
-                let ideal_balance = d_1.checked_mul(old_balances[i])?.checked_div(d_0)?;
-                let difference = if ideal_balance > new_balances[i] {
-                    ideal_balance.checked_sub(new_balances[i])?
-                } else {
-                    new_balances[i].checked_sub(ideal_balance)?
-                };
-                let fee = fees.normalized_trade_fee(N_COINS, difference)?;
-                new_balances[i] = new_balances[i].checked_sub(fee)?;
-            }
+            return Err(CurveError::DepositTooSmall);
+        }
+        // Recalculate the invariant accounting for fees
+        let ys = d_0.try_add(d_1)?.try_div(n_coins.into())?;
+        let mut token_fees = Vec::with_capacity(new_balances.len());
+        for i in 0..new_balances.len() {
+            let ideal_balance = d_1.try_mul(old_balances[i])?.try_div(d_0)?;
+            let difference = if ideal_balance > new_balances[i] {
+                ideal_balance.try_sub(new_balances[i])?
+            } else {
+                new_balances[i].try_sub(ideal_balance)?
+            };
+            let xs = old_balances[i].try_add(new_balances[i])?;
+            let fee = fees.dynamic_fee(fees.normalized_trade_fee(n_coins, difference)?, xs, ys)?;
+            new_balances[i] = new_balances[i].try_sub(fee)?;
+            token_fees.push(self.denormalize(i, fee)?);
+        }
 
-            let d_2 = self.compute_d(new_balances[0], new_balances[1])?;
-            let mint_amount_numerator = pool_token_supply.checked_mul(d_2.checked_sub(d_0)?)?;
-            let mint_amount = mint_amount_numerator.checked_div(d_0)?;
+        let d_2 = self.compute_d_n(&new_balances)?;
+        let mint_amount_numerator = pool_token_supply.try_mul(d_2.try_sub(d_0)?)?;
+        let mint_amount = mint_amount_numerator.try_div(d_0)?;
+        let mint_amount_before_fee = pool_token_supply.try_mul(d_1.try_sub(d_0)?)?.try_div(d_0)?;
+        let pool_share = mint_amount
+            .try_mul(RATE_PRECISION.into())?
+            .try_div(pool_token_supply.try_add(mint_amount)?)?;
 
-            Some(mint_amount)
-        }
+        Ok(DepositPreview {
+            result: DepositResult {
+                mint_amount,
+                mint_amount_before_fee,
+            },
+            fees: token_fees,
+            d_0,
+            d_1,
+            d_2,
+            pool_share,
+        })
     }
 
-    /// Compute swap amount `y` in proportion to `x`
-    /// Solve for y:
-    /// y**2 + y * (sum' - (A*n**n - 1) * D / (A * n**n)) = D ** (n + 1) / (n ** (2 * n) * prod' * A)
-    /// y**2 + b*y = c
-    #[allow(clippy::many_single_char_names)]
-    pub fn compute_y(&self, x: U256, d: U256) -> Option<U256> {
-        let amp_factor = self.compute_amp_factor()?;
-        let ann: U256 = amp_factor.checked_mul(N_COINS.into())?; // A * n ** n
+    /// Compute the amount of pool tokens to burn for a withdrawal of exact
+    /// token amounts
+    fn compute_withdraw_imbalance(
+        &self,
+        withdraw_amount_a: U256,
+        withdraw_amount_b: U256,
+        swap_amount_a: U256,
+        swap_amount_b: U256,
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<U256, CurveError> {
+        self.compute_withdraw_imbalance_n(
+            &[withdraw_amount_a, withdraw_amount_b],
+            &[swap_amount_a, swap_amount_b],
+            pool_token_supply,
+            fees,
+        )
+    }
 
-        // sum' = prod' = x
-        // c =  D ** (n + 1) / (n ** (2 * n) * prod' * A)
-        let mut c = d
-            .checked_mul(d)?
-            .checked_div(x.checked_mul(N_COINS.into())?)?;
-        c = c
-            .checked_mul(d)?
-            .checked_div(ann.checked_mul(N_COINS.into())?)?;
-        // b = sum' - (A*n**n - 1) * D / (A * n**n)
-        let b = d.checked_div(ann)?.checked_add(x)?; // d is subtracted on line 147
+    /// Compute the amount of pool tokens to burn for a withdrawal of exact
+    /// token amounts from a pool holding `swap_amounts.len()` coins
+    fn compute_withdraw_imbalance_n(
+        &self,
+        withdraw_amounts: &[U256],
+        swap_amounts: &[U256],
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<U256, CurveError> {
+        Ok(self
+            .preview_withdraw_imbalance_n(withdraw_amounts, swap_amounts, pool_token_supply, fees)?
+            .burn_amount)
+    }
 
-        // Solve for y by approximating: y**2 + b*y = c
-        let mut y_prev: U256;
-        let mut y = d;
-        for _ in 0..256 {
-            y_prev = y;
-            // y = (y * y + c) / (2 * y + b - d);
-            let y_numerator = y.checked_pow(2.into())?.checked_add(c)?;
-            let y_denominator = y.checked_mul(2.into())?.checked_add(b)?.checked_sub(d)?;
-            y = y_numerator.checked_div(y_denominator)?;
-            if y > y_prev {
-                if y.checked_sub(y_prev)? <= 1.into() {
-                    break;
-                }
-            } else if y_prev.checked_sub(y)? <= 1.into() {
-                break;
-            }
+    /// Compute WithdrawImbalancePreview of a withdrawal of exact token amounts
+    fn preview_withdraw_imbalance(
+        &self,
+        withdraw_amount_a: U256,
+        withdraw_amount_b: U256,
+        swap_amount_a: U256,
+        swap_amount_b: U256,
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<WithdrawImbalancePreview, CurveError> {
+        self.preview_withdraw_imbalance_n(
+            &[withdraw_amount_a, withdraw_amount_b],
+            &[swap_amount_a, swap_amount_b],
+            pool_token_supply,
+            fees,
+        )
+    }
+
+    /// Compute WithdrawImbalancePreview of a withdrawal of exact token amounts
+    /// from a pool holding `swap_amounts.len()` coins
+    fn preview_withdraw_imbalance_n(
+        &self,
+        withdraw_amounts: &[U256],
+        swap_amounts: &[U256],
+        pool_token_supply: U256,
+        fees: &Fees,
+    ) -> Result<WithdrawImbalancePreview, CurveError> {
+        if withdraw_amounts.len() != swap_amounts.len() {
+            return Err(CurveError::InvalidInput);
+        }
+        let n_coins = swap_amounts.len() as u64;
+        let withdraw_amounts = self.xp(withdraw_amounts)?;
+        let swap_amounts = self.xp(swap_amounts)?;
+        // Initial invariant
+        let d_0 = self.compute_d_n(&swap_amounts)?;
+        if d_0.is_zero() || pool_token_supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        let old_balances = &swap_amounts;
+        let mut new_balances = Vec::with_capacity(swap_amounts.len());
+        for (swap_amount, withdraw_amount) in swap_amounts.iter().zip(withdraw_amounts.iter()) {
+            new_balances.push(swap_amount.try_sub(*withdraw_amount)?);
+        }
+        // Invariant after change
+        let d_1 = self.compute_d_n(&new_balances)?;
+
+        // Recalculate the invariant accounting for fees
+        let ys = d_0.try_add(d_1)?.try_div(n_coins.into())?;
+        let mut token_fees = Vec::with_capacity(new_balances.len());
+        for i in 0..new_balances.len() {
+            let ideal_balance = d_1.try_mul(old_balances[i])?.try_div(d_0)?;
+            let difference = if ideal_balance > new_balances[i] {
+                ideal_balance.try_sub(new_balances[i])?
+            } else {
+                new_balances[i].try_sub(ideal_balance)?
+            };
+            let xs = old_balances[i].try_add(new_balances[i])?;
+            let fee = fees.dynamic_fee(fees.normalized_trade_fee(n_coins, difference)?, xs, ys)?;
+            new_balances[i] = new_balances[i].try_sub(fee)?;
+            token_fees.push(self.denormalize(i, fee)?);
         }
 
-        Some(y)
+        let d_2 = self.compute_d_n(&new_balances)?;
+        let burn_amount_numerator = pool_token_supply.try_mul(d_0.try_sub(d_2)?)?;
+        let burn_amount = burn_amount_numerator.try_div(d_0)?;
+
+        Ok(WithdrawImbalancePreview {
+            // Burn more to account for rounding errors
+            burn_amount: burn_amount.try_add(1.into())?,
+            fees: token_fees,
+        })
     }
 
     /// Calcuate withdrawal amount when withdrawing only one type of token
     /// Calculation:
     /// 1. Get current D
     /// 2. Solve Eqn against y_i for D - _token_amount
-    pub fn compute_withdraw_one(
+    fn compute_withdraw_one(
         &self,
         pool_token_amount: U256,
         pool_token_supply: U256,
         swap_base_amount: U256,  // Same denomination of token to be withdrawn
         swap_quote_amount: U256, // Counter denomination of token to be withdrawn
         fees: &Fees,
-    ) -> Option<(U256, U256)> {
-        let d_0 = self.compute_d(swap_base_amount, swap_quote_amount)?;
-        let d_1 = d_0.checked_sub(
-            pool_token_amount
-                .checked_mul(d_0)?
-                .checked_div(pool_token_supply)?,
+    ) -> Result<WithdrawOneResult, CurveError> {
+        self.compute_withdraw_one_n(
+            pool_token_amount,
+            pool_token_supply,
+            0,
+            &[swap_base_amount, swap_quote_amount],
+            fees,
+        )
+    }
+
+    /// Calcuate withdrawal amount and fee when withdrawing only coin `i` from
+    /// a pool holding `swap_amounts.len()` coins
+    fn compute_withdraw_one_n(
+        &self,
+        pool_token_amount: U256,
+        pool_token_supply: U256,
+        i: usize,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<WithdrawOneResult, CurveError> {
+        if pool_token_supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        let n_coins = swap_amounts.len() as u64;
+        let xp = self.xp(swap_amounts)?;
+        let swap_base_amount = *xp.get(i).ok_or(CurveError::InvalidInput)?;
+        let d_0 = self.compute_d_n(&xp)?;
+        let d_1 = d_0.try_sub(pool_token_amount.try_mul(d_0)?.try_div(pool_token_supply)?)?;
+        // Solve for coin i while any other coin k keeps its balance
+        let k = if i == 0 { 1 } else { 0 };
+        let x_k = *xp.get(k).ok_or(CurveError::InvalidInput)?;
+        let new_y = self.compute_y_n(k, i, x_k, &xp, d_1)?;
+
+        let ys = d_0.try_add(d_1)?.try_div((2 * n_coins).into())?;
+        let mut xp_reduced = Vec::with_capacity(xp.len());
+        for (k, swap_amount) in xp.iter().enumerate() {
+            let (expected_amount, x_avg) = if k == i {
+                // expected_base_amount = swap_base_amount * d_1 / d_0 - new_y;
+                (
+                    swap_amount.try_mul(d_1)?.try_div(d_0)?.try_sub(new_y)?,
+                    swap_amount.try_add(new_y)?.try_div(2.into())?,
+                )
+            } else {
+                // expected_quote_amount = swap_quote_amount - swap_quote_amount * d_1 / d_0;
+                (
+                    swap_amount.try_sub(swap_amount.try_mul(d_1)?.try_div(d_0)?)?,
+                    *swap_amount,
+                )
+            };
+            // new_amount = swap_amount - expected_amount * fee / fee_denominator;
+            let fee = fees.dynamic_fee(
+                fees.normalized_trade_fee(n_coins, expected_amount)?,
+                x_avg,
+                ys,
+            )?;
+            xp_reduced.push(swap_amount.try_sub(fee)?);
+        }
+        let dy = self.denormalize(
+            i,
+            xp_reduced[i]
+                .try_sub(self.compute_y_n(k, i, xp_reduced[k], &xp_reduced, d_1)?)?
+                .try_sub(1.into())?, // Withdraw less to account for rounding errors
         )?;
-        let new_y = self.compute_y(swap_quote_amount, d_1)?;
-
-        // expected_base_amount = swap_base_amount * d_1 / d_0 - new_y;
-        let expected_base_amount = swap_base_amount
-            .checked_mul(d_1)?
-            .checked_div(d_0)?
-            .checked_sub(new_y)?;
-        // expected_quote_amount = swap_quote_amount - swap_quote_amount * d_1 / d_0;
-        let expected_quote_amount =
-            swap_quote_amount.checked_sub(swap_quote_amount.checked_mul(d_1)?.checked_div(d_0)?)?;
-        // new_base_amount = swap_base_amount - expected_base_amount * fee / fee_denominator;
-        let new_base_amount = swap_base_amount
-            .checked_sub(fees.normalized_trade_fee(N_COINS, expected_base_amount)?)?;
-        // new_quote_amount = swap_quote_amount - expected_quote_amount * fee / fee_denominator;
-        let new_quote_amount = swap_quote_amount
-            .checked_sub(fees.normalized_trade_fee(N_COINS, expected_quote_amount)?)?;
-        let dy = new_base_amount
-            .checked_sub(self.compute_y(new_quote_amount, d_1)?)?
-            .checked_sub(1.into())?; // Withdraw less to account for rounding errors
-        let dy_0 = swap_base_amount.checked_sub(new_y)?;
-
-        Some((dy, dy_0 - dy))
+        let dy_0 = self.denormalize(i, swap_base_amount.try_sub(new_y)?)?;
+
+        WithdrawOneResult::new(dy, dy_0, fees)
     }
 
     /// Compute SwapResult after an exchange
-    pub fn swap_to(
+    fn swap_to(
         &self,
         source_amount: U256,
         swap_source_amount: U256,
         swap_destination_amount: U256,
         fees: &Fees,
-    ) -> Option<SwapResult> {
-        let y = self.compute_y(
-            swap_source_amount.checked_add(source_amount)?,
-            self.compute_d(swap_source_amount, swap_destination_amount)?,
+    ) -> Result<SwapResult, CurveError> {
+        self.swap_to_n(
+            0,
+            1,
+            source_amount,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
+
+    /// Compute SwapResult after an exchange of `source_amount` of coin `i`
+    /// for coin `j` in a pool holding `swap_amounts.len()` coins
+    fn swap_to_n(
+        &self,
+        i: usize,
+        j: usize,
+        source_amount: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<SwapResult, CurveError> {
+        let swap_source_amount = *swap_amounts.get(i).ok_or(CurveError::InvalidInput)?;
+        let swap_destination_amount = *swap_amounts.get(j).ok_or(CurveError::InvalidInput)?;
+        let xp = self.xp(swap_amounts)?;
+        let x = xp[i].try_add(self.normalize(i, source_amount)?)?;
+        let y = self.compute_y_n(i, j, x, &xp, self.compute_d_n(&xp)?)?;
+        let dy = self.denormalize(j, xp[j].try_sub(y)?)?;
+        // Fees rise with the imbalance of the balances averaged over the swap
+        let dy_fee = fees.dynamic_fee(
+            fees.trade_fee(dy)?,
+            xp[i].try_add(x)?.try_div(2.into())?,
+            xp[j].try_add(y)?.try_div(2.into())?,
         )?;
-        let dy = swap_destination_amount.checked_sub(y)?;
-        let dy_fee = fees.trade_fee(dy)?;
         let admin_fee = fees.admin_trade_fee(dy_fee)?;
 
-        let amount_swapped = dy.checked_sub(dy_fee)?;
+        let amount_swapped = dy.try_sub(dy_fee)?;
         let new_destination_amount = swap_destination_amount
-            .checked_sub(amount_swapped)?
-            .checked_sub(admin_fee)?;
-        let new_source_amount = swap_source_amount.checked_add(source_amount)?;
+            .try_sub(amount_swapped)?
+            .try_sub(admin_fee)?;
+        let new_source_amount = swap_source_amount.try_add(source_amount)?;
 
-        Some(SwapResult {
+        Ok(SwapResult {
             new_source_amount,
             new_destination_amount,
             amount_swapped,
+            amount_before_fee: dy,
+            fee: dy_fee,
             admin_fee,
+            host_fee: fees.host_fee(admin_fee)?,
+            lp_fee: dy_fee.try_sub(admin_fee)?,
         })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use proptest::prelude::*;
-    use rand::Rng;
-    use sim::{Model, MODEL_FEE_DENOMINATOR, MODEL_FEE_NUMERATOR};
-    use std::cmp;
+    /// Compute the source amount required to receive exactly `amount_out` of the
+    /// destination token, together with the SwapResult of that exchange.
+    /// The required amount includes the trade fee and is rounded up in the pool's favor.
+    fn swap_to_exact_out(
+        &self,
+        amount_out: U256,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<(U256, SwapResult), CurveError> {
+        self.swap_to_exact_out_n(
+            0,
+            1,
+            amount_out,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
 
-    const MODEL_FEES: Fees = Fees {
-        admin_trade_fee_numerator: 0,
-        admin_trade_fee_denominator: 1,
-        admin_withdraw_fee_numerator: 0,
-        admin_withdraw_fee_denominator: 1,
-        trade_fee_numerator: MODEL_FEE_NUMERATOR,
-        trade_fee_denominator: MODEL_FEE_DENOMINATOR,
-        withdraw_fee_numerator: 0,
-        withdraw_fee_denominator: 1,
-    };
+    /// Compute the amount of coin `i` required to receive exactly `amount_out`
+    /// of coin `j` in a pool holding `swap_amounts.len()` coins
+    fn swap_to_exact_out_n(
+        &self,
+        i: usize,
+        j: usize,
+        amount_out: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<(U256, SwapResult), CurveError> {
+        let swap_source_amount = *swap_amounts.get(i).ok_or(CurveError::InvalidInput)?;
+        let swap_destination_amount = *swap_amounts.get(j).ok_or(CurveError::InvalidInput)?;
+        let xp = self.xp(swap_amounts)?;
+        let d = self.compute_d_n(&xp)?;
+        // dy = ceil(amount_out * fee_denominator / (fee_denominator - fee_numerator)),
+        // with the fee scaled by the off-peg multiplier of the swap's balances
+        let gross_amount = |(numerator, denominator): (U256, U256)| {
+            let fee_denominator = U256::from(fees.trade_fee_denominator).try_mul(denominator)?;
+            let net_denominator = fee_denominator
+                .try_sub(U256::from(fees.trade_fee_numerator).try_mul(numerator)?)?;
+            let dy = amount_out
+                .try_mul(fee_denominator)?
+                .try_add(net_denominator.try_sub(1.into())?)?
+                .try_div(net_denominator)?;
+            if dy >= swap_destination_amount {
+                return Err(CurveError::ZeroLiquidity);
+            }
+            let y = xp[j].try_sub(self.normalize(j, dy)?)?;
+            Ok((dy, y, self.compute_y_n(j, i, y, &xp, d)?))
+        };
+        // The multiplier grows with dy when the swap moves off peg, so start
+        // from the flat fee and raise dy until it covers its own fee
+        let (mut dy, mut y, mut x) = gross_amount((U256::one(), U256::one()))?;
+        let mut iterations = 0;
+        loop {
+            let fee_factor = fees.offpeg_fee_factor(
+                xp[i].try_add(x)?.try_div(2.into())?,
+                xp[j].try_add(y)?.try_div(2.into())?,
+            )?;
+            let (next_dy, next_y, next_x) = gross_amount(fee_factor)?;
+            if next_dy <= dy {
+                break;
+            }
+            iterations += 1;
+            if iterations >= MAX_ITERATIONS {
+                return Err(CurveError::NonConvergence { iterations });
+            }
+            dy = next_dy;
+            y = next_y;
+            x = next_x;
+        }
+        let amount_in = self
+            .denormalize_up(i, x.try_sub(xp[i])?)?
+            .try_add(1.into())?; // Pay one more to account for rounding errors
+        let dy_fee = dy.try_sub(amount_out)?;
+        let admin_fee = fees.admin_trade_fee(dy_fee)?;
 
-    const RAMP_TICKS: i64 = 100000;
+        let new_destination_amount = swap_destination_amount
+            .try_sub(amount_out)?
+            .try_sub(admin_fee)?;
+        let new_source_amount = swap_source_amount.try_add(amount_in)?;
 
-    #[test]
-    fn test_ramp_amp_up() {
-        let mut rng = rand::thread_rng();
-        let initial_amp_factor = 100;
-        let target_amp_factor = initial_amp_factor * 2;
-        let start_ramp_ts = rng.gen_range(ZERO_TS, i64::MAX - RAMP_TICKS);
-        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
-        println!(
-            "start_ramp_ts: {}, stop_ramp_ts: {}",
-            start_ramp_ts, stop_ramp_ts
-        );
+        Ok((
+            amount_in,
+            SwapResult {
+                new_source_amount,
+                new_destination_amount,
+                amount_swapped: amount_out,
+                amount_before_fee: dy,
+                fee: dy_fee,
+                admin_fee,
+                host_fee: fees.host_fee(admin_fee)?,
+                lp_fee: dy_fee.try_sub(admin_fee)?,
+            },
+        ))
+    }
 
-        for tick in 0..RAMP_TICKS {
-            let current_ts = start_ramp_ts + tick;
-            let invariant = StableSwap::new(
-                initial_amp_factor,
-                target_amp_factor,
-                current_ts,
-                start_ramp_ts,
-                stop_ramp_ts,
-            );
-            let expected = if tick >= MIN_RAMP_DURATION {
-                target_amp_factor
-            } else {
-                initial_amp_factor + (initial_amp_factor * tick as u64 / MIN_RAMP_DURATION as u64)
-            };
-            assert_eq!(invariant.compute_amp_factor().unwrap(), expected.into());
+    /// Compute the virtual price of a pool token, i.e. D / pool_token_supply,
+    /// scaled by `RATE_PRECISION`
+    fn compute_virtual_price(
+        &self,
+        swap_token_a_amount: U256,
+        swap_token_b_amount: U256,
+        pool_token_supply: U256,
+    ) -> Result<U256, CurveError> {
+        self.compute_virtual_price_n(
+            &[swap_token_a_amount, swap_token_b_amount],
+            pool_token_supply,
+        )
+    }
+
+    /// Compute the virtual price of a pool token for a pool holding
+    /// `swap_amounts.len()` coins, scaled by `RATE_PRECISION`
+    fn compute_virtual_price_n(
+        &self,
+        swap_amounts: &[U256],
+        pool_token_supply: U256,
+    ) -> Result<U256, CurveError> {
+        if pool_token_supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
         }
+        self.compute_d_n(&self.xp(swap_amounts)?)?
+            .try_mul(RATE_PRECISION.into())?
+            .try_div(pool_token_supply)
     }
 
-    #[test]
-    fn test_ramp_amp_down() {
-        let mut rng = rand::thread_rng();
-        let initial_amp_factor = 100;
-        let target_amp_factor = initial_amp_factor / 10;
-        let amp_range = initial_amp_factor - target_amp_factor;
-        let start_ramp_ts = rng.gen_range(ZERO_TS, i64::MAX - RAMP_TICKS);
-        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+    /// Compute the marginal exchange rate of source token in destination token,
+    /// i.e. -dy/dx of the invariant, scaled by `RATE_PRECISION`
+    fn compute_marginal_rate(
+        &self,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+    ) -> Result<U256, CurveError> {
+        self.compute_marginal_rate_n(0, 1, &[swap_source_amount, swap_destination_amount])
+    }
+
+    /// Compute the marginal exchange rate of source token in destination token
+    /// net of the trade fee, scaled by `RATE_PRECISION`
+    fn compute_marginal_rate_with_fee(
+        &self,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<U256, CurveError> {
+        self.compute_marginal_rate_with_fee_n(
+            0,
+            1,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
+
+    /// Compute the marginal exchange rate of coin `i` in coin `j` net of the
+    /// trade fee, scaled by `RATE_PRECISION`. The fee is the dynamic fee at
+    /// the current balances, i.e. the one paid on the next unit swapped.
+    fn compute_marginal_rate_with_fee_n(
+        &self,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<U256, CurveError> {
+        let rate = self.compute_marginal_rate_n(i, j, swap_amounts)?;
+        let xp = self.xp(swap_amounts)?;
+        rate.try_sub(fees.dynamic_fee(fees.trade_fee(rate)?, xp[i], xp[j])?)
+    }
+
+    /// Compute SwapQuote of an exchange
+    fn quote_swap(
+        &self,
+        source_amount: U256,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<SwapQuote, CurveError> {
+        self.quote_swap_n(
+            0,
+            1,
+            source_amount,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
+
+    /// Compute SwapQuote of an exchange of `source_amount` of coin `i` for
+    /// coin `j` in a pool holding `swap_amounts.len()` coins. Unlike
+    /// `swap_to_n`, this solves the invariant again for the prices, so it is
+    /// meant for off-chain quotes.
+    fn quote_swap_n(
+        &self,
+        i: usize,
+        j: usize,
+        source_amount: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<SwapQuote, CurveError> {
+        if source_amount.is_zero() {
+            return Err(CurveError::InvalidInput);
+        }
+        let precision = U256::from(RATE_PRECISION);
+        let result = self.swap_to_n(i, j, source_amount, swap_amounts, fees)?;
+        let spot_price_before = self.compute_marginal_rate_n(i, j, swap_amounts)?;
+        let mut new_swap_amounts = swap_amounts.to_vec();
+        new_swap_amounts[i] = result.new_source_amount;
+        new_swap_amounts[j] = result.new_destination_amount;
+        let spot_price_after = self.compute_marginal_rate_n(i, j, &new_swap_amounts)?;
+        let execution_price = result
+            .amount_swapped
+            .try_mul(precision)?
+            .try_div(source_amount)?;
+        let net_spot_price = self.compute_marginal_rate_with_fee_n(i, j, swap_amounts, fees)?;
+        let price_impact = if execution_price >= net_spot_price {
+            U256::zero()
+        } else {
+            net_spot_price
+                .try_sub(execution_price)?
+                .try_mul(precision)?
+                .try_div(net_spot_price)?
+        };
+
+        Ok(SwapQuote {
+            result,
+            spot_price_before,
+            spot_price_after,
+            execution_price,
+            price_impact,
+        })
+    }
+}
+
+/// The StableSwap invariant calculator.
+#[derive(Clone)]
+pub struct StableSwap {
+    /// Initial amplification coefficient (A)
+    initial_amp_factor: U256,
+    /// Target amplificaiton coeffiecient (A)
+    target_amp_factor: U256,
+    /// Current unix timestamp
+    current_ts: i64,
+    /// Ramp A start timestamp
+    start_ramp_ts: i64,
+    /// Ramp A stop timestamp
+    stop_ramp_ts: i64,
+    /// Ramp A schedule
+    ramp_type: RampType,
+    /// Multipliers normalizing each coin's amounts to a common precision
+    precision_multipliers: [U256; MAX_N_COINS],
+    /// Exchange rates of each coin in a common unit of account, scaled by `RATE_PRECISION`
+    rates: [U256; MAX_N_COINS],
+}
+
+impl StableSwap {
+    /// New StableSwap calculator
+    pub fn new(
+        initial_amp_factor: u64,
+        target_amp_factor: u64,
+        current_ts: i64,
+        start_ramp_ts: i64,
+        stop_ramp_ts: i64,
+    ) -> Self {
+        Self {
+            initial_amp_factor: U256::from(initial_amp_factor),
+            target_amp_factor: U256::from(target_amp_factor),
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+            ramp_type: RampType::Linear,
+            precision_multipliers: [U256::one(); MAX_N_COINS],
+            rates: [U256::from(RATE_PRECISION); MAX_N_COINS],
+        }
+    }
+
+    /// Set the schedule A follows between `start_ramp_ts` and `stop_ramp_ts`,
+    /// linear by default
+    pub fn with_ramp_type(mut self, ramp_type: RampType) -> Self {
+        self.ramp_type = ramp_type;
+        self
+    }
+
+    /// Set the multipliers normalizing each coin's amounts to a common precision,
+    /// in pool order. The two-coin helpers treat their first amount as coin 0.
+    pub fn with_precision_multipliers(mut self, precision_multipliers: &[u64]) -> Self {
+        for (multiplier, precision_multiplier) in self
+            .precision_multipliers
+            .iter_mut()
+            .zip(precision_multipliers.iter())
+        {
+            *multiplier = U256::from(*precision_multiplier);
+        }
+        self
+    }
+
+    /// Set the exchange rates of each coin in a common unit of account, scaled
+    /// by `RATE_PRECISION`, in pool order. Coins of a yield-bearing pair are
+    /// valued at these rates before the invariant math.
+    pub fn with_rates(mut self, rates: &[u64]) -> Self {
+        for (rate, new_rate) in self.rates.iter_mut().zip(rates.iter()) {
+            *rate = U256::from(*new_rate);
+        }
+        self
+    }
+
+    fn compute_next_d<T: UnsignedInt>(
+        &self,
+        amp_factor: T,
+        d_init: T,
+        d_prod: T,
+        sum_x: T,
+        n_coins: u64,
+    ) -> Result<T, CurveError> {
+        let ann = amp_factor.try_mul(n_coins.into())?;
+        let leverage = ann.try_mul(sum_x)?;
+        // d = (ann * sum_x + d_prod * n_coins) * d / ((ann - 1) * d + (n_coins + 1) * d_prod)
+        let numerator = d_init.try_mul(d_prod.try_mul(n_coins.into())?.try_add(leverage)?)?;
+        let denominator = d_init
+            .try_mul(ann.try_sub(1.into())?)?
+            .try_add(d_prod.try_mul((n_coins + 1).into())?)?;
+        numerator.try_div(denominator)
+    }
+
+    /// Compute the amplification coefficient (A)
+    pub fn compute_amp_factor(&self) -> Result<U256, CurveError> {
+        if self.current_ts < self.stop_ramp_ts {
+            let time_range = U256::from(self.stop_ramp_ts.try_sub(self.start_ramp_ts)?);
+            let time_delta = U256::from(self.current_ts.try_sub(self.start_ramp_ts)?);
+
+            // Compute amp factor based on ramp time
+            if self.ramp_type == RampType::Geometric {
+                self.compute_geometric_amp_factor(time_delta, time_range)
+            } else if self.target_amp_factor >= self.initial_amp_factor {
+                // Ramp up
+                let amp_range = self.target_amp_factor.try_sub(self.initial_amp_factor)?;
+                let amp_delta = amp_range.try_mul(time_delta)?.try_div(time_range)?;
+                self.initial_amp_factor.try_add(amp_delta)
+            } else {
+                // Ramp down
+                let amp_range = self.initial_amp_factor.try_sub(self.target_amp_factor)?;
+                let amp_delta = amp_range.try_mul(time_delta)?.try_div(time_range)?;
+                self.initial_amp_factor.try_sub(amp_delta)
+            }
+        } else {
+            // when stop_ramp_ts == 0 or current_ts >= stop_ramp_ts
+            Ok(self.target_amp_factor)
+        }
+    }
+
+    /// A = A0 * (A1 / A0) ^ (time_delta / time_range), multiplying in the
+    /// (1/2^k)-th root of A1 / A0 for each binary digit k of the elapsed fraction
+    fn compute_geometric_amp_factor(
+        &self,
+        time_delta: U256,
+        time_range: U256,
+    ) -> Result<U256, CurveError> {
+        let precision = U256::from(RATE_PRECISION);
+        let fraction = time_delta
+            .try_mul(U256::one() << RAMP_FRACTION_BITS)?
+            .try_div(time_range)?;
+        let mut root = self
+            .target_amp_factor
+            .try_mul(precision)?
+            .try_div(self.initial_amp_factor)?;
+        let mut growth = precision;
+        for bit in (0..RAMP_FRACTION_BITS).rev() {
+            root = sqrt(root.try_mul(precision)?)?;
+            if fraction.bit(bit) {
+                growth = growth.try_mul(root)?.try_div(precision)?;
+            }
+        }
+        let amp_factor = self
+            .initial_amp_factor
+            .try_mul(growth)?
+            .try_div(precision)?;
+        // Rounding must not take A outside of the ramp
+        Ok(cmp::min(
+            cmp::max(
+                amp_factor,
+                cmp::min(self.initial_amp_factor, self.target_amp_factor),
+            ),
+            cmp::max(self.initial_amp_factor, self.target_amp_factor),
+        ))
+    }
+
+    /// Newton's method for D on U256, falling back to bisection when it fails
+    /// to settle on a root of the invariant equation
+    fn compute_d_u256(
+        &self,
+        amp_factor: U256,
+        amounts_times_coins: &[U256],
+        sum_x: U256,
+        n_coins: u64,
+    ) -> Result<U256, CurveError> {
+        match self.newton_d(amp_factor, amounts_times_coins, sum_x, n_coins) {
+            Ok(d) if self.is_d_root(amp_factor, amounts_times_coins, sum_x, n_coins, d)? => Ok(d),
+            _ => self.bisect_d(amp_factor, amounts_times_coins, sum_x, n_coins),
+        }
+    }
+
+    /// Newton's method for D on u128, or `None` if it overflows or does not
+    /// settle on a root of the invariant equation
+    fn compute_d_u128(
+        &self,
+        amp_factor: U256,
+        amounts_times_coins: &[U256],
+        sum_x: U256,
+        n_coins: u64,
+    ) -> Option<U256> {
+        let amp_factor = u128::try_from(amp_factor).ok()?;
+        let amounts_times_coins = to_u128s(amounts_times_coins)?;
+        let sum_x = u128::try_from(sum_x).ok()?;
+        let d = self
+            .newton_d(amp_factor, &amounts_times_coins, sum_x, n_coins)
+            .ok()?;
+        if self
+            .is_d_root(amp_factor, &amounts_times_coins, sum_x, n_coins, d)
+            .ok()?
+        {
+            Some(d.into())
+        } else {
+            None
+        }
+    }
+
+    fn newton_d<T: UnsignedInt>(
+        &self,
+        amp_factor: T,
+        amounts_times_coins: &[T],
+        sum_x: T,
+        n_coins: u64,
+    ) -> Result<T, CurveError> {
+        let mut d_prev: T;
+        let mut d = sum_x;
+        for _ in 0..MAX_ITERATIONS {
+            let d_prod = Self::compute_d_prod(amounts_times_coins, d)?;
+            d_prev = d;
+            d = self.compute_next_d(amp_factor, d, d_prod, sum_x, n_coins)?;
+            // Equality with the precision of 1
+            if d > d_prev {
+                if d.try_sub(d_prev)? <= 1.into() {
+                    return Ok(d);
+                }
+            } else if d_prev.try_sub(d)? <= 1.into() {
+                return Ok(d);
+            }
+        }
+
+        Err(CurveError::NonConvergence {
+            iterations: MAX_ITERATIONS,
+        })
+    }
+
+    /// d_prod = D ** (n + 1) / (n ** n * prod(x_i))
+    fn compute_d_prod<T: UnsignedInt>(amounts_times_coins: &[T], d: T) -> Result<T, CurveError> {
+        let mut d_prod = d;
+        for amount_times_coins in amounts_times_coins {
+            d_prod = d_prod.try_mul(d)?.try_div(*amount_times_coins)?;
+        }
+        Ok(d_prod)
+    }
+
+    /// Whether `d` is at or above the invariant, i.e. whether
+    /// `A * n * D + D ** (n + 1) / (n ** n * prod(x_i)) >= A * n * S + D`.
+    /// The left side grows faster than the right one, so this holds for
+    /// every value from the root of the invariant equation upwards.
+    fn d_at_or_above_root<T: UnsignedInt>(
+        &self,
+        amp_factor: T,
+        amounts_times_coins: &[T],
+        sum_x: T,
+        n_coins: u64,
+        d: T,
+    ) -> Result<bool, CurveError> {
+        let ann = amp_factor.try_mul(n_coins.into())?;
+        let lhs = ann
+            .try_mul(d)?
+            .try_add(Self::compute_d_prod(amounts_times_coins, d)?)?;
+        let rhs = ann.try_mul(sum_x)?.try_add(d)?;
+        Ok(lhs >= rhs)
+    }
+
+    /// Units D may be off from the root of the invariant equation. Each
+    /// floored division of `D_P` loses less than a unit, which the following
+    /// multiplications by `D / (n * x_i)` scale up, and the equation grows by
+    /// at least `A * n - 1` per unit of D. Newton's method adds up to two more
+    /// units, stopping within a unit of its previous iterate.
+    fn d_root_tolerance<T: UnsignedInt>(
+        amp_factor: T,
+        amounts_times_coins: &[T],
+        n_coins: u64,
+        d: T,
+    ) -> Result<T, CurveError> {
+        let ann = amp_factor.try_mul(n_coins.into())?;
+        let mut d_prod_error = T::from(0);
+        for amount_times_coins in amounts_times_coins {
+            d_prod_error = d_prod_error
+                .try_mul(d)?
+                .try_div(*amount_times_coins)?
+                .try_add(1.into())?;
+        }
+        d_prod_error
+            .try_div(ann.try_sub(1.into())?)?
+            .try_add(2.into())
+    }
+
+    /// Whether the invariant equation changes sign within
+    /// `d_root_tolerance` of `d`
+    fn is_d_root<T: UnsignedInt>(
+        &self,
+        amp_factor: T,
+        amounts_times_coins: &[T],
+        sum_x: T,
+        n_coins: u64,
+        d: T,
+    ) -> Result<bool, CurveError> {
+        let above = |d| self.d_at_or_above_root(amp_factor, amounts_times_coins, sum_x, n_coins, d);
+        let tolerance = Self::d_root_tolerance(amp_factor, amounts_times_coins, n_coins, d)?;
+        Ok(above(d.try_add(tolerance)?)? && (d <= tolerance || !above(d.try_sub(tolerance)?)?))
+    }
+
+    /// Find D by bisection over a bracket around the root of the invariant
+    /// equation. Slower than Newton's method, but always converges.
+    fn bisect_d(
+        &self,
+        amp_factor: U256,
+        amounts_times_coins: &[U256],
+        sum_x: U256,
+        n_coins: u64,
+    ) -> Result<U256, CurveError> {
+        let above = |d| self.d_at_or_above_root(amp_factor, amounts_times_coins, sum_x, n_coins, d);
+        let d = bisect(U256::zero(), sum_x, above)?;
+        if self.is_d_root(amp_factor, amounts_times_coins, sum_x, n_coins, d)? {
+            Ok(d)
+        } else {
+            Err(CurveError::NonConvergence {
+                iterations: MAX_ITERATIONS,
+            })
+        }
+    }
+
+    /// Compute swap amount `y` in proportion to `x`, both normalized
+    /// Solve for y:
+    /// y**2 + y * (sum' - (A*n**n - 1) * D / (A * n**n)) = D ** (n + 1) / (n ** (2 * n) * prod' * A)
+    /// y**2 + b*y = c
+    pub fn compute_y(&self, x: U256, d: U256) -> Result<U256, CurveError> {
+        self.solve_y(&[x], d)
+    }
+
+    /// Solve the invariant for the one balance missing from `other_balances`
+    fn solve_y(&self, other_balances: &[U256], d: U256) -> Result<U256, CurveError> {
+        let amp_factor = self.compute_amp_factor()?;
+        // Same u128 fast path as for D
+        if let Some(y) = Self::solve_y_u128(amp_factor, other_balances, d) {
+            return Ok(y);
+        }
+        Self::solve_y_u256(amp_factor, other_balances, d)
+    }
+
+    /// Newton's method for y on U256, falling back to bisection when it fails
+    /// to settle on a root
+    fn solve_y_u256(
+        amp_factor: U256,
+        other_balances: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError> {
+        let (b, c) = Self::compute_y_coefficients(amp_factor, other_balances, d)?;
+
+        // Solve for y by approximating: y**2 + b*y = c
+        match Self::newton_y(b, c, d) {
+            Ok(y) if Self::is_y_root(b, c, d, y)? => Ok(y),
+            _ => Self::bisect_y(b, c, d),
+        }
+    }
+
+    /// Coefficients `b` and `c` of the quadratic `y**2 + b*y = c` solved
+    /// for the balance of the remaining coin
+    fn compute_y_coefficients<T: UnsignedInt>(
+        amp_factor: T,
+        other_balances: &[T],
+        d: T,
+    ) -> Result<(T, T), CurveError> {
+        let n_coins = other_balances.len() as u64 + 1;
+        let ann = amp_factor.try_mul(n_coins.into())?; // A * n ** n
+
+        // c =  D ** (n + 1) / (n ** (2 * n) * prod' * A)
+        let mut c = d;
+        let mut sum_x = T::from(0);
+        for x in other_balances {
+            if *x == 0.into() {
+                return Err(CurveError::ZeroLiquidity);
+            }
+            c = c.try_mul(d)?.try_div(x.try_mul(n_coins.into())?)?;
+            sum_x = sum_x.try_add(*x)?;
+        }
+        c = c.try_mul(d)?.try_div(ann.try_mul(n_coins.into())?)?;
+        // b = sum' - (A*n**n - 1) * D / (A * n**n)
+        let b = d.try_div(ann)?.try_add(sum_x)?; // d is subtracted while solving
+
+        Ok((b, c))
+    }
+
+    /// Newton's method for y on u128, or `None` if it overflows or does not
+    /// settle on a root
+    fn solve_y_u128(amp_factor: U256, other_balances: &[U256], d: U256) -> Option<U256> {
+        let amp_factor = u128::try_from(amp_factor).ok()?;
+        let other_balances = to_u128s(other_balances)?;
+        let d = u128::try_from(d).ok()?;
+        let (b, c) = Self::compute_y_coefficients(amp_factor, &other_balances, d).ok()?;
+        let y = Self::newton_y(b, c, d).ok()?;
+        if Self::is_y_root(b, c, d, y).ok()? {
+            Some(y.into())
+        } else {
+            None
+        }
+    }
+
+    fn newton_y<T: UnsignedInt>(b: T, c: T, d: T) -> Result<T, CurveError> {
+        let mut y_prev: T;
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            y_prev = y;
+            // y = (y * y + c) / (2 * y + b - d);
+            let y_numerator = y.try_mul(y)?.try_add(c)?;
+            let y_denominator = y.try_mul(2.into())?.try_add(b)?.try_sub(d)?;
+            y = y_numerator.try_div(y_denominator)?;
+            if y > y_prev {
+                if y.try_sub(y_prev)? <= 1.into() {
+                    return Ok(y);
+                }
+            } else if y_prev.try_sub(y)? <= 1.into() {
+                return Ok(y);
+            }
+        }
+
+        Err(CurveError::NonConvergence {
+            iterations: MAX_ITERATIONS,
+        })
+    }
+
+    /// Whether `y * y + (b - d) * y >= c`, which holds for every value from
+    /// the positive root upwards
+    fn y_at_or_above_root<T: UnsignedInt>(b: T, c: T, d: T, y: T) -> Result<bool, CurveError> {
+        let lhs = y.try_mul(y)?.try_add(b.try_mul(y)?)?;
+        let rhs = c.try_add(d.try_mul(y)?)?;
+        Ok(lhs >= rhs)
+    }
+
+    /// Find y by bisection over a bracket around the positive root. Slower
+    /// than Newton's method, but always converges.
+    fn bisect_y(b: U256, c: U256, d: U256) -> Result<U256, CurveError> {
+        // The quadratic only grows from its vertex at (d - b) / 2, below which
+        // lies a second, non-positive root
+        let vertex = d.saturating_sub(b).try_div(2.into())?;
+        let y = bisect(vertex, d, |y| Self::y_at_or_above_root(b, c, d, y))?;
+        if Self::is_y_root(b, c, d, y)? {
+            Ok(y)
+        } else {
+            Err(CurveError::NonConvergence {
+                iterations: MAX_ITERATIONS,
+            })
+        }
+    }
+
+    /// Whether `y * y + (b - d) * y - c` changes sign within one unit of `y`
+    fn is_y_root<T: UnsignedInt>(b: T, c: T, d: T, y: T) -> Result<bool, CurveError> {
+        Ok(Self::y_at_or_above_root(b, c, d, y.try_add(1.into())?)?
+            && (y == 0.into() || !Self::y_at_or_above_root(b, c, d, y.try_sub(1.into())?)?))
+    }
+
+    /// Compute the marginal exchange rate of coin `i` in coin `j` at normalized
+    /// balances `xp` on the curve of invariant `d`, scaled by `RATE_PRECISION`
+    /// Equation:
+    /// dx_j/dx_i = x_j * (A * n**n * x_i + D_P) / (x_i * (A * n**n * x_j + D_P))
+    /// where D_P = D**(n+1) / (n**n * prod(x_k))
+    fn compute_marginal_rate_xp(
+        &self,
+        i: usize,
+        j: usize,
+        xp: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError> {
+        if i == j {
+            return Err(CurveError::InvalidInput);
+        }
+        let x_i = *xp.get(i).ok_or(CurveError::InvalidInput)?;
+        let x_j = *xp.get(j).ok_or(CurveError::InvalidInput)?;
+        let n_coins = xp.len() as u64;
+        let amp_factor = self.compute_amp_factor()?;
+        let ann = amp_factor.try_mul(n_coins.into())?; // A * n ** n
+        let mut d_prod = d;
+        for x in xp.iter() {
+            d_prod = d_prod.try_mul(d)?.try_div(x.try_mul(n_coins.into())?)?;
+        }
+
+        // Rate of normalized amounts, denormalized by scale_i / scale_j
+        let numerator = x_j.try_mul(ann.try_mul(x_i)?.try_add(d_prod)?)?;
+        let denominator = x_i.try_mul(ann.try_mul(x_j)?.try_add(d_prod)?)?;
+        let rate = try_mul_div(numerator, RATE_PRECISION.into(), denominator)?;
+        try_mul_div(rate, self.scale(i)?, self.scale(j)?)
+    }
+
+    /// Compute the amount of source token to sell into the pool to bring its
+    /// marginal rate net of the trade fee down to `target_rate`, scaled by
+    /// `RATE_PRECISION`, along with the result of the swap.
+    /// Returns `None` if the pool does not pay more than `target_rate`.
+    pub fn compute_arbitrage_amount(
+        &self,
+        target_rate: U256,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<Option<(U256, SwapResult)>, CurveError> {
+        self.compute_arbitrage_amount_n(
+            0,
+            1,
+            target_rate,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
+
+    /// Compute the amount of coin `i` to sell into the pool for coin `j` to
+    /// bring its marginal rate net of the trade fee down to `target_rate`,
+    /// scaled by `RATE_PRECISION`, along with the result of the swap.
+    /// The amount is the largest one leaving the rate at or above the target,
+    /// so every unit of it is sold at a better rate than `target_rate`. The
+    /// rate is followed along the curve of the current invariant, leaving out
+    /// the growth of the pool by the fees of the trade itself.
+    /// Returns `None` if the pool does not pay more than `target_rate`.
+    pub fn compute_arbitrage_amount_n(
+        &self,
+        i: usize,
+        j: usize,
+        target_rate: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<Option<(U256, SwapResult)>, CurveError> {
+        if target_rate.is_zero() {
+            return Err(CurveError::InvalidInput);
+        }
+        if self.compute_marginal_rate_with_fee_n(i, j, swap_amounts, fees)? <= target_rate {
+            return Ok(None);
+        }
+        let swap_source_amount = *swap_amounts.get(i).ok_or(CurveError::InvalidInput)?;
+        let xp = self.xp(swap_amounts)?;
+        let d = self.compute_d_n(&xp)?;
+        // The rate along the curve falls as the trade grows, find the smallest
+        // trade pushing it below the target and back off by one unit
+        let below_target = |source_amount: U256| -> Result<bool, CurveError> {
+            let x = xp[i].try_add(self.normalize(i, source_amount)?)?;
+            let y = self.compute_y_n(i, j, x, &xp, d)?;
+            let mut new_xp = xp.clone();
+            new_xp[i] = x;
+            new_xp[j] = y;
+            let rate = self.compute_marginal_rate_xp(i, j, &new_xp, d)?;
+            let fee = fees.dynamic_fee(fees.trade_fee(rate)?, new_xp[i], new_xp[j])?;
+            Ok(rate.try_sub(fee)? < target_rate)
+        };
+        let amount_in =
+            bisect(U256::zero(), swap_source_amount, below_target)?.try_sub(1.into())?;
+        if amount_in.is_zero() {
+            return Ok(None);
+        }
+        let result = self.swap_to_n(i, j, amount_in, swap_amounts, fees)?;
+        Ok(Some((amount_in, result)))
+    }
+
+    /// Compute the largest amount of source token that can be swapped for
+    /// destination token with a price impact of at most `max_price_impact`,
+    /// scaled by `RATE_PRECISION`, along with the result of the swap.
+    /// Returns `None` if no trade stays within the bound.
+    pub fn compute_max_trade_for_price_impact(
+        &self,
+        max_price_impact: U256,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<Option<(U256, SwapResult)>, CurveError> {
+        self.compute_max_trade_for_price_impact_n(
+            0,
+            1,
+            max_price_impact,
+            &[swap_source_amount, swap_destination_amount],
+            fees,
+        )
+    }
+
+    /// Compute the largest amount of coin `i` that can be swapped for coin `j`
+    /// with a price impact of at most `max_price_impact`, scaled by
+    /// `RATE_PRECISION`, along with the result of the swap.
+    /// The price impact is the shortfall of the trade's average rate from the
+    /// spot rate net of the trade fee, relative to the spot rate.
+    /// Returns `None` if no trade stays within the bound.
+    pub fn compute_max_trade_for_price_impact_n(
+        &self,
+        i: usize,
+        j: usize,
+        max_price_impact: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> Result<Option<(U256, SwapResult)>, CurveError> {
+        let precision = U256::from(RATE_PRECISION);
+        if max_price_impact >= precision {
+            return Err(CurveError::InvalidInput);
+        }
+        let spot_rate = self.compute_marginal_rate_with_fee_n(i, j, swap_amounts, fees)?;
+        let min_rate = spot_rate.try_mul(precision.try_sub(max_price_impact)?)?;
+        let swap_source_amount = *swap_amounts.get(i).ok_or(CurveError::InvalidInput)?;
+        // The average rate falls as the trade grows, find the smallest trade
+        // falling short of the bound and back off by one unit
+        let exceeds_bound = |source_amount: U256| -> Result<bool, CurveError> {
+            if source_amount.is_zero() {
+                return Ok(false);
+            }
+            let result = self.swap_to_n(i, j, source_amount, swap_amounts, fees)?;
+            Ok(result
+                .amount_swapped
+                .try_mul(precision)?
+                .try_mul(precision)?
+                < source_amount.try_mul(min_rate)?)
+        };
+        let amount_in =
+            bisect(U256::zero(), swap_source_amount, exceeds_bound)?.try_sub(1.into())?;
+        if amount_in.is_zero() {
+            return Ok(None);
+        }
+        let result = self.swap_to_n(i, j, amount_in, swap_amounts, fees)?;
+        Ok(Some((amount_in, result)))
+    }
+
+    /// Evaluate `quote` with the amplification coefficient of every `step`
+    /// seconds from `start_ts` to `end_ts`, both included, following the ramp
+    /// of this calculator. A holds at its initial value until the ramp starts.
+    pub fn forecast<T, F>(
+        &self,
+        start_ts: i64,
+        end_ts: i64,
+        step: i64,
+        quote: F,
+    ) -> Result<Vec<ForecastPoint<T>>, CurveError>
+    where
+        F: Fn(&StableSwap) -> Result<T, CurveError>,
+    {
+        if step <= 0 || end_ts < start_ts {
+            return Err(CurveError::InvalidInput);
+        }
+        let mut points = Vec::new();
+        let mut calculator = self.clone();
+        let mut timestamp = start_ts;
+        loop {
+            calculator.current_ts = cmp::max(timestamp, self.start_ramp_ts);
+            points.push(ForecastPoint {
+                timestamp,
+                amp_factor: calculator.compute_amp_factor()?,
+                quote: quote(&calculator)?,
+            });
+            if timestamp == end_ts {
+                return Ok(points);
+            }
+            timestamp = cmp::min(timestamp.saturating_add(step), end_ts);
+        }
+    }
+
+    /// Forecast the SwapQuote of exchanging `source_amount` of source token
+    /// every `step` seconds from `start_ts` to `end_ts`, see `forecast`
+    #[allow(clippy::too_many_arguments)]
+    pub fn forecast_swap(
+        &self,
+        start_ts: i64,
+        end_ts: i64,
+        step: i64,
+        source_amount: U256,
+        swap_source_amount: U256,
+        swap_destination_amount: U256,
+        fees: &Fees,
+    ) -> Result<Vec<ForecastPoint<SwapQuote>>, CurveError> {
+        self.forecast(start_ts, end_ts, step, |calculator| {
+            calculator.quote_swap(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                fees,
+            )
+        })
+    }
+
+    /// Forecast the WithdrawOneResult of withdrawing `pool_token_amount` in
+    /// the base token every `step` seconds from `start_ts` to `end_ts`, see
+    /// `forecast`
+    #[allow(clippy::too_many_arguments)]
+    pub fn forecast_withdraw_one(
+        &self,
+        start_ts: i64,
+        end_ts: i64,
+        step: i64,
+        pool_token_amount: U256,
+        pool_token_supply: U256,
+        swap_base_amount: U256,
+        swap_quote_amount: U256,
+        fees: &Fees,
+    ) -> Result<Vec<ForecastPoint<WithdrawOneResult>>, CurveError> {
+        self.forecast(start_ts, end_ts, step, |calculator| {
+            calculator.compute_withdraw_one(
+                pool_token_amount,
+                pool_token_supply,
+                swap_base_amount,
+                swap_quote_amount,
+                fees,
+            )
+        })
+    }
+}
+
+/// StableSwap invariant:
+/// A * sum(x_i) * n**n + D = A * D * n**n + D**(n+1) / (n**n * prod(x_i))
+impl CurveCalculator for StableSwap {
+    fn compute_marginal_rate_n(
+        &self,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+    ) -> Result<U256, CurveError> {
+        let xp = self.xp(swap_amounts)?;
+        self.compute_marginal_rate_xp(i, j, &xp, self.compute_d_n(&xp)?)
+    }
+
+    fn scale(&self, i: usize) -> Result<U256, CurveError> {
+        self.precision_multipliers
+            .get(i)
+            .ok_or(CurveError::InvalidInput)?
+            .try_mul(*self.rates.get(i).ok_or(CurveError::InvalidInput)?)
+    }
+
+    fn compute_d_n(&self, amounts: &[U256]) -> Result<U256, CurveError> {
+        let n_coins = amounts.len() as u64;
+        if n_coins < MIN_N_COINS as u64 {
+            return Err(CurveError::InvalidInput);
+        }
+        let mut sum_x = U256::zero(); // sum(x_i), a.k.a S
+        for amount in amounts {
+            sum_x = sum_x.try_add(*amount)?;
+        }
+        if sum_x == 0.into() {
+            Ok(0.into())
+        } else if amounts.iter().any(|amount| amount.is_zero()) {
+            Err(CurveError::ZeroLiquidity)
+        } else {
+            let amp_factor = self.compute_amp_factor()?;
+            let mut amounts_times_coins = Vec::with_capacity(amounts.len());
+            for amount in amounts {
+                amounts_times_coins.push(amount.try_mul(n_coins.into())?);
+            }
+
+            // Realistic pools fit in u128, which is much cheaper than U256.
+            // Anything the fast path cannot settle is redone on U256, so the
+            // result is the same either way.
+            if let Some(d) = self.compute_d_u128(amp_factor, &amounts_times_coins, sum_x, n_coins) {
+                return Ok(d);
+            }
+
+            self.compute_d_u256(amp_factor, &amounts_times_coins, sum_x, n_coins)
+        }
+    }
+
+    fn compute_y_n(
+        &self,
+        i: usize,
+        j: usize,
+        x: U256,
+        balances: &[U256],
+        d: U256,
+    ) -> Result<U256, CurveError> {
+        if i == j || i >= balances.len() || j >= balances.len() {
+            return Err(CurveError::InvalidInput);
+        }
+        let mut other_balances = Vec::with_capacity(balances.len() - 1);
+        for (k, balance) in balances.iter().enumerate() {
+            if k == i {
+                other_balances.push(x);
+            } else if k != j {
+                other_balances.push(*balance);
+            }
+        }
+        self.solve_y(&other_balances, d)
+    }
+}
+
+/// Narrow `values` to u128, or `None` if any of them does not fit
+fn to_u128s(values: &[U256]) -> Option<Vec<u128>> {
+    values
+        .iter()
+        .map(|value| u128::try_from(*value).ok())
+        .collect()
+}
+
+/// Compute `a * b / c`. If the product overflows, `a` and `c` are shifted
+/// right by the excess bits first, which only drops precision beyond what
+/// the quotient can hold.
+fn try_mul_div(a: U256, b: U256, c: U256) -> Result<U256, CurveError> {
+    match a.checked_mul(b) {
+        Some(product) => product.try_div(c),
+        None => {
+            let shift = (a.bits() + b.bits()).saturating_sub(256);
+            (a >> shift).try_mul(b)?.try_div(c >> shift)
+        }
+    }
+}
+
+/// Find the smallest value from `low` upwards for which the predicate
+/// `at_or_above_root` holds. The predicate must be monotone from `low`. The
+/// upper bound guess `hint` is doubled until it brackets the root.
+fn bisect<F>(mut low: U256, hint: U256, at_or_above_root: F) -> Result<U256, CurveError>
+where
+    F: Fn(U256) -> Result<bool, CurveError>,
+{
+    if at_or_above_root(low)? {
+        return Ok(low);
+    }
+    let mut high = hint.max(low.try_add(1.into())?);
+    let mut iterations = 0;
+    while !at_or_above_root(high)? {
+        low = high;
+        high = high.try_mul(2.into())?;
+        iterations += 1;
+    }
+    // Invariant: the predicate is false at `low` and true at `high`
+    while high.try_sub(low)? > 1.into() {
+        if iterations >= MAX_ITERATIONS * 2 {
+            return Err(CurveError::NonConvergence { iterations });
+        }
+        let mid = low.try_add(high.try_sub(low)?.try_div(2.into())?)?;
+        if at_or_above_root(mid)? {
+            high = mid;
+        } else {
+            low = mid;
+        }
+        iterations += 1;
+    }
+    Ok(high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::OFFPEG_FEE_MULTIPLIER_DENOMINATOR;
+    use proptest::prelude::*;
+    use rand::Rng;
+    use sim::{Model, MODEL_FEE_DENOMINATOR, MODEL_FEE_NUMERATOR};
+    use std::cmp;
+
+    /// Number of coins
+    const N_COINS: u64 = 2;
+
+    #[allow(clippy::assign_op_pattern, clippy::manual_range_contains)]
+    mod exact {
+        use uint::construct_uint;
+
+        construct_uint! {
+            /// 512-bit unsigned integer evaluating the invariant exactly
+            pub struct U512(8);
+        }
+    }
+    use exact::U512;
+
+    impl From<U256> for U512 {
+        fn from(value: U256) -> Self {
+            let mut words = [0; 8];
+            words[..4].copy_from_slice(&value.0);
+            U512(words)
+        }
+    }
+
+    const MODEL_FEES: Fees = Fees {
+        admin_trade_fee_numerator: 0,
+        admin_trade_fee_denominator: 1,
+        admin_withdraw_fee_numerator: 0,
+        admin_withdraw_fee_denominator: 1,
+        trade_fee_numerator: MODEL_FEE_NUMERATOR,
+        trade_fee_denominator: MODEL_FEE_DENOMINATOR,
+        withdraw_fee_numerator: 0,
+        withdraw_fee_denominator: 1,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
+    };
+
+    const RAMP_TICKS: i64 = 100000;
+
+    #[test]
+    fn test_ramp_amp_up() {
+        let mut rng = rand::thread_rng();
+        let initial_amp_factor = 100;
+        let target_amp_factor = initial_amp_factor * 2;
+        let start_ramp_ts = rng.gen_range(ZERO_TS, i64::MAX - RAMP_TICKS);
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+        println!(
+            "start_ramp_ts: {}, stop_ramp_ts: {}",
+            start_ramp_ts, stop_ramp_ts
+        );
+
+        for tick in 0..RAMP_TICKS {
+            let current_ts = start_ramp_ts + tick;
+            let invariant = StableSwap::new(
+                initial_amp_factor,
+                target_amp_factor,
+                current_ts,
+                start_ramp_ts,
+                stop_ramp_ts,
+            );
+            let expected = if tick >= MIN_RAMP_DURATION {
+                target_amp_factor
+            } else {
+                initial_amp_factor + (initial_amp_factor * tick as u64 / MIN_RAMP_DURATION as u64)
+            };
+            assert_eq!(invariant.compute_amp_factor().unwrap(), expected.into());
+        }
+    }
+
+    #[test]
+    fn test_ramp_amp_down() {
+        let mut rng = rand::thread_rng();
+        let initial_amp_factor = 100;
+        let target_amp_factor = initial_amp_factor / 10;
+        let amp_range = initial_amp_factor - target_amp_factor;
+        let start_ramp_ts = rng.gen_range(ZERO_TS, i64::MAX - RAMP_TICKS);
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+        println!(
+            "start_ramp_ts: {}, stop_ramp_ts: {}",
+            start_ramp_ts, stop_ramp_ts
+        );
+
+        for tick in 0..RAMP_TICKS {
+            let current_ts = start_ramp_ts + tick;
+            let invariant = StableSwap::new(
+                initial_amp_factor,
+                target_amp_factor,
+                current_ts,
+                start_ramp_ts,
+                stop_ramp_ts,
+            );
+            let expected = if tick >= MIN_RAMP_DURATION {
+                target_amp_factor
+            } else {
+                initial_amp_factor - (amp_range * tick as u64 / MIN_RAMP_DURATION as u64)
+            };
+            assert_eq!(invariant.compute_amp_factor().unwrap(), expected.into());
+        }
+    }
+
+    fn check_geometric_ramp(initial_amp_factor: u64, target_amp_factor: u64) {
+        let mut rng = rand::thread_rng();
+        let start_ramp_ts = rng.gen_range(ZERO_TS, i64::MAX - RAMP_TICKS);
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
         println!(
             "start_ramp_ts: {}, stop_ramp_ts: {}",
             start_ramp_ts, stop_ramp_ts
         );
 
-        for tick in 0..RAMP_TICKS {
-            let current_ts = start_ramp_ts + tick;
-            let invariant = StableSwap::new(
+        let ratio = target_amp_factor as f64 / initial_amp_factor as f64;
+        let mut last_amp_factor = initial_amp_factor;
+        for tick in (0..RAMP_TICKS).step_by(97) {
+            let current_ts = start_ramp_ts + tick;
+            let invariant = StableSwap::new(
+                initial_amp_factor,
+                target_amp_factor,
+                current_ts,
+                start_ramp_ts,
+                stop_ramp_ts,
+            )
+            .with_ramp_type(RampType::Geometric);
+            let amp_factor = invariant.compute_amp_factor().unwrap().as_u64();
+            if tick >= MIN_RAMP_DURATION {
+                assert_eq!(amp_factor, target_amp_factor);
+            } else {
+                let fraction = tick as f64 / MIN_RAMP_DURATION as f64;
+                let expected = initial_amp_factor as f64 * ratio.powf(fraction);
+                assert!(
+                    (amp_factor as i64 - expected.floor() as i64).abs() <= 1,
+                    "tick: {}, amp_factor: {}, expected: {}",
+                    tick,
+                    amp_factor,
+                    expected
+                );
+            }
+            // A only moves towards the target
+            if target_amp_factor >= initial_amp_factor {
+                assert!(amp_factor >= last_amp_factor);
+            } else {
+                assert!(amp_factor <= last_amp_factor);
+            }
+            last_amp_factor = amp_factor;
+        }
+    }
+
+    #[test]
+    fn test_geometric_ramp_amp_up() {
+        check_geometric_ramp(100, 200);
+        check_geometric_ramp(MAX_AMP / 10, MAX_AMP);
+    }
+
+    #[test]
+    fn test_geometric_ramp_amp_down() {
+        check_geometric_ramp(100, 10);
+        check_geometric_ramp(10, MIN_AMP);
+    }
+
+    #[test]
+    fn test_geometric_ramp_halfway() {
+        // A changes by the same factor over each half of the ramp
+        let invariant = StableSwap::new(
+            100,
+            10_000,
+            MIN_RAMP_DURATION,
+            ZERO_TS,
+            2 * MIN_RAMP_DURATION,
+        )
+        .with_ramp_type(RampType::Geometric);
+        assert_eq!(invariant.compute_amp_factor(), Ok(1_000.into()));
+        let invariant = StableSwap::new(
+            10_000,
+            100,
+            MIN_RAMP_DURATION,
+            ZERO_TS,
+            2 * MIN_RAMP_DURATION,
+        )
+        .with_ramp_type(RampType::Geometric);
+        assert_eq!(invariant.compute_amp_factor(), Ok(1_000.into()));
+        // and linearly in between by default
+        let invariant = StableSwap::new(
+            100,
+            10_000,
+            MIN_RAMP_DURATION,
+            ZERO_TS,
+            2 * MIN_RAMP_DURATION,
+        );
+        assert_eq!(invariant.compute_amp_factor(), Ok(5_050.into()));
+    }
+
+    fn check_d(
+        model: &Model,
+        amount_a: u64,
+        amount_b: u64,
+        current_ts: i64,
+        start_ramp_ts: i64,
+        stop_ramp_ts: i64,
+    ) -> U256 {
+        let swap = StableSwap {
+            initial_amp_factor: U256::from(model.amp_factor),
+            target_amp_factor: U256::from(model.amp_factor),
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+            ramp_type: RampType::Linear,
+            precision_multipliers: [U256::one(); MAX_N_COINS],
+            rates: [U256::from(RATE_PRECISION); MAX_N_COINS],
+        };
+        let d = swap
+            .compute_d(U256::from(amount_a), U256::from(amount_b))
+            .unwrap();
+        assert_eq!(d, model.sim_d().into());
+        d
+    }
+
+    fn check_y(
+        model: &Model,
+        x: u64,
+        d: U256,
+        current_ts: i64,
+        start_ramp_ts: i64,
+        stop_ramp_ts: i64,
+    ) {
+        let swap = StableSwap {
+            initial_amp_factor: U256::from(model.amp_factor),
+            target_amp_factor: U256::from(model.amp_factor),
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+            ramp_type: RampType::Linear,
+            precision_multipliers: [U256::one(); MAX_N_COINS],
+            rates: [U256::from(RATE_PRECISION); MAX_N_COINS],
+        };
+        assert_eq!(
+            swap.compute_y(x.into(), d).unwrap(),
+            model.sim_y(0, 1, x.into()).into()
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_curve_math(
+            current_ts in ZERO_TS..i64::MAX,
+            amp_factor in MIN_AMP..MAX_AMP,
+            amount_a in 1..u64::MAX,    // Start at 1 to prevent divide by 0 when computing d
+            amount_b in 1..u64::MAX,    // Start at 1 to prevent divide by 0 when computing d
+        ) {
+            let start_ramp_ts = cmp::max(0, current_ts - MIN_RAMP_DURATION);
+            let stop_ramp_ts = cmp::min(i64::MAX, current_ts + MIN_RAMP_DURATION);
+            let model = Model::new(amp_factor.into(), vec![amount_a.into(), amount_b.into()], N_COINS.into());
+            let d = check_d(&model, amount_a, amount_b, current_ts, start_ramp_ts, stop_ramp_ts);
+            check_y(&model, amount_a, d, current_ts, start_ramp_ts, stop_ramp_ts);
+        }
+    }
+
+    #[test]
+    fn test_curve_math_specific() {
+        // Specific cases
+        let current_ts = ZERO_TS;
+        let start_ramp_ts = ZERO_TS;
+        let stop_ramp_ts = ZERO_TS;
+        let model_no_balance = Model::new(1, vec![0, 0], N_COINS.into());
+        check_d(
+            &model_no_balance,
+            0,
+            0,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+
+        let amount_a: u64 = 10461290657254161082;
+        let amount_b: u64 = 12507100355549196829;
+        let model = Model::new(1188, vec![amount_a.into(), amount_b.into()], N_COINS.into());
+        let d = check_d(
+            &model,
+            amount_a,
+            amount_b,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+        let amount_x: u64 = 2045250484898639148;
+        check_y(
+            &model,
+            amount_x.into(),
+            d,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+
+        let amount_a: u64 = 8625384579714585493;
+        let amount_b: u64 = 4925481879098236733;
+        let model = Model::new(9, vec![amount_a.into(), amount_b.into()], N_COINS.into());
+        let d = check_d(
+            &model,
+            amount_a,
+            amount_b,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+        let amount_x: u64 = 8155777549389559399;
+        check_y(
+            &model,
+            amount_x.into(),
+            d,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+    }
+
+    fn check_d_n(model: &Model, amounts: &[u64], current_ts: i64) -> U256 {
+        let swap = StableSwap::new(
+            model.amp_factor as u64,
+            model.amp_factor as u64,
+            current_ts,
+            ZERO_TS,
+            ZERO_TS,
+        );
+        let amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+        let d = swap.compute_d_n(&amounts).unwrap();
+        assert_eq!(d, model.sim_d().into());
+        d
+    }
+
+    fn check_y_n(model: &Model, i: usize, j: usize, x: u64, amounts: &[u64], d: U256) {
+        let swap = StableSwap::new(
+            model.amp_factor as u64,
+            model.amp_factor as u64,
+            ZERO_TS,
+            ZERO_TS,
+            ZERO_TS,
+        );
+        let amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+        assert_eq!(
+            swap.compute_y_n(i, j, x.into(), &amounts, d).unwrap(),
+            model.sim_y(i as u128, j as u128, x.into()).into()
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_curve_math_n_coins(
+            current_ts in ZERO_TS..i64::MAX,
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            dx in 0..1_000_000_000_000_000u64,
+        ) {
+            let n_coins = amounts.len();
+            let model = Model::new(
+                amp_factor.into(),
+                amounts.iter().map(|a| u128::from(*a)).collect(),
+                n_coins as u128,
+            );
+            let d = check_d_n(&model, &amounts, current_ts);
+            let (i, j) = (n_coins - 1, 0);
+            check_y_n(&model, i, j, amounts[i] + dx, &amounts, d);
+        }
+    }
+
+    #[test]
+    fn test_curve_math_n_coins_matches_two_coins() {
+        let invariant = StableSwap::new(MIN_AMP * 100, MIN_AMP * 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount_a = U256::from(8625384579714585493u64);
+        let amount_b = U256::from(4925481879098236733u64);
+        let d = invariant.compute_d(amount_a, amount_b).unwrap();
+        assert_eq!(invariant.compute_d_n(&[amount_a, amount_b]).unwrap(), d);
+
+        let x = U256::from(8155777549389559399u64);
+        assert_eq!(
+            invariant
+                .compute_y_n(0, 1, x, &[amount_a, amount_b], d)
+                .unwrap(),
+            invariant.compute_y(x, d).unwrap()
+        );
+
+        // Pools need at least two coins and distinct swap indices
+        assert_eq!(
+            invariant.compute_d_n(&[amount_a]),
+            Err(CurveError::InvalidInput)
+        );
+        assert_eq!(
+            invariant.compute_y_n(0, 0, x, &[amount_a, amount_b], d),
+            Err(CurveError::InvalidInput)
+        );
+        assert_eq!(
+            invariant.compute_y_n(0, 2, x, &[amount_a, amount_b], d),
+            Err(CurveError::InvalidInput)
+        );
+    }
+
+    /// Check that D of `amount_a` and `amount_b` and the balance y solved back
+    /// from D and `amount_a` are accurate roots of their equations
+    fn check_convergence(amp_factor: u64, amount_a: u64, amount_b: u64) {
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amp = invariant.compute_amp_factor().unwrap();
+        let amounts = [U256::from(amount_a), U256::from(amount_b)];
+        let amounts_times_coins = [amounts[0] * N_COINS, amounts[1] * N_COINS];
+        let sum_x = amounts[0] + amounts[1];
+
+        // D is within the rounding tolerance of the exact root of the
+        // invariant equation, multiplied out on 512 bits:
+        // A * n * D * n ** n * prod(x_i) + D ** (n + 1) = (A * n * S + D) * n ** n * prod(x_i)
+        let d = invariant.compute_d(amounts[0], amounts[1]).unwrap();
+        assert!(invariant
+            .is_d_root(amp, &amounts_times_coins, sum_x, N_COINS, d)
+            .unwrap());
+        let ann = U512::from(amp * N_COINS);
+        let prod_x = U512::from(amounts_times_coins[0]) * U512::from(amounts_times_coins[1]);
+        let at_or_above_exact_root = |d: U256| {
+            let d = U512::from(d);
+            ann * d * prod_x + d * d * d >= (ann * U512::from(sum_x) + d) * prod_x
+        };
+        let tolerance =
+            StableSwap::d_root_tolerance(amp, &amounts_times_coins, N_COINS, d).unwrap();
+        assert!(at_or_above_exact_root(d + tolerance));
+        assert!(d <= tolerance || !at_or_above_exact_root(d - tolerance));
+
+        // y is within one unit of the root of the equation solved for it
+        let (b, c) = StableSwap::compute_y_coefficients(amp, &[amounts[0]], d).unwrap();
+        let y = invariant.compute_y(amounts[0], d).unwrap();
+        assert!(StableSwap::is_y_root(b, c, d, y).unwrap());
+        let bisected = StableSwap::bisect_y(b, c, d).unwrap();
+        assert!(cmp::max(y, bisected) - cmp::min(y, bisected) <= 1.into());
+    }
+
+    proptest! {
+        #[test]
+        fn test_convergence_at_extremes(
+            amp_factor in prop_oneof![Just(MIN_AMP), Just(MAX_AMP)],
+            amount_a in prop_oneof![1..1_000u64, 1..u64::MAX, u64::MAX - 1_000..u64::MAX],
+            amount_b in prop_oneof![1..1_000u64, 1..u64::MAX, u64::MAX - 1_000..u64::MAX],
+        ) {
+            check_convergence(amp_factor, amount_a, amount_b);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_u128_matches_u256(
+            current_ts in ZERO_TS..i64::MAX,
+            amp_factor in MIN_AMP..=MAX_AMP,
+            amounts in prop::collection::vec(
+                prop_oneof![1..u64::MAX, 1..1_000_000_000_000_000u64],
+                MIN_N_COINS..=MAX_N_COINS,
+            ),
+            multiplier in prop_oneof![Just(1u64), Just(1_000_000_000_000u64)],
+        ) {
+            let start_ramp_ts = cmp::max(0, current_ts - MIN_RAMP_DURATION);
+            let stop_ramp_ts = cmp::min(i64::MAX, current_ts + MIN_RAMP_DURATION);
+            let invariant = StableSwap::new(amp_factor, amp_factor, current_ts, start_ramp_ts, stop_ramp_ts);
+            let amp = invariant.compute_amp_factor().unwrap();
+            let n_coins = amounts.len() as u64;
+            let amounts: Vec<U256> = amounts.iter().map(|amount| U256::from(*amount) * multiplier).collect();
+            let amounts_times_coins: Vec<U256> = amounts.iter().map(|amount| *amount * n_coins).collect();
+            let sum_x = amounts.iter().fold(U256::zero(), |sum, amount| sum + amount);
+
+            let d = invariant.compute_d_u256(amp, &amounts_times_coins, sum_x, n_coins);
+            if let Some(fast) = invariant.compute_d_u128(amp, &amounts_times_coins, sum_x, n_coins) {
+                assert_eq!(Ok(fast), d);
+            }
+            let d = d.unwrap();
+            assert_eq!(invariant.compute_d_n(&amounts), Ok(d));
+
+            // Take the first coin out of the pool to solve for the last one
+            let other_balances = &amounts[1..amounts.len() - 1];
+            let mut balances = vec![amounts[0] / 2];
+            balances.extend_from_slice(other_balances);
+            let y = StableSwap::solve_y_u256(amp, &balances, d);
+            if let Some(fast) = StableSwap::solve_y_u128(amp, &balances, d) {
+                assert_eq!(Ok(fast), y);
+            }
+        }
+    }
+
+    /// Newton's method for D of a two coin pool as it was before the solvers
+    /// were verified and moved to u128, or `None` if it did not converge
+    fn baseline_newton_d(amp_factor: U256, amount_a: U256, amount_b: U256) -> Option<U256> {
+        let ann = amp_factor * N_COINS;
+        let sum_x = amount_a + amount_b;
+        let mut d = sum_x;
+        for _ in 0..MAX_ITERATIONS {
+            let d_prod = (d * d / (amount_a * N_COINS)).checked_mul(d)? / (amount_b * N_COINS);
+            let d_prev = d;
+            let numerator = d.checked_mul(d_prod.checked_mul(N_COINS.into())? + ann * sum_x)?;
+            let denominator = d * (ann - 1) + d_prod.checked_mul((N_COINS + 1).into())?;
+            d = numerator / denominator;
+            if cmp::max(d, d_prev) - cmp::min(d, d_prev) <= 1.into() {
+                return Some(d);
+            }
+        }
+        None
+    }
+
+    /// Newton's method for y of a two coin pool as it was before the solvers
+    /// were verified and moved to u128, or `None` if it did not converge
+    fn baseline_newton_y(amp_factor: U256, x: U256, d: U256) -> Option<U256> {
+        let ann = amp_factor * N_COINS;
+        let c = (d * d / (x * N_COINS)).checked_mul(d)? / (ann * N_COINS);
+        let b = d / ann + x;
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            let y_prev = y;
+            y = (y * y + c) / (y * U256::from(2u64) + b).checked_sub(d)?;
+            if cmp::max(y, y_prev) - cmp::min(y, y_prev) <= 1.into() {
+                return Some(y);
+            }
+        }
+        None
+    }
+
+    proptest! {
+        #[test]
+        fn test_matches_baseline_newton(
+            amp_factor in MIN_AMP..=MAX_AMP,
+            amount_a in prop_oneof![1..u64::MAX, 1..1_000_000_000_000_000u64],
+            amount_b in prop_oneof![1..u64::MAX, 1..1_000_000_000_000_000u64],
+            x in 1..u64::MAX,
+        ) {
+            let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let amp = invariant.compute_amp_factor().unwrap();
+            let (amount_a, amount_b) = (U256::from(amount_a), U256::from(amount_b));
+
+            if let Some(expected) = baseline_newton_d(amp, amount_a, amount_b) {
+                let d = invariant.compute_d(amount_a, amount_b).unwrap();
+                prop_assert_eq!(d, expected);
+
+                let x = U256::from(x);
+                if let Some(expected) = baseline_newton_y(amp, x, d) {
+                    prop_assert_eq!(invariant.compute_y(x, d).unwrap(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_u128_fast_path() {
+        let invariant = StableSwap::new(MAX_AMP, MAX_AMP, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amp = invariant.compute_amp_factor().unwrap();
+
+        // Realistic reserves stay on u128
+        let amounts_times_coins = [U256::from(2_000_000_000_000_000u64); 2];
+        let sum_x = U256::from(2_000_000_000_000_000u64);
+        let d = invariant
+            .compute_d_u128(amp, &amounts_times_coins, sum_x, N_COINS)
+            .unwrap();
+        assert_eq!(d, sum_x);
+        assert_eq!(
+            StableSwap::solve_y_u128(amp, &[sum_x / 2], d),
+            Some(sum_x / 2)
+        );
+
+        // Reserves whose products exceed u128 go to U256
+        let amounts_times_coins = [U256::from(u64::MAX) * 2_000_000_000_000u64; 2];
+        let sum_x = amounts_times_coins[0];
+        assert_eq!(
+            invariant.compute_d_u128(amp, &amounts_times_coins, sum_x, N_COINS),
+            None
+        );
+        assert_eq!(
+            invariant.compute_d_u256(amp, &amounts_times_coins, sum_x, N_COINS),
+            Ok(sum_x)
+        );
+    }
+
+    #[test]
+    fn test_convergence_regressions() {
+        // Newton's method lands two units from the root of D
+        check_convergence(12_199, 942_794_440_126_000_000, 1_502_153_944_040);
+        // Imbalanced reserves, where the floors of D_P move the root of D
+        check_convergence(39_799, 635_999_903_123_000_000, 46_157_698_887);
+        // Large reserves at the lowest amplification
+        check_convergence(
+            MIN_AMP,
+            9_902_203_567_566_599_748,
+            18_323_794_454_557_193_860,
+        );
+        // Dust reserves at the highest amplification, where the constant term
+        // of the equation for y rounds to zero
+        check_convergence(MAX_AMP, 6, 2);
+        check_convergence(MAX_AMP, 1, 2);
+        check_convergence(MAX_AMP, u64::MAX, 1);
+    }
+
+    #[test]
+    fn test_bisect() {
+        let square_at_least =
+            |target: u64| move |x: U256| -> Result<bool, CurveError> { Ok(x * x >= target.into()) };
+        assert_eq!(
+            bisect(0.into(), 0.into(), square_at_least(1_000_000)),
+            Ok(1_000.into())
+        );
+        assert_eq!(
+            bisect(0.into(), 1_000_000.into(), square_at_least(1_000_001)),
+            Ok(1_001.into())
+        );
+        assert_eq!(bisect(0.into(), 5.into(), square_at_least(0)), Ok(0.into()));
+        assert_eq!(
+            bisect(10.into(), 5.into(), square_at_least(0)),
+            Ok(10.into())
+        );
+        assert_eq!(
+            bisect(0.into(), 1.into(), |x: U256| Ok(x >= U256::MAX)),
+            Err(CurveError::Overflow)
+        );
+
+        // Bisection agrees with Newton's method where the latter converges
+        let invariant = StableSwap::new(MAX_AMP, MAX_AMP, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amp = invariant.compute_amp_factor().unwrap();
+        let amounts_times_coins = [U256::from(2), U256::from(u64::MAX) * N_COINS];
+        let sum_x = U256::from(u64::MAX) + 1;
+        let newton = invariant
+            .newton_d(amp, &amounts_times_coins, sum_x, N_COINS)
+            .unwrap();
+        let bisected = invariant
+            .bisect_d(amp, &amounts_times_coins, sum_x, N_COINS)
+            .unwrap();
+        assert!(cmp::max(newton, bisected) - cmp::min(newton, bisected) <= 1.into());
+    }
+
+    #[test]
+    fn test_compute_mint_amount_for_deposit() {
+        let initial_amp_factor = MIN_AMP;
+        let target_amp_factor = MAX_AMP;
+        let current_ts = MIN_RAMP_DURATION / 2;
+        let start_ramp_ts = ZERO_TS;
+        let stop_ramp_ts = MIN_RAMP_DURATION;
+        let invariant = StableSwap::new(
+            initial_amp_factor,
+            target_amp_factor,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+
+        let deposit_amount_a = U256::from(u64::MAX);
+        let deposit_amount_b = U256::from(u64::MAX);
+        let swap_amount_a = U256::from(u64::MAX);
+        let swap_amount_b = U256::from(u64::MAX);
+        let pool_token_supply = U256::from(u64::MAX);
+        let actual_mint_amount = invariant
+            .compute_mint_amount_for_deposit(
+                deposit_amount_a,
+                deposit_amount_b,
+                swap_amount_a,
+                swap_amount_b,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap()
+            .mint_amount;
+        let expected_mint_amount = U256::from(u64::MAX);
+        assert_eq!(actual_mint_amount, expected_mint_amount);
+    }
+
+    proptest! {
+        #[test]
+        fn test_compute_withdraw_imbalance(
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            // Leave part of each balance, the imbalance fee can exceed a
+            // nearly drained one, see `test_compute_withdraw_imbalance_specific`
+            withdraw_bps in prop::collection::vec(0..9_000u64, MAX_N_COINS),
+        ) {
+            let n_coins = amounts.len();
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            let withdraw_amounts: Vec<u64> = amounts
+                .iter()
+                .zip(withdraw_bps.iter())
+                .map(|(amount, bps)| amount / 10_000 * bps)
+                .collect();
+            let pool_token_supply = swap.compute_d_n(&swap_amounts).unwrap();
+            let burn_amount = swap
+                .compute_withdraw_imbalance_n(
+                    &withdraw_amounts.iter().map(|a| U256::from(*a)).collect::<Vec<_>>(),
+                    &swap_amounts,
+                    pool_token_supply,
+                    &MODEL_FEES,
+                )
+                .unwrap();
+            let model = Model::new_with_pool_tokens(
+                amp_factor.into(),
+                amounts.iter().map(|a| u128::from(*a)).collect(),
+                n_coins as u128,
+                U256::to_u128(pool_token_supply).unwrap(),
+            );
+
+            assert_eq!(
+                burn_amount,
+                U256::from(
+                    model.sim_remove_liquidity_imbalance(
+                        withdraw_amounts.iter().map(|a| u128::from(*a)).collect()
+                    )
+                ) + U256::from(1)
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_precision_multipliers(
+            amp_factor in MIN_AMP..MAX_AMP,
+            decimals_diff_a in 0..=6u32,
+            decimals_diff_b in 0..=6u32,
+            amount_a in 1_000_000..1_000_000_000_000u64,
+            amount_b in 1_000_000..1_000_000_000_000u64,
+            source_amount in 1..1_000_000_000u64,
+        ) {
+            let multipliers = [10u64.pow(decimals_diff_a), 10u64.pow(decimals_diff_b)];
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .with_precision_multipliers(&multipliers);
+            let normalized = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts = [U256::from(amount_a), U256::from(amount_b)];
+            let xp = swap.xp(&swap_amounts).unwrap();
+            assert_eq!(xp, vec![swap_amounts[0] * multipliers[0], swap_amounts[1] * multipliers[1]]);
+
+            let mut model = Model::new(
+                amp_factor.into(),
+                vec![amount_a.into(), amount_b.into()],
+                N_COINS.into(),
+            );
+            model.target_prices = multipliers
+                .iter()
+                .map(|m| u128::from(*m) * 1_000_000_000_000_000_000)
+                .collect();
+            let d = swap.compute_d_n(&xp).unwrap();
+            assert_eq!(d, model.sim_d().into());
+
+            // Swap output is the model's normalized output, denormalized
+            let result = swap
+                .swap_to(source_amount.into(), swap_amounts[0], swap_amounts[1], &MODEL_FEES)
+                .unwrap();
+            let model_amount_swapped = U256::from(model.sim_exchange(
+                0,
+                1,
+                u128::from(source_amount) * u128::from(multipliers[0]),
+            )) / U256::from(multipliers[1]);
+            assert!(result.amount_swapped <= model_amount_swapped + 1);
+            assert!(result.amount_swapped + 1 >= model_amount_swapped);
+
+            // Deposits mint exactly as into an already normalized pool
+            let pool_token_supply = d;
+            assert_eq!(
+                swap.compute_mint_amount_for_deposit(
+                    source_amount.into(),
+                    source_amount.into(),
+                    swap_amounts[0],
+                    swap_amounts[1],
+                    pool_token_supply,
+                    &MODEL_FEES,
+                ).map(|deposit| deposit.mint_amount),
+                normalized.compute_mint_amount_for_deposit(
+                    U256::from(source_amount) * multipliers[0],
+                    U256::from(source_amount) * multipliers[1],
+                    xp[0],
+                    xp[1],
+                    pool_token_supply,
+                    &MODEL_FEES,
+                ).map(|deposit| deposit.mint_amount)
+            );
+
+            // Single sided withdrawals are denormalized, rounding down
+            let pool_token_amount = pool_token_supply / 10;
+            let result = swap
+                .compute_withdraw_one_n(pool_token_amount, pool_token_supply, 1, &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            let normalized_result = normalized
+                .compute_withdraw_one_n(pool_token_amount, pool_token_supply, 1, &xp, &MODEL_FEES)
+                .unwrap();
+            assert_eq!(result.amount_withdrawn, normalized_result.amount_withdrawn / multipliers[1]);
+            assert_eq!(result.amount_before_fee, normalized_result.amount_before_fee / multipliers[1]);
+
+            // Virtual price is measured in normalized units
+            assert_eq!(
+                swap.compute_virtual_price(swap_amounts[0], swap_amounts[1], pool_token_supply),
+                normalized.compute_virtual_price(xp[0], xp[1], pool_token_supply)
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_rates(
+            amp_factor in MIN_AMP..MAX_AMP,
+            rate in RATE_PRECISION..2 * RATE_PRECISION,
+            amount_a in 1_000_000..1_000_000_000_000u64,
+            amount_b in 1_000_000..1_000_000_000_000u64,
+            source_amount in 1..1_000_000_000u64,
+        ) {
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .with_rates(&[RATE_PRECISION, rate]);
+            let swap_amounts = [U256::from(amount_a), U256::from(amount_b)];
+            let xp = swap.xp(&swap_amounts).unwrap();
+
+            let mut model = Model::new(
+                amp_factor.into(),
+                vec![amount_a.into(), amount_b.into()],
+                N_COINS.into(),
+            );
+            model.target_prices = vec![RATE_PRECISION.into(), rate.into()];
+            assert_eq!(swap.compute_d_n(&xp).unwrap(), model.sim_d().into());
+
+            // Token A is swapped for token B at about 1 / rate
+            let result = swap
+                .swap_to(source_amount.into(), swap_amounts[0], swap_amounts[1], &MODEL_FEES)
+                .unwrap();
+            let model_amount_swapped = U256::from(model.sim_exchange(0, 1, source_amount.into()))
+                * RATE_PRECISION
+                / rate;
+            assert!(result.amount_swapped <= model_amount_swapped + 1);
+            assert!(result.amount_swapped + 1 >= model_amount_swapped);
+
+            // and token B for token A at about rate
+            let result = swap
+                .with_rates(&[rate, RATE_PRECISION])
+                .swap_to(source_amount.into(), swap_amounts[1], swap_amounts[0], &MODEL_FEES)
+                .unwrap();
+            let model_amount_swapped = model.sim_exchange(
+                1,
+                0,
+                u128::from(source_amount) * u128::from(rate) / u128::from(RATE_PRECISION),
+            );
+            assert!(result.amount_swapped <= U256::from(model_amount_swapped) + 1);
+            assert!(result.amount_swapped + 1 >= model_amount_swapped.into());
+        }
+    }
+
+    #[test]
+    fn test_compute_withdraw_imbalance_specific() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount = U256::from(1_000_000_000u64);
+        let pool_token_supply = swap.compute_d(amount, amount).unwrap();
+
+        // Balanced withdrawal pays no fee
+        let burn_amount = swap
+            .compute_withdraw_imbalance(
+                amount / 10,
+                amount / 10,
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert_eq!(burn_amount, pool_token_supply / 10 + 1);
+
+        // Imbalanced withdrawal burns more than its share of D
+        let burn_amount = swap
+            .compute_withdraw_imbalance(
+                amount / 5,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert!(burn_amount > pool_token_supply / 10 + 1);
+
+        // and pays the imbalance fee in both tokens
+        let preview = swap
+            .preview_withdraw_imbalance(
+                amount / 5,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert_eq!(preview.burn_amount, burn_amount);
+        assert_eq!(preview.fees.len(), 2);
+        assert!(preview.fees.iter().all(|fee| !fee.is_zero()));
+
+        // Cannot withdraw more than the reserves
+        assert_eq!(
+            swap.compute_withdraw_imbalance(
+                amount + 1,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            ),
+            Err(CurveError::Underflow)
+        );
+
+        // Imbalance fee exceeds what is left of a nearly drained balance
+        assert_eq!(
+            swap.compute_withdraw_imbalance(
+                amount - 100,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            ),
+            Err(CurveError::Underflow)
+        );
+    }
+
+    #[test]
+    fn test_curve_math_with_random_inputs() {
+        for _ in 0..100 {
+            let mut rng = rand::thread_rng();
+
+            let amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
+            let amount_a: u64 = rng.gen_range(1, u64::MAX);
+            let amount_b: u64 = rng.gen_range(1, u64::MAX);
+            let start_ramp_ts: i64 = rng.gen_range(ZERO_TS, i64::MAX);
+            let stop_ramp_ts: i64 = rng.gen_range(start_ramp_ts, i64::MAX);
+            let current_ts: i64 = rng.gen_range(start_ramp_ts, stop_ramp_ts);
+            println!("testing curve_math_with_random_inputs:");
+            println!(
+                "current_ts: {}, start_ramp_ts: {}, stop_ramp_ts: {}",
+                current_ts, start_ramp_ts, stop_ramp_ts
+            );
+            println!(
+                "amp_factor: {}, amount_a: {}, amount_b: {}",
+                amp_factor, amount_a, amount_b,
+            );
+
+            let model = Model::new(
+                amp_factor.into(),
+                vec![amount_a.into(), amount_b.into()],
+                N_COINS.into(),
+            );
+            let d = check_d(
+                &model,
+                amount_a.into(),
+                amount_b.into(),
+                current_ts,
+                start_ramp_ts,
+                stop_ramp_ts,
+            );
+            let amount_x: u64 = rng.gen_range(0, amount_a);
+
+            println!("amount_x: {}", amount_x);
+            check_y(&model, amount_x, d, current_ts, start_ramp_ts, stop_ramp_ts);
+        }
+    }
+
+    fn check_swap(
+        initial_amp_factor: u64,
+        target_amp_factor: u64,
+        current_ts: i64,
+        start_ramp_ts: i64,
+        stop_ramp_ts: i64,
+        source_amount: u64,
+        swap_source_amount: u64,
+        swap_destination_amount: u64,
+    ) {
+        let swap = StableSwap::new(
+            initial_amp_factor,
+            target_amp_factor,
+            current_ts,
+            start_ramp_ts,
+            stop_ramp_ts,
+        );
+        let result = swap
+            .swap_to(
+                source_amount.into(),
+                swap_source_amount.into(),
+                swap_destination_amount.into(),
+                &MODEL_FEES,
+            )
+            .unwrap();
+        let model = Model::new(
+            U256::to_u128(swap.compute_amp_factor().unwrap()).unwrap(),
+            vec![swap_source_amount.into(), swap_destination_amount.into()],
+            N_COINS.into(),
+        );
+
+        assert_eq!(
+            result.amount_swapped,
+            model.sim_exchange(0, 1, source_amount.into()).into()
+        );
+        assert_eq!(
+            result.new_source_amount,
+            U256::from(swap_source_amount) + U256::from(source_amount)
+        );
+        assert_eq!(
+            result.new_destination_amount,
+            U256::from(swap_destination_amount) - result.amount_swapped
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_swap_calculation(
+            current_ts in ZERO_TS..i64::MAX,
+            amp_factor in MIN_AMP..MAX_AMP,
+            source_amount in 0..u64::MAX,
+            swap_source_amount in 0..u64::MAX,
+            swap_destination_amount in 0..u64::MAX,
+        ) {
+            let start_ramp_ts = cmp::max(0, current_ts - MIN_RAMP_DURATION);
+            let stop_ramp_ts = cmp::min(i64::MAX, current_ts + MIN_RAMP_DURATION);
+            check_swap(
+                amp_factor,
+                amp_factor,
+                current_ts,
+                start_ramp_ts,
+                stop_ramp_ts,
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_calculation_with_random_inputs() {
+        for _ in 0..100 {
+            let mut rng = rand::thread_rng();
+
+            let initial_amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
+            let target_amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
+            let start_ramp_ts: i64 = rng.gen_range(ZERO_TS, i64::MAX);
+            let stop_ramp_ts: i64 = rng.gen_range(start_ramp_ts, i64::MAX);
+            let current_ts: i64 = rng.gen_range(start_ramp_ts, stop_ramp_ts);
+            let source_amount: u64 = rng.gen_range(1, u64::MAX);
+            let swap_source_amount: u64 = rng.gen_range(1, u64::MAX);
+            let swap_destination_amount: u64 = rng.gen_range(1, u64::MAX);
+            println!("testing swap_calculation_with_random_inputs:");
+            println!(
+                "current_ts: {}, start_ramp_ts: {}, stop_ramp_ts: {}",
+                current_ts, start_ramp_ts, stop_ramp_ts
+            );
+            println!(
+                "initial_amp_factor: {}, target_amp_factor: {}, source_amount: {}, swap_source_amount: {}, swap_destination_amount: {}",
+                initial_amp_factor, target_amp_factor, source_amount, swap_source_amount, swap_destination_amount
+            );
+
+            check_swap(
                 initial_amp_factor,
                 target_amp_factor,
                 current_ts,
                 start_ramp_ts,
                 stop_ramp_ts,
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
             );
-            let expected = if tick >= MIN_RAMP_DURATION {
-                target_amp_factor
-            } else {
-                initial_amp_factor - (amp_range * tick as u64 / MIN_RAMP_DURATION as u64)
-            };
-            assert_eq!(invariant.compute_amp_factor().unwrap(), expected.into());
         }
     }
 
-    fn check_d(
-        model: &Model,
-        amount_a: u64,
-        amount_b: u64,
-        current_ts: i64,
-        start_ramp_ts: i64,
-        stop_ramp_ts: i64,
-    ) -> U256 {
-        let swap = StableSwap {
-            initial_amp_factor: U256::from(model.amp_factor),
-            target_amp_factor: U256::from(model.amp_factor),
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
-        };
-        let d = swap
-            .compute_d(U256::from(amount_a), U256::from(amount_b))
-            .unwrap();
-        assert_eq!(d, model.sim_d().into());
-        d
+    proptest! {
+        #[test]
+        fn test_swap_calculation_n_coins(
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            source_amount in 0..1_000_000_000_000_000u64,
+        ) {
+            let n_coins = amounts.len();
+            let (i, j) = (0, n_coins - 1);
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            let result = swap
+                .swap_to_n(i, j, source_amount.into(), &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            let model = Model::new(
+                amp_factor.into(),
+                amounts.iter().map(|a| u128::from(*a)).collect(),
+                n_coins as u128,
+            );
+
+            assert_eq!(
+                result.amount_swapped,
+                model.sim_exchange(i as u128, j as u128, source_amount.into()).into()
+            );
+            assert_eq!(
+                result.new_source_amount,
+                swap_amounts[i] + U256::from(source_amount)
+            );
+            assert_eq!(
+                result.new_destination_amount,
+                swap_amounts[j] - result.amount_swapped
+            );
+        }
     }
 
-    fn check_y(
-        model: &Model,
-        x: u64,
-        d: U256,
-        current_ts: i64,
-        start_ramp_ts: i64,
-        stop_ramp_ts: i64,
-    ) {
-        let swap = StableSwap {
-            initial_amp_factor: U256::from(model.amp_factor),
-            target_amp_factor: U256::from(model.amp_factor),
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
-        };
+    proptest! {
+        #[test]
+        fn test_swap_to_exact_out(
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            amount_out in 1..1_000_000u64,
+        ) {
+            let (i, j) = (0, amounts.len() - 1);
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            let (amount_in, result) = swap
+                .swap_to_exact_out_n(i, j, amount_out.into(), &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            assert_eq!(result.amount_swapped, amount_out.into());
+            assert_eq!(result.new_source_amount, swap_amounts[i] + amount_in);
+
+            // Swapping the quoted input delivers at least the requested output, and
+            // no more than rounding allows at the current marginal rate
+            let forward = swap
+                .swap_to_n(i, j, amount_in, &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            let precision = U256::from(RATE_PRECISION);
+            let rate = swap.compute_marginal_rate_n(i, j, &swap_amounts).unwrap();
+            assert!(forward.amount_swapped >= amount_out.into());
+            assert!(
+                forward.amount_swapped * precision
+                    <= (U256::from(amount_out) + U256::from(2)) * precision + rate * U256::from(2)
+            );
+
+            // The pool never loses value
+            let mut new_swap_amounts = swap_amounts.clone();
+            new_swap_amounts[i] = result.new_source_amount;
+            new_swap_amounts[j] = result.new_destination_amount;
+            assert!(swap.compute_d_n(&new_swap_amounts).unwrap() >= swap.compute_d_n(&swap_amounts).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_swap_to_exact_out_specific() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount = U256::from(1_000_000_000u64);
+
+        let (amount_in, result) = swap
+            .swap_to_exact_out(100_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        let forward = swap
+            .swap_to(amount_in, amount, amount, &MODEL_FEES)
+            .unwrap();
+        assert_eq!(result.amount_swapped, 100_000.into());
+        assert_eq!(forward.amount_swapped, 100_002.into());
+
+        // Cannot take out the whole reserve
         assert_eq!(
-            swap.compute_y(x.into(), d).unwrap(),
-            model.sim_y(0, 1, x.into()).into()
-        )
+            swap.swap_to_exact_out(amount, amount, amount, &MODEL_FEES)
+                .err(),
+            Some(CurveError::ZeroLiquidity)
+        );
     }
 
     proptest! {
         #[test]
-        fn test_curve_math(
-            current_ts in ZERO_TS..i64::MAX,
+        fn test_dynamic_fees(
             amp_factor in MIN_AMP..MAX_AMP,
-            amount_a in 1..u64::MAX,    // Start at 1 to prevent divide by 0 when computing d
-            amount_b in 1..u64::MAX,    // Start at 1 to prevent divide by 0 when computing d
+            amount_a in 1_000_000..1_000_000_000_000_000u64,
+            amount_b in 1_000_000..1_000_000_000_000_000u64,
+            source_amount in 1..1_000_000_000u64,
+            offpeg_fee_multiplier in OFFPEG_FEE_MULTIPLIER_DENOMINATOR..=10 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
         ) {
-            let start_ramp_ts = cmp::max(0, current_ts - MIN_RAMP_DURATION);
-            let stop_ramp_ts = cmp::min(i64::MAX, current_ts + MIN_RAMP_DURATION);
-            let model = Model::new(amp_factor.into(), vec![amount_a.into(), amount_b.into()], N_COINS.into());
-            let d = check_d(&model, amount_a, amount_b, current_ts, start_ramp_ts, stop_ramp_ts);
-            check_y(&model, amount_a, d, current_ts, start_ramp_ts, stop_ramp_ts);
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts = [U256::from(amount_a), U256::from(amount_b)];
+            let fees = Fees {
+                offpeg_fee_multiplier,
+                ..MODEL_FEES
+            };
+
+            // Swaps pay the flat fee up to the full multiplier on the same output
+            let flat = swap.swap_to(source_amount.into(), swap_amounts[0], swap_amounts[1], &MODEL_FEES).unwrap();
+            let dynamic = swap.swap_to(source_amount.into(), swap_amounts[0], swap_amounts[1], &fees).unwrap();
+            assert_eq!(dynamic.amount_swapped + dynamic.fee, flat.amount_swapped + flat.fee);
+            assert!(dynamic.fee >= flat.fee);
+            assert!(dynamic.fee <= flat.fee * offpeg_fee_multiplier / OFFPEG_FEE_MULTIPLIER_DENOMINATOR + 1);
+
+            // Exact output quotes cover the dynamic fee
+            let amount_out = dynamic.amount_swapped;
+            if amount_out > 0.into() {
+                let (amount_in, result) = swap
+                    .swap_to_exact_out(amount_out, swap_amounts[0], swap_amounts[1], &fees)
+                    .unwrap();
+                assert_eq!(result.amount_swapped, amount_out);
+                let forward = swap.swap_to(amount_in, swap_amounts[0], swap_amounts[1], &fees).unwrap();
+                assert!(forward.amount_swapped >= amount_out);
+            }
+
+            // Deposits mint and withdrawals pay out no more than with flat fees
+            let pool_token_supply = swap.compute_d(swap_amounts[0], swap_amounts[1]).unwrap();
+            let deposit = [U256::from(source_amount), U256::zero()];
+            assert!(
+                swap.compute_mint_amount_for_deposit(deposit[0], deposit[1], swap_amounts[0], swap_amounts[1], pool_token_supply, &fees).unwrap().mint_amount
+                    <= swap.compute_mint_amount_for_deposit(deposit[0], deposit[1], swap_amounts[0], swap_amounts[1], pool_token_supply, &MODEL_FEES).unwrap().mint_amount
+            );
+            let pool_token_amount = pool_token_supply / 100;
+            let result = swap.compute_withdraw_one(pool_token_amount, pool_token_supply, swap_amounts[0], swap_amounts[1], &fees).unwrap();
+            let flat_result = swap.compute_withdraw_one(pool_token_amount, pool_token_supply, swap_amounts[0], swap_amounts[1], &MODEL_FEES).unwrap();
+            assert!(result.amount_withdrawn <= flat_result.amount_withdrawn);
         }
     }
 
     #[test]
-    fn test_curve_math_specific() {
-        // Specific cases
-        let current_ts = ZERO_TS;
-        let start_ramp_ts = ZERO_TS;
-        let stop_ramp_ts = ZERO_TS;
-        let model_no_balance = Model::new(1, vec![0, 0], N_COINS.into());
-        check_d(
-            &model_no_balance,
-            0,
-            0,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+    fn test_dynamic_fees_specific() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let fees = Fees {
+            offpeg_fee_multiplier: 10 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+            ..MODEL_FEES
+        };
+        let amount = U256::from(1_000_000_000u64);
+
+        // Swaps at peg pay about the flat fee
+        let flat = swap
+            .swap_to(1_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        let dynamic = swap.swap_to(1_000.into(), amount, amount, &fees).unwrap();
+        assert!(dynamic.fee <= flat.fee + 1);
+
+        // A 19:1 pool pays 10 / (9 * 4 * 19 / 20 ** 2 + 1) = 3.69 times the flat fee
+        let heavy = amount * 19 / 10;
+        let light = amount / 10;
+        let flat = swap
+            .swap_to(1_000_000.into(), heavy, light, &MODEL_FEES)
+            .unwrap();
+        let dynamic = swap.swap_to(1_000_000.into(), heavy, light, &fees).unwrap();
+        assert!(dynamic.fee * 100 >= flat.fee * 368);
+        assert!(dynamic.fee * 100 <= flat.fee * 370);
+    }
+
+    #[test]
+    fn test_virtual_price() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let precision = U256::from(RATE_PRECISION);
+        let amount = U256::from(1_000_000_000u64);
+
+        // Pool initialized at D has a virtual price of one
+        let d = swap.compute_d(amount, amount).unwrap();
+        assert_eq!(swap.compute_virtual_price(amount, amount, d), Ok(precision));
+
+        // Fees retained in the pool grow the virtual price
+        let result = swap
+            .swap_to(100_000_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        let virtual_price = swap
+            .compute_virtual_price(result.new_source_amount, result.new_destination_amount, d)
+            .unwrap();
+        assert!(virtual_price > precision);
+        assert_eq!(
+            swap.compute_virtual_price_n(
+                &[result.new_source_amount, result.new_destination_amount],
+                d
+            ),
+            Ok(virtual_price)
         );
 
-        let amount_a: u64 = 10461290657254161082;
-        let amount_b: u64 = 12507100355549196829;
-        let model = Model::new(1188, vec![amount_a.into(), amount_b.into()], N_COINS.into());
-        let d = check_d(
-            &model,
-            amount_a,
-            amount_b,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+        // Virtual price follows the ramped amp factor
+        let ramping = StableSwap::new(100, 1000, 50, 0, 100);
+        let ramped_d = ramping
+            .compute_d(result.new_source_amount, result.new_destination_amount)
+            .unwrap();
+        assert_eq!(
+            ramping.compute_virtual_price(
+                result.new_source_amount,
+                result.new_destination_amount,
+                d
+            ),
+            Ok(ramped_d * precision / d)
         );
-        let amount_x: u64 = 2045250484898639148;
-        check_y(
-            &model,
-            amount_x.into(),
-            d,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+
+        assert_eq!(
+            swap.compute_virtual_price(amount, amount, 0.into()),
+            Err(CurveError::ZeroLiquidity)
         );
+    }
 
-        let amount_a: u64 = 8625384579714585493;
-        let amount_b: u64 = 4925481879098236733;
-        let model = Model::new(9, vec![amount_a.into(), amount_b.into()], N_COINS.into());
-        let d = check_d(
-            &model,
-            amount_a,
-            amount_b,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+    const ZERO_FEES: Fees = Fees {
+        admin_trade_fee_numerator: 0,
+        admin_trade_fee_denominator: 1,
+        admin_withdraw_fee_numerator: 0,
+        admin_withdraw_fee_denominator: 1,
+        trade_fee_numerator: 0,
+        trade_fee_denominator: 1,
+        withdraw_fee_numerator: 0,
+        withdraw_fee_denominator: 1,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
+    };
+
+    #[test]
+    fn test_marginal_rate() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let precision = U256::from(RATE_PRECISION);
+
+        // Balanced pool trades at par
+        let amount = U256::from(1_000_000_000u64);
+        assert_eq!(swap.compute_marginal_rate(amount, amount), Ok(precision));
+        assert_eq!(
+            swap.compute_marginal_rate_with_fee(amount, amount, &MODEL_FEES),
+            Ok(precision
+                - precision * U256::from(MODEL_FEE_NUMERATOR) / U256::from(MODEL_FEE_DENOMINATOR))
         );
-        let amount_x: u64 = 8155777549389559399;
-        check_y(
-            &model,
-            amount_x.into(),
-            d,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+        assert_eq!(
+            swap.compute_marginal_rate_n(1, 2, &[amount, amount, amount]),
+            Ok(precision)
+        );
+
+        // Scarce destination token is more expensive
+        let rate = swap
+            .compute_marginal_rate(U256::from(2_000_000_000u64), amount)
+            .unwrap();
+        assert!(rate < precision);
+        let rate = swap
+            .compute_marginal_rate(amount, U256::from(2_000_000_000u64))
+            .unwrap();
+        assert!(rate > precision);
+
+        // Off peg, the next unit pays the dynamic fee at the current balances
+        let offpeg_fees = Fees {
+            offpeg_fee_multiplier: 5 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+            ..MODEL_FEES
+        };
+        let rate_with_fee = swap
+            .compute_marginal_rate_with_fee(amount, U256::from(2_000_000_000u64), &offpeg_fees)
+            .unwrap();
+        let fee = offpeg_fees
+            .dynamic_fee(
+                MODEL_FEES.trade_fee(rate).unwrap(),
+                amount,
+                U256::from(2_000_000_000u64),
+            )
+            .unwrap();
+        assert_eq!(rate_with_fee, rate - fee);
+        assert!(fee > MODEL_FEES.trade_fee(rate).unwrap());
+        // Largest reserves at the highest amplification
+        let swap = StableSwap::new(MAX_AMP, MAX_AMP, ZERO_TS, ZERO_TS, ZERO_TS);
+        let max_amount = U256::from(u64::MAX);
+        assert_eq!(
+            swap.compute_marginal_rate(max_amount, max_amount),
+            Ok(precision)
+        );
+        for small_amount in &[1u64, 1_000, 1_000_000, u64::MAX / 1_000] {
+            let small_amount = U256::from(*small_amount);
+            let rate = swap
+                .compute_marginal_rate(max_amount, small_amount)
+                .unwrap();
+            let rate_back = swap
+                .compute_marginal_rate(small_amount, max_amount)
+                .unwrap();
+            assert!(rate < precision);
+            assert!(rate_back > precision);
+            // Rates in opposite directions are reciprocal up to truncation
+            let product = rate * rate_back;
+            assert!(product <= precision * precision);
+            assert!(product + rate + rate_back >= precision * precision);
+        }
+        let swap_amounts = [max_amount, max_amount, U256::from(1u64)];
+        assert_eq!(
+            swap.compute_marginal_rate_n(0, 1, &swap_amounts),
+            Ok(precision)
+        );
+        assert!(swap.compute_marginal_rate_n(1, 2, &swap_amounts).unwrap() < precision);
+        assert!(swap.compute_marginal_rate_n(2, 1, &swap_amounts).unwrap() > precision);
+
+        // Largest normalized reserves with a 12 decimal spread
+        let multiplier = 1_000_000_000_000u64;
+        let swap = StableSwap::new(MAX_AMP, MAX_AMP, ZERO_TS, ZERO_TS, ZERO_TS)
+            .with_precision_multipliers(&[multiplier, 1]);
+        let scaled_amount = max_amount * U256::from(multiplier);
+        assert_eq!(
+            swap.compute_marginal_rate(max_amount, scaled_amount),
+            Ok(precision * U256::from(multiplier))
+        );
+        let rate = swap
+            .compute_marginal_rate(max_amount, scaled_amount / 1_000)
+            .unwrap();
+        assert!(rate < precision * U256::from(multiplier));
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+
+        // Invalid inputs
+        assert_eq!(
+            swap.compute_marginal_rate(0.into(), amount),
+            Err(CurveError::ZeroLiquidity)
+        );
+        assert_eq!(
+            swap.compute_marginal_rate_n(0, 0, &[amount, amount]),
+            Err(CurveError::InvalidInput)
+        );
+        assert_eq!(
+            swap.compute_marginal_rate_n(0, 2, &[amount, amount]),
+            Err(CurveError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn test_try_mul_div() {
+        let max = U256::MAX;
+        assert_eq!(try_mul_div(6.into(), 4.into(), 3.into()), Ok(8.into()));
+        assert_eq!(
+            try_mul_div(1.into(), 1.into(), 0.into()),
+            Err(CurveError::DivisionByZero)
+        );
+        // Overflowing products are computed at reduced precision
+        assert_eq!(try_mul_div(max, 4.into(), max), Ok(4.into()));
+        assert_eq!(
+            try_mul_div(max / 3, RATE_PRECISION.into(), max / 2),
+            Ok(U256::from(RATE_PRECISION) * 2 / 3)
         );
     }
 
     #[test]
-    fn test_compute_mint_amount_for_deposit() {
-        let initial_amp_factor = MIN_AMP;
-        let target_amp_factor = MAX_AMP;
-        let current_ts = MIN_RAMP_DURATION / 2;
-        let start_ramp_ts = ZERO_TS;
-        let stop_ramp_ts = MIN_RAMP_DURATION;
-        let invariant = StableSwap::new(
-            initial_amp_factor,
-            target_amp_factor,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
+    fn test_swap_quote() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let precision = U256::from(RATE_PRECISION);
+        let amount = U256::from(1_000_000_000u64);
+
+        let quote = swap
+            .quote_swap(1_000_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        let result = &quote.result;
+        assert_eq!(result.amount_before_fee, result.amount_swapped + result.fee);
+        assert_eq!(result.fee, result.admin_fee + result.lp_fee);
+        assert_eq!(quote.spot_price_before, precision);
+        assert!(quote.spot_price_after < quote.spot_price_before);
+        assert_eq!(
+            quote.execution_price,
+            result.amount_swapped * precision / U256::from(1_000_000)
+        );
+        let net_spot_price = precision - MODEL_FEES.trade_fee(precision).unwrap();
+        assert!(quote.execution_price <= net_spot_price);
+        assert_eq!(
+            quote.price_impact,
+            (net_spot_price - quote.execution_price) * precision / net_spot_price
         );
 
-        let deposit_amount_a = U256::from(u64::MAX);
-        let deposit_amount_b = U256::from(u64::MAX);
-        let swap_amount_a = U256::from(u64::MAX);
-        let swap_amount_b = U256::from(u64::MAX);
-        let pool_token_supply = U256::from(u64::MAX);
-        let actual_mint_amount = invariant
+        // Larger trades move the price further
+        let large_quote = swap
+            .quote_swap(100_000_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        assert!(large_quote.price_impact > quote.price_impact);
+        assert!(large_quote.spot_price_after < quote.spot_price_after);
+
+        // The quote trades exactly as the swap
+        let result = swap
+            .swap_to(100_000_000.into(), amount, amount, &MODEL_FEES)
+            .unwrap();
+        assert_eq!(large_quote.result.amount_swapped, result.amount_swapped);
+        assert_eq!(
+            large_quote.result.new_destination_amount,
+            result.new_destination_amount
+        );
+
+        assert!(matches!(
+            swap.quote_swap(0.into(), amount, amount, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_one_results() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount = U256::from(1_000_000_000u64);
+        let pool_token_supply = amount * 2;
+
+        // Balanced deposits pay no imbalance fee, one sided ones do
+        let deposit = swap
             .compute_mint_amount_for_deposit(
-                deposit_amount_a,
-                deposit_amount_b,
-                swap_amount_a,
-                swap_amount_b,
+                amount,
+                amount,
+                amount,
+                amount,
                 pool_token_supply,
                 &MODEL_FEES,
             )
             .unwrap();
-        let expected_mint_amount = U256::from(u64::MAX);
-        assert_eq!(actual_mint_amount, expected_mint_amount);
+        assert_eq!(deposit.mint_amount, deposit.mint_amount_before_fee);
+        let deposit = swap
+            .compute_mint_amount_for_deposit(
+                amount,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert!(deposit.mint_amount < deposit.mint_amount_before_fee);
+
+        let withdrawal = swap
+            .compute_withdraw_one(amount / 10, pool_token_supply, amount, amount, &MODEL_FEES)
+            .unwrap();
+        assert!(withdrawal.amount_withdrawn < withdrawal.amount_before_fee);
+        assert_eq!(
+            withdrawal.fee,
+            withdrawal.amount_before_fee - withdrawal.amount_withdrawn
+        );
+        assert_eq!(
+            withdrawal.admin_fee,
+            MODEL_FEES.admin_trade_fee(withdrawal.fee).unwrap()
+        );
+        assert_eq!(withdrawal.fee, withdrawal.admin_fee + withdrawal.lp_fee);
     }
 
     #[test]
-    fn test_curve_math_with_random_inputs() {
-        for _ in 0..100 {
-            let mut rng = rand::thread_rng();
+    fn test_preview_deposit() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount = U256::from(1_000_000_000u64);
+        let pool_token_supply = amount * 2;
 
-            let amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
-            let amount_a: u64 = rng.gen_range(1, u64::MAX);
-            let amount_b: u64 = rng.gen_range(1, u64::MAX);
-            let start_ramp_ts: i64 = rng.gen_range(ZERO_TS, i64::MAX);
-            let stop_ramp_ts: i64 = rng.gen_range(start_ramp_ts, i64::MAX);
-            let current_ts: i64 = rng.gen_range(start_ramp_ts, stop_ramp_ts);
-            println!("testing curve_math_with_random_inputs:");
-            println!(
-                "current_ts: {}, start_ramp_ts: {}, stop_ramp_ts: {}",
-                current_ts, start_ramp_ts, stop_ramp_ts
-            );
-            println!(
-                "amp_factor: {}, amount_a: {}, amount_b: {}",
-                amp_factor, amount_a, amount_b,
-            );
+        // Balanced deposits pay no imbalance fee and double the pool's size
+        let preview = swap
+            .preview_deposit(
+                amount,
+                amount,
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert_eq!(preview.fees, vec![U256::zero(), U256::zero()]);
+        assert_eq!(preview.d_0, pool_token_supply);
+        assert_eq!(preview.d_1, preview.d_2);
+        assert_eq!(preview.result.mint_amount, pool_token_supply);
+        assert_eq!(preview.pool_share, U256::from(RATE_PRECISION / 2));
 
-            let model = Model::new(
-                amp_factor.into(),
-                vec![amount_a.into(), amount_b.into()],
-                N_COINS.into(),
-            );
-            let d = check_d(
-                &model,
-                amount_a.into(),
-                amount_b.into(),
-                current_ts,
-                start_ramp_ts,
-                stop_ramp_ts,
-            );
-            let amount_x: u64 = rng.gen_range(0, amount_a);
+        // One sided deposits pay imbalance fees in both tokens
+        let preview = swap
+            .preview_deposit(
+                amount,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert!(preview.fees.iter().all(|fee| !fee.is_zero()));
+        assert!(preview.d_0 < preview.d_2);
+        assert!(preview.d_2 < preview.d_1);
+        let deposit = swap
+            .compute_mint_amount_for_deposit(
+                amount,
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert_eq!(preview.result.mint_amount, deposit.mint_amount);
+        assert_eq!(
+            preview.pool_share,
+            deposit.mint_amount * RATE_PRECISION / (pool_token_supply + deposit.mint_amount)
+        );
 
-            println!("amount_x: {}", amount_x);
-            check_y(&model, amount_x, d, current_ts, start_ramp_ts, stop_ramp_ts);
+        // Deposits must increase the invariant
+        assert!(matches!(
+            swap.preview_deposit(
+                0.into(),
+                0.into(),
+                amount,
+                amount,
+                pool_token_supply,
+                &MODEL_FEES
+            ),
+            Err(CurveError::DepositTooSmall)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn test_marginal_rate_bounds_swap(
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            source_amount in 1..1_000_000_000_000u64,
+        ) {
+            let (i, j) = (0, amounts.len() - 1);
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let precision = U256::from(RATE_PRECISION);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            let rate_before = swap.compute_marginal_rate_n(i, j, &swap_amounts).unwrap();
+            let result = swap
+                .swap_to_n(i, j, source_amount.into(), &swap_amounts, &ZERO_FEES)
+                .unwrap();
+            prop_assume!(result.amount_swapped > 0.into());
+            let mut new_swap_amounts = swap_amounts.clone();
+            new_swap_amounts[i] = result.new_source_amount;
+            new_swap_amounts[j] = result.new_destination_amount;
+            let rate_after = swap.compute_marginal_rate_n(i, j, &new_swap_amounts).unwrap();
+
+            // The average rate of a trade lies between the marginal rates before and after it,
+            // up to the rounding of y and D
+            let dx = U256::from(source_amount);
+            let average = result.amount_swapped * precision;
+            let tolerance = precision * U256::from(2);
+            assert!(average <= rate_before * dx + tolerance);
+            assert!(rate_after * dx <= average + tolerance);
+            assert!(rate_after <= rate_before);
+
+            // Rates in opposite directions are reciprocal up to truncation
+            let rate_back = swap.compute_marginal_rate_n(j, i, &swap_amounts).unwrap();
+            let product = rate_before * rate_back;
+            assert!(product <= precision * precision);
+            assert!(product + rate_before + rate_back >= precision * precision);
         }
     }
 
-    fn check_swap(
-        initial_amp_factor: u64,
-        target_amp_factor: u64,
-        current_ts: i64,
-        start_ramp_ts: i64,
-        stop_ramp_ts: i64,
-        source_amount: u64,
-        swap_source_amount: u64,
-        swap_destination_amount: u64,
-    ) {
-        let swap = StableSwap::new(
-            initial_amp_factor,
-            target_amp_factor,
-            current_ts,
-            start_ramp_ts,
-            stop_ramp_ts,
-        );
-        let result = swap
-            .swap_to(
-                source_amount.into(),
-                swap_source_amount.into(),
-                swap_destination_amount.into(),
-                &MODEL_FEES,
-            )
+    /// Check that the arbitrage trade leaves the net rate along the curve at or
+    /// above the target, and one more unit would push it below
+    fn check_arbitrage_amount(
+        swap: &StableSwap,
+        target_rate: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> (U256, SwapResult) {
+        let (i, j) = (0, swap_amounts.len() - 1);
+        let (amount_in, result) = swap
+            .compute_arbitrage_amount_n(i, j, target_rate, swap_amounts, fees)
+            .unwrap()
             .unwrap();
-        let model = Model::new(
-            U256::to_u128(swap.compute_amp_factor().unwrap()).unwrap(),
-            vec![swap_source_amount.into(), swap_destination_amount.into()],
-            N_COINS.into(),
-        );
+        let xp = swap.xp(swap_amounts).unwrap();
+        let d = swap.compute_d_n(&xp).unwrap();
+        let rate_after = |source_amount: U256| {
+            let mut new_xp = xp.clone();
+            new_xp[i] = xp[i] + swap.normalize(i, source_amount).unwrap();
+            new_xp[j] = swap.compute_y_n(i, j, new_xp[i], &xp, d).unwrap();
+            let rate = swap.compute_marginal_rate_xp(i, j, &new_xp, d).unwrap();
+            rate - fees
+                .dynamic_fee(fees.trade_fee(rate).unwrap(), new_xp[i], new_xp[j])
+                .unwrap()
+        };
+        assert!(rate_after(amount_in) >= target_rate);
+        assert!(rate_after(amount_in + 1) < target_rate);
 
-        assert_eq!(
-            result.amount_swapped,
-            model.sim_exchange(0, 1, source_amount.into()).into()
+        // The pool keeps the fees, so its net rate after the trade is no lower
+        let mut new_swap_amounts = swap_amounts.to_vec();
+        new_swap_amounts[i] = result.new_source_amount;
+        new_swap_amounts[j] = result.new_destination_amount;
+        assert!(
+            swap.compute_marginal_rate_with_fee_n(i, j, &new_swap_amounts, fees)
+                .unwrap()
+                >= target_rate
         );
-        assert_eq!(
-            result.new_source_amount,
-            U256::from(swap_source_amount) + U256::from(source_amount)
+
+        // The trade as a whole beats the target rate
+        let precision = U256::from(RATE_PRECISION);
+        assert!(result.amount_swapped * precision >= amount_in * target_rate);
+        (amount_in, result)
+    }
+
+    #[test]
+    fn test_arbitrage_amount() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let precision = U256::from(RATE_PRECISION);
+        let amount = U256::from(1_000_000_000u64);
+
+        // A balanced pool pays less than par net of fees, and nothing is
+        // worth selling at or above its rate
+        assert!(matches!(
+            swap.compute_arbitrage_amount(precision, amount, amount, &MODEL_FEES),
+            Ok(None)
+        ));
+        let rate = swap
+            .compute_marginal_rate_with_fee(amount, amount, &MODEL_FEES)
+            .unwrap();
+        assert!(matches!(
+            swap.compute_arbitrage_amount(rate, amount, amount, &MODEL_FEES),
+            Ok(None)
+        ));
+
+        // Without fees, arbitrage at par rebalances a lopsided pool
+        let (amount_in, result) =
+            check_arbitrage_amount(&swap, precision, &[amount, amount * 3], &ZERO_FEES);
+        let imbalance = if result.new_source_amount > result.new_destination_amount {
+            result.new_source_amount - result.new_destination_amount
+        } else {
+            result.new_destination_amount - result.new_source_amount
+        };
+        assert!(imbalance <= 2.into());
+        assert!(amount_in < amount);
+
+        // Fees stop the trade short of the target's balance
+        let (amount_in_with_fees, _) =
+            check_arbitrage_amount(&swap, precision, &[amount, amount * 3], &MODEL_FEES);
+        assert!(amount_in_with_fees < amount_in);
+
+        // Selling into the pool down to a lower price trades more
+        let (amount_in_cheap, _) = check_arbitrage_amount(
+            &swap,
+            precision * 9 / 10,
+            &[amount, amount * 3],
+            &MODEL_FEES,
         );
-        assert_eq!(
-            result.new_destination_amount,
-            U256::from(swap_destination_amount) - result.amount_swapped
+        assert!(amount_in_cheap > amount_in_with_fees);
+
+        // Off-peg fees rise as the pool is pushed further off peg, so the
+        // trade stops shorter still
+        let offpeg_fees = Fees {
+            offpeg_fee_multiplier: 5 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+            ..MODEL_FEES
+        };
+        let (amount_in_offpeg, _) = check_arbitrage_amount(
+            &swap,
+            precision * 9 / 10,
+            &[amount, amount * 3],
+            &offpeg_fees,
         );
+        assert!(amount_in_offpeg < amount_in_cheap);
+
+        // Invalid inputs
+        assert!(matches!(
+            swap.compute_arbitrage_amount(0.into(), amount, amount, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+        assert!(matches!(
+            swap.compute_arbitrage_amount_n(0, 0, precision, &[amount, amount], &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
     }
 
     proptest! {
         #[test]
-        fn test_swap_calculation(
-            current_ts in ZERO_TS..i64::MAX,
+        fn test_arbitrage_amount_reaches_target(
             amp_factor in MIN_AMP..MAX_AMP,
-            source_amount in 0..u64::MAX,
-            swap_source_amount in 0..u64::MAX,
-            swap_destination_amount in 0..u64::MAX,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            target_bps in 1..10_000u64,
         ) {
-            let start_ramp_ts = cmp::max(0, current_ts - MIN_RAMP_DURATION);
-            let stop_ramp_ts = cmp::min(i64::MAX, current_ts + MIN_RAMP_DURATION);
-            check_swap(
-                amp_factor,
-                amp_factor,
-                current_ts,
-                start_ramp_ts,
-                stop_ramp_ts,
-                source_amount,
-                swap_source_amount,
-                swap_destination_amount,
-            );
+            let (i, j) = (0, amounts.len() - 1);
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            // Target a fraction of the pool's current rate
+            let rate = swap
+                .compute_marginal_rate_with_fee_n(i, j, &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            let target_rate = rate * U256::from(target_bps) / 10_000;
+            prop_assume!(!target_rate.is_zero());
+            let arbitrage = swap
+                .compute_arbitrage_amount_n(i, j, target_rate, &swap_amounts, &MODEL_FEES)
+                .unwrap();
+            if arbitrage.is_some() {
+                check_arbitrage_amount(&swap, target_rate, &swap_amounts, &MODEL_FEES);
+            }
         }
     }
 
+    /// Check that the trade stays within the price impact bound, and one more
+    /// unit would exceed it
+    fn check_max_trade_for_price_impact(
+        swap: &StableSwap,
+        i: usize,
+        j: usize,
+        max_price_impact: U256,
+        swap_amounts: &[U256],
+        fees: &Fees,
+    ) -> U256 {
+        let precision = U256::from(RATE_PRECISION);
+        let (amount_in, result) = swap
+            .compute_max_trade_for_price_impact_n(i, j, max_price_impact, swap_amounts, fees)
+            .unwrap()
+            .unwrap();
+        let spot_rate = swap
+            .compute_marginal_rate_with_fee_n(i, j, swap_amounts, fees)
+            .unwrap();
+        let min_rate = spot_rate * (precision - max_price_impact);
+        assert!(result.amount_swapped * precision * precision >= amount_in * min_rate);
+        let result = swap
+            .swap_to_n(i, j, amount_in + 1, swap_amounts, fees)
+            .unwrap();
+        assert!(result.amount_swapped * precision * precision < (amount_in + 1) * min_rate);
+        amount_in
+    }
+
     #[test]
-    fn test_swap_calculation_with_random_inputs() {
-        for _ in 0..100 {
-            let mut rng = rand::thread_rng();
+    fn test_max_trade_for_price_impact() {
+        let swap = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let precision = U256::from(RATE_PRECISION);
+        let amount = U256::from(1_000_000_000u64);
+        let half_percent = precision / 200;
 
-            let initial_amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
-            let target_amp_factor: u64 = rng.gen_range(MIN_AMP, MAX_AMP);
-            let start_ramp_ts: i64 = rng.gen_range(ZERO_TS, i64::MAX);
-            let stop_ramp_ts: i64 = rng.gen_range(start_ramp_ts, i64::MAX);
-            let current_ts: i64 = rng.gen_range(start_ramp_ts, stop_ramp_ts);
-            let source_amount: u64 = rng.gen_range(1, u64::MAX);
-            let swap_source_amount: u64 = rng.gen_range(1, u64::MAX);
-            let swap_destination_amount: u64 = rng.gen_range(1, u64::MAX);
-            println!("testing swap_calculation_with_random_inputs:");
-            println!(
-                "current_ts: {}, start_ramp_ts: {}, stop_ramp_ts: {}",
-                current_ts, start_ramp_ts, stop_ramp_ts
-            );
-            println!(
-                "initial_amp_factor: {}, target_amp_factor: {}, source_amount: {}, swap_source_amount: {}, swap_destination_amount: {}",
-                initial_amp_factor, target_amp_factor, source_amount, swap_source_amount, swap_destination_amount
-            );
+        // Looser bounds allow larger trades
+        let balanced = [amount, amount];
+        let small =
+            check_max_trade_for_price_impact(&swap, 0, 1, half_percent, &balanced, &MODEL_FEES);
+        let large = check_max_trade_for_price_impact(
+            &swap,
+            0,
+            1,
+            half_percent * 10,
+            &balanced,
+            &MODEL_FEES,
+        );
+        assert!(small < large);
+        let (amount_in, result) = swap
+            .compute_max_trade_for_price_impact(half_percent, amount, amount, &MODEL_FEES)
+            .unwrap()
+            .unwrap();
+        assert_eq!(amount_in, small);
+        assert_eq!(result.new_source_amount, amount + small);
 
-            check_swap(
-                initial_amp_factor,
-                target_amp_factor,
-                current_ts,
+        // Both directions of a lopsided pool, selling the scarce coin moves
+        // the pool towards balance and goes further
+        let lopsided = [amount, amount * 3];
+        let scarce_in =
+            check_max_trade_for_price_impact(&swap, 0, 1, half_percent, &lopsided, &MODEL_FEES);
+        let abundant_in =
+            check_max_trade_for_price_impact(&swap, 1, 0, half_percent, &lopsided, &MODEL_FEES);
+        assert!(scarce_in > abundant_in);
+
+        // Invalid inputs
+        assert!(matches!(
+            swap.compute_max_trade_for_price_impact(precision, amount, amount, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+        assert!(matches!(
+            swap.compute_max_trade_for_price_impact_n(0, 0, half_percent, &balanced, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+    }
+
+    #[test]
+    fn test_forecast() {
+        let start_ramp_ts = 1_000;
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+        let swap = StableSwap::new(100, 1_000, ZERO_TS, start_ramp_ts, stop_ramp_ts);
+        let amount = U256::from(1_000_000_000u64);
+        let step = MIN_RAMP_DURATION / 4;
+        // Starts before the ramp and stops off the step, after the ramp
+        let end_ts = stop_ramp_ts + 10;
+
+        let forecast = swap
+            .forecast_swap(
+                ZERO_TS,
+                end_ts,
+                step,
+                amount / 2,
+                amount,
+                amount,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        let timestamps: Vec<i64> = forecast.iter().map(|point| point.timestamp).collect();
+        assert_eq!(
+            timestamps,
+            vec![0, step, 2 * step, 3 * step, 4 * step, end_ts]
+        );
+        assert_eq!(forecast[0].amp_factor, 100.into());
+        assert_eq!(forecast[5].amp_factor, 1_000.into());
+        // A flatter curve pays more for a large trade on a balanced pool
+        for pair in forecast.windows(2) {
+            assert!(pair[1].amp_factor >= pair[0].amp_factor);
+            assert!(pair[1].quote.result.amount_swapped >= pair[0].quote.result.amount_swapped);
+        }
+        // Each point matches a calculator at its timestamp
+        let at_ts = StableSwap::new(100, 1_000, 2 * step, start_ramp_ts, stop_ramp_ts);
+        assert_eq!(forecast[2].amp_factor, at_ts.compute_amp_factor().unwrap());
+        assert_eq!(
+            forecast[2].quote.result.amount_swapped,
+            at_ts
+                .swap_to(amount / 2, amount, amount, &MODEL_FEES)
+                .unwrap()
+                .amount_swapped
+        );
+
+        let forecast = swap
+            .forecast_withdraw_one(
                 start_ramp_ts,
                 stop_ramp_ts,
-                source_amount,
-                swap_source_amount,
-                swap_destination_amount,
-            );
+                MIN_RAMP_DURATION,
+                amount,
+                amount * 2,
+                amount,
+                amount,
+                &MODEL_FEES,
+            )
+            .unwrap();
+        assert_eq!(forecast.len(), 2);
+        assert!(forecast[1].quote.amount_withdrawn > forecast[0].quote.amount_withdrawn);
+
+        // A single point when the range is empty
+        assert_eq!(
+            swap.forecast(stop_ramp_ts, stop_ramp_ts, 1, |calculator| calculator
+                .compute_amp_factor())
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Invalid inputs
+        assert!(matches!(
+            swap.forecast_swap(ZERO_TS, end_ts, 0, amount, amount, amount, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+        assert!(matches!(
+            swap.forecast_swap(end_ts, ZERO_TS, step, amount, amount, amount, &MODEL_FEES),
+            Err(CurveError::InvalidInput)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn test_max_trade_for_price_impact_bound(
+            amp_factor in MIN_AMP..MAX_AMP,
+            amounts in prop::collection::vec(1_000_000..1_000_000_000_000_000u64, MIN_N_COINS..=MAX_N_COINS),
+            max_price_impact_bps in 1..1_000u64,
+        ) {
+            let swap = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+            let swap_amounts: Vec<U256> = amounts.iter().map(|a| U256::from(*a)).collect();
+            let max_price_impact = U256::from(RATE_PRECISION) * U256::from(max_price_impact_bps) / 10_000;
+            let last = amounts.len() - 1;
+            for (i, j) in [(0, last), (last, 0)] {
+                let trade = swap
+                    .compute_max_trade_for_price_impact_n(i, j, max_price_impact, &swap_amounts, &MODEL_FEES)
+                    .unwrap();
+                if trade.is_some() {
+                    check_max_trade_for_price_impact(&swap, i, j, max_price_impact, &swap_amounts, &MODEL_FEES);
+                }
+            }
         }
     }
 
@@ -708,14 +3593,14 @@ mod tests {
             pool_token_supply.into(),
         );
         assert_eq!(
-            result.0,
+            result.amount_withdrawn,
             model
                 .sim_calc_withdraw_one_coin(pool_token_amount.into(), 0)
                 .0
                 .into()
         );
         assert_eq!(
-            result.1,
+            result.fee,
             model
                 .sim_calc_withdraw_one_coin(pool_token_amount.into(), 0)
                 .1
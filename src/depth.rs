@@ -0,0 +1,239 @@
+//! Liquidity depth tables for off-chain market analysis, built with the
+//! `depth` feature
+
+use crate::{
+    bn::U256,
+    curve::{CurveCalculator, RATE_PRECISION},
+    error::CurveError,
+    fees::Fees,
+    processor::Processor,
+    state::SwapInfo,
+};
+
+/// Outcome of swapping one input size bucket through the pool. Prices are
+/// scaled by `RATE_PRECISION` and in output token per input token.
+pub struct DepthLevel {
+    /// Amount of input token swapped
+    pub amount_in: U256,
+    /// Amount of output token received, net of fees
+    pub amount_out: U256,
+    /// Average price of the swap, net of fees
+    pub effective_price: U256,
+    /// Shortfall of the effective price from the spot price net of the trade
+    /// fee, relative to the latter
+    pub price_impact: U256,
+}
+
+/// Depth of a two token pool in both directions, one level per input size
+/// bucket. Buckets too large for the pool to fill are left out.
+pub struct DepthTable {
+    /// Levels of swaps from token A to token B
+    pub a_to_b: Vec<DepthLevel>,
+    /// Levels of swaps from token B to token A
+    pub b_to_a: Vec<DepthLevel>,
+}
+
+impl DepthTable {
+    /// Depth table of a pool with the given reserves, swapping each of
+    /// `buckets`, in input token, in both directions
+    pub fn new(
+        calculator: &dyn CurveCalculator,
+        swap_token_a_amount: U256,
+        swap_token_b_amount: U256,
+        fees: &Fees,
+        buckets: &[U256],
+    ) -> Result<Self, CurveError> {
+        let swap_amounts = [swap_token_a_amount, swap_token_b_amount];
+        Ok(Self {
+            a_to_b: Self::levels(calculator, 0, 1, &swap_amounts, fees, buckets)?,
+            b_to_a: Self::levels(calculator, 1, 0, &swap_amounts, fees, buckets)?,
+        })
+    }
+
+    /// Depth table of a swap at `current_ts`, with the rate of token B in
+    /// token A read from its rate provider or price, see
+    /// `Processor::token_b_rate`, and the balances of the pool net of accrued
+    /// admin fees, see `SwapInfo::pool_amount`
+    pub fn from_swap_info(
+        token_swap: &SwapInfo,
+        current_ts: i64,
+        token_b_rate: u64,
+        swap_token_a_amount: U256,
+        swap_token_b_amount: U256,
+        buckets: &[U256],
+    ) -> Result<Self, CurveError> {
+        let calculator = Processor::calculator(
+            token_swap,
+            current_ts,
+            &token_swap.precision_multipliers(),
+            &[RATE_PRECISION, token_b_rate],
+        );
+        Self::new(
+            calculator.as_ref(),
+            swap_token_a_amount,
+            swap_token_b_amount,
+            &token_swap.fees,
+            buckets,
+        )
+    }
+
+    fn levels(
+        calculator: &dyn CurveCalculator,
+        i: usize,
+        j: usize,
+        swap_amounts: &[U256],
+        fees: &Fees,
+        buckets: &[U256],
+    ) -> Result<Vec<DepthLevel>, CurveError> {
+        let mut levels = Vec::with_capacity(buckets.len());
+        for amount_in in buckets {
+            let quote = match calculator.quote_swap_n(i, j, *amount_in, swap_amounts, fees) {
+                Err(CurveError::ZeroLiquidity) => continue,
+                quote => quote?,
+            };
+            levels.push(DepthLevel {
+                amount_in: *amount_in,
+                amount_out: quote.result.amount_swapped,
+                effective_price: quote.execution_price,
+                price_impact: quote.price_impact,
+            });
+        }
+        Ok(levels)
+    }
+
+    /// CSV with a header row and one row per level, A to B first
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("direction,amount_in,amount_out,effective_price,price_impact\n");
+        for (direction, levels) in self.directions() {
+            for level in levels {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    direction,
+                    level.amount_in,
+                    level.amount_out,
+                    level.effective_price,
+                    level.price_impact
+                ));
+            }
+        }
+        csv
+    }
+
+    /// JSON object with an array of levels per direction. Amounts and prices
+    /// are decimal strings, since they can exceed the safe integer range of
+    /// JSON numbers.
+    pub fn to_json(&self) -> String {
+        let directions: Vec<String> = self
+            .directions()
+            .iter()
+            .map(|(direction, levels)| {
+                let levels: Vec<String> = levels
+                    .iter()
+                    .map(|level| {
+                        format!(
+                            "{{\"amount_in\":\"{}\",\"amount_out\":\"{}\",\"effective_price\":\"{}\",\"price_impact\":\"{}\"}}",
+                            level.amount_in, level.amount_out, level.effective_price, level.price_impact
+                        )
+                    })
+                    .collect();
+                format!("\"{}\":[{}]", direction, levels.join(","))
+            })
+            .collect();
+        format!("{{{}}}", directions.join(","))
+    }
+
+    fn directions(&self) -> [(&str, &[DepthLevel]); 2] {
+        [("a_to_b", &self.a_to_b), ("b_to_a", &self.b_to_a)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constant_price::ConstantPrice,
+        curve::{StableSwap, ZERO_TS},
+    };
+
+    const FEES: Fees = Fees {
+        admin_trade_fee_numerator: 0,
+        admin_trade_fee_denominator: 1,
+        admin_withdraw_fee_numerator: 0,
+        admin_withdraw_fee_denominator: 1,
+        trade_fee_numerator: 1,
+        trade_fee_denominator: 100,
+        withdraw_fee_numerator: 0,
+        withdraw_fee_denominator: 1,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
+    };
+
+    #[test]
+    fn test_depth_table() {
+        let curve = StableSwap::new(100, 100, ZERO_TS, ZERO_TS, ZERO_TS);
+        let buckets: Vec<U256> = [1_000u64, 10_000, 100_000, 1_000_000]
+            .iter()
+            .map(|amount| U256::from(*amount))
+            .collect();
+        let table =
+            DepthTable::new(&curve, 1_000_000.into(), 2_000_000.into(), &FEES, &buckets).unwrap();
+        assert_eq!(table.a_to_b.len(), buckets.len());
+        assert_eq!(table.b_to_a.len(), buckets.len());
+
+        // Larger swaps get worse prices
+        for levels in [&table.a_to_b, &table.b_to_a].iter() {
+            for pair in levels.windows(2) {
+                assert!(pair[1].amount_out > pair[0].amount_out);
+                assert!(pair[1].effective_price <= pair[0].effective_price);
+                assert!(pair[1].price_impact >= pair[0].price_impact);
+            }
+        }
+        // The scarce token A is dearer
+        assert!(table.a_to_b[0].effective_price > table.b_to_a[0].effective_price);
+
+        let csv = table.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 1 + 2 * buckets.len());
+        assert_eq!(
+            rows[0],
+            "direction,amount_in,amount_out,effective_price,price_impact"
+        );
+        assert!(rows[1].starts_with("a_to_b,1000,"));
+        assert!(rows[5].starts_with("b_to_a,1000,"));
+    }
+
+    #[test]
+    fn test_depth_table_export() {
+        let curve = ConstantPrice::new(&[1, 1], &[RATE_PRECISION, RATE_PRECISION]);
+        let buckets = [U256::from(100), U256::from(300)];
+        let table = DepthTable::new(&curve, 1_000.into(), 200.into(), &FEES, &buckets).unwrap();
+
+        // The pool only holds 200 token B
+        assert_eq!(table.a_to_b.len(), 1);
+        assert_eq!(table.b_to_a.len(), 2);
+        assert_eq!(
+            table.to_csv(),
+            "direction,amount_in,amount_out,effective_price,price_impact\n\
+             a_to_b,100,99,990000000000000000,0\n\
+             b_to_a,100,99,990000000000000000,0\n\
+             b_to_a,300,297,990000000000000000,0\n"
+        );
+        assert_eq!(
+            table.to_json(),
+            "{\"a_to_b\":[\
+             {\"amount_in\":\"100\",\"amount_out\":\"99\",\"effective_price\":\"990000000000000000\",\"price_impact\":\"0\"}\
+             ],\"b_to_a\":[\
+             {\"amount_in\":\"100\",\"amount_out\":\"99\",\"effective_price\":\"990000000000000000\",\"price_impact\":\"0\"},\
+             {\"amount_in\":\"300\",\"amount_out\":\"297\",\"effective_price\":\"990000000000000000\",\"price_impact\":\"0\"}\
+             ]}"
+        );
+
+        let empty = DepthTable::new(&curve, 1_000.into(), 200.into(), &FEES, &[]).unwrap();
+        assert_eq!(empty.to_json(), "{\"a_to_b\":[],\"b_to_a\":[]}");
+        assert_eq!(
+            DepthTable::new(&curve, 1_000.into(), 200.into(), &FEES, &[0.into()]).err(),
+            Some(CurveError::InvalidInput)
+        );
+    }
+}
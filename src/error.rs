@@ -91,9 +91,45 @@ pub enum SwapError {
     /// Admin transfer deadline exceeded
     #[error("Admin transfer deadline exceeded")]
     AdminDeadlineExceeded,
-    /// Token mint decimals must be the same.
-    #[error("Token mints must have same decimals")]
+    /// Token mint decimals must not exceed the pool mint decimals.
+    #[error("Token mint decimals are incompatible with the pool mint decimals")]
     MismatchedDecimals,
+    /// The rate provider account is not the swap's or holds no valid rate
+    #[error("Invalid rate provider account")]
+    InvalidRateProvider,
+    /// The rate provider's rate is older than the swap allows
+    #[error("Rate provider rate is stale")]
+    StaleRate,
+    /// Calculation overflowed
+    #[error("Calculation overflowed")]
+    CalculationOverflow,
+    /// Calculation underflowed
+    #[error("Calculation underflowed")]
+    CalculationUnderflow,
+    /// Calculation divided by zero
+    #[error("Calculation divided by zero")]
+    DivisionByZero,
+    /// Invariant calculation did not converge
+    #[error("Invariant calculation did not converge")]
+    NonConvergence,
+    /// Pool has insufficient liquidity for the calculation
+    #[error("Pool has insufficient liquidity")]
+    ZeroLiquidity,
+    /// Deposit does not increase the invariant
+    #[error("Deposit does not increase the pool invariant")]
+    DepositTooSmall,
+    /// Fee parameters have a zero denominator or exceed the maximum fees
+    #[error("Fee parameters are invalid or exceed the maximum fees")]
+    InvalidFee,
+    /// Active fee change in progress
+    #[error("Active fee change in progress")]
+    ActiveFeeChange,
+    /// No active fee change in progress
+    #[error("No active fee change in progress")]
+    NoActiveFeeChange,
+    /// Committed fees cannot be applied before their activation time
+    #[error("Fee change is locked until its activation time")]
+    FeeChangeLocked,
 }
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
@@ -105,3 +141,51 @@ impl<T> DecodeError<T> for SwapError {
         "Swap Error"
     }
 }
+
+/// Errors that may be returned by the curve, fee and pool token calculations.
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub enum CurveError {
+    /// Arithmetic overflow
+    #[error("Arithmetic overflow")]
+    Overflow,
+    /// Arithmetic underflow
+    #[error("Arithmetic underflow")]
+    Underflow,
+    /// Division by zero
+    #[error("Division by zero")]
+    DivisionByZero,
+    /// An iterative approximation did not converge
+    #[error("Did not converge after {iterations} iterations")]
+    NonConvergence {
+        /// Number of iterations run
+        iterations: u64,
+    },
+    /// The pool holds none, or too little, of a token or pool token involved
+    /// in the calculation
+    #[error("Pool has insufficient liquidity")]
+    ZeroLiquidity,
+    /// Amounts or coin indices do not fit the pool
+    #[error("Invalid input")]
+    InvalidInput,
+    /// Deposit amounts do not increase the invariant
+    #[error("Deposit does not increase the pool invariant")]
+    DepositTooSmall,
+}
+impl From<CurveError> for SwapError {
+    fn from(e: CurveError) -> Self {
+        match e {
+            CurveError::Overflow => SwapError::CalculationOverflow,
+            CurveError::Underflow => SwapError::CalculationUnderflow,
+            CurveError::DivisionByZero => SwapError::DivisionByZero,
+            CurveError::NonConvergence { .. } => SwapError::NonConvergence,
+            CurveError::ZeroLiquidity => SwapError::ZeroLiquidity,
+            CurveError::InvalidInput => SwapError::InvalidInput,
+            CurveError::DepositTooSmall => SwapError::DepositTooSmall,
+        }
+    }
+}
+impl From<CurveError> for ProgramError {
+    fn from(e: CurveError) -> Self {
+        SwapError::from(e).into()
+    }
+}
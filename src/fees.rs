@@ -1,12 +1,35 @@
 //! Program fees
 
-use crate::bn::U256;
+use crate::{
+    bn::U256,
+    error::{CurveError, SwapError},
+    math::TryMath,
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
     program_error::ProgramError,
     program_pack::{Pack, Sealed},
 };
 
+/// Precision of the off-peg fee multiplier, i.e. the multiplier of a flat fee
+pub const OFFPEG_FEE_MULTIPLIER_DENOMINATOR: u64 = 10_000_000_000;
+/// Maximum trade fee numerator, see `MAX_TRADE_FEE_DENOMINATOR`
+pub const MAX_TRADE_FEE_NUMERATOR: u64 = 1;
+/// Maximum trade fee denominator, trade fees are capped at 50%
+pub const MAX_TRADE_FEE_DENOMINATOR: u64 = 2;
+/// Maximum withdraw fee numerator, see `MAX_WITHDRAW_FEE_DENOMINATOR`
+pub const MAX_WITHDRAW_FEE_NUMERATOR: u64 = 1;
+/// Maximum withdraw fee denominator, withdraw fees are capped at 50%
+pub const MAX_WITHDRAW_FEE_DENOMINATOR: u64 = 2;
+/// Maximum admin fee numerator, see `MAX_ADMIN_FEE_DENOMINATOR`
+pub const MAX_ADMIN_FEE_NUMERATOR: u64 = 1;
+/// Maximum admin fee denominator, the admin can take at most all of a fee
+pub const MAX_ADMIN_FEE_DENOMINATOR: u64 = 1;
+/// Maximum host fee numerator, see `MAX_HOST_FEE_DENOMINATOR`
+pub const MAX_HOST_FEE_NUMERATOR: u64 = 1;
+/// Maximum host fee denominator, the host can take at most all of the admin fee
+pub const MAX_HOST_FEE_DENOMINATOR: u64 = 1;
+
 /// Fees struct
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -27,56 +50,163 @@ pub struct Fees {
     pub withdraw_fee_numerator: u64,
     /// Withdraw fee denominator
     pub withdraw_fee_denominator: u64,
+    /// Multiplier of trade fees when the pool is fully off peg, scaled by
+    /// `OFFPEG_FEE_MULTIPLIER_DENOMINATOR`. Fees are flat at or below the denominator.
+    pub offpeg_fee_multiplier: u64,
+    /// Host fee numerator, the part of the admin trade fee paid to the host
+    /// fee account of a swap, if any
+    pub host_fee_numerator: u64,
+    /// Host fee denominator
+    pub host_fee_denominator: u64,
 }
 
 impl Fees {
+    /// Check every fee has a nonzero denominator and stays within the maximum
+    /// trade, withdraw, admin and host fee ratios, and that the trade fee
+    /// scaled by the off-peg multiplier stays within the maximum trade fee
+    pub fn validate(&self) -> Result<(), SwapError> {
+        let valid = fee_within(
+            self.trade_fee_numerator,
+            self.trade_fee_denominator,
+            MAX_TRADE_FEE_NUMERATOR,
+            MAX_TRADE_FEE_DENOMINATOR,
+        ) && fee_within(
+            self.withdraw_fee_numerator,
+            self.withdraw_fee_denominator,
+            MAX_WITHDRAW_FEE_NUMERATOR,
+            MAX_WITHDRAW_FEE_DENOMINATOR,
+        ) && fee_within(
+            self.admin_trade_fee_numerator,
+            self.admin_trade_fee_denominator,
+            MAX_ADMIN_FEE_NUMERATOR,
+            MAX_ADMIN_FEE_DENOMINATOR,
+        ) && fee_within(
+            self.admin_withdraw_fee_numerator,
+            self.admin_withdraw_fee_denominator,
+            MAX_ADMIN_FEE_NUMERATOR,
+            MAX_ADMIN_FEE_DENOMINATOR,
+        ) && fee_within(
+            self.host_fee_numerator,
+            self.host_fee_denominator,
+            MAX_HOST_FEE_NUMERATOR,
+            MAX_HOST_FEE_DENOMINATOR,
+        ) && self.offpeg_fee_within_max();
+        if !valid {
+            return Err(SwapError::InvalidFee);
+        }
+        Ok(())
+    }
+
     /// Apply admin trade fee
-    pub fn admin_trade_fee(&self, fee_amount: U256) -> Option<U256> {
+    pub fn admin_trade_fee(&self, fee_amount: U256) -> Result<U256, CurveError> {
         fee_amount
-            .checked_mul(self.admin_trade_fee_numerator.into())?
-            .checked_div(self.admin_trade_fee_denominator.into())
+            .try_mul(self.admin_trade_fee_numerator.into())?
+            .try_div(self.admin_trade_fee_denominator.into())
     }
 
     /// Apply admin withdraw fee
-    pub fn admin_withdraw_fee(&self, fee_amount: U256) -> Option<U256> {
+    pub fn admin_withdraw_fee(&self, fee_amount: U256) -> Result<U256, CurveError> {
         fee_amount
-            .checked_mul(self.admin_withdraw_fee_numerator.into())?
-            .checked_div(self.admin_withdraw_fee_denominator.into())
+            .try_mul(self.admin_withdraw_fee_numerator.into())?
+            .try_div(self.admin_withdraw_fee_denominator.into())
+    }
+
+    /// Apply host fee to an admin fee
+    pub fn host_fee(&self, admin_fee_amount: U256) -> Result<U256, CurveError> {
+        admin_fee_amount
+            .try_mul(self.host_fee_numerator.into())?
+            .try_div(self.host_fee_denominator.into())
     }
 
     /// Compute trade fee from amount
-    pub fn trade_fee(&self, trade_amount: U256) -> Option<U256> {
+    pub fn trade_fee(&self, trade_amount: U256) -> Result<U256, CurveError> {
         trade_amount
-            .checked_mul(self.trade_fee_numerator.into())?
-            .checked_div(self.trade_fee_denominator.into())
+            .try_mul(self.trade_fee_numerator.into())?
+            .try_div(self.trade_fee_denominator.into())
     }
 
     /// Compute withdraw fee from amount
-    pub fn withdraw_fee(&self, withdraw_amount: U256) -> Option<U256> {
+    pub fn withdraw_fee(&self, withdraw_amount: U256) -> Result<U256, CurveError> {
         withdraw_amount
-            .checked_mul(self.withdraw_fee_numerator.into())?
-            .checked_div(self.withdraw_fee_denominator.into())
+            .try_mul(self.withdraw_fee_numerator.into())?
+            .try_div(self.withdraw_fee_denominator.into())
+    }
+
+    /// Whether fees rise as the pool goes off peg
+    pub fn has_offpeg_fees(&self) -> bool {
+        self.offpeg_fee_multiplier > OFFPEG_FEE_MULTIPLIER_DENOMINATOR
+    }
+
+    /// Whether the trade fee of a fully off-peg pool, i.e. the trade fee times
+    /// the off-peg multiplier, is at most the maximum trade fee
+    fn offpeg_fee_within_max(&self) -> bool {
+        if !self.has_offpeg_fees() {
+            return true;
+        }
+        let numerator =
+            u128::from(self.trade_fee_numerator) * u128::from(self.offpeg_fee_multiplier);
+        let denominator =
+            u128::from(self.trade_fee_denominator) * u128::from(OFFPEG_FEE_MULTIPLIER_DENOMINATOR);
+        U256::from(numerator) * U256::from(MAX_TRADE_FEE_DENOMINATOR)
+            <= U256::from(denominator) * U256::from(MAX_TRADE_FEE_NUMERATOR)
+    }
+
+    /// Scale a fee by the off-peg multiplier, from 1 when the normalized
+    /// balances `xp_i` and `xp_j` are equal up to the full multiplier as either
+    /// one runs dry. Callers pass the balances averaged over the operation.
+    /// Equation:
+    /// fee * m / ((m - 1) * 4 * xp_i * xp_j / (xp_i + xp_j) ** 2 + 1)
+    pub fn dynamic_fee(&self, fee: U256, xp_i: U256, xp_j: U256) -> Result<U256, CurveError> {
+        let (numerator, denominator) = self.offpeg_fee_factor(xp_i, xp_j)?;
+        fee.try_mul(numerator)?.try_div(denominator)
+    }
+
+    /// Numerator and denominator of the off-peg multiplier of fees for the
+    /// normalized balances `xp_i` and `xp_j`
+    pub fn offpeg_fee_factor(&self, xp_i: U256, xp_j: U256) -> Result<(U256, U256), CurveError> {
+        let multiplier_denominator = U256::from(OFFPEG_FEE_MULTIPLIER_DENOMINATOR);
+        let sum = xp_i.try_add(xp_j)?;
+        if !self.has_offpeg_fees() || sum.is_zero() {
+            return Ok((multiplier_denominator, multiplier_denominator));
+        }
+        let multiplier = U256::from(self.offpeg_fee_multiplier);
+        let denominator = multiplier
+            .try_sub(multiplier_denominator)?
+            .try_mul(4.into())?
+            .try_mul(xp_i)?
+            .try_mul(xp_j)?
+            .try_div(sum.try_mul(sum)?)?
+            .try_add(multiplier_denominator)?;
+        Ok((multiplier, denominator))
     }
 
     /// Compute normalized fee for symmetric/asymmetric deposits/withdraws
-    pub fn normalized_trade_fee(&self, n_coins: u64, amount: U256) -> Option<U256> {
+    pub fn normalized_trade_fee(&self, n_coins: u64, amount: U256) -> Result<U256, CurveError> {
         // adjusted_fee_numerator: uint256 = self.fee * N_COINS / (4 * (N_COINS - 1))
         let adjusted_trade_fee_numerator = self
             .trade_fee_numerator
-            .checked_mul(n_coins)?
-            .checked_div((n_coins.checked_sub(1)?).checked_mul(4)?)?; // XXX: Why divide by 4?
+            .try_mul(n_coins)?
+            .try_div((n_coins.try_sub(1)?).try_mul(4)?)?; // XXX: Why divide by 4?
 
         amount
-            .checked_mul(adjusted_trade_fee_numerator.into())?
-            .checked_div(self.trade_fee_denominator.into())
+            .try_mul(adjusted_trade_fee_numerator.into())?
+            .try_div(self.trade_fee_denominator.into())
     }
 }
 
+/// Whether `numerator / denominator` is a fee of at most
+/// `max_numerator / max_denominator`
+fn fee_within(numerator: u64, denominator: u64, max_numerator: u64, max_denominator: u64) -> bool {
+    denominator != 0
+        && u128::from(numerator) * u128::from(max_denominator)
+            <= u128::from(max_numerator) * u128::from(denominator)
+}
+
 impl Sealed for Fees {}
 impl Pack for Fees {
-    const LEN: usize = 64;
+    const LEN: usize = 88;
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
-        let input = array_ref![input, 0, 64];
+        let input = array_ref![input, 0, 88];
         #[allow(clippy::ptr_offset_with_cast)]
         let (
             admin_trade_fee_numerator,
@@ -87,7 +217,10 @@ impl Pack for Fees {
             trade_fee_denominator,
             withdraw_fee_numerator,
             withdraw_fee_denominator,
-        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8];
+            offpeg_fee_multiplier,
+            host_fee_numerator,
+            host_fee_denominator,
+        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8];
         Ok(Self {
             admin_trade_fee_numerator: u64::from_le_bytes(*admin_trade_fee_numerator),
             admin_trade_fee_denominator: u64::from_le_bytes(*admin_trade_fee_denominator),
@@ -97,11 +230,14 @@ impl Pack for Fees {
             trade_fee_denominator: u64::from_le_bytes(*trade_fee_denominator),
             withdraw_fee_numerator: u64::from_le_bytes(*withdraw_fee_numerator),
             withdraw_fee_denominator: u64::from_le_bytes(*withdraw_fee_denominator),
+            offpeg_fee_multiplier: u64::from_le_bytes(*offpeg_fee_multiplier),
+            host_fee_numerator: u64::from_le_bytes(*host_fee_numerator),
+            host_fee_denominator: u64::from_le_bytes(*host_fee_denominator),
         })
     }
 
     fn pack_into_slice(&self, output: &mut [u8]) {
-        let output = array_mut_ref![output, 0, 64];
+        let output = array_mut_ref![output, 0, 88];
         let (
             admin_trade_fee_numerator,
             admin_trade_fee_denominator,
@@ -111,7 +247,10 @@ impl Pack for Fees {
             trade_fee_denominator,
             withdraw_fee_numerator,
             withdraw_fee_denominator,
-        ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8];
+            offpeg_fee_multiplier,
+            host_fee_numerator,
+            host_fee_denominator,
+        ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8];
         *admin_trade_fee_numerator = self.admin_trade_fee_numerator.to_le_bytes();
         *admin_trade_fee_denominator = self.admin_trade_fee_denominator.to_le_bytes();
         *admin_withdraw_fee_numerator = self.admin_withdraw_fee_numerator.to_le_bytes();
@@ -120,6 +259,9 @@ impl Pack for Fees {
         *trade_fee_denominator = self.trade_fee_denominator.to_le_bytes();
         *withdraw_fee_numerator = self.withdraw_fee_numerator.to_le_bytes();
         *withdraw_fee_denominator = self.withdraw_fee_denominator.to_le_bytes();
+        *offpeg_fee_multiplier = self.offpeg_fee_multiplier.to_le_bytes();
+        *host_fee_numerator = self.host_fee_numerator.to_le_bytes();
+        *host_fee_denominator = self.host_fee_denominator.to_le_bytes();
     }
 }
 
@@ -137,6 +279,9 @@ mod tests {
         let trade_fee_denominator = 6;
         let withdraw_fee_numerator = 7;
         let withdraw_fee_denominator = 8;
+        let offpeg_fee_multiplier = 9;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 11;
         let fees = Fees {
             admin_trade_fee_numerator,
             admin_trade_fee_denominator,
@@ -146,6 +291,9 @@ mod tests {
             trade_fee_denominator,
             withdraw_fee_numerator,
             withdraw_fee_denominator,
+            offpeg_fee_multiplier,
+            host_fee_numerator,
+            host_fee_denominator,
         };
 
         let mut packed = [0u8; Fees::LEN];
@@ -162,6 +310,9 @@ mod tests {
         packed.extend_from_slice(&trade_fee_denominator.to_le_bytes());
         packed.extend_from_slice(&withdraw_fee_numerator.to_le_bytes());
         packed.extend_from_slice(&withdraw_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&offpeg_fee_multiplier.to_le_bytes());
+        packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
         let unpacked = Fees::unpack_from_slice(&packed).unwrap();
         assert_eq!(fees, unpacked);
     }
@@ -176,6 +327,9 @@ mod tests {
         let trade_fee_denominator = 6;
         let withdraw_fee_numerator = 7;
         let withdraw_fee_denominator = 8;
+        let offpeg_fee_multiplier = 9;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 11;
         let fees = Fees {
             admin_trade_fee_numerator,
             admin_trade_fee_denominator,
@@ -185,6 +339,9 @@ mod tests {
             trade_fee_denominator,
             withdraw_fee_numerator,
             withdraw_fee_denominator,
+            offpeg_fee_multiplier,
+            host_fee_numerator,
+            host_fee_denominator,
         };
 
         let trade_amount = 1_000_000_000;
@@ -197,6 +354,12 @@ mod tests {
             fees.admin_trade_fee(trade_fee).unwrap(),
             expected_admin_trade_fee.into()
         );
+        let expected_host_fee =
+            expected_admin_trade_fee * host_fee_numerator / host_fee_denominator;
+        assert_eq!(
+            fees.host_fee(expected_admin_trade_fee.into()).unwrap(),
+            expected_host_fee.into()
+        );
 
         let withdraw_amount = 100_000_000_000;
         let expected_withdraw_fee =
@@ -221,4 +384,109 @@ mod tests {
             expected_normalized_fee.into()
         );
     }
+    #[test]
+    fn dynamic_fee_results() {
+        let fee = U256::from(1_000_000);
+        let mut fees = Fees {
+            offpeg_fee_multiplier: 2 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+            ..Fees::default()
+        };
+
+        // balanced pools pay the flat fee
+        assert_eq!(fees.dynamic_fee(fee, 500.into(), 500.into()), Ok(fee));
+        // fees double as one side runs dry
+        assert_eq!(fees.dynamic_fee(fee, 1_000.into(), 0.into()), Ok(fee * 2));
+        // and rise in between, 1 / (1 * 4 * 900 * 100 / 1000 ** 2 + 1) = 2 / 1.36
+        assert_eq!(
+            fees.dynamic_fee(fee, 900.into(), 100.into()),
+            Ok(1_470_588.into())
+        );
+        assert_eq!(fees.dynamic_fee(fee, 0.into(), 0.into()), Ok(fee));
+
+        // multipliers up to 1 keep fees flat
+        fees.offpeg_fee_multiplier = OFFPEG_FEE_MULTIPLIER_DENOMINATOR;
+        assert_eq!(fees.dynamic_fee(fee, 1_000.into(), 0.into()), Ok(fee));
+        fees.offpeg_fee_multiplier = 0;
+        assert_eq!(fees.dynamic_fee(fee, 1_000.into(), 0.into()), Ok(fee));
+    }
+
+    #[test]
+    fn validate_fees() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 1,
+            admin_withdraw_fee_numerator: 0,
+            admin_withdraw_fee_denominator: 1,
+            trade_fee_numerator: MAX_TRADE_FEE_NUMERATOR,
+            trade_fee_denominator: MAX_TRADE_FEE_DENOMINATOR,
+            withdraw_fee_numerator: 3,
+            withdraw_fee_denominator: 10_000,
+            offpeg_fee_multiplier: 0,
+            host_fee_numerator: 1,
+            host_fee_denominator: 5,
+        };
+        assert_eq!(fees.validate(), Ok(()));
+
+        // zero denominators
+        let invalid = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 0,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            admin_withdraw_fee_denominator: 0,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+
+        // fees above the maximum ratios
+        let invalid = Fees {
+            trade_fee_numerator: 5_001,
+            trade_fee_denominator: 10_000,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            withdraw_fee_numerator: u64::MAX,
+            withdraw_fee_denominator: u64::MAX - 1,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            admin_trade_fee_numerator: 2,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            host_fee_numerator: 6,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+
+        // off-peg multipliers may raise the trade fee up to the maximum ratio
+        let offpeg = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            offpeg_fee_multiplier: 5 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+            ..fees
+        };
+        assert_eq!(offpeg.validate(), Ok(()));
+        let invalid = Fees {
+            offpeg_fee_multiplier: offpeg.offpeg_fee_multiplier + 1,
+            ..offpeg
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+        let invalid = Fees {
+            offpeg_fee_multiplier: u64::MAX,
+            ..fees
+        };
+        assert_eq!(invalid.validate(), Err(SwapError::InvalidFee));
+    }
 }
@@ -2,8 +2,10 @@
 
 #![allow(clippy::too_many_arguments)]
 
+use crate::curve::{CurveType, RampType, RATE_PRECISION};
 use crate::error::SwapError;
 use crate::fees::Fees;
+use num_traits::FromPrimitive;
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
@@ -24,6 +26,16 @@ pub struct InitializeData {
     pub amp_factor: u64,
     /// Fees
     pub fees: Fees,
+    /// Program trusted to own the rate provider account, or the default key
+    /// for pools without a rate provider
+    pub rate_provider_program: Pubkey,
+    /// Maximum age of the rate provider's rate in seconds
+    pub max_rate_age: i64,
+    /// Curve the pool prices trades with
+    pub curve_type: CurveType,
+    /// Price of one token B in token A for constant price pools, scaled by
+    /// `curve::RATE_PRECISION`
+    pub token_b_price: u64,
 }
 
 /// Swap instruction data
@@ -36,6 +48,16 @@ pub struct SwapData {
     pub minimum_amount_out: u64,
 }
 
+/// SwapExactOut instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapExactOutData {
+    /// DESTINATION amount to receive, input from SOURCE is based on the exchange rate
+    pub amount_out: u64,
+    /// Maximum amount of SOURCE token to transfer, prevents excessive slippage
+    pub maximum_amount_in: u64,
+}
+
 /// Deposit instruction data
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
@@ -72,6 +94,79 @@ pub struct WithdrawOneData {
     pub minimum_token_amount: u64,
 }
 
+/// DepositExactLp instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositExactLpData {
+    /// Exact amount of pool tokens to mint
+    pub pool_token_amount: u64,
+    /// Maximum amount of token A to deposit, prevents excessive slippage
+    pub max_token_a: u64,
+    /// Maximum amount of token B to deposit, prevents excessive slippage
+    pub max_token_b: u64,
+}
+
+/// WithdrawImbalance instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawImbalanceData {
+    /// Exact amount of token A to receive
+    pub token_a_amount: u64,
+    /// Exact amount of token B to receive
+    pub token_b_amount: u64,
+    /// Maximum amount of pool tokens to burn, prevents excessive slippage
+    pub max_burn_amount: u64,
+}
+
+/// InitializeMulti instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeMultiData {
+    /// Nonce used to create valid program address
+    pub nonce: u8,
+    /// Number of tokens in the pool
+    pub n_coins: u8,
+    /// Amplification coefficient (A)
+    pub amp_factor: u64,
+    /// Fees
+    pub fees: Fees,
+}
+
+/// SwapMulti instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapMultiData {
+    /// Index of the SOURCE token in the pool
+    pub token_index_in: u8,
+    /// Index of the DESTINATION token in the pool
+    pub token_index_out: u8,
+    /// SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+    pub amount_in: u64,
+    /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+    pub minimum_amount_out: u64,
+}
+
+/// DepositMulti instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositMultiData {
+    /// Amount of each token to deposit, in pool order
+    pub token_amounts: Vec<u64>,
+    /// Minimum LP tokens to mint, prevents excessive slippage
+    pub min_mint_amount: u64,
+}
+
+/// WithdrawMulti instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawMultiData {
+    /// Amount of pool tokens to burn. User receives an output of each token
+    /// based on the percentage of the pool tokens that are returned.
+    pub pool_token_amount: u64,
+    /// Minimum amount of each token to receive, in pool order, prevents excessive slippage
+    pub minimum_token_amounts: Vec<u64>,
+}
+
 /// RampA instruction data
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
@@ -80,9 +175,11 @@ pub struct RampAData {
     pub target_amp: u64,
     /// Unix timestamp to stop ramp
     pub stop_ramp_ts: i64,
+    /// Schedule A follows until `stop_ramp_ts`
+    pub ramp_type: RampType,
 }
 
-/// Admin only instructions.
+/// Admin only instructions, for both two-token and multi-token swaps.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum AdminInstruction {
@@ -100,8 +197,41 @@ pub enum AdminInstruction {
     ApplyNewAdmin,
     /// TODO: Docs
     CommitNewAdmin,
-    /// TODO: Docs
-    SetNewFees(Fees),
+    /// Commit new fees, to be applied after `admin::FEE_CHANGE_DELAY`
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` Admin account
+    ///   3. `[]` Clock sysvar
+    CommitNewFees(Fees),
+    /// Apply the committed fees once their delay has passed
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` Admin account
+    ///   3. `[]` Clock sysvar
+    ApplyNewFees,
+    /// Cancel the committed fees
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` Admin account
+    RevertNewFees,
+    /// Transfer the admin fees accrued in the pool to the admin fee accounts
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` Admin account
+    ///   3. `[writable]` token_a Swap Account to withdraw the fees FROM.
+    ///   4. `[writable]` token_b Swap Account to withdraw the fees FROM.
+    ///   5. `[writable]` admin_fee_a admin fee Account for token_a.
+    ///   6. `[writable]` admin_fee_b admin fee Account for token_b.
+    ///   7. `[]` Token program id
+    ///
+    ///   Multi-token swaps instead pass the `n_coins` token Swap Accounts,
+    ///   then the `n_coins` admin fee Accounts, in pool order, and then the
+    ///   Token program id.
+    WithdrawAdminFees,
 }
 
 impl AdminInstruction {
@@ -111,10 +241,18 @@ impl AdminInstruction {
         Ok(match tag {
             100 => {
                 let (target_amp, rest) = unpack_u64(rest)?;
-                let (stop_ramp_ts, _rest) = unpack_i64(rest)?;
+                let (stop_ramp_ts, rest) = unpack_i64(rest)?;
+                // Clients predating ramp types ramp linearly
+                let ramp_type = match rest.first() {
+                    None => RampType::Linear,
+                    Some(&ramp_type) => {
+                        RampType::from_u8(ramp_type).ok_or(SwapError::InvalidInstruction)?
+                    }
+                };
                 Some(Self::RampA(RampAData {
                     target_amp,
                     stop_ramp_ts,
+                    ramp_type,
                 }))
             }
             101 => Some(Self::StopRampA),
@@ -123,10 +261,15 @@ impl AdminInstruction {
             104 => Some(Self::SetFeeAccount),
             105 => Some(Self::ApplyNewAdmin),
             106 => Some(Self::CommitNewAdmin),
-            107 => {
+            // SetNewFees changed fees without the fee change delay
+            107 => return Err(SwapError::InvalidInstruction.into()),
+            108 => {
                 let fees = Fees::unpack_unchecked(rest)?;
-                Some(Self::SetNewFees(fees))
+                Some(Self::CommitNewFees(fees))
             }
+            109 => Some(Self::ApplyNewFees),
+            110 => Some(Self::RevertNewFees),
+            111 => Some(Self::WithdrawAdminFees),
             _ => None,
         })
     }
@@ -138,10 +281,12 @@ impl AdminInstruction {
             Self::RampA(RampAData {
                 target_amp,
                 stop_ramp_ts,
+                ramp_type,
             }) => {
                 buf.push(100);
                 buf.extend_from_slice(&target_amp.to_le_bytes());
                 buf.extend_from_slice(&stop_ramp_ts.to_le_bytes());
+                buf.push(ramp_type as u8);
             }
             Self::StopRampA => buf.push(101),
             Self::Pause => buf.push(102),
@@ -149,12 +294,15 @@ impl AdminInstruction {
             Self::SetFeeAccount => buf.push(104),
             Self::ApplyNewAdmin => buf.push(105),
             Self::CommitNewAdmin => buf.push(106),
-            Self::SetNewFees(fees) => {
-                buf.push(107);
+            Self::CommitNewFees(fees) => {
+                buf.push(108);
                 let mut fees_slice = [0u8; Fees::LEN];
                 Pack::pack_into_slice(&fees, &mut fees_slice[..]);
                 buf.extend_from_slice(&fees_slice);
             }
+            Self::ApplyNewFees => buf.push(109),
+            Self::RevertNewFees => buf.push(110),
+            Self::WithdrawAdminFees => buf.push(111),
         }
         buf
     }
@@ -168,10 +316,12 @@ pub fn ramp_a(
     admin_pubkey: &Pubkey,
     target_amp: u64,
     stop_ramp_ts: i64,
+    ramp_type: RampType,
 ) -> Result<Instruction, ProgramError> {
     let data = AdminInstruction::RampA(RampAData {
         target_amp,
         stop_ramp_ts,
+        ramp_type,
     })
     .pack();
 
@@ -328,21 +478,132 @@ pub fn set_fee_account(
     })
 }
 
-/// Creates a 'set_new_fees' instruction
-pub fn set_new_fees(
+/// Creates a 'commit_new_fees' instruction
+pub fn commit_new_fees(
     program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     admin_pubkey: &Pubkey,
     new_fees: Fees,
 ) -> Result<Instruction, ProgramError> {
-    let data = AdminInstruction::SetNewFees(new_fees).pack();
+    let data = AdminInstruction::CommitNewFees(new_fees).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
+        AccountMeta::new(clock::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'apply_new_fees' instruction
+pub fn apply_new_fees(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AdminInstruction::ApplyNewFees.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
+        AccountMeta::new(clock::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'revert_new_fees' instruction
+pub fn revert_new_fees(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AdminInstruction::RevertNewFees.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_admin_fees' instruction
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_admin_fees(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    admin_fee_a_pubkey: &Pubkey,
+    admin_fee_b_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AdminInstruction::WithdrawAdminFees.pack();
 
     let accounts = vec![
         AccountMeta::new(*swap_pubkey, true),
         AccountMeta::new(*authority_pubkey, false),
         AccountMeta::new(*admin_pubkey, true),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*admin_fee_a_pubkey, false),
+        AccountMeta::new(*admin_fee_b_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_admin_fees' instruction for a multi-token swap
+pub fn withdraw_admin_fees_multi(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    admin_fee_pubkeys: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    if admin_fee_pubkeys.len() != swap_token_pubkeys.len() {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = AdminInstruction::WithdrawAdminFees.pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
     ];
+    for pubkey in swap_token_pubkeys.iter().chain(admin_fee_pubkeys.iter()) {
+        accounts.push(AccountMeta::new(*pubkey, false));
+    }
+    accounts.push(AccountMeta::new(*token_program_id, false));
 
     Ok(Instruction {
         program_id: *program_id,
@@ -365,25 +626,34 @@ pub enum SwapInstruction {
     ///   5. `[]` token_a Account. Must be non zero, owned by $authority.
     ///   6. `[]` token_b Account. Must be non zero, owned by $authority.
     ///   7. `[writable]` Pool Token Mint. Must be empty, owned by $authority.
+    ///
+    ///   Optionally followed by the rate provider account publishing the rate
+    ///   of token B in token A, see `rate_provider::RateInfo`. It must be owned
+    ///   by `InitializeData::rate_provider_program`. Only StableSwap pools can
+    ///   have one.
     Initialize(InitializeData),
 
-    ///   Swap the tokens in the pool.
+    ///   Swap the tokens in the pool. The admin fee accrues in the pool until
+    ///   swept by `AdminInstruction::WithdrawAdminFees`.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` $authority
     ///   2. `[writable]` token_(A|B) SOURCE Account, amount is transferable by $authority,
     ///   3. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
     ///   4. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
     ///   5. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
-    ///   6. `[writable]` token_(A|B) admin fee Account. Must have same mint as DESTINATION token.
-    ///   7. `[]` Token program id
-    ///   8. `[]` Clock sysvar
+    ///   6. `[]` Token program id
+    ///   7. `[]` Clock sysvar
+    ///   8. `[]` Rate provider account, if the swap has one.
+    ///   9. `[writable]` token_(A|B) host fee Account, optional, follows the rate provider
+    ///      account if the swap has one. Must have same mint as DESTINATION token.
+    ///      Receives the host fee share of the admin fee.
     Swap(SwapData),
 
     ///   Deposit some tokens into the pool.  The output is a "pool" token representing ownership
     ///   into the pool. Inputs are converted to the current ratio.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` $authority
     ///   2. `[writable]` token_a $authority can transfer amount,
     ///   3. `[writable]` token_b $authority can transfer amount,
@@ -393,11 +663,13 @@ pub enum SwapInstruction {
     ///   7. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
     ///   8. `[]` Token program id
     ///   9. `[]` Clock sysvar
+    ///   10. `[]` Rate provider account, if the swap has one.
     Deposit(DepositData),
 
-    ///   Withdraw tokens from the pool at the current ratio.
+    ///   Withdraw tokens from the pool at the current ratio. The admin fee
+    ///   accrues in the pool until swept by `AdminInstruction::WithdrawAdminFees`.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` $authority
     ///   2. `[writable]` Pool mint account, $authority is the owner
     ///   3. `[writable]` SOURCE Pool account, amount is transferable by $authority.
@@ -405,24 +677,130 @@ pub enum SwapInstruction {
     ///   5. `[writable]` token_b Swap Account to withdraw FROM.
     ///   6. `[writable]` token_a user Account to credit.
     ///   7. `[writable]` token_b user Account to credit.
-    ///   8. `[writable]` admin_fee_a admin fee Account for token_a.
-    ///   9. `[writable]` admin_fee_b admin fee Account for token_b.
-    ///   10. `[]` Token program id
+    ///   8. `[]` Token program id
     Withdraw(WithdrawData),
 
-    ///   Withdraw one token from the pool at the current ratio.
+    ///   Withdraw one token from the pool at the current ratio. The admin fee
+    ///   accrues in the pool until swept by `AdminInstruction::WithdrawAdminFees`.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` $authority
     ///   2. `[writable]` Pool mint account, $authority is the owner
     ///   3. `[writable]` SOURCE Pool account, amount is transferable by $authority.
     ///   4. `[writable]` token_(A|B) BASE token Swap Account to withdraw FROM.
     ///   5. `[writable]` token_(A|B) QUOTE token Swap Account to exchange to base token.
     ///   6. `[writable]` token_(A|B) BASE token user Account to credit.
-    ///   7. `[writable]` token_(A|B) admin fee Account. Must have same mint as BASE token.
+    ///   7. `[]` Token program id
+    ///   8. `[]` Clock sysvar
+    ///   9. `[]` Rate provider account, if the swap has one.
+    ///   10. `[writable]` token_(A|B) host fee Account, optional, follows the rate provider
+    ///       account if the swap has one. Must have same mint as BASE token.
+    ///       Receives the host fee share of the admin trade fee.
+    WithdrawOne(WithdrawOneData),
+
+    ///   Initializes a new MultiSwapInfo holding `n_coins` tokens.
+    ///
+    ///   0. `[writable, signer]` New Token-swap to create.
+    ///   1. `[]` $authority derived from `create_program_address(&[Token-swap account])`
+    ///   2. `[]` admin Account.
+    ///   3. `[writable]` Pool Token Mint. Must be empty, owned by $authority.
+    ///   4. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   5. `[]` Token program id
+    ///
+    ///   Followed by `n_coins` accounts of each kind, in pool order:
+    ///   * `[]` token mints.
+    ///   * `[]` token Accounts. Must be non zero, owned by $authority.
+    ///   * `[]` admin fee Accounts, one per token.
+    InitializeMulti(InitializeMultiData),
+
+    ///   Swap two of the tokens in a multi-token pool. The admin fee accrues in
+    ///   the pool until swept by `AdminInstruction::WithdrawAdminFees`.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` SOURCE Account, amount is transferable by $authority,
+    ///   3. `[writable]` DESTINATION Account assigned to USER as the owner.
+    ///   4. `[]` Token program id
+    ///   5. `[]` Clock sysvar
+    ///
+    ///   Followed by `n_coins` accounts, in pool order:
+    ///   * `[writable]` token Swap Accounts.
+    ///
+    ///   Optionally followed by:
+    ///   * `[writable]` host fee Account. Must have same mint as DESTINATION token.
+    ///     Receives the host fee share of the admin fee.
+    SwapMulti(SwapMultiData),
+
+    ///   Deposit some tokens into a multi-token pool.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` Pool MINT account, $authority is the owner.
+    ///   3. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   4. `[]` Token program id
+    ///   5. `[]` Clock sysvar
+    ///
+    ///   Followed by `n_coins` accounts of each kind, in pool order:
+    ///   * `[writable]` token Accounts $authority can transfer amount.
+    ///   * `[writable]` token Swap Accounts to deposit into.
+    DepositMulti(DepositMultiData),
+
+    ///   Withdraw tokens from a multi-token pool at the current ratio. The admin
+    ///   fee accrues in the pool until swept by `AdminInstruction::WithdrawAdminFees`.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` Pool mint account, $authority is the owner
+    ///   3. `[writable]` SOURCE Pool account, amount is transferable by $authority.
+    ///   4. `[]` Token program id
+    ///
+    ///   Followed by `n_coins` accounts of each kind, in pool order:
+    ///   * `[writable]` token Swap Accounts to withdraw FROM.
+    ///   * `[writable]` token user Accounts to credit.
+    WithdrawMulti(WithdrawMultiData),
+
+    ///   Swap the tokens in the pool, receiving an exact amount of the DESTINATION token.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` token_(A|B) SOURCE Account, amount is transferable by $authority,
+    ///   3. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   4. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   5. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   6. `[]` Token program id
+    ///   7. `[]` Clock sysvar
+    ///   8. `[]` Rate provider account, if the swap has one.
+    SwapExactOut(SwapExactOutData),
+
+    ///   Withdraw exact amounts of tokens from the pool, burning pool tokens
+    ///   according to the resulting change in the invariant.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` Pool mint account, $authority is the owner
+    ///   3. `[writable]` SOURCE Pool account, amount is transferable by $authority.
+    ///   4. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   5. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   6. `[writable]` token_a user Account to credit.
+    ///   7. `[writable]` token_b user Account to credit.
     ///   8. `[]` Token program id
     ///   9. `[]` Clock sysvar
-    WithdrawOne(WithdrawOneData),
+    ///   10. `[]` Rate provider account, if the swap has one.
+    WithdrawImbalance(WithdrawImbalanceData),
+
+    ///   Deposit tokens into the pool at the current ratio, minting an exact
+    ///   amount of pool tokens. No imbalance fee is charged.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` $authority
+    ///   2. `[writable]` token_a $authority can transfer amount,
+    ///   3. `[writable]` token_b $authority can transfer amount,
+    ///   4. `[writable]` token_a Base Account to deposit into.
+    ///   5. `[writable]` token_b Base Account to deposit into.
+    ///   6. `[writable]` Pool MINT account, $authority is the owner.
+    ///   7. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   8. `[]` Token program id
+    DepositExactLp(DepositExactLpData),
 }
 
 impl SwapInstruction {
@@ -433,11 +811,29 @@ impl SwapInstruction {
             0 => {
                 let (&nonce, rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
                 let (amp_factor, rest) = unpack_u64(rest)?;
-                let fees = Fees::unpack_unchecked(rest)?;
+                if rest.len() < Fees::LEN {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+                let (fees, rest) = rest.split_at(Fees::LEN);
+                let fees = Fees::unpack_unchecked(fees)?;
+                let (rate_provider_program, rest) = unpack_pubkey(rest)?;
+                let (max_rate_age, rest) = unpack_i64(rest)?;
+                // Clients predating curve types initialize StableSwap pools
+                let (curve_type, token_b_price) = match rest.split_first() {
+                    None => (CurveType::StableSwap, RATE_PRECISION),
+                    Some((&curve_type, rest)) => (
+                        CurveType::from_u8(curve_type).ok_or(SwapError::InvalidInstruction)?,
+                        unpack_u64(rest)?.0,
+                    ),
+                };
                 Self::Initialize(InitializeData {
                     nonce,
                     amp_factor,
                     fees,
+                    rate_provider_program,
+                    max_rate_age,
+                    curve_type,
+                    token_b_price,
                 })
             }
             1 => {
@@ -476,6 +872,76 @@ impl SwapInstruction {
                     minimum_token_amount,
                 })
             }
+            5 => {
+                let (&nonce, rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let (&n_coins, rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let (amp_factor, rest) = unpack_u64(rest)?;
+                let fees = Fees::unpack_unchecked(rest)?;
+                Self::InitializeMulti(InitializeMultiData {
+                    nonce,
+                    n_coins,
+                    amp_factor,
+                    fees,
+                })
+            }
+            6 => {
+                let (&token_index_in, rest) =
+                    rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let (&token_index_out, rest) =
+                    rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let (amount_in, rest) = unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = unpack_u64(rest)?;
+                Self::SwapMulti(SwapMultiData {
+                    token_index_in,
+                    token_index_out,
+                    amount_in,
+                    minimum_amount_out,
+                })
+            }
+            7 => {
+                let (token_amounts, rest) = unpack_u64_vec(rest)?;
+                let (min_mint_amount, _rest) = unpack_u64(rest)?;
+                Self::DepositMulti(DepositMultiData {
+                    token_amounts,
+                    min_mint_amount,
+                })
+            }
+            8 => {
+                let (pool_token_amount, rest) = unpack_u64(rest)?;
+                let (minimum_token_amounts, _rest) = unpack_u64_vec(rest)?;
+                Self::WithdrawMulti(WithdrawMultiData {
+                    pool_token_amount,
+                    minimum_token_amounts,
+                })
+            }
+            9 => {
+                let (amount_out, rest) = unpack_u64(rest)?;
+                let (maximum_amount_in, _rest) = unpack_u64(rest)?;
+                Self::SwapExactOut(SwapExactOutData {
+                    amount_out,
+                    maximum_amount_in,
+                })
+            }
+            10 => {
+                let (token_a_amount, rest) = unpack_u64(rest)?;
+                let (token_b_amount, rest) = unpack_u64(rest)?;
+                let (max_burn_amount, _rest) = unpack_u64(rest)?;
+                Self::WithdrawImbalance(WithdrawImbalanceData {
+                    token_a_amount,
+                    token_b_amount,
+                    max_burn_amount,
+                })
+            }
+            11 => {
+                let (pool_token_amount, rest) = unpack_u64(rest)?;
+                let (max_token_a, rest) = unpack_u64(rest)?;
+                let (max_token_b, _rest) = unpack_u64(rest)?;
+                Self::DepositExactLp(DepositExactLpData {
+                    pool_token_amount,
+                    max_token_a,
+                    max_token_b,
+                })
+            }
             _ => return Err(SwapError::InvalidInstruction.into()),
         })
     }
@@ -488,6 +954,10 @@ impl SwapInstruction {
                 nonce,
                 amp_factor,
                 fees,
+                rate_provider_program,
+                max_rate_age,
+                curve_type,
+                token_b_price,
             }) => {
                 buf.push(0);
                 buf.push(nonce);
@@ -495,6 +965,10 @@ impl SwapInstruction {
                 let mut fees_slice = [0u8; Fees::LEN];
                 Pack::pack_into_slice(&fees, &mut fees_slice[..]);
                 buf.extend_from_slice(&fees_slice);
+                buf.extend_from_slice(rate_provider_program.as_ref());
+                buf.extend_from_slice(&max_rate_age.to_le_bytes());
+                buf.push(curve_type as u8);
+                buf.extend_from_slice(&token_b_price.to_le_bytes());
             }
             Self::Swap(SwapData {
                 amount_in,
@@ -532,21 +1006,91 @@ impl SwapInstruction {
                 buf.extend_from_slice(&pool_token_amount.to_le_bytes());
                 buf.extend_from_slice(&minimum_token_amount.to_le_bytes());
             }
-        }
-        buf
-    }
-}
-
-/// Creates an 'initialize' instruction.
-pub fn initialize(
-    program_id: &Pubkey,
-    pool_token_program_id: &Pubkey, // Token program used for the pool token
-    swap_pubkey: &Pubkey,
-    authority_pubkey: &Pubkey,
-    admin_pubkey: &Pubkey,
-    admin_fee_a_pubkey: &Pubkey,
-    admin_fee_b_pubkey: &Pubkey,
-    token_a_mint_pubkey: &Pubkey,
+            Self::InitializeMulti(InitializeMultiData {
+                nonce,
+                n_coins,
+                amp_factor,
+                fees,
+            }) => {
+                buf.push(5);
+                buf.push(nonce);
+                buf.push(n_coins);
+                buf.extend_from_slice(&amp_factor.to_le_bytes());
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(&fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+            }
+            Self::SwapMulti(SwapMultiData {
+                token_index_in,
+                token_index_out,
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.push(6);
+                buf.push(token_index_in);
+                buf.push(token_index_out);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::DepositMulti(DepositMultiData {
+                ref token_amounts,
+                min_mint_amount,
+            }) => {
+                buf.push(7);
+                pack_u64_vec(token_amounts, &mut buf);
+                buf.extend_from_slice(&min_mint_amount.to_le_bytes());
+            }
+            Self::WithdrawMulti(WithdrawMultiData {
+                pool_token_amount,
+                ref minimum_token_amounts,
+            }) => {
+                buf.push(8);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                pack_u64_vec(minimum_token_amounts, &mut buf);
+            }
+            Self::SwapExactOut(SwapExactOutData {
+                amount_out,
+                maximum_amount_in,
+            }) => {
+                buf.push(9);
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&maximum_amount_in.to_le_bytes());
+            }
+            Self::WithdrawImbalance(WithdrawImbalanceData {
+                token_a_amount,
+                token_b_amount,
+                max_burn_amount,
+            }) => {
+                buf.push(10);
+                buf.extend_from_slice(&token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&token_b_amount.to_le_bytes());
+                buf.extend_from_slice(&max_burn_amount.to_le_bytes());
+            }
+            Self::DepositExactLp(DepositExactLpData {
+                pool_token_amount,
+                max_token_a,
+                max_token_b,
+            }) => {
+                buf.push(11);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&max_token_a.to_le_bytes());
+                buf.extend_from_slice(&max_token_b.to_le_bytes());
+            }
+        }
+        buf
+    }
+}
+
+/// Creates an 'initialize' instruction.
+pub fn initialize(
+    program_id: &Pubkey,
+    pool_token_program_id: &Pubkey, // Token program used for the pool token
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    admin_fee_a_pubkey: &Pubkey,
+    admin_fee_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
     token_a_pubkey: &Pubkey,
     token_b_mint_pubkey: &Pubkey,
     token_b_pubkey: &Pubkey,
@@ -555,11 +1099,19 @@ pub fn initialize(
     nonce: u8,
     amp_factor: u64,
     fees: Fees,
+    rate_provider_program: &Pubkey,
+    max_rate_age: i64,
+    curve_type: CurveType,
+    token_b_price: u64,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::Initialize(InitializeData {
         nonce,
         amp_factor,
         fees,
+        rate_provider_program: *rate_provider_program,
+        max_rate_age,
+        curve_type,
+        token_b_price,
     })
     .pack();
 
@@ -628,6 +1180,48 @@ pub fn deposit(
     })
 }
 
+/// Creates a 'deposit_exact_lp' instruction.
+pub fn deposit_exact_lp(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    deposit_token_b_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_token_amount: u64,
+    max_token_a: u64,
+    max_token_b: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::DepositExactLp(DepositExactLpData {
+        pool_token_amount,
+        max_token_a,
+        max_token_b,
+    })
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*deposit_token_b_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
 /// Creates a 'withdraw' instruction.
 pub fn withdraw(
     program_id: &Pubkey,
@@ -640,8 +1234,6 @@ pub fn withdraw(
     swap_token_b_pubkey: &Pubkey,
     destination_token_a_pubkey: &Pubkey,
     destination_token_b_pubkey: &Pubkey,
-    admin_fee_a_pubkey: &Pubkey,
-    admin_fee_b_pubkey: &Pubkey,
     pool_token_amount: u64,
     minimum_token_a_amount: u64,
     minimum_token_b_amount: u64,
@@ -662,8 +1254,6 @@ pub fn withdraw(
         AccountMeta::new(*swap_token_b_pubkey, false),
         AccountMeta::new(*destination_token_a_pubkey, false),
         AccountMeta::new(*destination_token_b_pubkey, false),
-        AccountMeta::new(*admin_fee_a_pubkey, false),
-        AccountMeta::new(*admin_fee_b_pubkey, false),
         AccountMeta::new(*token_program_id, false),
     ];
 
@@ -674,6 +1264,49 @@ pub fn withdraw(
     })
 }
 
+/// Creates a 'withdraw_imbalance' instruction.
+pub fn withdraw_imbalance(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    token_a_amount: u64,
+    token_b_amount: u64,
+    max_burn_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::WithdrawImbalance(WithdrawImbalanceData {
+        token_a_amount,
+        token_b_amount,
+        max_burn_amount,
+    })
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+        AccountMeta::new(clock::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
 /// Creates a 'swap' instruction.
 pub fn swap(
     program_id: &Pubkey,
@@ -684,7 +1317,6 @@ pub fn swap(
     swap_source_pubkey: &Pubkey,
     swap_destination_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
-    admin_fee_destination_pubkey: &Pubkey,
     amount_in: u64,
     minimum_amount_out: u64,
 ) -> Result<Instruction, ProgramError> {
@@ -701,7 +1333,43 @@ pub fn swap(
         AccountMeta::new(*swap_source_pubkey, false),
         AccountMeta::new(*swap_destination_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
-        AccountMeta::new(*admin_fee_destination_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+        AccountMeta::new(clock::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_exact_out' instruction.
+pub fn swap_exact_out(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    amount_out: u64,
+    maximum_amount_in: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapExactOut(SwapExactOutData {
+        amount_out,
+        maximum_amount_in,
+    })
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new(*token_program_id, false),
         AccountMeta::new(clock::id(), false),
     ];
@@ -724,7 +1392,6 @@ pub fn withdraw_one(
     swap_base_token_pubkey: &Pubkey,
     swap_quote_token_pubkey: &Pubkey,
     base_destination_pubkey: &Pubkey,
-    admin_fee_destination_pubkey: &Pubkey,
     pool_token_amount: u64,
     minimum_token_amount: u64,
 ) -> Result<Instruction, ProgramError> {
@@ -742,7 +1409,6 @@ pub fn withdraw_one(
         AccountMeta::new(*swap_base_token_pubkey, false),
         AccountMeta::new(*swap_quote_token_pubkey, false),
         AccountMeta::new(*base_destination_pubkey, false),
-        AccountMeta::new(*admin_fee_destination_pubkey, false),
         AccountMeta::new(*token_program_id, false),
         AccountMeta::new(clock::id(), false),
     ];
@@ -754,6 +1420,213 @@ pub fn withdraw_one(
     })
 }
 
+/// Creates an 'initialize_multi' instruction.
+pub fn initialize_multi(
+    program_id: &Pubkey,
+    pool_token_program_id: &Pubkey, // Token program used for the pool token
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey, // Desintation to mint pool tokens for bootstrapper
+    token_mint_pubkeys: &[Pubkey],
+    token_pubkeys: &[Pubkey],
+    admin_fee_pubkeys: &[Pubkey],
+    nonce: u8,
+    amp_factor: u64,
+    fees: Fees,
+) -> Result<Instruction, ProgramError> {
+    let n_coins = token_pubkeys.len();
+    if token_mint_pubkeys.len() != n_coins || admin_fee_pubkeys.len() != n_coins {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::InitializeMulti(InitializeMultiData {
+        nonce,
+        n_coins: n_coins as u8,
+        amp_factor,
+        fees,
+    })
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*admin_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*pool_token_program_id, false),
+    ];
+    for pubkey in token_mint_pubkeys
+        .iter()
+        .chain(token_pubkeys.iter())
+        .chain(admin_fee_pubkeys.iter())
+    {
+        accounts.push(AccountMeta::new(*pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_multi' instruction.
+pub fn swap_multi(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    token_index_in: u8,
+    token_index_out: u8,
+    amount_in: u64,
+    minimum_amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapMulti(SwapMultiData {
+        token_index_in,
+        token_index_out,
+        amount_in,
+        minimum_amount_out,
+    })
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+        AccountMeta::new(clock::id(), false),
+    ];
+    for pubkey in swap_token_pubkeys {
+        accounts.push(AccountMeta::new(*pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_multi' instruction.
+pub fn deposit_multi(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    deposit_token_pubkeys: &[Pubkey],
+    swap_token_pubkeys: &[Pubkey],
+    token_amounts: Vec<u64>,
+    min_mint_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    if deposit_token_pubkeys.len() != swap_token_pubkeys.len() {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::DepositMulti(DepositMultiData {
+        token_amounts,
+        min_mint_amount,
+    })
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+        AccountMeta::new(clock::id(), false),
+    ];
+    for pubkey in deposit_token_pubkeys
+        .iter()
+        .chain(swap_token_pubkeys.iter())
+    {
+        accounts.push(AccountMeta::new(*pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_multi' instruction.
+pub fn withdraw_multi(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    destination_token_pubkeys: &[Pubkey],
+    pool_token_amount: u64,
+    minimum_token_amounts: Vec<u64>,
+) -> Result<Instruction, ProgramError> {
+    if destination_token_pubkeys.len() != swap_token_pubkeys.len() {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::WithdrawMulti(WithdrawMultiData {
+        pool_token_amount,
+        minimum_token_amounts,
+    })
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new(*authority_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*token_program_id, false),
+    ];
+    for pubkey in swap_token_pubkeys
+        .iter()
+        .chain(destination_token_pubkeys.iter())
+    {
+        accounts.push(AccountMeta::new(*pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Appends the rate provider account to an 'initialize', 'swap', 'swap_exact_out',
+/// 'deposit', 'withdraw_one' or 'withdraw_imbalance' instruction, for swaps
+/// configured with one.
+pub fn with_rate_provider(mut instruction: Instruction, rate_provider: &Pubkey) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(*rate_provider, false));
+    instruction
+}
+
+/// Appends the host fee account to a 'swap', 'swap_multi' or 'withdraw_one'
+/// instruction, after the rate provider account if the swap has one.
+pub fn with_host_fee_account(mut instruction: Instruction, host_fee: &Pubkey) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new(*host_fee, false));
+    instruction
+}
+
+fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+    if input.len() >= 32 {
+        let (key, rest) = input.split_at(32);
+        Ok((Pubkey::new(key), rest))
+    } else {
+        Err(SwapError::InvalidInstruction.into())
+    }
+}
+
 fn unpack_i64(input: &[u8]) -> Result<(i64, &[u8]), ProgramError> {
     if input.len() >= 8 {
         let (amount, rest) = input.split_at(8);
@@ -782,6 +1655,24 @@ fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
     }
 }
 
+fn unpack_u64_vec(input: &[u8]) -> Result<(Vec<u64>, &[u8]), ProgramError> {
+    let (&len, mut rest) = input.split_first().ok_or(SwapError::InvalidInstruction)?;
+    let mut values = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let (value, next) = unpack_u64(rest)?;
+        values.push(value);
+        rest = next;
+    }
+    Ok((values, rest))
+}
+
+fn pack_u64_vec(values: &[u64], buf: &mut Vec<u8>) {
+    buf.push(values.len() as u8);
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
 /// Unpacks a reference from a bytes buffer.
 /// TODO actually pack / unpack instead of relying on normal memory layout.
 pub fn unpack<T>(input: &[u8]) -> Result<&T, ProgramError> {
@@ -801,19 +1692,39 @@ mod tests {
     fn test_admin_instruction_packing() {
         let target_amp = 100;
         let stop_ramp_ts = i64::MAX;
+        let ramp_type = RampType::Geometric;
         let check = AdminInstruction::RampA(RampAData {
             target_amp,
             stop_ramp_ts,
+            ramp_type,
         });
         let packed = check.pack();
         let mut expect = vec![];
         expect.push(100 as u8);
         expect.extend_from_slice(&target_amp.to_le_bytes());
         expect.extend_from_slice(&stop_ramp_ts.to_le_bytes());
+        expect.push(ramp_type as u8);
         assert_eq!(packed, expect);
         let unpacked = AdminInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, Some(check));
 
+        // legacy clients ramp linearly
+        expect.pop();
+        let unpacked = AdminInstruction::unpack(&expect).unwrap();
+        assert_eq!(
+            unpacked,
+            Some(AdminInstruction::RampA(RampAData {
+                target_amp,
+                stop_ramp_ts,
+                ramp_type: RampType::Linear,
+            }))
+        );
+        expect.push(2);
+        assert_eq!(
+            AdminInstruction::unpack(&expect),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
         let check = AdminInstruction::StopRampA;
         let packed = check.pack();
         let mut expect = vec![];
@@ -871,17 +1782,52 @@ mod tests {
             trade_fee_denominator: 6,
             withdraw_fee_numerator: 7,
             withdraw_fee_denominator: 8,
+            offpeg_fee_multiplier: 9,
+            host_fee_numerator: 10,
+            host_fee_denominator: 11,
         };
-        let check = AdminInstruction::SetNewFees(new_fees);
-        let packed = check.pack();
-        let mut expect = vec![];
-        expect.push(107 as u8);
         let mut new_fees_slice = [0u8; Fees::LEN];
         new_fees.pack_into_slice(&mut new_fees_slice[..]);
+        let check = AdminInstruction::CommitNewFees(new_fees);
+        let packed = check.pack();
+        let mut expect = vec![];
+        expect.push(108 as u8);
         expect.extend_from_slice(&new_fees_slice);
         assert_eq!(packed, expect);
         let unpacked = AdminInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, Some(check));
+
+        let check = AdminInstruction::ApplyNewFees;
+        let packed = check.pack();
+        let mut expect = vec![];
+        expect.push(109 as u8);
+        assert_eq!(packed, expect);
+        let unpacked = AdminInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, Some(check));
+
+        let check = AdminInstruction::RevertNewFees;
+        let packed = check.pack();
+        let mut expect = vec![];
+        expect.push(110 as u8);
+        assert_eq!(packed, expect);
+        let unpacked = AdminInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, Some(check));
+
+        let check = AdminInstruction::WithdrawAdminFees;
+        let packed = check.pack();
+        let mut expect = vec![];
+        expect.push(111 as u8);
+        assert_eq!(packed, expect);
+        let unpacked = AdminInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, Some(check));
+
+        // SetNewFees was removed in favor of the fee change delay
+        let mut set_new_fees = vec![107];
+        set_new_fees.extend_from_slice(&new_fees_slice);
+        assert_eq!(
+            AdminInstruction::unpack(&set_new_fees),
+            Err(SwapError::InvalidInstruction.into())
+        );
     }
 
     #[test]
@@ -897,11 +1843,22 @@ mod tests {
             trade_fee_denominator: 6,
             withdraw_fee_numerator: 7,
             withdraw_fee_denominator: 8,
+            offpeg_fee_multiplier: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
+        let rate_provider_program = Pubkey::new_unique();
+        let max_rate_age: i64 = 3600;
+        let curve_type = CurveType::ConstantProduct;
+        let token_b_price: u64 = 42;
         let check = SwapInstruction::Initialize(InitializeData {
             nonce,
             amp_factor,
             fees,
+            rate_provider_program,
+            max_rate_age,
+            curve_type,
+            token_b_price,
         });
         let packed = check.pack();
         let mut expect = vec![];
@@ -911,10 +1868,29 @@ mod tests {
         let mut fees_slice = [0u8; Fees::LEN];
         fees.pack_into_slice(&mut fees_slice[..]);
         expect.extend_from_slice(&fees_slice);
+        expect.extend_from_slice(rate_provider_program.as_ref());
+        expect.extend_from_slice(&max_rate_age.to_le_bytes());
+        expect.push(curve_type as u8);
+        expect.extend_from_slice(&token_b_price.to_le_bytes());
         assert_eq!(packed, expect);
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
 
+        // Without curve data the pool is a StableSwap pool
+        let unpacked = SwapInstruction::unpack(&expect[..expect.len() - 9]).unwrap();
+        assert_eq!(
+            unpacked,
+            SwapInstruction::Initialize(InitializeData {
+                nonce,
+                amp_factor,
+                fees,
+                rate_provider_program,
+                max_rate_age,
+                curve_type: CurveType::StableSwap,
+                token_b_price: RATE_PRECISION,
+            })
+        );
+
         let amount_in: u64 = 2;
         let minimum_amount_out: u64 = 10;
         let check = SwapInstruction::Swap(SwapData {
@@ -976,5 +1952,119 @@ mod tests {
         assert_eq!(packed, expect);
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
+
+        let n_coins: u8 = 3;
+        let check = SwapInstruction::InitializeMulti(InitializeMultiData {
+            nonce,
+            n_coins,
+            amp_factor,
+            fees,
+        });
+        let packed = check.pack();
+        let mut expect = vec![5, nonce, n_coins];
+        expect.extend_from_slice(&amp_factor.to_le_bytes());
+        expect.extend_from_slice(&fees_slice);
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let token_index_in: u8 = 2;
+        let token_index_out: u8 = 0;
+        let check = SwapInstruction::SwapMulti(SwapMultiData {
+            token_index_in,
+            token_index_out,
+            amount_in,
+            minimum_amount_out,
+        });
+        let packed = check.pack();
+        let mut expect = vec![6, token_index_in, token_index_out];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let token_amounts: Vec<u64> = vec![10, 20, 30];
+        let check = SwapInstruction::DepositMulti(DepositMultiData {
+            token_amounts: token_amounts.clone(),
+            min_mint_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![7, token_amounts.len() as u8];
+        for token_amount in token_amounts.iter() {
+            expect.extend_from_slice(&token_amount.to_le_bytes());
+        }
+        expect.extend_from_slice(&min_mint_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let minimum_token_amounts: Vec<u64> = vec![102198761982612, 2011239855213, 1];
+        let check = SwapInstruction::WithdrawMulti(WithdrawMultiData {
+            pool_token_amount,
+            minimum_token_amounts: minimum_token_amounts.clone(),
+        });
+        let packed = check.pack();
+        let mut expect = vec![8];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.push(minimum_token_amounts.len() as u8);
+        for minimum_token_amount in minimum_token_amounts.iter() {
+            expect.extend_from_slice(&minimum_token_amount.to_le_bytes());
+        }
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        // truncated token amounts
+        let err = SwapInstruction::unpack(&expect[..expect.len() - 1]).unwrap_err();
+        assert_eq!(err, SwapError::InvalidInstruction.into());
+
+        let amount_out: u64 = 2;
+        let maximum_amount_in: u64 = 10;
+        let check = SwapInstruction::SwapExactOut(SwapExactOutData {
+            amount_out,
+            maximum_amount_in,
+        });
+        let packed = check.pack();
+        let mut expect = vec![9];
+        expect.extend_from_slice(&amount_out.to_le_bytes());
+        expect.extend_from_slice(&maximum_amount_in.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let token_a_amount: u64 = 10;
+        let token_b_amount: u64 = 20;
+        let max_burn_amount: u64 = 40;
+        let check = SwapInstruction::WithdrawImbalance(WithdrawImbalanceData {
+            token_a_amount,
+            token_b_amount,
+            max_burn_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![10];
+        expect.extend_from_slice(&token_a_amount.to_le_bytes());
+        expect.extend_from_slice(&token_b_amount.to_le_bytes());
+        expect.extend_from_slice(&max_burn_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let pool_token_amount: u64 = 1212438012089;
+        let max_token_a: u64 = 102198761982612;
+        let max_token_b: u64 = 1961234567;
+        let check = SwapInstruction::DepositExactLp(DepositExactLpData {
+            pool_token_amount,
+            max_token_a,
+            max_token_b,
+        });
+        let packed = check.pack();
+        let mut expect = vec![11];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&max_token_a.to_le_bytes());
+        expect.extend_from_slice(&max_token_b.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
     }
 }
@@ -4,13 +4,19 @@
 
 pub mod admin;
 pub mod bn;
+pub mod constant_price;
+pub mod constant_product;
 pub mod curve;
+#[cfg(feature = "depth")]
+pub mod depth;
 pub mod entrypoint;
 pub mod error;
 pub mod fees;
 pub mod instruction;
+pub mod math;
 pub mod pool_converter;
 pub mod processor;
+pub mod rate_provider;
 pub mod state;
 pub mod utils;
 
@@ -0,0 +1,89 @@
+//! Checked arithmetic reporting which operation failed
+
+use crate::{bn::U256, error::CurveError};
+
+/// Checked arithmetic returning a `CurveError` on failure
+pub trait TryMath: Sized {
+    /// Checked addition, failing with `CurveError::Overflow`
+    fn try_add(self, rhs: Self) -> Result<Self, CurveError>;
+    /// Checked subtraction, failing with `CurveError::Underflow`
+    fn try_sub(self, rhs: Self) -> Result<Self, CurveError>;
+    /// Checked multiplication, failing with `CurveError::Overflow`
+    fn try_mul(self, rhs: Self) -> Result<Self, CurveError>;
+    /// Checked division, failing with `CurveError::DivisionByZero`
+    fn try_div(self, rhs: Self) -> Result<Self, CurveError>;
+}
+
+/// Unsigned integer types the invariant math runs on, so that it can use
+/// u128 for realistic pools and U256 only when an intermediate overflows
+pub trait UnsignedInt: TryMath + Copy + Ord + From<u64> {}
+
+impl UnsignedInt for u128 {}
+impl UnsignedInt for U256 {}
+
+macro_rules! impl_try_math {
+    ($t:ty) => {
+        impl TryMath for $t {
+            fn try_add(self, rhs: Self) -> Result<Self, CurveError> {
+                self.checked_add(rhs).ok_or(CurveError::Overflow)
+            }
+            fn try_sub(self, rhs: Self) -> Result<Self, CurveError> {
+                self.checked_sub(rhs).ok_or(CurveError::Underflow)
+            }
+            fn try_mul(self, rhs: Self) -> Result<Self, CurveError> {
+                self.checked_mul(rhs).ok_or(CurveError::Overflow)
+            }
+            fn try_div(self, rhs: Self) -> Result<Self, CurveError> {
+                self.checked_div(rhs).ok_or(CurveError::DivisionByZero)
+            }
+        }
+    };
+}
+
+impl_try_math!(U256);
+impl_try_math!(u128);
+impl_try_math!(u64);
+impl_try_math!(i64);
+
+/// Integer square root, rounding down, by Newton's method
+pub fn sqrt(value: U256) -> Result<U256, CurveError> {
+    if value < 2.into() {
+        return Ok(value);
+    }
+    // Start from a power of two at or above the root
+    let mut x = U256::one() << value.bits().div_ceil(2);
+    let mut y = x.try_add(value.try_div(x)?)?.try_div(2.into())?;
+    while y < x {
+        x = y;
+        y = x.try_add(value.try_div(x)?)?.try_div(2.into())?;
+    }
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_math() {
+        assert_eq!(U256::MAX.try_add(1.into()), Err(CurveError::Overflow));
+        assert_eq!(U256::zero().try_sub(1.into()), Err(CurveError::Underflow));
+        assert_eq!(U256::MAX.try_mul(2.into()), Err(CurveError::Overflow));
+        assert_eq!(
+            U256::one().try_div(0.into()),
+            Err(CurveError::DivisionByZero)
+        );
+        assert_eq!(u128::MAX.try_mul(2), Err(CurveError::Overflow));
+        assert_eq!(6u64.try_div(3), Ok(2));
+        assert_eq!(i64::MIN.try_sub(1), Err(CurveError::Underflow));
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(sqrt(0.into()), Ok(0.into()));
+        assert_eq!(sqrt(1.into()), Ok(1.into()));
+        assert_eq!(sqrt(8.into()), Ok(2.into()));
+        assert_eq!(sqrt(9.into()), Ok(3.into()));
+        assert_eq!(sqrt(U256::MAX), Ok(U256::from(u128::MAX)));
+    }
+}
@@ -1,5 +1,5 @@
 //! Conversions for pool tokens
-use crate::{bn::U256, fees::Fees};
+use crate::{bn::U256, error::CurveError, fees::Fees, math::TryMath};
 
 /// Converter to determine how much to deposit / withdraw, along with
 /// proper initialization
@@ -16,25 +16,66 @@ pub struct PoolTokenConverter<'a> {
 
 impl PoolTokenConverter<'_> {
     /// A tokens for pool tokens
-    pub fn token_a_rate(&self, pool_tokens: U256) -> Option<(U256, U256)> {
-        let amount = pool_tokens
-            .checked_mul(self.token_a)?
-            .checked_div(self.supply)?;
-        let fee = self.fees.withdraw_fee(amount)?;
-        let admin_fee = self.fees.admin_withdraw_fee(fee)?;
-
-        Some((amount.checked_sub(fee)?, admin_fee))
+    pub fn token_a_rate(&self, pool_tokens: U256) -> Result<(U256, U256), CurveError> {
+        self.token_rate(pool_tokens, self.token_a)
     }
 
     /// B tokens for pool tokens
-    pub fn token_b_rate(&self, pool_tokens: U256) -> Option<(U256, U256)> {
-        let amount = pool_tokens
-            .checked_mul(self.token_b)?
-            .checked_div(self.supply)?;
+    pub fn token_b_rate(&self, pool_tokens: U256) -> Result<(U256, U256), CurveError> {
+        self.token_rate(pool_tokens, self.token_b)
+    }
+
+    /// Tokens of a pool reserve holding `token_amount` for pool tokens
+    fn token_rate(
+        &self,
+        pool_tokens: U256,
+        token_amount: U256,
+    ) -> Result<(U256, U256), CurveError> {
+        let (amount, _fee, admin_fee) = self.token_withdrawal(pool_tokens, token_amount)?;
+        Ok((amount, admin_fee))
+    }
+
+    /// Tokens of a pool reserve holding `token_amount` for pool tokens, along
+    /// with the withdraw fee they pay and its admin part
+    pub fn token_withdrawal(
+        &self,
+        pool_tokens: U256,
+        token_amount: U256,
+    ) -> Result<(U256, U256, U256), CurveError> {
+        if self.supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        let amount = pool_tokens.try_mul(token_amount)?.try_div(self.supply)?;
         let fee = self.fees.withdraw_fee(amount)?;
         let admin_fee = self.fees.admin_withdraw_fee(fee)?;
 
-        Some((amount.checked_sub(fee)?, admin_fee))
+        Ok((amount.try_sub(fee)?, fee, admin_fee))
+    }
+
+    /// A tokens to deposit for pool tokens
+    pub fn token_a_deposit_rate(&self, pool_tokens: U256) -> Result<U256, CurveError> {
+        self.token_deposit_rate(pool_tokens, self.token_a)
+    }
+
+    /// B tokens to deposit for pool tokens
+    pub fn token_b_deposit_rate(&self, pool_tokens: U256) -> Result<U256, CurveError> {
+        self.token_deposit_rate(pool_tokens, self.token_b)
+    }
+
+    /// Tokens to deposit into a pool reserve holding `token_amount` for pool
+    /// tokens, rounded up in the pool's favor
+    pub fn token_deposit_rate(
+        &self,
+        pool_tokens: U256,
+        token_amount: U256,
+    ) -> Result<U256, CurveError> {
+        if self.supply.is_zero() {
+            return Err(CurveError::ZeroLiquidity);
+        }
+        pool_tokens
+            .try_mul(token_amount)?
+            .try_add(self.supply.try_sub(1.into())?)?
+            .try_div(self.supply)
     }
 }
 
@@ -47,7 +88,7 @@ mod tests {
         token_b: U256,
         deposit: U256,
         supply: U256,
-        expected_before_fees: Option<U256>,
+        expected_before_fees: Result<U256, CurveError>,
     ) {
         let fees = Fees {
             admin_trade_fee_numerator: 0,
@@ -58,6 +99,9 @@ mod tests {
             trade_fee_denominator: 1,
             withdraw_fee_numerator: 1,
             withdraw_fee_denominator: 2,
+            offpeg_fee_multiplier: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
         let calculator = PoolTokenConverter {
             supply,
@@ -65,24 +109,72 @@ mod tests {
             token_b,
             fees: &fees,
         };
-        let expected_result = if expected_before_fees.is_some() {
-            let expected_fees = fees.withdraw_fee(expected_before_fees.unwrap()).unwrap();
+        let expected_result = expected_before_fees.map(|expected_before_fees| {
+            let expected_fees = fees.withdraw_fee(expected_before_fees).unwrap();
             let expected_admin_fees = fees.admin_withdraw_fee(expected_fees).unwrap();
-            let expected_amount = expected_before_fees.unwrap() - expected_fees;
-            Some((expected_amount, expected_admin_fees))
-        } else {
-            None
-        };
+            let expected_amount = expected_before_fees - expected_fees;
+            (expected_amount, expected_admin_fees)
+        });
         assert_eq!(calculator.token_a_rate(deposit), expected_result);
         assert_eq!(calculator.supply, supply);
     }
 
     #[test]
     fn issued_tokens() {
-        check_pool_token_a_rate(2.into(), 50.into(), 5.into(), 10.into(), Some(1.into()));
-        check_pool_token_a_rate(10.into(), 10.into(), 5.into(), 10.into(), Some(5.into()));
-        check_pool_token_a_rate(5.into(), 100.into(), 5.into(), 10.into(), Some(2.into()));
-        check_pool_token_a_rate(5.into(), U256::MAX, 5.into(), 10.into(), Some(2.into()));
-        check_pool_token_a_rate(U256::MAX, U256::MAX, 5.into(), 10.into(), None);
+        check_pool_token_a_rate(2.into(), 50.into(), 5.into(), 10.into(), Ok(1.into()));
+        check_pool_token_a_rate(10.into(), 10.into(), 5.into(), 10.into(), Ok(5.into()));
+        check_pool_token_a_rate(5.into(), 100.into(), 5.into(), 10.into(), Ok(2.into()));
+        check_pool_token_a_rate(5.into(), U256::MAX, 5.into(), 10.into(), Ok(2.into()));
+        check_pool_token_a_rate(
+            U256::MAX,
+            U256::MAX,
+            5.into(),
+            10.into(),
+            Err(CurveError::Overflow),
+        );
+        check_pool_token_a_rate(
+            5.into(),
+            5.into(),
+            5.into(),
+            0.into(),
+            Err(CurveError::ZeroLiquidity),
+        );
+    }
+
+    #[test]
+    fn deposited_tokens() {
+        let fees = Fees {
+            admin_trade_fee_numerator: 0,
+            admin_trade_fee_denominator: 1,
+            admin_withdraw_fee_numerator: 1,
+            admin_withdraw_fee_denominator: 2,
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            withdraw_fee_numerator: 1,
+            withdraw_fee_denominator: 2,
+            offpeg_fee_multiplier: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let calculator = PoolTokenConverter {
+            supply: 10.into(),
+            token_a: 2.into(),
+            token_b: 50.into(),
+            fees: &fees,
+        };
+        // Deposits round up and pay no withdraw fee
+        assert_eq!(calculator.token_a_deposit_rate(5.into()), Ok(1.into()));
+        assert_eq!(calculator.token_a_deposit_rate(6.into()), Ok(2.into()));
+        assert_eq!(calculator.token_b_deposit_rate(5.into()), Ok(25.into()));
+        assert_eq!(calculator.token_b_deposit_rate(0.into()), Ok(0.into()));
+
+        let calculator = PoolTokenConverter {
+            supply: 0.into(),
+            ..calculator
+        };
+        assert_eq!(
+            calculator.token_a_deposit_rate(5.into()),
+            Err(CurveError::ZeroLiquidity)
+        );
     }
 }
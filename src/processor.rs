@@ -3,20 +3,27 @@
 use crate::{
     admin::process_admin_instruction,
     bn::U256,
-    curve::{StableSwap, MAX_AMP, MIN_AMP, ZERO_TS},
+    constant_price::ConstantPrice,
+    constant_product::ConstantProduct,
+    curve::{
+        CurveCalculator, CurveType, RampType, StableSwap, MAX_AMP, MAX_N_COINS, MIN_AMP,
+        MIN_N_COINS, RATE_PRECISION, ZERO_TS,
+    },
     error::SwapError,
     fees::Fees,
     instruction::{
-        AdminInstruction, DepositData, InitializeData, SwapData, SwapInstruction, WithdrawData,
-        WithdrawOneData,
+        AdminInstruction, DepositData, DepositExactLpData, DepositMultiData, InitializeData,
+        InitializeMultiData, SwapData, SwapExactOutData, SwapInstruction, SwapMultiData,
+        WithdrawData, WithdrawImbalanceData, WithdrawMultiData, WithdrawOneData,
     },
+    math::TryMath,
     pool_converter::PoolTokenConverter,
-    state::SwapInfo,
+    state::{MultiSwapInfo, SwapInfo},
     utils,
 };
 use num_traits::FromPrimitive;
 use solana_program::{
-    account_info::{next_account_info, AccountInfo},
+    account_info::{next_account_info, next_account_infos, AccountInfo},
     decode_error::DecodeError,
     entrypoint::ProgramResult,
     msg,
@@ -39,6 +46,76 @@ impl Processor {
         Mint::unpack(data).map_err(|_| SwapError::ExpectedMint)
     }
 
+    /// StableSwap calculator of a multi-token swap at `current_ts`, with the
+    /// swap's precision multipliers in pool order
+    pub fn multi_calculator(token_swap: &MultiSwapInfo, current_ts: i64) -> StableSwap {
+        StableSwap::new(
+            token_swap.initial_amp_factor,
+            token_swap.target_amp_factor,
+            current_ts,
+            token_swap.start_ramp_ts,
+            token_swap.stop_ramp_ts,
+        )
+        .with_ramp_type(token_swap.ramp_type)
+        .with_precision_multipliers(
+            &token_swap.precision_multipliers[..token_swap.n_coins as usize],
+        )
+    }
+
+    /// Calculator of the swap's curve at `current_ts`, with precision
+    /// multipliers and rates in the order the calculator takes amounts
+    pub fn calculator(
+        token_swap: &SwapInfo,
+        current_ts: i64,
+        precision_multipliers: &[u64],
+        rates: &[u64],
+    ) -> Box<dyn CurveCalculator> {
+        match token_swap.curve_type {
+            CurveType::StableSwap => Box::new(
+                StableSwap::new(
+                    token_swap.initial_amp_factor,
+                    token_swap.target_amp_factor,
+                    current_ts,
+                    token_swap.start_ramp_ts,
+                    token_swap.stop_ramp_ts,
+                )
+                .with_ramp_type(token_swap.ramp_type)
+                .with_precision_multipliers(precision_multipliers)
+                .with_rates(rates),
+            ),
+            CurveType::ConstantProduct => Box::new(ConstantProduct::new(precision_multipliers)),
+            CurveType::ConstantPrice => Box::new(ConstantPrice::new(precision_multipliers, rates)),
+        }
+    }
+
+    /// Reads the rate of token B in token A from the swap's rate provider
+    /// account, or the constant price of constant price pools, or 1:1 if the
+    /// swap has neither. Rates older than the swap's `max_rate_age` at
+    /// `current_ts` are rejected.
+    pub fn token_b_rate(
+        token_swap: &SwapInfo,
+        rate_provider_info: Option<&AccountInfo>,
+        current_ts: i64,
+    ) -> Result<u64, ProgramError> {
+        if !token_swap.has_rate_provider() {
+            return Ok(match token_swap.curve_type {
+                CurveType::ConstantPrice => token_swap.token_b_price,
+                _ => RATE_PRECISION,
+            });
+        }
+        let rate_provider_info = rate_provider_info.ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if *rate_provider_info.key != token_swap.rate_provider
+            || *rate_provider_info.owner != token_swap.rate_provider_program
+        {
+            return Err(SwapError::InvalidRateProvider.into());
+        }
+        let rate_info = utils::unpack_rate_info(&rate_provider_info.data.borrow())?;
+        if current_ts.saturating_sub(rate_info.last_update_ts) > token_swap.max_rate_age {
+            return Err(SwapError::StaleRate.into());
+        }
+        Ok(rate_info.rate)
+    }
+
     /// Issue a spl_token `Burn` instruction.
     pub fn token_burn<'a>(
         swap: &Pubkey,
@@ -123,11 +200,16 @@ impl Processor {
     }
 
     /// Processes an [Initialize](enum.Instruction.html).
+    #[allow(clippy::too_many_arguments)]
     pub fn process_initialize(
         program_id: &Pubkey,
         nonce: u8,
         amp_factor: u64,
         fees: Fees,
+        rate_provider_program: Pubkey,
+        max_rate_age: i64,
+        curve_type: CurveType,
+        token_b_price: u64,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -143,10 +225,18 @@ impl Processor {
         let pool_mint_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?; // Destination account to mint LP tokens to
         let token_program_info = next_account_info(account_info_iter)?;
+        let rate_provider_info = account_info_iter.next();
 
         if !(MIN_AMP..=MAX_AMP).contains(&amp_factor) {
             return Err(SwapError::InvalidInput.into());
         }
+        if curve_type == CurveType::ConstantPrice && token_b_price == 0 {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if curve_type == CurveType::ConstantProduct && fees.has_offpeg_fees() {
+            return Err(SwapError::InvalidInput.into());
+        }
+        fees.validate()?;
 
         let token_swap = SwapInfo::unpack_unchecked(&swap_info.data.borrow())?;
         if token_swap.is_initialized {
@@ -208,12 +298,10 @@ impl Processor {
         }
         let token_a_mint = Self::unpack_mint(&token_a_mint_info.data.borrow())?;
         let token_b_mint = Self::unpack_mint(&token_b_mint_info.data.borrow())?;
-        if token_a_mint.decimals != token_b_mint.decimals {
-            return Err(SwapError::MismatchedDecimals.into());
-        }
-        if pool_mint.decimals != token_a_mint.decimals {
-            return Err(SwapError::MismatchedDecimals.into());
-        }
+        let token_a_precision_multiplier =
+            utils::precision_multiplier(token_a_mint.decimals, pool_mint.decimals)?;
+        let token_b_precision_multiplier =
+            utils::precision_multiplier(token_b_mint.decimals, pool_mint.decimals)?;
         let admin_fee_key_a = utils::unpack_token_account(&admin_fee_a_info.data.borrow())?;
         let admin_fee_key_b = utils::unpack_token_account(&admin_fee_b_info.data.borrow())?;
         if token_a.mint != admin_fee_key_a.mint {
@@ -223,21 +311,25 @@ impl Processor {
             return Err(SwapError::InvalidAdmin.into());
         }
 
-        // amp_factor == intial_amp_factor == target_amp_factor on init
-        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
-        // Compute amount of LP tokens to mint for bootstrapper
-        let mint_amount = invariant
-            .compute_d(U256::from(token_a.amount), U256::from(token_b.amount))
-            .ok_or(SwapError::CalculationFailure)?;
-        Self::token_mint_to(
-            swap_info.key,
-            token_program_info.clone(),
-            pool_mint_info.clone(),
-            destination_info.clone(),
-            authority_info.clone(),
-            nonce,
-            U256::to_u64(mint_amount)?,
-        )?;
+        // Only StableSwap pools value token B at a floating rate
+        let (rate_provider, token_b_rate) = match rate_provider_info {
+            Some(_) if curve_type != CurveType::StableSwap => {
+                return Err(SwapError::InvalidRateProvider.into());
+            }
+            Some(rate_provider_info) => {
+                if rate_provider_program == Pubkey::default()
+                    || *rate_provider_info.owner != rate_provider_program
+                {
+                    return Err(SwapError::InvalidRateProvider.into());
+                }
+                (
+                    *rate_provider_info.key,
+                    utils::unpack_rate_info(&rate_provider_info.data.borrow())?.rate,
+                )
+            }
+            None if curve_type == CurveType::ConstantPrice => (Pubkey::default(), token_b_price),
+            None => (Pubkey::default(), RATE_PRECISION),
+        };
 
         let obj = SwapInfo {
             is_initialized: true,
@@ -258,7 +350,49 @@ impl Processor {
             admin_fee_key_a: *admin_fee_a_info.key,
             admin_fee_key_b: *admin_fee_b_info.key,
             fees,
+            token_a_precision_multiplier,
+            token_b_precision_multiplier,
+            rate_provider,
+            rate_provider_program,
+            max_rate_age,
+            curve_type,
+            token_b_price,
+            ramp_type: RampType::Linear,
+            future_fees: Fees::default(),
+            future_fees_ts: ZERO_TS,
+            admin_fees_a: 0,
+            admin_fees_b: 0,
+            total_volume_a: 0,
+            total_volume_b: 0,
+            total_lp_fees_a: 0,
+            total_lp_fees_b: 0,
+            total_admin_fees_a: 0,
+            total_admin_fees_b: 0,
+            swap_count: 0,
+            last_trade_ts: ZERO_TS,
         };
+
+        // amp_factor == intial_amp_factor == target_amp_factor on init
+        let invariant = Self::calculator(
+            &obj,
+            ZERO_TS,
+            &obj.precision_multipliers(),
+            &[RATE_PRECISION, token_b_rate],
+        );
+        // Compute amount of LP tokens to mint for bootstrapper, in pool token precision
+        let mint_amount = invariant
+            .xp(&[U256::from(token_a.amount), U256::from(token_b.amount)])
+            .and_then(|xp| invariant.compute_d_n(&xp))?;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            nonce,
+            U256::to_u64(mint_amount)?,
+        )?;
+
         SwapInfo::pack(obj, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
@@ -277,11 +411,16 @@ impl Processor {
         let swap_source_info = next_account_info(account_info_iter)?;
         let swap_destination_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
-        let admin_destination_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
         let clock_sysvar_info = next_account_info(account_info_iter)?;
 
-        let token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let rate_provider_info = if token_swap.has_rate_provider() {
+            account_info_iter.next()
+        } else {
+            None
+        };
+        let host_fee_info = account_info_iter.next();
         if token_swap.is_paused {
             return Err(SwapError::IsPaused.into());
         }
@@ -299,16 +438,6 @@ impl Processor {
         {
             return Err(SwapError::IncorrectSwapAccount.into());
         }
-        if *swap_destination_info.key == token_swap.token_a
-            && *admin_destination_info.key != token_swap.admin_fee_key_a
-        {
-            return Err(SwapError::InvalidAdmin.into());
-        }
-        if *swap_destination_info.key == token_swap.token_b
-            && *admin_destination_info.key != token_swap.admin_fee_key_b
-        {
-            return Err(SwapError::InvalidAdmin.into());
-        }
         if *swap_source_info.key == *swap_destination_info.key {
             return Err(SwapError::InvalidInput.into());
         }
@@ -317,22 +446,37 @@ impl Processor {
         let swap_source_account = utils::unpack_token_account(&swap_source_info.data.borrow())?;
         let swap_destination_account =
             utils::unpack_token_account(&swap_destination_info.data.borrow())?;
+        if let Some(host_fee_info) = host_fee_info {
+            let host_fee_account = utils::unpack_token_account(&host_fee_info.data.borrow())?;
+            if host_fee_account.mint != swap_destination_account.mint {
+                return Err(SwapError::IncorrectMint.into());
+            }
+        }
 
-        let invariant = StableSwap::new(
-            token_swap.initial_amp_factor,
-            token_swap.target_amp_factor,
+        let mut precision_multipliers = token_swap.precision_multipliers();
+        let mut rates = [
+            RATE_PRECISION,
+            Self::token_b_rate(&token_swap, rate_provider_info, clock.unix_timestamp)?,
+        ];
+        if *swap_source_info.key == token_swap.token_b {
+            precision_multipliers.reverse();
+            rates.reverse();
+        }
+        let invariant = Self::calculator(
+            &token_swap,
             clock.unix_timestamp,
-            token_swap.start_ramp_ts,
-            token_swap.stop_ramp_ts,
+            &precision_multipliers,
+            &rates,
         );
-        let result = invariant
-            .swap_to(
-                U256::from(amount_in),
-                U256::from(swap_source_account.amount),
-                U256::from(swap_destination_account.amount),
-                &token_swap.fees,
-            )
-            .ok_or(SwapError::CalculationFailure)?;
+        let result = invariant.swap_to(
+            U256::from(amount_in),
+            U256::from(token_swap.pool_amount(swap_source_info.key, swap_source_account.amount)?),
+            U256::from(
+                token_swap
+                    .pool_amount(swap_destination_info.key, swap_destination_account.amount)?,
+            ),
+            &token_swap.fees,
+        )?;
         let amount_swapped = U256::to_u64(result.amount_swapped)?;
         if amount_swapped < minimum_amount_out {
             return Err(SwapError::ExceededSlippage.into());
@@ -356,15 +500,138 @@ impl Processor {
             token_swap.nonce,
             amount_swapped,
         )?;
+        let mut admin_fee = result.admin_fee;
+        if let Some(host_fee_info) = host_fee_info {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                swap_destination_info.clone(),
+                host_fee_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce,
+                U256::to_u64(result.host_fee)?,
+            )?;
+            admin_fee = admin_fee
+                .checked_sub(result.host_fee)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+        token_swap.accrue_admin_fees(swap_destination_info.key, U256::to_u64(admin_fee)?)?;
+        token_swap.record_trade(swap_source_info.key, amount_in, 0, 0)?;
+        token_swap.record_trade(
+            swap_destination_info.key,
+            0,
+            U256::to_u64(result.lp_fee)?,
+            U256::to_u64(result.admin_fee)?,
+        )?;
+        token_swap.swap_count = token_swap.swap_count.saturating_add(1);
+        token_swap.last_trade_ts = clock.unix_timestamp;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [SwapExactOut](enum.Instruction.html).
+    pub fn process_swap_exact_out(
+        program_id: &Pubkey,
+        amount_out: u64,
+        maximum_amount_in: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let swap_source_info = next_account_info(account_info_iter)?;
+        let swap_destination_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let rate_provider_info = account_info_iter.next();
+
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_paused {
+            return Err(SwapError::IsPaused.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !(*swap_source_info.key == token_swap.token_a
+            || *swap_source_info.key == token_swap.token_b)
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if !(*swap_destination_info.key == token_swap.token_a
+            || *swap_destination_info.key == token_swap.token_b)
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *swap_source_info.key == *swap_destination_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+
+        let clock = Clock::from_account_info(clock_sysvar_info)?;
+        let swap_source_account = utils::unpack_token_account(&swap_source_info.data.borrow())?;
+        let swap_destination_account =
+            utils::unpack_token_account(&swap_destination_info.data.borrow())?;
+
+        let mut precision_multipliers = token_swap.precision_multipliers();
+        let mut rates = [
+            RATE_PRECISION,
+            Self::token_b_rate(&token_swap, rate_provider_info, clock.unix_timestamp)?,
+        ];
+        if *swap_source_info.key == token_swap.token_b {
+            precision_multipliers.reverse();
+            rates.reverse();
+        }
+        let invariant = Self::calculator(
+            &token_swap,
+            clock.unix_timestamp,
+            &precision_multipliers,
+            &rates,
+        );
+        let (amount_in, result) = invariant.swap_to_exact_out(
+            U256::from(amount_out),
+            U256::from(token_swap.pool_amount(swap_source_info.key, swap_source_account.amount)?),
+            U256::from(
+                token_swap
+                    .pool_amount(swap_destination_info.key, swap_destination_account.amount)?,
+            ),
+            &token_swap.fees,
+        )?;
+        let amount_in = U256::to_u64(amount_in)?;
+        if amount_in > maximum_amount_in {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            swap_source_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            amount_in,
+        )?;
         Self::token_transfer(
             swap_info.key,
             token_program_info.clone(),
             swap_destination_info.clone(),
-            admin_destination_info.clone(),
+            destination_info.clone(),
             authority_info.clone(),
             token_swap.nonce,
+            amount_out,
+        )?;
+        token_swap.accrue_admin_fees(swap_destination_info.key, U256::to_u64(result.admin_fee)?)?;
+        token_swap.record_trade(swap_source_info.key, amount_in, 0, 0)?;
+        token_swap.record_trade(
+            swap_destination_info.key,
+            0,
+            U256::to_u64(result.lp_fee)?,
             U256::to_u64(result.admin_fee)?,
         )?;
+        token_swap.swap_count = token_swap.swap_count.saturating_add(1);
+        token_swap.last_trade_ts = clock.unix_timestamp;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
 
@@ -387,8 +654,9 @@ impl Processor {
         let dest_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
         let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let rate_provider_info = account_info_iter.next();
 
-        let token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
         if token_swap.is_paused {
             return Err(SwapError::IsPaused.into());
         }
@@ -411,24 +679,24 @@ impl Processor {
         let token_b = utils::unpack_token_account(&token_b_info.data.borrow())?;
         let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
 
-        let invariant = StableSwap::new(
-            token_swap.initial_amp_factor,
-            token_swap.target_amp_factor,
+        let invariant = Self::calculator(
+            &token_swap,
             clock.unix_timestamp,
-            token_swap.start_ramp_ts,
-            token_swap.stop_ramp_ts,
+            &token_swap.precision_multipliers(),
+            &[
+                RATE_PRECISION,
+                Self::token_b_rate(&token_swap, rate_provider_info, clock.unix_timestamp)?,
+            ],
         );
-        let mint_amount_u256 = invariant
-            .compute_mint_amount_for_deposit(
-                U256::from(token_a_amount),
-                U256::from(token_b_amount),
-                U256::from(token_a.amount),
-                U256::from(token_b.amount),
-                U256::from(pool_mint.supply),
-                &token_swap.fees,
-            )
-            .ok_or(SwapError::CalculationFailure)?;
-        let mint_amount = U256::to_u64(mint_amount_u256)?;
+        let deposit = invariant.preview_deposit(
+            U256::from(token_a_amount),
+            U256::from(token_b_amount),
+            U256::from(token_swap.pool_amount(token_a_info.key, token_a.amount)?),
+            U256::from(token_swap.pool_amount(token_b_info.key, token_b.amount)?),
+            U256::from(pool_mint.supply),
+            &token_swap.fees,
+        )?;
+        let mint_amount = U256::to_u64(deposit.result.mint_amount)?;
         if mint_amount < min_mint_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
@@ -460,6 +728,101 @@ impl Processor {
             token_swap.nonce,
             mint_amount,
         )?;
+        // Imbalance fees stay in the pool
+        token_swap.record_trade(token_a_info.key, 0, U256::to_u64(deposit.fees[0])?, 0)?;
+        token_swap.record_trade(token_b_info.key, 0, U256::to_u64(deposit.fees[1])?, 0)?;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [DepositExactLp](enum.Instruction.html).
+    pub fn process_deposit_exact_lp(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        max_token_a: u64,
+        max_token_b: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let source_b_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_paused {
+            return Err(SwapError::IsPaused.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectMint.into());
+        }
+        let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
+        if pool_mint.supply == 0 {
+            return Err(SwapError::EmptyPool.into());
+        }
+
+        let token_a = utils::unpack_token_account(&token_a_info.data.borrow())?;
+        let token_b = utils::unpack_token_account(&token_b_info.data.borrow())?;
+
+        let converter = PoolTokenConverter {
+            supply: U256::from(pool_mint.supply),
+            token_a: U256::from(token_swap.pool_amount(token_a_info.key, token_a.amount)?),
+            token_b: U256::from(token_swap.pool_amount(token_b_info.key, token_b.amount)?),
+            fees: &token_swap.fees,
+        };
+        let pool_token_amount_u256 = U256::from(pool_token_amount);
+        let a_amount = U256::to_u64(converter.token_a_deposit_rate(pool_token_amount_u256)?)?;
+        if a_amount > max_token_a {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        let b_amount = U256::to_u64(converter.token_b_deposit_rate(pool_token_amount_u256)?)?;
+        if b_amount > max_token_b {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_a_info.clone(),
+            token_a_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            a_amount,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_b_info.clone(),
+            token_b_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            b_amount,
+        )?;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            pool_token_amount,
+        )?;
+        // Proportional deposits pay no fees, so the trade counters are unchanged
         Ok(())
     }
 
@@ -480,11 +843,9 @@ impl Processor {
         let token_b_info = next_account_info(account_info_iter)?;
         let dest_token_a_info = next_account_info(account_info_iter)?;
         let dest_token_b_info = next_account_info(account_info_iter)?;
-        let admin_fee_dest_a_info = next_account_info(account_info_iter)?;
-        let admin_fee_dest_b_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
 
-        let token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
         if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
         {
             return Err(SwapError::InvalidProgramAddress.into());
@@ -498,12 +859,6 @@ impl Processor {
         if *pool_mint_info.key != token_swap.pool_mint {
             return Err(SwapError::IncorrectMint.into());
         }
-        if *admin_fee_dest_a_info.key != token_swap.admin_fee_key_a {
-            return Err(SwapError::InvalidAdmin.into());
-        }
-        if *admin_fee_dest_b_info.key != token_swap.admin_fee_key_b {
-            return Err(SwapError::InvalidAdmin.into());
-        }
         let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
         if pool_mint.supply == 0 {
             return Err(SwapError::EmptyPool.into());
@@ -514,14 +869,13 @@ impl Processor {
 
         let converter = PoolTokenConverter {
             supply: U256::from(pool_mint.supply),
-            token_a: U256::from(token_a.amount),
-            token_b: U256::from(token_b.amount),
+            token_a: U256::from(token_swap.pool_amount(token_a_info.key, token_a.amount)?),
+            token_b: U256::from(token_swap.pool_amount(token_b_info.key, token_b.amount)?),
             fees: &token_swap.fees,
         };
         let pool_token_amount_u256 = U256::from(pool_token_amount);
-        let (a_amount_u256, a_admin_fee_u256) = converter
-            .token_a_rate(pool_token_amount_u256)
-            .ok_or(SwapError::CalculationFailure)?;
+        let (a_amount_u256, a_fee_u256, a_admin_fee_u256) =
+            converter.token_withdrawal(pool_token_amount_u256, converter.token_a)?;
         let (a_amount, a_admin_fee) = (
             U256::to_u64(a_amount_u256)?,
             U256::to_u64(a_admin_fee_u256)?,
@@ -529,9 +883,8 @@ impl Processor {
         if a_amount < minimum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        let (b_amount_u256, b_admin_fee_u256) = converter
-            .token_b_rate(pool_token_amount_u256)
-            .ok_or(SwapError::CalculationFailure)?;
+        let (b_amount_u256, b_fee_u256, b_admin_fee_u256) =
+            converter.token_withdrawal(pool_token_amount_u256, converter.token_b)?;
         let (b_amount, b_admin_fee) = (
             U256::to_u64(b_amount_u256)?,
             U256::to_u64(b_admin_fee_u256)?,
@@ -549,15 +902,6 @@ impl Processor {
             token_swap.nonce,
             a_amount,
         )?;
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            token_a_info.clone(),
-            admin_fee_dest_a_info.clone(),
-            authority_info.clone(),
-            token_swap.nonce,
-            a_admin_fee,
-        )?;
         Self::token_transfer(
             swap_info.key,
             token_program_info.clone(),
@@ -567,15 +911,6 @@ impl Processor {
             token_swap.nonce,
             b_amount,
         )?;
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            token_b_info.clone(),
-            admin_fee_dest_b_info.clone(),
-            authority_info.clone(),
-            token_swap.nonce,
-            b_admin_fee,
-        )?;
         Self::token_burn(
             swap_info.key,
             token_program_info.clone(),
@@ -585,6 +920,29 @@ impl Processor {
             token_swap.nonce,
             pool_token_amount,
         )?;
+        token_swap.accrue_admin_fees(token_a_info.key, a_admin_fee)?;
+        token_swap.accrue_admin_fees(token_b_info.key, b_admin_fee)?;
+        token_swap.record_trade(
+            token_a_info.key,
+            0,
+            U256::to_u64(
+                a_fee_u256
+                    .checked_sub(a_admin_fee_u256)
+                    .ok_or(SwapError::CalculationFailure)?,
+            )?,
+            a_admin_fee,
+        )?;
+        token_swap.record_trade(
+            token_b_info.key,
+            0,
+            U256::to_u64(
+                b_fee_u256
+                    .checked_sub(b_admin_fee_u256)
+                    .ok_or(SwapError::CalculationFailure)?,
+            )?,
+            b_admin_fee,
+        )?;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
 
@@ -603,14 +961,19 @@ impl Processor {
         let base_token_info = next_account_info(account_info_iter)?;
         let quote_token_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
-        let admin_destination_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
         let clock_sysvar_info = next_account_info(account_info_iter)?;
 
         if *base_token_info.key == *quote_token_info.key {
             return Err(SwapError::InvalidInput.into());
         }
-        let token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        let rate_provider_info = if token_swap.has_rate_provider() {
+            account_info_iter.next()
+        } else {
+            None
+        };
+        let host_fee_info = account_info_iter.next();
         if token_swap.is_paused {
             return Err(SwapError::IsPaused.into());
         }
@@ -627,16 +990,6 @@ impl Processor {
         {
             return Err(SwapError::IncorrectSwapAccount.into());
         }
-        if *base_token_info.key == token_swap.token_a
-            && *admin_destination_info.key != token_swap.admin_fee_key_a
-        {
-            return Err(SwapError::InvalidAdmin.into());
-        }
-        if *base_token_info.key == token_swap.token_b
-            && *admin_destination_info.key != token_swap.admin_fee_key_b
-        {
-            return Err(SwapError::InvalidAdmin.into());
-        }
         if *pool_mint_info.key != token_swap.pool_mint {
             return Err(SwapError::IncorrectMint.into());
         }
@@ -648,27 +1001,37 @@ impl Processor {
         let clock = Clock::from_account_info(clock_sysvar_info)?;
         let base_token = utils::unpack_token_account(&base_token_info.data.borrow())?;
         let quote_token = utils::unpack_token_account(&quote_token_info.data.borrow())?;
+        if let Some(host_fee_info) = host_fee_info {
+            let host_fee_account = utils::unpack_token_account(&host_fee_info.data.borrow())?;
+            if host_fee_account.mint != base_token.mint {
+                return Err(SwapError::IncorrectMint.into());
+            }
+        }
 
-        let invariant = StableSwap::new(
-            token_swap.initial_amp_factor,
-            token_swap.target_amp_factor,
+        let mut precision_multipliers = token_swap.precision_multipliers();
+        let mut rates = [
+            RATE_PRECISION,
+            Self::token_b_rate(&token_swap, rate_provider_info, clock.unix_timestamp)?,
+        ];
+        if *base_token_info.key == token_swap.token_b {
+            precision_multipliers.reverse();
+            rates.reverse();
+        }
+        let invariant = Self::calculator(
+            &token_swap,
             clock.unix_timestamp,
-            token_swap.start_ramp_ts,
-            token_swap.stop_ramp_ts,
+            &precision_multipliers,
+            &rates,
         );
-        let (dy, dy_fee) = invariant
-            .compute_withdraw_one(
-                U256::from(pool_token_amount),
-                U256::from(pool_mint.supply),
-                U256::from(base_token.amount),
-                U256::from(quote_token.amount),
-                &token_swap.fees,
-            )
-            .ok_or(SwapError::CalculationFailure)?;
-        let withdraw_fee = token_swap
-            .fees
-            .withdraw_fee(dy)
-            .ok_or(SwapError::CalculationFailure)?;
+        let withdrawal = invariant.compute_withdraw_one(
+            U256::from(pool_token_amount),
+            U256::from(pool_mint.supply),
+            U256::from(token_swap.pool_amount(base_token_info.key, base_token.amount)?),
+            U256::from(token_swap.pool_amount(quote_token_info.key, quote_token.amount)?),
+            &token_swap.fees,
+        )?;
+        let dy = withdrawal.amount_withdrawn;
+        let withdraw_fee = token_swap.fees.withdraw_fee(dy)?;
         let token_amount = U256::to_u64(
             dy.checked_sub(withdraw_fee)
                 .ok_or(SwapError::CalculationFailure)?,
@@ -677,17 +1040,22 @@ impl Processor {
             return Err(SwapError::ExceededSlippage.into());
         }
 
-        let admin_trade_fee = token_swap
-            .fees
-            .admin_trade_fee(dy_fee)
-            .ok_or(SwapError::CalculationFailure)?;
-        let admin_withdraw_fee = token_swap
-            .fees
-            .admin_withdraw_fee(withdraw_fee)
-            .ok_or(SwapError::CalculationFailure)?;
-        let admin_fee = admin_trade_fee
+        let admin_trade_fee = withdrawal.admin_fee;
+        let admin_withdraw_fee = token_swap.fees.admin_withdraw_fee(withdraw_fee)?;
+        let mut admin_fee = admin_trade_fee
             .checked_add(admin_withdraw_fee)
             .ok_or(SwapError::CalculationFailure)?;
+        let lp_fee = withdrawal
+            .lp_fee
+            .checked_add(withdraw_fee)
+            .and_then(|fee| fee.checked_sub(admin_withdraw_fee))
+            .ok_or(SwapError::CalculationFailure)?;
+        token_swap.record_trade(
+            base_token_info.key,
+            0,
+            U256::to_u64(lp_fee)?,
+            U256::to_u64(admin_fee)?,
+        )?;
 
         Self::token_transfer(
             swap_info.key,
@@ -698,14 +1066,120 @@ impl Processor {
             token_swap.nonce,
             token_amount,
         )?;
+        if let Some(host_fee_info) = host_fee_info {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                base_token_info.clone(),
+                host_fee_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce,
+                U256::to_u64(withdrawal.host_fee)?,
+            )?;
+            admin_fee = admin_fee
+                .checked_sub(withdrawal.host_fee)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            pool_token_amount,
+        )?;
+        token_swap.accrue_admin_fees(base_token_info.key, U256::to_u64(admin_fee)?)?;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [WithdrawImbalance](enum.Instruction.html).
+    pub fn process_withdraw_imbalance(
+        program_id: &Pubkey,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        max_burn_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dest_token_a_info = next_account_info(account_info_iter)?;
+        let dest_token_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let rate_provider_info = account_info_iter.next();
+
+        let mut token_swap = SwapInfo::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_paused {
+            return Err(SwapError::IsPaused.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectMint.into());
+        }
+        let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
+        if pool_mint.supply == 0 {
+            return Err(SwapError::EmptyPool.into());
+        }
+
+        let clock = Clock::from_account_info(clock_sysvar_info)?;
+        let token_a = utils::unpack_token_account(&token_a_info.data.borrow())?;
+        let token_b = utils::unpack_token_account(&token_b_info.data.borrow())?;
+
+        let invariant = Self::calculator(
+            &token_swap,
+            clock.unix_timestamp,
+            &token_swap.precision_multipliers(),
+            &[
+                RATE_PRECISION,
+                Self::token_b_rate(&token_swap, rate_provider_info, clock.unix_timestamp)?,
+            ],
+        );
+        let withdrawal = invariant.preview_withdraw_imbalance(
+            U256::from(token_a_amount),
+            U256::from(token_b_amount),
+            U256::from(token_swap.pool_amount(token_a_info.key, token_a.amount)?),
+            U256::from(token_swap.pool_amount(token_b_info.key, token_b.amount)?),
+            U256::from(pool_mint.supply),
+            &token_swap.fees,
+        )?;
+        let burn_amount = U256::to_u64(withdrawal.burn_amount)?;
+        if burn_amount > max_burn_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            token_a_info.clone(),
+            dest_token_a_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            token_a_amount,
+        )?;
         Self::token_transfer(
             swap_info.key,
             token_program_info.clone(),
-            base_token_info.clone(),
-            admin_destination_info.clone(),
+            token_b_info.clone(),
+            dest_token_b_info.clone(),
             authority_info.clone(),
             token_swap.nonce,
-            U256::to_u64(admin_fee)?,
+            token_b_amount,
         )?;
         Self::token_burn(
             swap_info.key,
@@ -714,744 +1188,2612 @@ impl Processor {
             pool_mint_info.clone(),
             authority_info.clone(),
             token_swap.nonce,
-            pool_token_amount,
+            burn_amount,
         )?;
+        // Imbalance fees stay in the pool
+        token_swap.record_trade(token_a_info.key, 0, U256::to_u64(withdrawal.fees[0])?, 0)?;
+        token_swap.record_trade(token_b_info.key, 0, U256::to_u64(withdrawal.fees[1])?, 0)?;
+        SwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        let instruction = AdminInstruction::unpack(input)?;
-        match instruction {
-            None => Self::process_swap_instruction(program_id, accounts, input),
-            Some(admin_instruction) => {
-                process_admin_instruction(&admin_instruction, program_id, accounts)
-            }
-        }
-    }
-
-    fn process_swap_instruction(
+    /// Processes an [InitializeMulti](enum.Instruction.html).
+    pub fn process_initialize_multi(
         program_id: &Pubkey,
+        nonce: u8,
+        n_coins: u8,
+        amp_factor: u64,
+        fees: Fees,
         accounts: &[AccountInfo],
-        input: &[u8],
     ) -> ProgramResult {
-        let instruction = SwapInstruction::unpack(input)?;
-        match instruction {
-            SwapInstruction::Initialize(InitializeData {
-                nonce,
-                amp_factor,
-                fees,
-            }) => {
-                msg!("Instruction: Init");
-                Self::process_initialize(program_id, nonce, amp_factor, fees, accounts)
-            }
-            SwapInstruction::Swap(SwapData {
-                amount_in,
-                minimum_amount_out,
-            }) => {
-                msg!("Instruction: Swap");
-                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
-            }
-            SwapInstruction::Deposit(DepositData {
-                token_a_amount,
-                token_b_amount,
-                min_mint_amount,
-            }) => {
-                msg!("Instruction: Deposit");
-                Self::process_deposit(
-                    program_id,
-                    token_a_amount,
-                    token_b_amount,
-                    min_mint_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::Withdraw(WithdrawData {
-                pool_token_amount,
-                minimum_token_a_amount,
-                minimum_token_b_amount,
-            }) => {
-                msg!("Instruction: Withdraw");
-                Self::process_withdraw(
-                    program_id,
-                    pool_token_amount,
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::WithdrawOne(WithdrawOneData {
-                pool_token_amount,
-                minimum_token_amount,
-            }) => {
-                msg!("Instruction: Withdraw One");
-                Self::process_withdraw_one(
-                    program_id,
-                    pool_token_amount,
-                    minimum_token_amount,
-                    accounts,
-                )
-            }
+        let n = n_coins as usize;
+        if !(MIN_N_COINS..=MAX_N_COINS).contains(&n) {
+            return Err(SwapError::InvalidInput.into());
         }
-    }
-}
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_key_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?; // Destination account to mint LP tokens to
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_mint_infos = next_account_infos(account_info_iter, n)?;
+        let token_infos = next_account_infos(account_info_iter, n)?;
+        let admin_fee_infos = next_account_infos(account_info_iter, n)?;
 
-impl PrintProgramError for SwapError {
-    fn print<E>(&self)
-    where
-        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
-    {
-        match self {
-            SwapError::AlreadyInUse => msg!("Error: Swap account already in use"),
-            SwapError::InvalidAdmin => {
-                msg!("Error: Address of the admin fee account is incorrect")
-            }
-            SwapError::InvalidOwner => {
-                msg!("Error: The input account owner is not the program address")
-            }
-            SwapError::InvalidOutputOwner => {
-                msg!("Error: Output pool account owner cannot be the program address")
-            }
-            SwapError::InvalidProgramAddress => {
-                msg!("Error: Invalid program address generated from nonce and key")
-            }
-            SwapError::ExpectedMint => {
-                msg!("Error: Deserialized account is not an SPL Token mint")
+        if !(MIN_AMP..=MAX_AMP).contains(&amp_factor) {
+            return Err(SwapError::InvalidInput.into());
+        }
+        fees.validate()?;
+
+        let token_swap = MultiSwapInfo::unpack_unchecked(&swap_info.data.borrow())?;
+        if token_swap.is_initialized {
+            return Err(SwapError::AlreadyInUse.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, nonce)? {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let destination = utils::unpack_token_account(&destination_info.data.borrow())?;
+        if *authority_info.key == destination.owner {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
+        if pool_mint.mint_authority.is_some()
+            && *authority_info.key != pool_mint.mint_authority.unwrap()
+        {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if pool_mint.freeze_authority.is_some() {
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+        if pool_mint.supply != 0 {
+            return Err(SwapError::InvalidSupply.into());
+        }
+
+        let mut tokens = [Pubkey::default(); MAX_N_COINS];
+        let mut token_mints = [Pubkey::default(); MAX_N_COINS];
+        let mut admin_fee_keys = [Pubkey::default(); MAX_N_COINS];
+        let mut precision_multipliers = [0u64; MAX_N_COINS];
+        let mut amounts = Vec::with_capacity(n);
+        for i in 0..n {
+            let token = utils::unpack_token_account(&token_infos[i].data.borrow())?;
+            if *authority_info.key != token.owner {
+                return Err(SwapError::InvalidOwner.into());
             }
-            SwapError::ExpectedAccount => {
-                msg!("Error: Deserialized account is not an SPL Token account")
+            if token_mints[..i].contains(&token.mint) {
+                return Err(SwapError::RepeatedMint.into());
             }
-            SwapError::EmptySupply => msg!("Error: Input token account empty"),
-            SwapError::EmptyPool => msg!("Error: Pool token supply is 0"),
-            SwapError::InvalidSupply => msg!("Error: Pool token mint has a non-zero supply"),
-            SwapError::RepeatedMint => msg!("Error: Swap input token accounts have the same mint"),
-            SwapError::InvalidDelegate => msg!("Error: Token account has a delegate"),
-            SwapError::InvalidInput => msg!("Error: InvalidInput"),
-            SwapError::IncorrectSwapAccount => {
-                msg!("Error: Address of the provided swap token account is incorrect")
+            if token.amount == 0 {
+                return Err(SwapError::EmptySupply.into());
             }
-            SwapError::IncorrectMint => {
-                msg!("Error: Address of the provided token mint is incorrect")
+            if token.delegate.is_some() {
+                return Err(SwapError::InvalidDelegate.into());
             }
-            SwapError::CalculationFailure => msg!("Error: CalculationFailure"),
-            SwapError::InvalidInstruction => msg!("Error: InvalidInstruction"),
-            SwapError::ExceededSlippage => {
-                msg!("Error: Swap instruction exceeds desired slippage limit")
+            if token.mint != *token_mint_infos[i].key {
+                return Err(SwapError::IncorrectMint.into());
             }
-            SwapError::InvalidCloseAuthority => msg!("Error: Token account has a close authority"),
-            SwapError::InvalidFreezeAuthority => {
-                msg!("Error: Pool token mint has a freeze authority")
+            if token.close_authority.is_some() {
+                return Err(SwapError::InvalidCloseAuthority.into());
             }
-            SwapError::ConversionFailure => msg!("Error: Conversion to or from u64 failed"),
-            SwapError::Unauthorized => {
-                msg!("Error: Account is not authorized to execute this instruction")
+            let token_mint = Self::unpack_mint(&token_mint_infos[i].data.borrow())?;
+            precision_multipliers[i] =
+                utils::precision_multiplier(token_mint.decimals, pool_mint.decimals)?;
+            let admin_fee_key = utils::unpack_token_account(&admin_fee_infos[i].data.borrow())?;
+            if token.mint != admin_fee_key.mint {
+                return Err(SwapError::InvalidAdmin.into());
             }
-            SwapError::IsPaused => msg!("Error: Swap pool is paused"),
-            SwapError::RampLocked => msg!("Error: Ramp is locked in this time period"),
-            SwapError::InsufficientRampTime => msg!("Error: Insufficient ramp time"),
-            SwapError::ActiveTransfer => msg!("Error: Active admin transfer in progress"),
-            SwapError::NoActiveTransfer => msg!("Error: No active admin transfer in progress"),
-            SwapError::AdminDeadlineExceeded => msg!("Error: Admin transfer deadline exceeded"),
-            SwapError::MismatchedDecimals => msg!("Error: Token mints must have same decimals"),
+            tokens[i] = *token_infos[i].key;
+            token_mints[i] = token.mint;
+            admin_fee_keys[i] = *admin_fee_infos[i].key;
+            amounts.push(U256::from(token.amount));
         }
-    }
+
+        let obj = MultiSwapInfo {
+            is_initialized: true,
+            is_paused: false,
+            nonce,
+            n_coins,
+            initial_amp_factor: amp_factor,
+            target_amp_factor: amp_factor,
+            start_ramp_ts: ZERO_TS,
+            stop_ramp_ts: ZERO_TS,
+            future_admin_deadline: ZERO_TS,
+            future_admin_key: Pubkey::default(),
+            admin_key: *admin_key_info.key,
+            pool_mint: *pool_mint_info.key,
+            tokens,
+            token_mints,
+            admin_fee_keys,
+            fees,
+            precision_multipliers,
+            ramp_type: RampType::Linear,
+            future_fees: Fees::default(),
+            future_fees_ts: ZERO_TS,
+            admin_fees: [0; MAX_N_COINS],
+        };
+
+        // amp_factor == intial_amp_factor == target_amp_factor on init
+        let invariant = Self::multi_calculator(&obj, ZERO_TS);
+        // Compute amount of LP tokens to mint for bootstrapper, in pool token precision
+        let mint_amount = invariant
+            .xp(&amounts)
+            .and_then(|xp| invariant.compute_d_n(&xp))?;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            nonce,
+            U256::to_u64(mint_amount)?,
+        )?;
+        MultiSwapInfo::pack(obj, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [SwapMulti](enum.Instruction.html).
+    pub fn process_swap_multi(
+        program_id: &Pubkey,
+        token_index_in: u8,
+        token_index_out: u8,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+
+        let mut token_swap = MultiSwapInfo::unpack(&swap_info.data.borrow())?;
+        let n = token_swap.n_coins as usize;
+        let swap_token_infos = next_account_infos(account_info_iter, n)?;
+        let host_fee_info = account_info_iter.next();
+        if token_swap.is_paused {
+            return Err(SwapError::IsPaused.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let (i, j) = (token_index_in as usize, token_index_out as usize);
+        if i >= n || j >= n || i == j {
+            return Err(SwapError::InvalidInput.into());
+        }
+        for (swap_token_info, token) in swap_token_infos.iter().zip(token_swap.tokens.iter()) {
+            if swap_token_info.key != token {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            }
+        }
+        if let Some(host_fee_info) = host_fee_info {
+            let host_fee_account = utils::unpack_token_account(&host_fee_info.data.borrow())?;
+            if host_fee_account.mint != token_swap.token_mints[j] {
+                return Err(SwapError::IncorrectMint.into());
+            }
+        }
+
+        let clock = Clock::from_account_info(clock_sysvar_info)?;
+        let mut swap_amounts = Vec::with_capacity(n);
+        for (k, swap_token_info) in swap_token_infos.iter().enumerate() {
+            let swap_token = utils::unpack_token_account(&swap_token_info.data.borrow())?;
+            swap_amounts.push(U256::from(token_swap.pool_amount(k, swap_token.amount)?));
+        }
+
+        let invariant = Self::multi_calculator(&token_swap, clock.unix_timestamp);
+        let result =
+            invariant.swap_to_n(i, j, U256::from(amount_in), &swap_amounts, &token_swap.fees)?;
+        let amount_swapped = U256::to_u64(result.amount_swapped)?;
+        if amount_swapped < minimum_amount_out {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            swap_token_infos[i].clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            amount_in,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_infos[j].clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            amount_swapped,
+        )?;
+        let mut admin_fee = result.admin_fee;
+        if let Some(host_fee_info) = host_fee_info {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                swap_token_infos[j].clone(),
+                host_fee_info.clone(),
+                authority_info.clone(),
+                token_swap.nonce,
+                U256::to_u64(result.host_fee)?,
+            )?;
+            admin_fee = admin_fee
+                .checked_sub(result.host_fee)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+        token_swap.accrue_admin_fees(j, U256::to_u64(admin_fee)?)?;
+        MultiSwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [DepositMulti](enum.Instruction.html).
+    pub fn process_deposit_multi(
+        program_id: &Pubkey,
+        token_amounts: &[u64],
+        min_mint_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+
+        let token_swap = MultiSwapInfo::unpack(&swap_info.data.borrow())?;
+        let n = token_swap.n_coins as usize;
+        let source_infos = next_account_infos(account_info_iter, n)?;
+        let swap_token_infos = next_account_infos(account_info_iter, n)?;
+        if token_swap.is_paused {
+            return Err(SwapError::IsPaused.into());
+        }
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if token_amounts.len() != n {
+            return Err(SwapError::InvalidInput.into());
+        }
+        for (swap_token_info, token) in swap_token_infos.iter().zip(token_swap.tokens.iter()) {
+            if swap_token_info.key != token {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            }
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectMint.into());
+        }
+
+        let clock = Clock::from_account_info(clock_sysvar_info)?;
+        let mut swap_amounts = Vec::with_capacity(n);
+        for (i, swap_token_info) in swap_token_infos.iter().enumerate() {
+            let swap_token = utils::unpack_token_account(&swap_token_info.data.borrow())?;
+            swap_amounts.push(U256::from(token_swap.pool_amount(i, swap_token.amount)?));
+        }
+        let deposit_amounts: Vec<U256> = token_amounts.iter().map(|a| U256::from(*a)).collect();
+        let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
+
+        let invariant = Self::multi_calculator(&token_swap, clock.unix_timestamp);
+        let deposit = invariant.compute_mint_amount_for_deposit_n(
+            &deposit_amounts,
+            &swap_amounts,
+            U256::from(pool_mint.supply),
+            &token_swap.fees,
+        )?;
+        let mint_amount = U256::to_u64(deposit.mint_amount)?;
+        if mint_amount < min_mint_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        for i in 0..n {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_infos[i].clone(),
+                swap_token_infos[i].clone(),
+                authority_info.clone(),
+                token_swap.nonce,
+                token_amounts[i],
+            )?;
+        }
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            mint_amount,
+        )?;
+        Ok(())
+    }
+
+    /// Processes a [WithdrawMulti](enum.Instruction.html).
+    pub fn process_withdraw_multi(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        minimum_token_amounts: &[u64],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut token_swap = MultiSwapInfo::unpack(&swap_info.data.borrow())?;
+        let n = token_swap.n_coins as usize;
+        let swap_token_infos = next_account_infos(account_info_iter, n)?;
+        let dest_token_infos = next_account_infos(account_info_iter, n)?;
+        if *authority_info.key != utils::authority_id(program_id, swap_info.key, token_swap.nonce)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if minimum_token_amounts.len() != n {
+            return Err(SwapError::InvalidInput.into());
+        }
+        for (swap_token_info, token) in swap_token_infos.iter().zip(token_swap.tokens.iter()) {
+            if swap_token_info.key != token {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            }
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectMint.into());
+        }
+        let pool_mint = Self::unpack_mint(&pool_mint_info.data.borrow())?;
+        if pool_mint.supply == 0 {
+            return Err(SwapError::EmptyPool.into());
+        }
+
+        let mut swap_amounts = Vec::with_capacity(n);
+        for (i, swap_token_info) in swap_token_infos.iter().enumerate() {
+            let swap_token = utils::unpack_token_account(&swap_token_info.data.borrow())?;
+            swap_amounts.push(U256::from(token_swap.pool_amount(i, swap_token.amount)?));
+        }
+        let pool_token_amount_u256 = U256::from(pool_token_amount);
+        for (i, swap_amount) in swap_amounts.into_iter().enumerate() {
+            // Each token is withdrawn in proportion to the pool tokens burned
+            let amount_u256 = pool_token_amount_u256
+                .try_mul(swap_amount)?
+                .try_div(U256::from(pool_mint.supply))?;
+            let fee_u256 = token_swap.fees.withdraw_fee(amount_u256)?;
+            let admin_fee_u256 = token_swap.fees.admin_withdraw_fee(fee_u256)?;
+            let amount = U256::to_u64(amount_u256.try_sub(fee_u256)?)?;
+            let admin_fee = U256::to_u64(admin_fee_u256)?;
+            if amount < minimum_token_amounts[i] {
+                return Err(SwapError::ExceededSlippage.into());
+            }
+
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                swap_token_infos[i].clone(),
+                dest_token_infos[i].clone(),
+                authority_info.clone(),
+                token_swap.nonce,
+                amount,
+            )?;
+            token_swap.accrue_admin_fees(i, admin_fee)?;
+        }
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            authority_info.clone(),
+            token_swap.nonce,
+            pool_token_amount,
+        )?;
+        MultiSwapInfo::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let instruction = AdminInstruction::unpack(input)?;
+        match instruction {
+            None => Self::process_swap_instruction(program_id, accounts, input),
+            Some(admin_instruction) => {
+                process_admin_instruction(&admin_instruction, program_id, accounts)
+            }
+        }
+    }
+
+    fn process_swap_instruction(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        input: &[u8],
+    ) -> ProgramResult {
+        let instruction = SwapInstruction::unpack(input)?;
+        match instruction {
+            SwapInstruction::Initialize(InitializeData {
+                nonce,
+                amp_factor,
+                fees,
+                rate_provider_program,
+                max_rate_age,
+                curve_type,
+                token_b_price,
+            }) => {
+                msg!("Instruction: Init");
+                Self::process_initialize(
+                    program_id,
+                    nonce,
+                    amp_factor,
+                    fees,
+                    rate_provider_program,
+                    max_rate_age,
+                    curve_type,
+                    token_b_price,
+                    accounts,
+                )
+            }
+            SwapInstruction::Swap(SwapData {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: Swap");
+                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::Deposit(DepositData {
+                token_a_amount,
+                token_b_amount,
+                min_mint_amount,
+            }) => {
+                msg!("Instruction: Deposit");
+                Self::process_deposit(
+                    program_id,
+                    token_a_amount,
+                    token_b_amount,
+                    min_mint_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::Withdraw(WithdrawData {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+            }) => {
+                msg!("Instruction: Withdraw");
+                Self::process_withdraw(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::WithdrawOne(WithdrawOneData {
+                pool_token_amount,
+                minimum_token_amount,
+            }) => {
+                msg!("Instruction: Withdraw One");
+                Self::process_withdraw_one(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::InitializeMulti(InitializeMultiData {
+                nonce,
+                n_coins,
+                amp_factor,
+                fees,
+            }) => {
+                msg!("Instruction: Init Multi");
+                Self::process_initialize_multi(
+                    program_id, nonce, n_coins, amp_factor, fees, accounts,
+                )
+            }
+            SwapInstruction::SwapMulti(SwapMultiData {
+                token_index_in,
+                token_index_out,
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: Swap Multi");
+                Self::process_swap_multi(
+                    program_id,
+                    token_index_in,
+                    token_index_out,
+                    amount_in,
+                    minimum_amount_out,
+                    accounts,
+                )
+            }
+            SwapInstruction::DepositMulti(DepositMultiData {
+                token_amounts,
+                min_mint_amount,
+            }) => {
+                msg!("Instruction: Deposit Multi");
+                Self::process_deposit_multi(program_id, &token_amounts, min_mint_amount, accounts)
+            }
+            SwapInstruction::WithdrawMulti(WithdrawMultiData {
+                pool_token_amount,
+                minimum_token_amounts,
+            }) => {
+                msg!("Instruction: Withdraw Multi");
+                Self::process_withdraw_multi(
+                    program_id,
+                    pool_token_amount,
+                    &minimum_token_amounts,
+                    accounts,
+                )
+            }
+            SwapInstruction::SwapExactOut(SwapExactOutData {
+                amount_out,
+                maximum_amount_in,
+            }) => {
+                msg!("Instruction: Swap Exact Out");
+                Self::process_swap_exact_out(program_id, amount_out, maximum_amount_in, accounts)
+            }
+            SwapInstruction::WithdrawImbalance(WithdrawImbalanceData {
+                token_a_amount,
+                token_b_amount,
+                max_burn_amount,
+            }) => {
+                msg!("Instruction: Withdraw Imbalance");
+                Self::process_withdraw_imbalance(
+                    program_id,
+                    token_a_amount,
+                    token_b_amount,
+                    max_burn_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::DepositExactLp(DepositExactLpData {
+                pool_token_amount,
+                max_token_a,
+                max_token_b,
+            }) => {
+                msg!("Instruction: Deposit Exact LP");
+                Self::process_deposit_exact_lp(
+                    program_id,
+                    pool_token_amount,
+                    max_token_a,
+                    max_token_b,
+                    accounts,
+                )
+            }
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        instruction::{deposit, swap, withdraw, withdraw_one},
-        utils::test_utils::*,
-    };
-    use solana_sdk::account::Account;
-    use spl_token::{
-        error::TokenError,
-        instruction::{approve, mint_to, revoke, set_authority, AuthorityType},
-    };
+impl PrintProgramError for SwapError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        match self {
+            SwapError::AlreadyInUse => msg!("Error: Swap account already in use"),
+            SwapError::InvalidAdmin => {
+                msg!("Error: Address of the admin fee account is incorrect")
+            }
+            SwapError::InvalidOwner => {
+                msg!("Error: The input account owner is not the program address")
+            }
+            SwapError::InvalidOutputOwner => {
+                msg!("Error: Output pool account owner cannot be the program address")
+            }
+            SwapError::InvalidProgramAddress => {
+                msg!("Error: Invalid program address generated from nonce and key")
+            }
+            SwapError::ExpectedMint => {
+                msg!("Error: Deserialized account is not an SPL Token mint")
+            }
+            SwapError::ExpectedAccount => {
+                msg!("Error: Deserialized account is not an SPL Token account")
+            }
+            SwapError::EmptySupply => msg!("Error: Input token account empty"),
+            SwapError::EmptyPool => msg!("Error: Pool token supply is 0"),
+            SwapError::InvalidSupply => msg!("Error: Pool token mint has a non-zero supply"),
+            SwapError::RepeatedMint => msg!("Error: Swap input token accounts have the same mint"),
+            SwapError::InvalidDelegate => msg!("Error: Token account has a delegate"),
+            SwapError::InvalidInput => msg!("Error: InvalidInput"),
+            SwapError::IncorrectSwapAccount => {
+                msg!("Error: Address of the provided swap token account is incorrect")
+            }
+            SwapError::IncorrectMint => {
+                msg!("Error: Address of the provided token mint is incorrect")
+            }
+            SwapError::CalculationFailure => msg!("Error: CalculationFailure"),
+            SwapError::InvalidInstruction => msg!("Error: InvalidInstruction"),
+            SwapError::ExceededSlippage => {
+                msg!("Error: Swap instruction exceeds desired slippage limit")
+            }
+            SwapError::InvalidCloseAuthority => msg!("Error: Token account has a close authority"),
+            SwapError::InvalidFreezeAuthority => {
+                msg!("Error: Pool token mint has a freeze authority")
+            }
+            SwapError::ConversionFailure => msg!("Error: Conversion to or from u64 failed"),
+            SwapError::Unauthorized => {
+                msg!("Error: Account is not authorized to execute this instruction")
+            }
+            SwapError::IsPaused => msg!("Error: Swap pool is paused"),
+            SwapError::RampLocked => msg!("Error: Ramp is locked in this time period"),
+            SwapError::InsufficientRampTime => msg!("Error: Insufficient ramp time"),
+            SwapError::ActiveTransfer => msg!("Error: Active admin transfer in progress"),
+            SwapError::NoActiveTransfer => msg!("Error: No active admin transfer in progress"),
+            SwapError::AdminDeadlineExceeded => msg!("Error: Admin transfer deadline exceeded"),
+            SwapError::MismatchedDecimals => {
+                msg!("Error: Token mint decimals are incompatible with the pool mint decimals")
+            }
+            SwapError::InvalidRateProvider => msg!("Error: Invalid rate provider account"),
+            SwapError::StaleRate => msg!("Error: Rate provider rate is stale"),
+            SwapError::CalculationOverflow => msg!("Error: Calculation overflowed"),
+            SwapError::CalculationUnderflow => msg!("Error: Calculation underflowed"),
+            SwapError::DivisionByZero => msg!("Error: Calculation divided by zero"),
+            SwapError::NonConvergence => msg!("Error: Invariant calculation did not converge"),
+            SwapError::ZeroLiquidity => msg!("Error: Pool has insufficient liquidity"),
+            SwapError::DepositTooSmall => {
+                msg!("Error: Deposit does not increase the pool invariant")
+            }
+            SwapError::InvalidFee => {
+                msg!("Error: Fee parameters are invalid or exceed the maximum fees")
+            }
+            SwapError::ActiveFeeChange => msg!("Error: Active fee change in progress"),
+            SwapError::NoActiveFeeChange => msg!("Error: No active fee change in progress"),
+            SwapError::FeeChangeLocked => {
+                msg!("Error: Fee change is locked until its activation time")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fees::OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+        instruction::{deposit, swap, withdraw, withdraw_one},
+        rate_provider::RateInfo,
+        utils::test_utils::*,
+    };
+    use solana_sdk::account::Account;
+    use spl_token::{
+        error::TokenError,
+        instruction::{approve, mint_to, revoke, set_authority, AuthorityType},
+    };
+
+    /// Initial amount of pool tokens for swap contract, hard-coded to something
+    /// "sensible" given a maximum of u64.
+    /// Note that on Ethereum, Uniswap uses the geometric mean of all provided
+    /// input amounts, and Balancer uses 100 * 10 ^ 18.
+    const INITIAL_SWAP_POOL_AMOUNT: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_token_program_id_error() {
+        let swap_key = pubkey_rand();
+        let mut mint = (pubkey_rand(), Account::default());
+        let mut destination = (pubkey_rand(), Account::default());
+        let token_program = (TOKEN_PROGRAM_ID, Account::default());
+        let (authority_key, nonce) =
+            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        let mut authority = (authority_key, Account::default());
+        let swap_bytes = swap_key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = mint_to(
+            &token_program.0,
+            &mint.0,
+            &destination.0,
+            &authority.0,
+            &[],
+            10,
+        )
+        .unwrap();
+        let mint = (&mut mint).into();
+        let destination = (&mut destination).into();
+        let authority = (&mut authority).into();
+
+        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let user_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let pool_token_amount = 10;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        // wrong nonce for authority_key
+        {
+            let old_nonce = accounts.nonce;
+            accounts.nonce = old_nonce - 1;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.initialize_swap()
+            );
+            accounts.nonce = old_nonce;
+        }
+
+        // invalid amp factors
+        {
+            let old_initial_amp_factor = accounts.initial_amp_factor;
+            accounts.initial_amp_factor = MIN_AMP - 1;
+            // amp factor too low
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.initialize_swap()
+            );
+            accounts.initial_amp_factor = MAX_AMP + 1;
+            // amp factor too high
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.initialize_swap()
+            );
+            accounts.initial_amp_factor = old_initial_amp_factor;
+        }
+
+        // invalid fees
+        {
+            let old_fees = accounts.fees;
+            // zero denominator
+            accounts.fees = Fees {
+                trade_fee_numerator: 0,
+                trade_fee_denominator: 0,
+                ..old_fees
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
+            );
+            // fee above 100%
+            accounts.fees = Fees {
+                withdraw_fee_numerator: 2,
+                withdraw_fee_denominator: 1,
+                ..old_fees
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
+            );
+            accounts.fees = old_fees;
+        }
+
+        // uninitialized token a account
+        {
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = Account::default();
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // uninitialized token b account
+        {
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = Account::default();
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // uninitialized pool mint
+        {
+            let old_account = accounts.pool_mint_account;
+            accounts.pool_mint_account = Account::default();
+            assert_eq!(
+                Err(SwapError::ExpectedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_account;
+        }
+
+        // token A account owner is not swap authority
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owner is not swap authority
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // pool token account owner is swap authority
+        {
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_token_account;
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_token_account = old_account;
+        }
+
+        // pool mint authority is not swap authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&TOKEN_PROGRAM_ID, &user_key, DEFAULT_TOKEN_DECIMALS, None);
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // pool mint token has freeze authority
+        {
+            let (_pool_mint_key, pool_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &accounts.authority_key,
+                DEFAULT_TOKEN_DECIMALS,
+                Some(&user_key),
+            );
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidFreezeAuthority.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // empty token A account
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // empty token B account
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // invalid pool tokens
+        {
+            let old_mint = accounts.pool_mint_account;
+            let old_pool_account = accounts.pool_token_account;
+
+            let (_pool_mint_key, pool_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &accounts.authority_key,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
+            );
+            accounts.pool_mint_account = pool_mint_account;
+
+            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                pool_token_amount,
+            );
+
+            // non-empty pool token account
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            // pool tokens already in circulation
+            accounts.pool_token_account = empty_pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.pool_mint_account = old_mint;
+            accounts.pool_token_account = old_pool_account;
+        }
+
+        // token A account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_a_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_a_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_b_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_b_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token A account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_a_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_a_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_b_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &TOKEN_PROGRAM_ID,
+                    &accounts.token_b_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // mismatched admin mints
+        {
+            let (wrong_admin_fee_key, wrong_admin_fee_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+
+            // wrong admin_fee_key_a
+            let old_admin_fee_account_a = accounts.admin_fee_a_account;
+            let old_admin_fee_key_a = accounts.admin_fee_a_key;
+            accounts.admin_fee_a_account = wrong_admin_fee_account.clone();
+            accounts.admin_fee_a_key = wrong_admin_fee_key;
+
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.admin_fee_a_account = old_admin_fee_account_a;
+            accounts.admin_fee_a_key = old_admin_fee_key_a;
+
+            // wrong admin_fee_key_b
+            let old_admin_fee_account_b = accounts.admin_fee_b_account;
+            let old_admin_fee_key_b = accounts.admin_fee_b_key;
+            accounts.admin_fee_b_account = wrong_admin_fee_account.clone();
+            accounts.admin_fee_b_key = wrong_admin_fee_key;
+
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.admin_fee_b_account = old_admin_fee_account_b;
+            accounts.admin_fee_b_key = old_admin_fee_key_b;
+        }
+
+        // mimatched mint decimals
+        {
+            let (bad_mint_key, bad_mint_account) =
+                create_mint(&TOKEN_PROGRAM_ID, &accounts.authority_key, 2, None);
+
+            // Pool mint decimals are less than token decimals
+            let old_pool_mint_key = accounts.pool_mint_key;
+            let old_pool_mint_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = bad_mint_key;
+            accounts.pool_mint_account = bad_mint_account.clone();
+
+            assert_eq!(
+                Err(SwapError::MismatchedDecimals.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.pool_mint_key = old_pool_mint_key;
+            accounts.pool_mint_account = old_pool_mint_account;
+
+            // Token a mint decimals exceed pool mint decimals
+            let (bad_mint_key, mut bad_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &accounts.authority_key,
+                DEFAULT_TOKEN_DECIMALS + 1,
+                None,
+            );
+            let (bad_token_key, bad_token_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &bad_mint_key,
+                &mut bad_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                10,
+            );
+
+            let old_token_a_key = accounts.token_a_key;
+            let old_token_a_account = accounts.token_a_account;
+            let old_token_a_mint_key = accounts.token_a_mint_key;
+            let old_token_a_mint_account = accounts.token_a_mint_account;
+            accounts.token_a_key = bad_token_key;
+            accounts.token_a_account = bad_token_account;
+            accounts.token_a_mint_key = bad_mint_key;
+            accounts.token_a_mint_account = bad_mint_account.clone();
+
+            assert_eq!(
+                Err(SwapError::MismatchedDecimals.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.token_a_key = old_token_a_key;
+            accounts.token_a_account = old_token_a_account;
+            accounts.token_a_mint_key = old_token_a_mint_key;
+            accounts.token_a_mint_account = old_token_a_mint_account;
+        }
+
+        // create swap with same token A and B
+        {
+            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                10,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_a_repeat_account;
+            assert_eq!(
+                Err(SwapError::RepeatedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // create valid swap
+        accounts.initialize_swap().unwrap();
+
+        // create again
+        {
+            assert_eq!(
+                Err(SwapError::AlreadyInUse.into()),
+                accounts.initialize_swap()
+            );
+        }
+        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.is_initialized, true);
+        assert_eq!(swap_info.is_paused, false);
+        assert_eq!(swap_info.nonce, accounts.nonce);
+        assert_eq!(swap_info.initial_amp_factor, amp_factor);
+        assert_eq!(swap_info.target_amp_factor, amp_factor);
+        assert_eq!(swap_info.start_ramp_ts, ZERO_TS);
+        assert_eq!(swap_info.stop_ramp_ts, ZERO_TS);
+        assert_eq!(swap_info.future_admin_deadline, ZERO_TS);
+        assert_eq!(swap_info.future_admin_key, Pubkey::default());
+        assert_eq!(swap_info.admin_key, accounts.admin_key);
+        assert_eq!(swap_info.token_a, accounts.token_a_key);
+        assert_eq!(swap_info.token_b, accounts.token_b_key);
+        assert_eq!(swap_info.pool_mint, accounts.pool_mint_key);
+        assert_eq!(swap_info.token_a_mint, accounts.token_a_mint_key);
+        assert_eq!(swap_info.token_b_mint, accounts.token_b_mint_key);
+        assert_eq!(swap_info.admin_fee_key_a, accounts.admin_fee_a_key);
+        assert_eq!(swap_info.admin_fee_key_b, accounts.admin_fee_b_key);
+        assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
+        let token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let pool_account = utils::unpack_token_account(&accounts.pool_token_account.data).unwrap();
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, pool_account.amount);
+    }
+
+    #[test]
+    fn test_deposit() {
+        let user_key = pubkey_rand();
+        let depositor_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+        let min_mint_amount = 0;
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong nonce for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &TOKEN_PROGRAM_ID,
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+        }
+
+        // not enough token B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (
+                wrong_token_key,
+                mut wrong_token_account,
+                _token_b_key,
+                mut _token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &wrong_token_key,
+                    &mut wrong_token_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit(
+                        &SWAP_PROGRAM_ID,
+                        &TOKEN_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        deposit_a,
+                        deposit_b,
+                        min_mint_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                        &mut clock_account(ZERO_TS),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = pubkey_rand();
+            assert_eq!(
+                Err(ProgramError::InvalidAccountData),
+                do_process_instruction(
+                    deposit(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        deposit_a,
+                        deposit_b,
+                        min_mint_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                        &mut clock_account(ZERO_TS),
+                    ],
+                )
+            );
+        }
 
-    /// Initial amount of pool tokens for swap contract, hard-coded to something
-    /// "sensible" given a maximum of u64.
-    /// Note that on Ethereum, Uniswap uses the geometric mean of all provided
-    /// input amounts, and Balancer uses 100 * 10 ^ 18.
-    const INITIAL_SWAP_POOL_AMOUNT: u64 = 1_000_000_000;
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
 
-    #[test]
-    fn test_token_program_id_error() {
-        let swap_key = pubkey_rand();
-        let mut mint = (pubkey_rand(), Account::default());
-        let mut destination = (pubkey_rand(), Account::default());
-        let token_program = (TOKEN_PROGRAM_ID, Account::default());
-        let (authority_key, nonce) =
-            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-        let mut authority = (authority_key, Account::default());
-        let swap_bytes = swap_key.to_bytes();
-        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
-        let signers = &[&authority_signature_seeds[..]];
-        let ix = mint_to(
-            &token_program.0,
-            &mint.0,
-            &destination.0,
-            &authority.0,
-            &[],
-            10,
-        )
-        .unwrap();
-        let mint = (&mut mint).into();
-        let destination = (&mut destination).into();
-        let authority = (&mut authority).into();
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
 
-        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
-        assert_eq!(err, ProgramError::InvalidAccountData);
-    }
+            accounts.token_a_key = token_a_key.clone();
+            accounts.token_a_account = token_a_account.clone();
 
-    #[test]
-    fn test_initialize() {
-        let user_key = pubkey_rand();
-        let amp_factor = MIN_AMP;
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let pool_token_amount = 10;
-        let mut accounts = SwapAccountInfo::new(
-            &user_key,
-            amp_factor,
-            token_a_amount,
-            token_b_amount,
-            DEFAULT_TEST_FEES,
-        );
-        // wrong nonce for authority_key
-        {
-            let old_nonce = accounts.nonce;
-            accounts.nonce = old_nonce - 1;
+            // wrong swap token a account
             assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
             );
-            accounts.nonce = old_nonce;
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key.clone();
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
         }
 
-        // invalid amp factors
+        // wrong mint
         {
-            let old_initial_amp_factor = accounts.initial_amp_factor;
-            accounts.initial_amp_factor = MIN_AMP - 1;
-            // amp factor too low
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &accounts.authority_key,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
+            );
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectMint.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
             );
-            accounts.initial_amp_factor = MAX_AMP + 1;
-            // amp factor too high
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // slippage exceeeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // min mint_amount in too high
+            let high_min_mint_amount = 10000000000000;
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.initialize_swap()
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    deposit_a,
+                    deposit_b,
+                    high_min_mint_amount,
+                )
             );
-            accounts.initial_amp_factor = old_initial_amp_factor;
         }
 
-        // uninitialized token a account
+        // deposit does not increase the invariant
         {
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = Account::default();
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
             assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
+                Err(SwapError::DepositTooSmall.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    0,
+                    0,
+                    min_mint_amount,
+                )
             );
-            accounts.token_a_account = old_account;
         }
 
-        // uninitialized token b account
+        // correctly deposit
         {
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = Account::default();
-            assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let preview = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .preview_deposit(
+                    deposit_a.into(),
+                    deposit_b.into(),
+                    token_a_amount.into(),
+                    token_b_amount.into(),
+                    old_pool_mint.supply.into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            accounts
+                .deposit(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
+                .unwrap();
 
-        // uninitialized pool mint
-        {
-            let old_account = accounts.pool_mint_account;
-            accounts.pool_mint_account = Account::default();
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            let swap_pool_account =
+                utils::unpack_token_account(&accounts.pool_token_account.data).unwrap();
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            // XXX: Revisit and make sure amount of LP tokens minted is corrected.
             assert_eq!(
-                Err(SwapError::ExpectedMint.into()),
-                accounts.initialize_swap()
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount
             );
-            accounts.pool_mint_account = old_account;
-        }
 
-        // token A account owner is not swap authority
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+            // imbalance fees accrue to liquidity providers
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.total_lp_fees_a, preview.fees[0].as_u128());
+            assert_eq!(swap_info.total_lp_fees_b, preview.fees[1].as_u128());
+            assert_eq!(swap_info.total_admin_fees_a, 0);
+            assert_eq!(swap_info.total_volume_a, 0);
+            assert_eq!(swap_info.swap_count, 0);
         }
 
-        // token B account owner is not swap authority
+        // Pool is paused
         {
-            let (_token_b_key, token_b_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // Pause pool
+            accounts.pause().unwrap();
+
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IsPaused.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    deposit_a,
+                    deposit_b,
+                    min_mint_amount,
+                )
             );
-            accounts.token_b_account = old_account;
         }
+    }
 
-        // pool token account owner is swap authority
+    #[test]
+    fn test_deposit_exact_lp() {
+        let user_key = pubkey_rand();
+        let depositor_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        let supply = pool_mint.supply;
+        // Not a round fraction of the supply, so the amounts are rounded up
+        let pool_token_amount = supply / 7;
+        let deposit_a =
+            (pool_token_amount as u128 * token_a_amount as u128).div_ceil(supply as u128) as u64;
+        let deposit_b =
+            (pool_token_amount as u128 * token_b_amount as u128).div_ceil(supply as u128) as u64;
+        assert_ne!(
+            pool_token_amount as u128 * token_a_amount as u128 % supply as u128,
+            0
+        );
+
+        // wrong nonce for authority_key
         {
-            let (_pool_token_key, pool_token_account) = mint_token(
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
                 &TOKEN_PROGRAM_ID,
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
             );
-            let old_account = accounts.pool_token_account;
-            accounts.pool_token_account = pool_token_account;
+            accounts.authority_key = bad_authority_key;
             assert_eq!(
-                Err(SwapError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.deposit_exact_lp(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_token_amount,
+                    deposit_a,
+                    deposit_b,
+                )
             );
-            accounts.pool_token_account = old_account;
+            accounts.authority_key = old_authority;
         }
 
-        // pool mint authority is not swap authority
+        // maximum token A too low
         {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&TOKEN_PROGRAM_ID, &user_key, DEFAULT_TOKEN_DECIMALS, None);
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_exact_lp(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_token_amount,
+                    deposit_a - 1,
+                    deposit_b,
+                )
             );
-            accounts.pool_mint_account = old_mint;
         }
 
-        // pool mint token has freeze authority
+        // maximum token B too low
         {
-            let (_pool_mint_key, pool_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &accounts.authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                Some(&user_key),
-            );
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
             assert_eq!(
-                Err(SwapError::InvalidFreezeAuthority.into()),
-                accounts.initialize_swap()
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_exact_lp(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_token_amount,
+                    deposit_a,
+                    deposit_b - 1,
+                )
             );
-            accounts.pool_mint_account = old_mint;
         }
 
-        // empty token A account
+        // correctly deposit
         {
-            let (_token_a_key, token_a_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
                 &user_key,
-                &accounts.authority_key,
+                &depositor_key,
+                deposit_a + 10,
+                deposit_b + 10,
                 0,
             );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+            accounts
+                .deposit_exact_lp(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_token_amount,
+                    deposit_a + 10,
+                    deposit_b + 10,
+                )
+                .unwrap();
+
+            // Only the proportional amounts are taken
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 10);
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 10);
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, pool_token_amount);
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, supply + pool_token_amount);
+
+            // Proportional deposits pay no fees and are not swaps
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.total_volume_a, 0);
+            assert_eq!(swap_info.total_volume_b, 0);
+            assert_eq!(swap_info.total_lp_fees_a, 0);
+            assert_eq!(swap_info.total_lp_fees_b, 0);
+            assert_eq!(swap_info.swap_count, 0);
         }
 
-        // empty token B account
+        // Pool is paused
         {
-            let (_token_b_key, token_b_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts.pause().unwrap();
+
             assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IsPaused.into()),
+                accounts.deposit_exact_lp(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_token_amount,
+                    deposit_a,
+                    deposit_b,
+                )
             );
-            accounts.token_b_account = old_account;
         }
+    }
 
-        // invalid pool tokens
-        {
-            let old_mint = accounts.pool_mint_account;
-            let old_pool_account = accounts.pool_token_account;
+    #[test]
+    fn test_withdraw() {
+        let user_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        let withdrawer_key = pubkey_rand();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = INITIAL_SWAP_POOL_AMOUNT;
+        let withdraw_amount = initial_pool / 4;
+        let minimum_a_amount = initial_a / 40;
+        let minimum_b_amount = initial_b / 40;
 
-            let (_pool_mint_key, pool_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &accounts.authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
             );
-            accounts.pool_mint_account = pool_mint_account;
+        }
 
-            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                0,
-            );
+        accounts.initialize_swap().unwrap();
 
-            let (_pool_token_key, pool_token_account) = mint_token(
+        // wrong nonce for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
                 &TOKEN_PROGRAM_ID,
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                pool_token_amount,
             );
-
-            // non-empty pool token account
-            accounts.pool_token_account = pool_token_account;
+            accounts.authority_key = bad_authority_key;
             assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
             );
+            accounts.authority_key = old_authority;
+        }
 
-            // pool tokens already in circulation
-            accounts.pool_token_account = empty_pool_token_account;
+        // not enough pool tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount / 2,
+            );
             assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount / 2,
+                    minimum_b_amount / 2,
+                )
             );
-
-            accounts.pool_mint_account = old_mint;
-            accounts.pool_token_account = old_pool_account;
         }
 
-        // token A account is delegated
+        // wrong token a / b accounts
         {
-            do_process_instruction(
-                approve(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_a_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
             );
-
-            do_process_instruction(
-                revoke(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_a_key,
-                    &accounts.authority_key,
-                    &[],
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
                 )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // token B account is delegated
+        // wrong pool token account
         {
-            do_process_instruction(
-                approve(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_b_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
             );
-
-            do_process_instruction(
-                revoke(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_b_key,
-                    &accounts.authority_key,
-                    &[],
+            let (
+                wrong_pool_key,
+                mut wrong_pool_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                withdraw_amount,
+                initial_b,
+                withdraw_amount,
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &wrong_pool_key,
+                    &mut wrong_pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
                 )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // token A account has close authority
+        // no approval
         {
-            do_process_instruction(
-                set_authority(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_a_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, withdraw_amount);
             assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                set_authority(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_a_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    withdraw(
+                        &SWAP_PROGRAM_ID,
+                        &TOKEN_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        withdraw_amount,
+                        minimum_a_amount,
+                        minimum_b_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut Account::default(),
+                    ],
                 )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // token B account has close authority
+        // wrong token program id
         {
-            do_process_instruction(
-                set_authority(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_b_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
             );
-
-            do_process_instruction(
-                set_authority(
-                    &TOKEN_PROGRAM_ID,
-                    &accounts.token_b_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
+            let wrong_key = pubkey_rand();
+            assert_eq!(
+                Err(ProgramError::InvalidAccountData),
+                do_process_instruction(
+                    withdraw(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        withdraw_amount,
+                        minimum_a_amount,
+                        minimum_b_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut Account::default(),
+                    ],
                 )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // mismatched admin mints
+        // wrong swap token accounts
         {
-            let (wrong_admin_fee_key, wrong_admin_fee_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
                 &user_key,
-                0,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
             );
 
-            // wrong admin_fee_key_a
-            let old_admin_fee_account_a = accounts.admin_fee_a_account;
-            let old_admin_fee_key_a = accounts.admin_fee_a_key;
-            accounts.admin_fee_a_account = wrong_admin_fee_account.clone();
-            accounts.admin_fee_a_key = wrong_admin_fee_key;
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key.clone();
+            accounts.token_a_account = token_a_account.clone();
 
+            // wrong swap token a account
             assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
             );
 
-            accounts.admin_fee_a_account = old_admin_fee_account_a;
-            accounts.admin_fee_a_key = old_admin_fee_key_a;
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
 
-            // wrong admin_fee_key_b
-            let old_admin_fee_account_b = accounts.admin_fee_b_account;
-            let old_admin_fee_key_b = accounts.admin_fee_b_key;
-            accounts.admin_fee_b_account = wrong_admin_fee_account.clone();
-            accounts.admin_fee_b_key = wrong_admin_fee_key;
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
 
+            accounts.token_b_key = token_b_key.clone();
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
             assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
             );
 
-            accounts.admin_fee_b_account = old_admin_fee_account_b;
-            accounts.admin_fee_b_key = old_admin_fee_key_b;
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
         }
 
-        // mimatched mint decimals
+        // wrong mint
         {
-            let (bad_mint_key, mut bad_mint_account) =
-                create_mint(&TOKEN_PROGRAM_ID, &accounts.authority_key, 2, None);
-
-            // Pool mint decimal does not match
-            let old_pool_mint_key = accounts.pool_mint_key;
-            let old_pool_mint_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = bad_mint_key;
-            accounts.pool_mint_account = bad_mint_account.clone();
-
-            assert_eq!(
-                Err(SwapError::MismatchedDecimals.into()),
-                accounts.initialize_swap()
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
             );
-
-            accounts.pool_mint_key = old_pool_mint_key;
-            accounts.pool_mint_account = old_pool_mint_account;
-
-            // Token a mint decimal does not match token b decimals
-            let (bad_token_key, bad_token_account) = mint_token(
+            let (pool_mint_key, pool_mint_account) = create_mint(
                 &TOKEN_PROGRAM_ID,
-                &bad_mint_key,
-                &mut bad_mint_account,
                 &accounts.authority_key,
-                &accounts.authority_key,
-                10,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
             );
-
-            let old_token_a_key = accounts.token_a_key;
-            let old_token_a_account = accounts.token_a_account;
-            let old_token_a_mint_key = accounts.token_a_mint_key;
-            let old_token_a_mint_account = accounts.token_a_mint_account;
-            accounts.token_a_key = bad_token_key;
-            accounts.token_a_account = bad_token_account;
-            accounts.token_a_mint_key = bad_mint_key;
-            accounts.token_a_mint_account = bad_mint_account.clone();
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
 
             assert_eq!(
-                Err(SwapError::MismatchedDecimals.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectMint.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
             );
 
-            accounts.token_a_key = old_token_a_key;
-            accounts.token_a_account = old_token_a_account;
-            accounts.token_a_mint_key = old_token_a_mint_key;
-            accounts.token_a_mint_account = old_token_a_mint_account;
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
         }
 
-        // create swap with same token A and B
+        // slippage exceeeded
         {
-            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
                 &user_key,
-                &accounts.authority_key,
-                10,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+            // minimum A amount out too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount * 30, // XXX: 10 -> 30: Revisit this slippage multiplier
+                    minimum_b_amount,
+                )
+            );
+            // minimum B amount out too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount * 30, // XXX: 10 -> 30; Revisit this splippage multiplier
+                )
+            );
+        }
+
+        // correct withdrawal
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+
+            let old_swap_token_a =
+                utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let old_swap_token_b =
+                utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let old_pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let old_pool_converter = PoolTokenConverter {
+                supply: U256::from(old_pool_mint.supply),
+                token_a: U256::from(old_swap_token_a.amount),
+                token_b: U256::from(old_swap_token_b.amount),
+                fees: &DEFAULT_TEST_FEES,
+            };
+            let (_, fee_a, _) = old_pool_converter
+                .token_withdrawal(U256::from(withdraw_amount), old_pool_converter.token_a)
+                .unwrap();
+            let (_, fee_b, _) = old_pool_converter
+                .token_withdrawal(U256::from(withdraw_amount), old_pool_converter.token_b)
+                .unwrap();
+
+            accounts
+                .withdraw(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
+                )
+                .unwrap();
+
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let pool_converter = PoolTokenConverter {
+                supply: U256::from(pool_mint.supply),
+                token_a: U256::from(swap_token_a.amount),
+                token_b: U256::from(swap_token_b.amount),
+                fees: &DEFAULT_TEST_FEES,
+            };
+
+            let (withdrawn_a, admin_fee_a) = pool_converter
+                .token_a_rate(U256::from(withdraw_amount))
+                .unwrap();
+            assert_eq!(
+                swap_token_a.amount,
+                token_a_amount - U256::to_u64(withdrawn_a).unwrap()
+            );
+            let (withdrawn_b, admin_fee_b) = pool_converter
+                .token_b_rate(U256::from(withdraw_amount))
+                .unwrap();
+            assert_eq!(
+                swap_token_b.amount,
+                token_b_amount - U256::to_u64(withdrawn_b).unwrap()
             );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_a_repeat_account;
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
             assert_eq!(
-                Err(SwapError::RepeatedMint.into()),
-                accounts.initialize_swap()
+                token_a.amount,
+                initial_a + U256::to_u64(withdrawn_a).unwrap()
             );
-            accounts.token_b_account = old_account;
-        }
-
-        // create valid swap
-        accounts.initialize_swap().unwrap();
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(
+                token_b.amount,
+                initial_b + U256::to_u64(withdrawn_b).unwrap()
+            );
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, initial_pool - withdraw_amount);
+            // the admin fees stay in the swap token accounts until swept
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.admin_fees_a, U256::to_u64(admin_fee_a).unwrap());
+            assert_eq!(swap_info.admin_fees_b, U256::to_u64(admin_fee_b).unwrap());
 
-        // create again
-        {
+            assert_eq!(swap_info.total_admin_fees_a, admin_fee_a.as_u128());
+            assert_eq!(swap_info.total_admin_fees_b, admin_fee_b.as_u128());
             assert_eq!(
-                Err(SwapError::AlreadyInUse.into()),
-                accounts.initialize_swap()
+                swap_info.total_lp_fees_a,
+                fee_a.as_u128() - swap_info.total_admin_fees_a
+            );
+            assert_eq!(
+                swap_info.total_lp_fees_b,
+                fee_b.as_u128() - swap_info.total_admin_fees_b
             );
+            assert_eq!(swap_info.swap_count, 0);
         }
-        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
-        assert_eq!(swap_info.is_initialized, true);
-        assert_eq!(swap_info.is_paused, false);
-        assert_eq!(swap_info.nonce, accounts.nonce);
-        assert_eq!(swap_info.initial_amp_factor, amp_factor);
-        assert_eq!(swap_info.target_amp_factor, amp_factor);
-        assert_eq!(swap_info.start_ramp_ts, ZERO_TS);
-        assert_eq!(swap_info.stop_ramp_ts, ZERO_TS);
-        assert_eq!(swap_info.future_admin_deadline, ZERO_TS);
-        assert_eq!(swap_info.future_admin_key, Pubkey::default());
-        assert_eq!(swap_info.admin_key, accounts.admin_key);
-        assert_eq!(swap_info.token_a, accounts.token_a_key);
-        assert_eq!(swap_info.token_b, accounts.token_b_key);
-        assert_eq!(swap_info.pool_mint, accounts.pool_mint_key);
-        assert_eq!(swap_info.token_a_mint, accounts.token_a_mint_key);
-        assert_eq!(swap_info.token_b_mint, accounts.token_b_mint_key);
-        assert_eq!(swap_info.admin_fee_key_a, accounts.admin_fee_a_key);
-        assert_eq!(swap_info.admin_fee_key_b, accounts.admin_fee_b_key);
-        assert_eq!(swap_info.fees, DEFAULT_TEST_FEES);
-        let token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let pool_account = utils::unpack_token_account(&accounts.pool_token_account.data).unwrap();
-        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
-        assert_eq!(pool_mint.supply, pool_account.amount);
     }
 
     #[test]
-    fn test_deposit() {
+    fn test_swap() {
         let user_key = pubkey_rand();
-        let depositor_key = pubkey_rand();
-        let amp_factor = MIN_AMP;
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_a_amount = 5000;
+        let token_b_amount = 5000;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
             amp_factor,
@@ -1459,10 +3801,12 @@ mod tests {
             token_b_amount,
             DEFAULT_TEST_FEES,
         );
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        let minimum_b_amount = initial_b / 2;
 
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
-        let min_mint_amount = 0;
+        let swap_token_a_key = accounts.token_a_key.clone();
+        let swap_token_b_key = accounts.token_b_key.clone();
 
         // swap not initialized
         {
@@ -1471,38 +3815,37 @@ mod tests {
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
                 Err(ProgramError::UninitializedAccount),
-                accounts.deposit(
-                    &depositor_key,
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    initial_a,
+                    minimum_b_amount,
                 )
             );
         }
 
         accounts.initialize_swap().unwrap();
 
-        // wrong nonce for authority_key
+        // wrong nonce
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             let old_authority = accounts.authority_key;
             let (bad_authority_key, _nonce) = Pubkey::find_program_address(
                 &[&accounts.swap_key.to_bytes()[..]],
@@ -1511,116 +3854,63 @@ mod tests {
             accounts.authority_key = bad_authority_key;
             assert_eq!(
                 Err(SwapError::InvalidProgramAddress.into()),
-                accounts.deposit(
-                    &depositor_key,
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    initial_a,
+                    minimum_b_amount,
                 )
             );
             accounts.authority_key = old_authority;
         }
 
-        // not enough token A
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
-                )
-            );
-        }
-
-        // not enough token B
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a,
-                deposit_b / 2,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
-                )
-            );
-        }
-
-        // wrong swap token accounts
+        // wrong token program id
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let wrong_program_id = pubkey_rand();
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
-                )
+                Err(ProgramError::InvalidAccountData),
+                do_process_instruction(
+                    swap(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_program_id,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_b_key,
+                        initial_a,
+                        minimum_b_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_b_account,
+                        &mut Account::default(),
+                        &mut clock_account(ZERO_TS),
+                    ],
+                ),
             );
         }
 
-        // wrong pool token account
+        // not enough token a to swap
         {
             let (
                 token_a_key,
@@ -1628,299 +3918,331 @@ mod tests {
                 token_b_key,
                 mut token_b_account,
                 _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (
-                wrong_token_key,
-                mut wrong_token_account,
-                _token_b_key,
-                mut _token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit(
-                    &depositor_key,
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &wrong_token_key,
-                    &mut wrong_token_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a * 2,
+                    minimum_b_amount * 2,
                 )
             );
         }
 
-        // no approval
+        // wrong swap token A / B accounts
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
+                Err(SwapError::IncorrectSwapAccount.into()),
                 do_process_instruction(
-                    deposit(
+                    swap(
                         &SWAP_PROGRAM_ID,
                         &TOKEN_PROGRAM_ID,
                         &accounts.swap_key,
                         &accounts.authority_key,
                         &token_a_key,
+                        &token_a_key,
                         &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        deposit_a,
-                        deposit_b,
-                        min_mint_amount,
+                        &token_b_key,
+                        initial_a,
+                        minimum_b_amount,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
+                        &mut token_a_account.clone(),
                         &mut token_a_account,
+                        &mut token_b_account.clone(),
                         &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
                         &mut Account::default(),
                         &mut clock_account(ZERO_TS),
                     ],
+                ),
+            );
+        }
+
+        // wrong user token A / B accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    initial_a,
+                    minimum_b_amount,
                 )
             );
         }
 
-        // wrong token program id
+        // swap from a to a
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account.clone(),
+                    &swap_token_a_key,
+                    &swap_token_a_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    initial_a,
+                    minimum_b_amount,
+                )
+            );
+        }
+
+        // no approval
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = pubkey_rand();
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(ProgramError::InvalidAccountData),
+                Err(TokenError::OwnerMismatch.into()),
                 do_process_instruction(
-                    deposit(
+                    swap(
                         &SWAP_PROGRAM_ID,
-                        &wrong_key,
+                        &TOKEN_PROGRAM_ID,
                         &accounts.swap_key,
                         &accounts.authority_key,
                         &token_a_key,
-                        &token_b_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        deposit_a,
-                        deposit_b,
-                        min_mint_amount,
+                        &token_b_key,
+                        initial_a,
+                        minimum_b_amount,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
                         &mut token_a_account,
-                        &mut token_b_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
+                        &mut token_b_account,
                         &mut Account::default(),
                         &mut clock_account(ZERO_TS),
                     ],
-                )
+                ),
             );
         }
 
-        // wrong swap token accounts
+        // slippage exceeeded: minimum out amount too high
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key.clone();
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
-                )
-            );
-
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
-
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
-
-            accounts.token_b_key = token_b_key.clone();
-            accounts.token_b_account = token_b_account.clone();
-
-            // wrong swap token b account
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit(
-                    &depositor_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    initial_a,
+                    minimum_b_amount * 2,
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
         }
 
-        // wrong mint
+        // correct swap
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &accounts.authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
-            );
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
-            assert_eq!(
-                Err(SwapError::IncorrectMint.into()),
-                accounts.deposit(
-                    &depositor_key,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            // swap one way
+            let a_to_b_amount = initial_a / 10;
+            let minimum_b_amount = initial_b / 20;
+            accounts
+                .swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    a_to_b_amount,
+                    minimum_b_amount,
+                )
+                .unwrap();
+
+            let invariant = StableSwap::new(
+                accounts.initial_amp_factor,
+                accounts.target_amp_factor,
+                ZERO_TS,
+                ZERO_TS,
+                ZERO_TS,
+            );
+            let result = invariant
+                .swap_to(
+                    U256::from(a_to_b_amount),
+                    U256::from(token_a_amount),
+                    U256::from(token_b_amount),
+                    &DEFAULT_TEST_FEES,
                 )
+                .unwrap();
+
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let token_a_amount = swap_token_a.amount;
+            assert_eq!(token_a_amount, 5100);
+            assert_eq!(
+                token_a_amount,
+                U256::to_u64(result.new_source_amount).unwrap()
+            );
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a - a_to_b_amount);
+
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let token_b_amount = token_swap
+                .pool_amount(&swap_token_b_key, swap_token_b.amount)
+                .unwrap();
+            assert_eq!(token_b_amount, 4903);
+            assert_eq!(
+                token_b_amount,
+                U256::to_u64(result.new_destination_amount).unwrap()
+            );
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 1094);
+            assert_eq!(
+                token_b.amount,
+                initial_b + U256::to_u64(result.amount_swapped).unwrap()
+            );
+            // the admin fee accrues in the pool
+            assert_eq!(
+                token_swap.admin_fees_b,
+                U256::to_u64(result.admin_fee).unwrap()
             );
-
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
-
-        // slippage exceeeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // min mint_amount in too high
-            let high_min_mint_amount = 10000000000000;
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    deposit_a,
-                    deposit_b,
-                    high_min_mint_amount,
-                )
+                swap_token_b.amount,
+                token_b_amount + token_swap.admin_fees_b
             );
-        }
 
-        // correctly deposit
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let first_swap_amount = result.amount_swapped;
+
+            // swap the other way
+            let b_to_a_amount = initial_b / 10;
+            let minimum_a_amount = initial_a / 20;
             accounts
-                .deposit(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
+                .swap(
+                    &swapper_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    b_to_a_amount,
+                    minimum_a_amount,
                 )
                 .unwrap();
 
+            let invariant = StableSwap::new(
+                accounts.initial_amp_factor,
+                accounts.target_amp_factor,
+                ZERO_TS,
+                ZERO_TS,
+                ZERO_TS,
+            );
+            let result = invariant
+                .swap_to(
+                    U256::from(b_to_a_amount),
+                    U256::from(token_b_amount),
+                    U256::from(token_a_amount),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
             let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
-            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a_amount = token_swap
+                .pool_amount(&swap_token_a_key, swap_token_a.amount)
+                .unwrap();
+            assert_eq!(token_a_amount, 5002);
+            assert_eq!(
+                token_a_amount,
+                U256::to_u64(result.new_destination_amount).unwrap()
+            );
             let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
+            assert_eq!(token_a.amount, 995);
+            assert_eq!(
+                token_a.amount,
+                initial_a - a_to_b_amount + U256::to_u64(result.amount_swapped).unwrap()
+            );
+
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let token_b_amount = token_swap
+                .pool_amount(&swap_token_b_key, swap_token_b.amount)
+                .unwrap();
+            assert_eq!(token_b_amount, 5003);
+            assert_eq!(
+                token_b_amount,
+                U256::to_u64(result.new_source_amount).unwrap()
+            );
             let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
-            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
-            let swap_pool_account =
-                utils::unpack_token_account(&accounts.pool_token_account.data).unwrap();
-            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
-            // XXX: Revisit and make sure amount of LP tokens minted is corrected.
+            assert_eq!(token_b.amount, 994);
             assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
+                token_b.amount,
+                initial_b + U256::to_u64(first_swap_amount).unwrap() - b_to_a_amount
+            );
+            assert_eq!(
+                token_swap.admin_fees_a,
+                U256::to_u64(result.admin_fee).unwrap()
             );
         }
 
@@ -1931,36 +4253,36 @@ mod tests {
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             // Pause pool
             accounts.pause().unwrap();
 
             assert_eq!(
                 Err(SwapError::IsPaused.into()),
-                accounts.deposit(
-                    &depositor_key,
-                    &pool_key,
-                    &mut pool_account,
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    deposit_a,
-                    deposit_b,
-                    min_mint_amount,
+                    initial_a,
+                    minimum_b_amount,
                 )
             );
         }
     }
 
     #[test]
-    fn test_withdraw() {
+    fn test_swap_exact_out() {
         let user_key = pubkey_rand();
-        let amp_factor = MIN_AMP;
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_a_amount = 5000;
+        let token_b_amount = 5000;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
             amp_factor,
@@ -1968,13 +4290,12 @@ mod tests {
             token_b_amount,
             DEFAULT_TEST_FEES,
         );
-        let withdrawer_key = pubkey_rand();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = INITIAL_SWAP_POOL_AMOUNT;
-        let withdraw_amount = initial_pool / 4;
-        let minimum_a_amount = initial_a / 40;
-        let minimum_b_amount = initial_b / 40;
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        let amount_out = initial_b / 2;
+
+        let swap_token_a_key = accounts.token_a_key.clone();
+        let swap_token_b_key = accounts.token_b_key.clone();
 
         // swap not initialized
         {
@@ -1983,209 +4304,117 @@ mod tests {
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
                 Err(ProgramError::UninitializedAccount),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                accounts.swap_exact_out(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    amount_out,
+                    initial_a,
                 )
             );
         }
 
         accounts.initialize_swap().unwrap();
 
-        // wrong nonce for authority_key
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &TOKEN_PROGRAM_ID,
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
-                )
-            );
-            accounts.authority_key = old_authority;
-        }
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+        let (amount_in, result) = invariant
+            .swap_to_exact_out(
+                U256::from(amount_out),
+                U256::from(token_a_amount),
+                U256::from(token_b_amount),
+                &DEFAULT_TEST_FEES,
+            )
+            .unwrap();
+        let amount_in = U256::to_u64(amount_in).unwrap();
 
-        // not enough pool tokens
+        // slippage exceeded
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount / 2,
-            );
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap_exact_out(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount,
-                    minimum_a_amount / 2,
-                    minimum_b_amount / 2,
+                    amount_out,
+                    amount_in - 1,
                 )
             );
         }
 
-        // wrong token a / b accounts
+        // wrong swap token accounts
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap_exact_out(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    &swap_token_a_key,
+                    &swap_token_a_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_out,
+                    initial_a,
                 )
             );
         }
 
-        // wrong admin a / b accounts
+        // output exceeds the pool's liquidity
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            let (
-                wrong_admin_a_key,
-                wrong_admin_a_account,
-                wrong_admin_b_key,
-                wrong_admin_b_account,
                 _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-
-            let old_admin_a_key = accounts.admin_fee_a_key;
-            let old_admin_a_account = accounts.admin_fee_a_account;
-            accounts.admin_fee_a_key = wrong_admin_a_key;
-            accounts.admin_fee_a_account = wrong_admin_a_account;
-
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                Err(SwapError::ZeroLiquidity.into()),
+                accounts.swap_exact_out(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
-                )
-            );
-
-            accounts.admin_fee_a_key = old_admin_a_key;
-            accounts.admin_fee_a_account = old_admin_a_account;
-
-            let old_admin_b_key = accounts.admin_fee_b_key;
-            let old_admin_b_account = accounts.admin_fee_b_account;
-            accounts.admin_fee_b_key = wrong_admin_b_key;
-            accounts.admin_fee_b_account = wrong_admin_b_account;
-
-            assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    token_b_amount,
+                    u64::MAX,
                 )
             );
-
-            accounts.admin_fee_b_key = old_admin_b_key;
-            accounts.admin_fee_b_account = old_admin_b_account;
         }
 
-        // wrong pool token account
+        // correct swap
         {
             let (
                 token_a_key,
@@ -2194,271 +4423,352 @@ mod tests {
                 mut token_b_account,
                 _pool_key,
                 _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            accounts
+                .swap_exact_out(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_out,
+                    amount_in,
+                )
+                .unwrap();
+
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            assert_eq!(
+                swap_token_a.amount,
+                U256::to_u64(result.new_source_amount).unwrap()
             );
-            let (
-                wrong_pool_key,
-                mut wrong_pool_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                withdraw_amount,
-                initial_b,
-                withdraw_amount,
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a - amount_in);
+
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let pool_token_b_amount = token_swap
+                .pool_amount(&swap_token_b_key, swap_token_b.amount)
+                .unwrap();
+            assert_eq!(
+                pool_token_b_amount,
+                U256::to_u64(result.new_destination_amount).unwrap()
             );
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, initial_b + amount_out);
+
+            // the admin fee accrues in the pool
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
-                    &wrong_pool_key,
-                    &mut wrong_pool_account,
+                token_swap.admin_fees_b,
+                U256::to_u64(result.admin_fee).unwrap()
+            );
+            assert_eq!(
+                swap_token_b.amount,
+                pool_token_b_amount + token_swap.admin_fees_b
+            );
+            let admin_fee_b_account =
+                utils::unpack_token_account(&accounts.admin_fee_b_account.data).unwrap();
+            assert_eq!(admin_fee_b_account.amount, 0);
+
+            // and the trade is counted
+            assert_eq!(token_swap.swap_count, 1);
+            assert_eq!(token_swap.last_trade_ts, ZERO_TS);
+            assert_eq!(token_swap.total_volume_a, amount_in as u128);
+            assert_eq!(token_swap.total_volume_b, 0);
+            assert_eq!(token_swap.total_lp_fees_a, 0);
+            assert_eq!(token_swap.total_lp_fees_b, result.lp_fee.as_u128());
+            assert_eq!(token_swap.total_admin_fees_b, result.admin_fee.as_u128());
+
+            // later swaps price off the pool balances, without the accrued fees
+            let next_amount_out = amount_out / 4;
+            let (next_amount_in, next_result) = invariant
+                .swap_to_exact_out(
+                    U256::from(next_amount_out),
+                    result.new_source_amount,
+                    result.new_destination_amount,
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let next_amount_in = U256::to_u64(next_amount_in).unwrap();
+            accounts
+                .swap_exact_out(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    next_amount_out,
+                    next_amount_in,
                 )
+                .unwrap();
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a - amount_in - next_amount_in);
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                token_swap.admin_fees_b,
+                U256::to_u64(result.admin_fee + next_result.admin_fee).unwrap()
+            );
+            assert_eq!(token_swap.swap_count, 2);
+            assert_eq!(
+                token_swap.total_volume_a,
+                (amount_in + next_amount_in) as u128
+            );
+            assert_eq!(
+                token_swap.total_lp_fees_b,
+                (result.lp_fee + next_result.lp_fee).as_u128()
             );
         }
+    }
 
-        // no approval
+    #[test]
+    fn test_host_fee() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_a_amount = 5000;
+        let token_b_amount = 5000;
+        let fees = Fees {
+            host_fee_numerator: 1,
+            host_fee_denominator: 2,
+            ..DEFAULT_TEST_FEES
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, amp_factor, token_a_amount, token_b_amount, fees);
+        accounts.initialize_swap().unwrap();
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+
+        // host fee account for the wrong mint
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, withdraw_amount);
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let (host_key, host_account, _, _, _, _) =
+                accounts.setup_token_accounts(&user_key, &swapper_key, 0, 0, 0);
+            accounts.host_fee_account = Some((host_key, host_account));
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw(
-                        &SWAP_PROGRAM_ID,
-                        &TOKEN_PROGRAM_ID,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.admin_fee_a_key,
-                        &accounts.admin_fee_b_key,
-                        withdraw_amount,
-                        minimum_a_amount,
-                        minimum_b_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.admin_fee_a_account,
-                        &mut accounts.admin_fee_b_account,
-                        &mut Account::default(),
-                    ],
+                Err(SwapError::IncorrectMint.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a,
+                    0,
                 )
             );
+            accounts.host_fee_account = None;
         }
 
-        // wrong token program id
+        // swap splits the admin fee with the host
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            let wrong_key = pubkey_rand();
-            assert_eq!(
-                Err(ProgramError::InvalidAccountData),
-                do_process_instruction(
-                    withdraw(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.admin_fee_a_key,
-                        &accounts.admin_fee_b_key,
-                        withdraw_amount,
-                        minimum_a_amount,
-                        minimum_b_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.admin_fee_a_account,
-                        &mut accounts.admin_fee_b_account,
-                        &mut Account::default(),
-                    ],
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let (_, _, host_key, host_account, _, _) =
+                accounts.setup_token_accounts(&user_key, &swapper_key, 0, 0, 0);
+            accounts.host_fee_account = Some((host_key, host_account));
+            let result = invariant
+                .swap_to(
+                    U256::from(initial_a),
+                    U256::from(token_a_amount),
+                    U256::from(token_b_amount),
+                    &fees,
+                )
+                .unwrap();
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a,
+                    0,
                 )
+                .unwrap();
+
+            let (_, host_account) = accounts.host_fee_account.take().unwrap();
+            let host_fee = utils::unpack_token_account(&host_account.data).unwrap();
+            assert!(host_fee.amount > 0);
+            assert_eq!(U256::from(host_fee.amount), result.host_fee);
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                U256::from(token_swap.admin_fees_b + host_fee.amount),
+                result.admin_fee
+            );
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            assert_eq!(
+                U256::from(swap_token_b.amount - token_swap.admin_fees_b),
+                result.new_destination_amount
             );
         }
 
-        // wrong swap token accounts
+        // withdraw one splits the admin trade fee with the host
         {
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let old_swap_token_a =
+                utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let old_swap_token_b =
+                utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let withdraw_amount = initial_a + initial_b;
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key.clone();
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw(
-                    &withdrawer_key,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, 0, 0, withdraw_amount);
+            let old_pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let (host_key, host_account, _, _, _, _) =
+                accounts.setup_token_accounts(&user_key, &swapper_key, 0, 0, 0);
+            accounts.host_fee_account = Some((host_key, host_account));
+            let withdrawal = invariant
+                .compute_withdraw_one(
+                    withdraw_amount.into(),
+                    old_pool_mint.supply.into(),
+                    old_swap_token_a.amount.into(),
+                    token_swap
+                        .pool_amount(&swap_token_b_key, old_swap_token_b.amount)
+                        .unwrap()
+                        .into(),
+                    &fees,
+                )
+                .unwrap();
+            let admin_withdraw_fee = fees
+                .admin_withdraw_fee(fees.withdraw_fee(withdrawal.amount_withdrawn).unwrap())
+                .unwrap();
+            accounts
+                .withdraw_one(
+                    &swapper_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
                     withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    0,
                 )
-            );
-
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+                .unwrap();
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+            let (_, host_account) = accounts.host_fee_account.take().unwrap();
+            let host_fee = utils::unpack_token_account(&host_account.data).unwrap();
+            assert!(host_fee.amount > 0);
+            assert_eq!(U256::from(host_fee.amount), withdrawal.host_fee);
+            let new_token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                U256::from(new_token_swap.admin_fees_a - token_swap.admin_fees_a + host_fee.amount),
+                withdrawal.admin_fee + admin_withdraw_fee
+            );
+        }
+    }
 
-            accounts.token_b_key = token_b_key.clone();
-            accounts.token_b_account = token_b_account.clone();
+    #[test]
+    fn test_withdraw_one() {
+        let user_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 1000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        let withdrawer_key = pubkey_rand();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = initial_a + initial_b;
+        // Withdraw entire pool share
+        let withdraw_amount = initial_pool;
+        let minimum_amount = 0;
 
-            // wrong swap token b account
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_one(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
                     withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    minimum_amount,
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
         }
 
-        // wrong mint
+        accounts.initialize_swap().unwrap();
+
+        // wrong nonce for authority_key
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
-            let (pool_mint_key, pool_mint_account) = create_mint(
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
                 &TOKEN_PROGRAM_ID,
-                &accounts.authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
             );
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
+            accounts.authority_key = bad_authority_key;
             assert_eq!(
-                Err(SwapError::IncorrectMint.into()),
-                accounts.withdraw(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw_one(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
                     withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    minimum_amount,
                 )
             );
-
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+            accounts.authority_key = old_authority;
         }
 
-        // slippage exceeeded
+        // not enough pool tokens
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
             ) = accounts.setup_token_accounts(
@@ -2466,49 +4776,68 @@ mod tests {
                 &withdrawer_key,
                 initial_a,
                 initial_b,
-                initial_pool,
+                withdraw_amount,
             );
-            // minimum A amount out too high
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_one(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount,
-                    minimum_a_amount * 30, // XXX: 10 -> 30: Revisit this slippage multiplier
-                    minimum_b_amount,
+                    withdraw_amount * 100,
+                    minimum_amount,
                 )
             );
-            // minimum B amount out too high
+        }
+
+        // same swap / quote accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
+            );
+
+            let old_token_b_key = accounts.token_b_key;
+            let old_token_b_account = accounts.token_b_account;
+            accounts.token_b_key = accounts.token_a_key.clone();
+            accounts.token_b_account = accounts.token_a_account.clone();
+
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_one(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
                     withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount * 30, // XXX: 10 -> 30; Revisit this splippage multiplier
+                    minimum_amount,
                 )
             );
+
+            accounts.token_b_key = old_token_b_key;
+            accounts.token_b_account = old_token_b_account;
         }
 
-        // correct withdrawal
+        // foreign swap / quote accounts
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
             ) = accounts.setup_token_accounts(
@@ -2516,145 +4845,137 @@ mod tests {
                 &withdrawer_key,
                 initial_a,
                 initial_b,
-                initial_pool,
+                withdraw_amount,
+            );
+            let foreign_authority = pubkey_rand();
+            let (foreign_mint_key, mut foreign_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &foreign_authority,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
+            );
+            let (foreign_token_key, foreign_token_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &foreign_mint_key,
+                &mut foreign_mint_account,
+                &foreign_authority,
+                &pubkey_rand(),
+                0,
             );
 
-            accounts
-                .withdraw(
+            let old_token_a_key = accounts.token_a_key;
+            let old_token_a_account = accounts.token_a_account;
+            accounts.token_a_key = foreign_token_key.clone();
+            accounts.token_a_account = foreign_token_account.clone();
+
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount,
+                    minimum_amount,
+                )
+            );
+
+            accounts.token_a_key = old_token_a_key;
+            accounts.token_a_account = old_token_a_account;
+
+            let old_token_b_key = accounts.token_b_key;
+            let old_token_b_account = accounts.token_b_account;
+            accounts.token_b_key = foreign_token_key.clone();
+            accounts.token_b_account = foreign_token_account.clone();
+
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_one(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
                     withdraw_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    minimum_amount,
                 )
-                .unwrap();
-
-            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
-            let pool_converter = PoolTokenConverter {
-                supply: U256::from(pool_mint.supply),
-                token_a: U256::from(swap_token_a.amount),
-                token_b: U256::from(swap_token_b.amount),
-                fees: &DEFAULT_TEST_FEES,
-            };
-
-            let (withdrawn_a, admin_fee_a) = pool_converter
-                .token_a_rate(U256::from(withdraw_amount))
-                .unwrap();
-            let withrawn_total_a = U256::to_u64(withdrawn_a + admin_fee_a).unwrap();
-            assert_eq!(swap_token_a.amount, token_a_amount - withrawn_total_a);
-            let (withdrawn_b, admin_fee_b) = pool_converter
-                .token_b_rate(U256::from(withdraw_amount))
-                .unwrap();
-            let withrawn_total_b = U256::to_u64(withdrawn_b + admin_fee_b).unwrap();
-            assert_eq!(swap_token_b.amount, token_b_amount - withrawn_total_b);
-            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
-            assert_eq!(
-                token_a.amount,
-                initial_a + U256::to_u64(withdrawn_a).unwrap()
-            );
-            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
-            assert_eq!(
-                token_b.amount,
-                initial_b + U256::to_u64(withdrawn_b).unwrap()
             );
-            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
-            assert_eq!(pool_account.amount, initial_pool - withdraw_amount);
-            let admin_fee_key_a =
-                utils::unpack_token_account(&accounts.admin_fee_a_account.data).unwrap();
-            assert_eq!(admin_fee_key_a.amount, U256::to_u64(admin_fee_a).unwrap());
-            let admin_fee_key_b =
-                utils::unpack_token_account(&accounts.admin_fee_b_account.data).unwrap();
-            assert_eq!(admin_fee_key_b.amount, U256::to_u64(admin_fee_b).unwrap());
-        }
-    }
-
-    #[test]
-    fn test_swap() {
-        let user_key = pubkey_rand();
-        let swapper_key = pubkey_rand();
-        let amp_factor = 85;
-        let token_a_amount = 5000;
-        let token_b_amount = 5000;
-        let mut accounts = SwapAccountInfo::new(
-            &user_key,
-            amp_factor,
-            token_a_amount,
-            token_b_amount,
-            DEFAULT_TEST_FEES,
-        );
-        let initial_a = token_a_amount / 5;
-        let initial_b = token_b_amount / 5;
-        let minimum_b_amount = initial_b / 2;
 
-        let swap_token_a_key = accounts.token_a_key.clone();
-        let swap_token_b_key = accounts.token_b_key.clone();
+            accounts.token_b_key = old_token_b_key;
+            accounts.token_b_account = old_token_b_account;
+        }
 
-        // swap not initialized
+        // wrong pool token account
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                wrong_token_b_key,
+                mut wrong_token_b_account,
                 _pool_key,
                 _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                withdraw_amount,
+                withdraw_amount,
+            );
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.swap(
-                    &swapper_key,
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &wrong_token_b_key,
+                    &mut wrong_token_b_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_b_amount,
+                    withdraw_amount,
+                    minimum_amount,
                 )
             );
         }
 
-        accounts.initialize_swap().unwrap();
-
-        // wrong nonce
+        // no approval
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &TOKEN_PROGRAM_ID,
-            );
-            accounts.authority_key = bad_authority_key;
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, withdraw_amount);
             assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_b_amount,
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    withdraw_one(
+                        &SWAP_PROGRAM_ID,
+                        &TOKEN_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        withdraw_amount,
+                        minimum_amount,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut Account::default(),
+                        &mut clock_account(ZERO_TS),
+                    ],
                 )
             );
-            accounts.authority_key = old_authority;
         }
 
         // wrong token program id
@@ -2662,451 +4983,816 @@ mod tests {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let wrong_program_id = pubkey_rand();
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
+            );
+            let wrong_key = pubkey_rand();
             assert_eq!(
                 Err(ProgramError::InvalidAccountData),
                 do_process_instruction(
-                    swap(
+                    withdraw_one(
                         &SWAP_PROGRAM_ID,
-                        &wrong_program_id,
+                        &wrong_key,
                         &accounts.swap_key,
                         &accounts.authority_key,
-                        &token_a_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.admin_fee_b_key,
-                        initial_a,
-                        minimum_b_amount,
+                        &token_a_key,
+                        withdraw_amount,
+                        minimum_amount,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
-                        &mut token_a_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut token_b_account,
-                        &mut accounts.admin_fee_b_account,
+                        &mut token_a_account,
                         &mut Account::default(),
                         &mut clock_account(ZERO_TS),
                     ],
-                ),
+                )
             );
         }
 
-        // not enough token a to swap
+        // wrong mint
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+            let (pool_mint_key, pool_mint_account) = create_mint(
+                &TOKEN_PROGRAM_ID,
+                &accounts.authority_key,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
+            );
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.swap(
-                    &swapper_key,
+                Err(SwapError::IncorrectMint.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
+                    withdraw_amount,
+                    minimum_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // wrong destination account
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount,
+            );
+
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_b_key,
                     &mut token_b_account,
-                    initial_a * 2,
-                    minimum_b_amount * 2,
+                    withdraw_amount,
+                    minimum_amount,
                 )
             );
         }
 
-        // wrong swap token A / B accounts
+        // slippage exceeeded
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+
+            let high_minimum_amount = 100000;
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                do_process_instruction(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &TOKEN_PROGRAM_ID,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &token_b_key,
-                        &accounts.admin_fee_b_key,
-                        initial_a,
-                        minimum_b_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut token_a_account.clone(),
-                        &mut token_a_account,
-                        &mut token_b_account.clone(),
-                        &mut token_b_account,
-                        &mut accounts.admin_fee_b_account,
-                        &mut Account::default(),
-                        &mut clock_account(ZERO_TS),
-                    ],
-                ),
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount,
+                    high_minimum_amount,
+                )
             );
         }
 
-        // wrong admin account
+        // correct withdraw
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                wrong_admin_key,
-                mut wrong_admin_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+
+            let old_swap_token_a =
+                utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let old_swap_token_b =
+                utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let old_pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+
+            let invariant = StableSwap::new(
+                accounts.initial_amp_factor,
+                accounts.target_amp_factor,
+                ZERO_TS,
+                ZERO_TS,
+                ZERO_TS,
+            );
+            let withdrawal = invariant
+                .compute_withdraw_one(
+                    withdraw_amount.into(),
+                    old_pool_mint.supply.into(),
+                    old_swap_token_a.amount.into(),
+                    old_swap_token_b.amount.into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let withdraw_one_amount_before_fees = withdrawal.amount_withdrawn;
+            let withdraw_one_withdraw_fee = DEFAULT_TEST_FEES
+                .withdraw_fee(withdraw_one_amount_before_fees)
+                .unwrap();
+            let expected_withdraw_one_amount =
+                withdraw_one_amount_before_fees - withdraw_one_withdraw_fee;
+            let expected_admin_fee = U256::to_u64(
+                DEFAULT_TEST_FEES.admin_trade_fee(withdrawal.fee).unwrap()
+                    + DEFAULT_TEST_FEES
+                        .admin_withdraw_fee(withdraw_one_withdraw_fee)
+                        .unwrap(),
+            )
+            .unwrap();
+
+            accounts
+                .withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount,
+                    minimum_amount,
+                )
+                .unwrap();
+
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
             assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                do_process_instruction(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &TOKEN_PROGRAM_ID,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &wrong_admin_key,
-                        initial_a,
-                        minimum_b_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_b_account,
-                        &mut wrong_admin_account,
-                        &mut Account::default(),
-                        &mut clock_account(ZERO_TS),
-                    ],
-                ),
+                old_swap_token_a.amount - swap_token_a.amount,
+                U256::to_u64(expected_withdraw_one_amount).unwrap()
+            );
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(token_swap.admin_fees_a, expected_admin_fee);
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, old_swap_token_b.amount);
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, old_pool_mint.supply - withdraw_amount);
+
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.total_admin_fees_a, expected_admin_fee as u128);
+            assert_eq!(
+                swap_info.total_lp_fees_a,
+                (withdrawal.lp_fee + withdraw_one_withdraw_fee
+                    - DEFAULT_TEST_FEES
+                        .admin_withdraw_fee(withdraw_one_withdraw_fee)
+                        .unwrap())
+                .as_u128()
             );
+            assert_eq!(swap_info.total_lp_fees_b, 0);
+            assert_eq!(swap_info.total_volume_a, 0);
         }
 
-        // wrong user token A / B accounts
+        // pool is paused
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool,
+            );
+            // pause pool
+            accounts.pause().unwrap();
+
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
+                Err(SwapError::IsPaused.into()),
+                accounts.withdraw_one(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    initial_a,
-                    minimum_b_amount,
+                    withdraw_amount,
+                    minimum_amount,
                 )
             );
         }
+    }
 
-        // swap from a to a
+    #[test]
+    fn test_withdraw_imbalance() {
+        let user_key = pubkey_rand();
+        let amp_factor = MIN_AMP;
+        let token_a_amount = 1000;
+        let token_b_amount = 1000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        let withdrawer_key = pubkey_rand();
+        let initial_pool = 400;
+        let withdraw_a = 150;
+        let withdraw_b = 10;
+
+        // swap not initialized
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account.clone(),
-                    &swap_token_a_key,
-                    &swap_token_a_key,
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_imbalance(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    initial_a,
-                    minimum_b_amount,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_a,
+                    withdraw_b,
+                    initial_pool,
                 )
             );
         }
 
-        // no approval
+        accounts.initialize_swap().unwrap();
+
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+        let compute_burn_amount = |accounts: &SwapAccountInfo| {
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let burn_amount = invariant
+                .compute_withdraw_imbalance(
+                    U256::from(withdraw_a),
+                    U256::from(withdraw_b),
+                    U256::from(token_a_amount),
+                    U256::from(token_b_amount),
+                    U256::from(pool_mint.supply),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            U256::to_u64(burn_amount).unwrap()
+        };
+
+        // wrong swap token accounts
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, initial_pool);
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account.clone();
+            accounts.token_a_key = accounts.token_b_key;
+            accounts.token_a_account = accounts.token_b_account.clone();
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &TOKEN_PROGRAM_ID,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.admin_fee_b_key,
-                        initial_a,
-                        minimum_b_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.admin_fee_b_account,
-                        &mut token_b_account,
-                        &mut Account::default(),
-                        &mut clock_account(ZERO_TS),
-                    ],
-                ),
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_imbalance(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_a,
+                    withdraw_b,
+                    initial_pool,
+                )
             );
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
         }
 
-        // slippage exceeeded: minimum out amount too high
+        // slippage exceeded
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, initial_pool);
+            let burn_amount = compute_burn_amount(&accounts);
             assert_eq!(
                 Err(SwapError::ExceededSlippage.into()),
-                accounts.swap(
-                    &swapper_key,
+                accounts.withdraw_imbalance(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    initial_a,
-                    minimum_b_amount * 2,
+                    withdraw_a,
+                    withdraw_b,
+                    burn_amount - 1,
                 )
             );
         }
 
-        // correct swap
+        // correct withdrawal
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            // swap one way
-            let a_to_b_amount = initial_a / 10;
-            let minimum_b_amount = initial_b / 20;
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, initial_pool);
+            let burn_amount = compute_burn_amount(&accounts);
             accounts
-                .swap(
-                    &swapper_key,
+                .withdraw_imbalance(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    a_to_b_amount,
-                    minimum_b_amount,
+                    withdraw_a,
+                    withdraw_b,
+                    burn_amount,
                 )
                 .unwrap();
 
-            let invariant = StableSwap::new(
-                accounts.initial_amp_factor,
-                accounts.target_amp_factor,
-                ZERO_TS,
-                ZERO_TS,
-                ZERO_TS,
-            );
-            let result = invariant
-                .swap_to(
-                    U256::from(a_to_b_amount),
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, token_a_amount - withdraw_a);
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, token_b_amount - withdraw_b);
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, withdraw_a);
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, withdraw_b);
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, initial_pool - burn_amount);
+
+            // Imbalance fees are counted for liquidity providers
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let preview = invariant
+                .preview_withdraw_imbalance(
+                    U256::from(withdraw_a),
+                    U256::from(withdraw_b),
                     U256::from(token_a_amount),
                     U256::from(token_b_amount),
+                    U256::from(pool_mint.supply + burn_amount),
                     &DEFAULT_TEST_FEES,
                 )
                 .unwrap();
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.total_lp_fees_a, preview.fees[0].as_u128());
+            assert_eq!(swap_info.total_lp_fees_b, preview.fees[1].as_u128());
+            assert!(swap_info.total_lp_fees_a > 0);
+            assert_eq!(swap_info.total_admin_fees_a, 0);
+            assert_eq!(swap_info.total_volume_a, 0);
+            assert_eq!(swap_info.swap_count, 0);
+        }
 
-            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-            let token_a_amount = swap_token_a.amount;
-            assert_eq!(token_a_amount, 5100);
-            assert_eq!(
-                token_a_amount,
-                U256::to_u64(result.new_source_amount).unwrap()
-            );
-            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a - a_to_b_amount);
+        // Pool is paused
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, initial_pool);
+            accounts.pause().unwrap();
 
-            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            let token_b_amount = swap_token_b.amount;
-            assert_eq!(token_b_amount, 4903);
-            assert_eq!(
-                token_b_amount,
-                U256::to_u64(result.new_destination_amount).unwrap()
-            );
-            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 1094);
-            assert_eq!(
-                token_b.amount,
-                initial_b + U256::to_u64(result.amount_swapped).unwrap()
-            );
-            let admin_fee_b_account =
-                utils::unpack_token_account(&accounts.admin_fee_b_account.data).unwrap();
             assert_eq!(
-                admin_fee_b_account.amount,
-                U256::to_u64(result.admin_fee).unwrap()
-            );
-
-            let first_swap_amount = result.amount_swapped;
-
-            // swap the other way
-            let b_to_a_amount = initial_b / 10;
-            let minimum_a_amount = initial_a / 20;
-            accounts
-                .swap(
-                    &swapper_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &swap_token_b_key,
-                    &swap_token_a_key,
+                Err(SwapError::IsPaused.into()),
+                accounts.withdraw_imbalance(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    b_to_a_amount,
-                    minimum_a_amount,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_a,
+                    withdraw_b,
+                    initial_pool,
                 )
-                .unwrap();
+            );
+        }
+    }
 
-            let invariant = StableSwap::new(
-                accounts.initial_amp_factor,
-                accounts.target_amp_factor,
-                ZERO_TS,
+    #[test]
+    fn test_virtual_price() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_a_amount = 5000;
+        let token_b_amount = 5000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let virtual_price = |accounts: &SwapAccountInfo| {
+            utils::virtual_price(
+                &SwapInfo::unpack(&accounts.swap_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_a_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_b_account.data).unwrap(),
+                &Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap(),
+                None,
                 ZERO_TS,
+            )
+        };
+        assert_eq!(virtual_price(&accounts), Ok(RATE_PRECISION.into()));
+
+        // trade fees accrue to pool token holders
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &swapper_key, 1000, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1000,
+                0,
+            )
+            .unwrap();
+        assert!(virtual_price(&accounts).unwrap() > RATE_PRECISION.into());
+
+        // reserve accounts must match the swap's mints
+        assert_eq!(
+            Err(SwapError::IncorrectMint),
+            utils::virtual_price(
+                &SwapInfo::unpack(&accounts.swap_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_b_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_a_account.data).unwrap(),
+                &Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap(),
+                None,
                 ZERO_TS,
-            );
-            let result = invariant
-                .swap_to(
-                    U256::from(b_to_a_amount),
-                    U256::from(token_b_amount),
-                    U256::from(token_a_amount),
-                    &DEFAULT_TEST_FEES,
-                )
-                .unwrap();
+            )
+        );
+    }
 
-            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, 5002);
-            assert_eq!(
-                swap_token_a.amount,
-                U256::to_u64(result.new_destination_amount).unwrap()
-            );
-            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 995);
-            assert_eq!(
-                token_a.amount,
-                initial_a - a_to_b_amount + U256::to_u64(result.amount_swapped).unwrap()
-            );
+    #[test]
+    fn test_fee_apr() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_a_amount = 5000;
+        let token_b_amount = 5000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+        );
+        accounts.initialize_swap().unwrap();
+        let earlier = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(earlier.swap_count, 0);
+        assert_eq!(earlier.total_volume_a, 0);
+        assert_eq!(earlier.total_lp_fees_b, 0);
 
-            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, 5003);
-            assert_eq!(
-                swap_token_b.amount,
-                U256::to_u64(result.new_source_amount).unwrap()
-            );
-            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 994);
-            assert_eq!(
-                token_b.amount,
-                initial_b + U256::to_u64(first_swap_amount).unwrap() - b_to_a_amount
-            );
-            let admin_fee_a_account =
-                utils::unpack_token_account(&accounts.admin_fee_a_account.data).unwrap();
-            assert_eq!(
-                admin_fee_a_account.amount,
-                U256::to_u64(result.admin_fee).unwrap()
-            );
-        }
+        let amount_in = 1000;
+        let result = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+            .swap_to(
+                amount_in.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                &DEFAULT_TEST_FEES,
+            )
+            .unwrap();
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &swapper_key, amount_in, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_in,
+                0,
+            )
+            .unwrap();
+        let later = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(later.swap_count, 1);
+        assert_eq!(later.total_volume_a, amount_in as u128);
+        assert_eq!(later.total_volume_b, 0);
+        assert_eq!(later.total_lp_fees_a, 0);
+        assert_eq!(later.total_lp_fees_b, result.lp_fee.as_u128());
+        assert_eq!(later.total_admin_fees_b, result.admin_fee.as_u128());
+        assert_eq!(later.last_trade_ts, ZERO_TS);
+
+        // one day of fees, annualized, over the pool value in token A, however
+        // long ago the last trade was
+        let (earlier_ts, later_ts) = (ZERO_TS, 86_400);
+        let token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+        let token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+        let (pool_a, pool_b) = accounts.pool_amounts();
+        let expected = result.lp_fee * utils::SECONDS_PER_YEAR * RATE_PRECISION
+            / (U256::from(pool_a + pool_b) * 86_400);
+        assert_eq!(
+            utils::fee_apr(
+                &earlier,
+                earlier_ts,
+                &later,
+                later_ts,
+                &token_a,
+                &token_b,
+                RATE_PRECISION
+            ),
+            Ok(expected)
+        );
+        assert!(expected > 0.into());
+
+        // snapshots must be in order
+        assert_eq!(
+            utils::fee_apr(
+                &later,
+                later_ts,
+                &earlier,
+                earlier_ts,
+                &token_a,
+                &token_b,
+                RATE_PRECISION
+            ),
+            Err(SwapError::InvalidInput)
+        );
+        assert_eq!(
+            utils::fee_apr(
+                &later,
+                later_ts,
+                &later,
+                later_ts,
+                &token_a,
+                &token_b,
+                RATE_PRECISION
+            ),
+            Err(SwapError::InvalidInput)
+        );
+
+        // snapshots must be of the same pool
+        let mut other = later;
+        other.pool_mint = pubkey_rand();
+        assert_eq!(
+            utils::fee_apr(
+                &earlier,
+                earlier_ts,
+                &other,
+                later_ts,
+                &token_a,
+                &token_b,
+                RATE_PRECISION
+            ),
+            Err(SwapError::InvalidInput)
+        );
+
+        // reserve accounts must match the swap's mints
+        assert_eq!(
+            utils::fee_apr(
+                &earlier,
+                earlier_ts,
+                &later,
+                later_ts,
+                &token_b,
+                &token_a,
+                RATE_PRECISION
+            ),
+            Err(SwapError::IncorrectMint)
+        );
+    }
+
+    #[test]
+    fn test_swap_with_different_decimals() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        // 5000 units of each token, with 6 and 8 decimals respectively
+        let token_a_amount = 5_000_000_000;
+        let token_b_amount = 500_000_000_000;
+        let mut accounts = SwapAccountInfo::new_with_decimals(
+            &user_key,
+            amp_factor,
+            token_a_amount,
+            token_b_amount,
+            DEFAULT_TEST_FEES,
+            [6, 8],
+            8,
+        );
+        accounts.initialize_swap().unwrap();
 
-        // Pool is paused
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            // Pause pool
-            accounts.pause().unwrap();
+        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.token_a_precision_multiplier, 100);
+        assert_eq!(swap_info.token_b_precision_multiplier, 1);
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, 2 * token_b_amount);
 
-            assert_eq!(
-                Err(SwapError::IsPaused.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_b_amount,
-                )
-            );
-        }
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+            .with_precision_multipliers(&[100, 1]);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // swap 1 token A for about 1 token B, less the 6% trade fee
+        let amount_in = 1_000_000;
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &swapper_key, amount_in, 0, 0);
+        let result = invariant
+            .swap_to(
+                amount_in.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                &DEFAULT_TEST_FEES,
+            )
+            .unwrap();
+        let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
+        assert!(amount_swapped > 93_000_000 && amount_swapped < 94_000_000);
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_in,
+                amount_swapped,
+            )
+            .unwrap();
+        let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, amount_swapped);
+
+        // and swap it back, paying the trade fee again
+        let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+        let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+        let result = invariant
+            .with_precision_multipliers(&[1, 100])
+            .swap_to(
+                amount_swapped.into(),
+                swap_token_b.amount.into(),
+                swap_token_a.amount.into(),
+                &DEFAULT_TEST_FEES,
+            )
+            .unwrap();
+        let amount_returned = U256::to_u64(result.amount_swapped).unwrap();
+        assert!(amount_returned > 880_000 && amount_returned < 884_000);
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                amount_swapped,
+                amount_returned,
+            )
+            .unwrap();
+        let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, amount_returned);
+
+        // balanced deposits mint pool tokens in pool token precision
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 1_000_000, 100_000_000, 0);
+        accounts
+            .deposit(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000_000,
+                100_000_000,
+                195_000_000,
+            )
+            .unwrap();
     }
 
     #[test]
-    fn test_withdraw_one() {
+    fn test_rate_provider() {
         let user_key = pubkey_rand();
-        let amp_factor = MIN_AMP;
-        let token_a_amount = 1000;
-        let token_b_amount = 1000;
+        let swapper_key = pubkey_rand();
+        let rate_provider_program = pubkey_rand();
+        let max_rate_age = 3600;
+        let amp_factor = 85;
+        // token B is worth 1.1 token A
+        let rate = 1_100_000_000_000_000_000;
+        let token_a_amount = 5_500_000_000;
+        let token_b_amount = 5_000_000_000;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
             amp_factor,
@@ -3114,596 +5800,834 @@ mod tests {
             token_b_amount,
             DEFAULT_TEST_FEES,
         );
-        let withdrawer_key = pubkey_rand();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = initial_a + initial_b;
-        // Withdraw entire pool share
-        let withdraw_amount = initial_pool;
-        let minimum_amount = 0;
+        let (rate_provider_key, rate_provider_account) =
+            create_rate_provider(&rate_provider_program, rate);
+        accounts.rate_provider = Some((rate_provider_key, rate_provider_account.clone()));
+
+        // rate provider not owned by the given program
+        accounts.rate_provider_program = pubkey_rand();
+        assert_eq!(
+            Err(SwapError::InvalidRateProvider.into()),
+            accounts.initialize_swap()
+        );
 
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
-                )
-            );
-        }
+        // rate provider without a program
+        accounts.rate_provider_program = Pubkey::default();
+        assert_eq!(
+            Err(SwapError::InvalidRateProvider.into()),
+            accounts.initialize_swap()
+        );
 
+        accounts.rate_provider_program = rate_provider_program;
+        accounts.max_rate_age = max_rate_age;
         accounts.initialize_swap().unwrap();
 
-        // wrong nonce for authority_key
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &TOKEN_PROGRAM_ID,
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
-                )
-            );
-            accounts.authority_key = old_authority;
-        }
+        let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.rate_provider, rate_provider_key);
+        assert_eq!(swap_info.rate_provider_program, rate_provider_program);
+        assert_eq!(swap_info.max_rate_age, max_rate_age);
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, 2 * token_a_amount);
+
+        let virtual_price = |accounts: &SwapAccountInfo, rate_info: Option<&RateInfo>| {
+            utils::virtual_price(
+                &SwapInfo::unpack(&accounts.swap_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_a_account.data).unwrap(),
+                &utils::unpack_token_account(&accounts.token_b_account.data).unwrap(),
+                &Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap(),
+                rate_info,
+                ZERO_TS,
+            )
+        };
+        let rate_info = utils::unpack_rate_info(&rate_provider_account.data).unwrap();
+        assert_eq!(
+            virtual_price(&accounts, Some(&rate_info)),
+            Ok(RATE_PRECISION.into())
+        );
+        assert_eq!(
+            virtual_price(&accounts, None),
+            Err(SwapError::InvalidRateProvider)
+        );
 
-        // not enough pool tokens
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let amount_in = 100_000_000;
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &swapper_key, 0, amount_in, 0);
+
+        // swap token B for token A at about the rate
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+            let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .with_rates(&[rate, RATE_PRECISION]);
+            let result = invariant
+                .swap_to(
+                    amount_in.into(),
+                    token_b_amount.into(),
+                    token_a_amount.into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
+            // 110 token A, less the 6% trade fee
+            assert!(amount_swapped > 103_000_000 && amount_swapped < 103_400_000);
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount * 100,
-                    minimum_amount,
+                    amount_in,
+                    amount_swapped,
                 )
-            );
+                .unwrap();
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, amount_swapped);
         }
 
-        // same swap / quote accounts
+        // the rate is read on every swap
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-
-            let old_token_b_key = accounts.token_b_key;
-            let old_token_b_account = accounts.token_b_account;
-            accounts.token_b_key = accounts.token_a_key.clone();
-            accounts.token_b_account = accounts.token_a_account.clone();
-
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+            let (_, rate_provider_account) = accounts.rate_provider.as_mut().unwrap();
+            set_rate(rate_provider_account, 2 * RATE_PRECISION);
+            let token_swap = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .with_rates(&[RATE_PRECISION, 2 * RATE_PRECISION]);
+            let result = invariant
+                .swap_to(
+                    token_a.amount.into(),
+                    token_swap
+                        .pool_amount(&swap_token_a_key, swap_token_a.amount)
+                        .unwrap()
+                        .into(),
+                    token_swap
+                        .pool_amount(&swap_token_b_key, swap_token_b.amount)
+                        .unwrap()
+                        .into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
+            assert!(amount_swapped < amount_in / 2);
+            accounts
+                .swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    token_a.amount,
+                    amount_swapped,
                 )
-            );
-
-            accounts.token_b_key = old_token_b_key;
-            accounts.token_b_account = old_token_b_account;
+                .unwrap();
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, amount_swapped);
         }
 
-        // foreign swap / quote accounts
+        // deposits and withdrawals value token B at the rate
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                _token_b_key,
-                _token_b_account,
+                token_b_key,
+                mut token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            let foreign_authority = pubkey_rand();
-            let (foreign_mint_key, mut foreign_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &foreign_authority,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
-            );
-            let (foreign_token_key, foreign_token_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &foreign_mint_key,
-                &mut foreign_mint_account,
-                &foreign_authority,
-                &pubkey_rand(),
-                0,
-            );
-
-            let old_token_a_key = accounts.token_a_key;
-            let old_token_a_account = accounts.token_a_account;
-            accounts.token_a_key = foreign_token_key.clone();
-            accounts.token_a_account = foreign_token_account.clone();
-
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, 0, amount_in, 0);
+            let (swap_token_a_amount, swap_token_b_amount) = accounts.pool_amounts();
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+                .with_rates(&[RATE_PRECISION, 2 * RATE_PRECISION]);
+            let deposit = invariant
+                .compute_mint_amount_for_deposit(
+                    0.into(),
+                    amount_in.into(),
+                    swap_token_a_amount.into(),
+                    swap_token_b_amount.into(),
+                    pool_mint.supply.into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let mint_amount = U256::to_u64(deposit.mint_amount).unwrap();
+            accounts
+                .deposit(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    0,
+                    amount_in,
+                    mint_amount,
+                )
+                .unwrap();
+            let pool_amount = utils::unpack_token_account(&pool_account.data)
+                .unwrap()
+                .amount;
+            assert_eq!(pool_amount, mint_amount);
+            accounts
+                .withdraw_one(
+                    &swapper_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    pool_amount,
+                    170_000_000,
                 )
-            );
+                .unwrap();
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert!(token_a.amount < 2 * amount_in);
+        }
+
+        let (rate_provider_key, rate_provider_account) = accounts.rate_provider.take().unwrap();
+        let swap = |accounts: &mut SwapAccountInfo| {
+            accounts.swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account.clone(),
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account.clone(),
+                1,
+                0,
+            )
+        };
 
-            accounts.token_a_key = old_token_a_key;
-            accounts.token_a_account = old_token_a_account;
+        // missing rate provider
+        assert_eq!(Err(ProgramError::NotEnoughAccountKeys), swap(&mut accounts));
 
-            let old_token_b_key = accounts.token_b_key;
-            let old_token_b_account = accounts.token_b_account;
-            accounts.token_b_key = foreign_token_key.clone();
-            accounts.token_b_account = foreign_token_account.clone();
+        // wrong rate provider
+        accounts.rate_provider = Some(create_rate_provider(&rate_provider_program, rate));
+        assert_eq!(
+            Err(SwapError::InvalidRateProvider.into()),
+            swap(&mut accounts)
+        );
 
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+        // wrong rate provider program
+        let mut wrong_owner_account = rate_provider_account.clone();
+        wrong_owner_account.owner = pubkey_rand();
+        accounts.rate_provider = Some((rate_provider_key, wrong_owner_account));
+        assert_eq!(
+            Err(SwapError::InvalidRateProvider.into()),
+            swap(&mut accounts)
+        );
+
+        // uninitialized rate
+        let mut zero_rate_account = rate_provider_account.clone();
+        set_rate(&mut zero_rate_account, 0);
+        accounts.rate_provider = Some((rate_provider_key, zero_rate_account));
+        assert_eq!(
+            Err(SwapError::InvalidRateProvider.into()),
+            swap(&mut accounts)
+        );
+
+        // rate as old as allowed
+        let mut old_rate_account = rate_provider_account.clone();
+        set_rate_at(&mut old_rate_account, rate, ZERO_TS - max_rate_age);
+        accounts.rate_provider = Some((rate_provider_key, old_rate_account));
+        swap(&mut accounts).unwrap();
+
+        // stale rate
+        let mut stale_rate_account = rate_provider_account;
+        set_rate_at(&mut stale_rate_account, rate, ZERO_TS - max_rate_age - 1);
+        let stale_rate_info = utils::unpack_rate_info(&stale_rate_account.data).unwrap();
+        accounts.rate_provider = Some((rate_provider_key, stale_rate_account));
+        assert_eq!(Err(SwapError::StaleRate.into()), swap(&mut accounts));
+        assert_eq!(
+            virtual_price(&accounts, Some(&stale_rate_info)),
+            Err(SwapError::StaleRate)
+        );
+    }
+
+    #[test]
+    fn test_curve_types() {
+        let user_key = pubkey_rand();
+        let swapper_key = pubkey_rand();
+        let amp_factor = 85;
+        let amount_in = 10_000;
+
+        // constant product pools mint the geometric mean and trade on x * y = k
+        {
+            let token_a_amount = 1_000_000;
+            let token_b_amount = 4_000_000;
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                DEFAULT_TEST_FEES,
+            );
+            accounts.curve_type = CurveType::ConstantProduct;
+            accounts.initialize_swap().unwrap();
+            let swap_info = SwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_info.curve_type, CurveType::ConstantProduct);
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, 4_000_000);
+
+            let swap_token_a_key = accounts.token_a_key;
+            let swap_token_b_key = accounts.token_b_key;
+            let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+                accounts.setup_token_accounts(&user_key, &swapper_key, amount_in, 0, 0);
+            let result = ConstantProduct::new(&[1, 1])
+                .swap_to(
+                    amount_in.into(),
+                    token_a_amount.into(),
+                    token_b_amount.into(),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
+            assert!(amount_swapped < 4 * amount_in);
+            accounts
+                .swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_in,
+                    amount_swapped,
                 )
+                .unwrap();
+            let token_b = utils::unpack_token_account(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, amount_swapped);
+
+            // constant product pools have no peg
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                Fees {
+                    offpeg_fee_multiplier: 2 * OFFPEG_FEE_MULTIPLIER_DENOMINATOR,
+                    ..DEFAULT_TEST_FEES
+                },
+            );
+            accounts.curve_type = CurveType::ConstantProduct;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.initialize_swap()
             );
 
-            accounts.token_b_key = old_token_b_key;
-            accounts.token_b_account = old_token_b_account;
+            // only stable swap pools read a rate provider
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                DEFAULT_TEST_FEES,
+            );
+            accounts.curve_type = CurveType::ConstantProduct;
+            accounts.rate_provider = Some(create_rate_provider(&pubkey_rand(), RATE_PRECISION));
+            assert_eq!(
+                Err(SwapError::InvalidRateProvider.into()),
+                accounts.initialize_swap()
+            );
         }
 
-        // wrong pool token account
+        // constant price pools value token B at the configured price
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                wrong_token_b_key,
-                mut wrong_token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
+            let token_a_amount = 2_000_000;
+            let token_b_amount = 1_000_000;
+            let mut accounts = SwapAccountInfo::new(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                withdraw_amount,
-                withdraw_amount,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                DEFAULT_TEST_FEES,
             );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &wrong_token_b_key,
-                    &mut wrong_token_b_account,
+            accounts.curve_type = CurveType::ConstantPrice;
+            accounts.token_b_price = 2 * RATE_PRECISION;
+            accounts.initialize_swap().unwrap();
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, 4_000_000);
+
+            let swap_token_a_key = accounts.token_a_key;
+            let swap_token_b_key = accounts.token_b_key;
+            let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+                accounts.setup_token_accounts(&user_key, &swapper_key, 0, amount_in, 0);
+            let dy = U256::from(2 * amount_in);
+            let amount_swapped =
+                U256::to_u64(dy - DEFAULT_TEST_FEES.trade_fee(dy).unwrap()).unwrap();
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
                     &token_a_key,
                     &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    amount_in,
+                    amount_swapped,
                 )
+                .unwrap();
+            let token_a = utils::unpack_token_account(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, amount_swapped);
+
+            // a constant price pool needs a price
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                DEFAULT_TEST_FEES,
+            );
+            accounts.curve_type = CurveType::ConstantPrice;
+            accounts.token_b_price = 0;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.initialize_swap()
             );
         }
+    }
 
-        // no approval
+    #[test]
+    fn test_multi_swap_pool() {
+        let user_key = pubkey_rand();
+        let trader_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_amounts = [5000, 5000, 5000];
+        let mut accounts =
+            MultiSwapAccountInfo::new(&user_key, amp_factor, &token_amounts, DEFAULT_TEST_FEES);
+
+        // swap not initialized
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, withdraw_amount);
+            let (mut token_keys, mut token_accounts, _pool_key, _pool_account) =
+                accounts.setup_token_accounts(&user_key, &trader_key, &[1000, 0, 0], 0);
+            let mut destination_account = token_accounts.remove(1);
+            let destination_key = token_keys.remove(1);
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_one(
-                        &SWAP_PROGRAM_ID,
-                        &TOKEN_PROGRAM_ID,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &accounts.admin_fee_a_key,
-                        withdraw_amount,
-                        minimum_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.admin_fee_a_account,
-                        &mut Account::default(),
-                        &mut clock_account(ZERO_TS),
-                    ],
+                Err(ProgramError::UninitializedAccount),
+                accounts.swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    1,
+                    1000,
+                    0,
                 )
             );
         }
 
-        // wrong token program id
+        // invalid fees
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
-            let wrong_key = pubkey_rand();
+            let old_fees = accounts.fees;
+            accounts.fees = Fees {
+                admin_trade_fee_denominator: 0,
+                ..old_fees
+            };
             assert_eq!(
-                Err(ProgramError::InvalidAccountData),
-                do_process_instruction(
-                    withdraw_one(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &accounts.admin_fee_a_key,
-                        withdraw_amount,
-                        minimum_amount,
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.admin_fee_a_account,
-                        &mut Account::default(),
-                        &mut clock_account(ZERO_TS),
-                    ],
-                )
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
             );
+            accounts.fees = old_fees;
         }
 
-        // wrong mint
+        accounts.initialize_swap().unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.n_coins, 3);
+        assert_eq!(swap_info.token_index(&accounts.token_keys[2]), Some(2));
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+        let initial_d = invariant
+            .compute_d_n(&[U256::from(5000), U256::from(5000), U256::from(5000)])
+            .unwrap();
+        assert_eq!(pool_mint.supply, U256::to_u64(initial_d).unwrap());
+
+        // already initialized
+        assert_eq!(
+            Err(SwapError::AlreadyInUse.into()),
+            accounts.initialize_swap()
+        );
+
+        // swap token 0 for token 2
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
-            let (pool_mint_key, pool_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &accounts.authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
-            );
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+            let (mut token_keys, mut token_accounts, _pool_key, _pool_account) =
+                accounts.setup_token_accounts(&user_key, &trader_key, &[1000, 0, 0], 0);
+            let mut destination_account = token_accounts.remove(2);
+            let destination_key = token_keys.remove(2);
+            let swap_amounts: Vec<U256> = token_amounts.iter().map(|a| U256::from(*a)).collect();
+            let result = invariant
+                .swap_to_n(0, 2, U256::from(1000), &swap_amounts, &DEFAULT_TEST_FEES)
+                .unwrap();
+            let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
 
+            // slippage exceeded
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    2,
+                    1000,
+                    amount_swapped + 1,
+                )
+            );
+            // same token in and out
             assert_eq!(
-                Err(SwapError::IncorrectMint.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    2,
+                    2,
+                    1000,
+                    0,
                 )
             );
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+            accounts
+                .swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    2,
+                    1000,
+                    amount_swapped,
+                )
+                .unwrap();
+
+            let source = utils::unpack_token_account(&token_accounts[0].data).unwrap();
+            assert_eq!(source.amount, 0);
+            let destination = utils::unpack_token_account(&destination_account.data).unwrap();
+            assert_eq!(destination.amount, amount_swapped);
+            let swap_token_0 =
+                utils::unpack_token_account(&accounts.token_accounts[0].data).unwrap();
+            assert_eq!(swap_token_0.amount, 6000);
+            assert_eq!(
+                accounts.pool_amounts()[2],
+                U256::to_u64(result.new_destination_amount).unwrap()
+            );
+            // the admin fee accrues in the pool
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                swap_info.admin_fees[2],
+                U256::to_u64(result.admin_fee).unwrap()
+            );
         }
 
-        // wrong destination account
+        // deposit into all coins
+        let depositor_key = pubkey_rand();
+        let deposit_amounts = [100, 200, 300];
         {
-            let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
+            let (token_keys, mut token_accounts, pool_key, mut pool_account) =
+                accounts.setup_token_accounts(&user_key, &depositor_key, &deposit_amounts, 0);
 
+            // wrong number of amounts
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
+                Err(SwapError::InvalidInput.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_keys,
+                    &mut token_accounts,
                     &pool_key,
                     &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    &deposit_amounts[..2],
+                    0,
                 )
             );
-        }
-
-        // wrong admin account
-        {
-            let (
-                wrong_admin_key,
-                wrong_admin_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount,
-            );
 
-            let old_admin_a_key = accounts.admin_fee_a_key;
-            let old_admin_a_account = accounts.admin_fee_a_account;
-            accounts.admin_fee_a_key = wrong_admin_key;
-            accounts.admin_fee_a_account = wrong_admin_account;
+            let swap_amounts: Vec<U256> = accounts
+                .pool_amounts()
+                .into_iter()
+                .map(U256::from)
+                .collect();
+            let deposit_amounts_u256: Vec<U256> =
+                deposit_amounts.iter().map(|a| U256::from(*a)).collect();
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let deposit = invariant
+                .compute_mint_amount_for_deposit_n(
+                    &deposit_amounts_u256,
+                    &swap_amounts,
+                    U256::from(pool_mint.supply),
+                    &DEFAULT_TEST_FEES,
+                )
+                .unwrap();
+            let mint_amount = U256::to_u64(deposit.mint_amount).unwrap();
 
             assert_eq!(
-                Err(SwapError::InvalidAdmin.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit(
+                    &depositor_key,
+                    &token_keys,
+                    &mut token_accounts,
                     &pool_key,
                     &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount,
-                    minimum_amount,
+                    &deposit_amounts,
+                    mint_amount + 1,
                 )
             );
+            accounts
+                .deposit(
+                    &depositor_key,
+                    &token_keys,
+                    &mut token_accounts,
+                    &pool_key,
+                    &mut pool_account,
+                    &deposit_amounts,
+                    mint_amount,
+                )
+                .unwrap();
 
-            accounts.admin_fee_a_key = old_admin_a_key;
-            accounts.admin_fee_a_account = old_admin_a_account;
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, mint_amount);
+            let pool_amounts = accounts.pool_amounts();
+            for (i, token_account) in token_accounts.iter().enumerate() {
+                let token = utils::unpack_token_account(&token_account.data).unwrap();
+                assert_eq!(token.amount, 0);
+                assert_eq!(
+                    pool_amounts[i],
+                    U256::to_u64(swap_amounts[i]).unwrap() + deposit_amounts[i]
+                );
+            }
         }
 
-        // slippage exceeeded
+        // withdraw from all coins
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
+            let withdrawer_key = pubkey_rand();
+            let withdraw_amount = 1500;
+            let (token_keys, mut token_accounts, pool_key, mut pool_account) = accounts
+                .setup_token_accounts(&user_key, &withdrawer_key, &[0, 0, 0], withdraw_amount);
+
+            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+            let swap_amounts = accounts.pool_amounts();
+            let old_swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
 
-            let high_minimum_amount = 100000;
             assert_eq!(
                 Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_one(
+                accounts.withdraw(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
+                    &token_keys,
+                    &mut token_accounts,
                     withdraw_amount,
-                    high_minimum_amount,
+                    &[0, 0, u64::MAX],
                 )
             );
-        }
-
-        // correct withdraw
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
-
-            let old_swap_token_a =
-                utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
-            let old_swap_token_b =
-                utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            let old_pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
-
-            let invariant = StableSwap::new(
-                accounts.initial_amp_factor,
-                accounts.target_amp_factor,
-                ZERO_TS,
-                ZERO_TS,
-                ZERO_TS,
-            );
-            let (withdraw_one_amount_before_fees, withdraw_one_trade_fee) = invariant
-                .compute_withdraw_one(
-                    withdraw_amount.into(),
-                    old_pool_mint.supply.into(),
-                    old_swap_token_a.amount.into(),
-                    old_swap_token_b.amount.into(),
-                    &DEFAULT_TEST_FEES,
-                )
-                .unwrap();
-            let withdraw_one_withdraw_fee = DEFAULT_TEST_FEES
-                .withdraw_fee(withdraw_one_amount_before_fees)
-                .unwrap();
-            let expected_withdraw_one_amount =
-                withdraw_one_amount_before_fees - withdraw_one_withdraw_fee;
-            let expected_admin_fee = U256::to_u64(
-                DEFAULT_TEST_FEES
-                    .admin_trade_fee(withdraw_one_trade_fee)
-                    .unwrap()
-                    + DEFAULT_TEST_FEES
-                        .admin_withdraw_fee(withdraw_one_withdraw_fee)
-                        .unwrap(),
-            )
-            .unwrap();
-
             accounts
-                .withdraw_one(
+                .withdraw(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
+                    &token_keys,
+                    &mut token_accounts,
                     withdraw_amount,
-                    minimum_amount,
+                    &[0, 0, 0],
                 )
                 .unwrap();
 
-            let swap_token_a = utils::unpack_token_account(&accounts.token_a_account.data).unwrap();
+            let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, 0);
+            let pool_amounts = accounts.pool_amounts();
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+            for (i, token_account) in token_accounts.iter().enumerate() {
+                let amount =
+                    U256::from(withdraw_amount) * swap_amounts[i] / U256::from(pool_mint.supply);
+                let fee = DEFAULT_TEST_FEES.withdraw_fee(amount).unwrap();
+                let admin_fee =
+                    U256::to_u64(DEFAULT_TEST_FEES.admin_withdraw_fee(fee).unwrap()).unwrap();
+                let token = utils::unpack_token_account(&token_account.data).unwrap();
+                assert_eq!(token.amount, U256::to_u64(amount - fee).unwrap());
+                assert_eq!(pool_amounts[i], swap_amounts[i] - token.amount - admin_fee);
+                assert_eq!(
+                    swap_info.admin_fees[i],
+                    old_swap_info.admin_fees[i] + admin_fee
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_swap_pool_with_different_decimals() {
+        let user_key = pubkey_rand();
+        let trader_key = pubkey_rand();
+        let amp_factor = 85;
+        // 5000 units of each token, with 6, 8 and 9 decimals respectively
+        let token_amounts = [5_000_000_000, 500_000_000_000, 5_000_000_000_000];
+        let token_decimals = [6, 8, 9];
+
+        // Token decimals exceed pool mint decimals
+        let mut accounts = MultiSwapAccountInfo::new_with_decimals(
+            &user_key,
+            amp_factor,
+            &token_amounts,
+            DEFAULT_TEST_FEES,
+            &token_decimals,
+            8,
+        );
+        assert_eq!(
+            Err(SwapError::MismatchedDecimals.into()),
+            accounts.initialize_swap()
+        );
+
+        let mut accounts = MultiSwapAccountInfo::new_with_decimals(
+            &user_key,
+            amp_factor,
+            &token_amounts,
+            DEFAULT_TEST_FEES,
+            &token_decimals,
+            9,
+        );
+        accounts.initialize_swap().unwrap();
+        let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_info.precision_multipliers, [1_000, 10, 1, 0]);
+        let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, 3 * token_amounts[2]);
+
+        // swap 1 token 0 for about 1 token 2, less the trade fee
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS)
+            .with_precision_multipliers(&[1_000, 10, 1]);
+        let amount_in = 1_000_000;
+        let (mut token_keys, mut token_accounts, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(&user_key, &trader_key, &[amount_in, 0, 0], 0);
+        let mut destination_account = token_accounts.remove(2);
+        let destination_key = token_keys.remove(2);
+        let swap_amounts: Vec<U256> = token_amounts.iter().map(|a| U256::from(*a)).collect();
+        let result = invariant
+            .swap_to_n(0, 2, amount_in.into(), &swap_amounts, &DEFAULT_TEST_FEES)
+            .unwrap();
+        let amount_swapped = U256::to_u64(result.amount_swapped).unwrap();
+        assert!(amount_swapped > 930_000_000 && amount_swapped < 940_000_000);
+        accounts
+            .swap(
+                &trader_key,
+                &token_keys[0],
+                &mut token_accounts[0],
+                &destination_key,
+                &mut destination_account,
+                0,
+                2,
+                amount_in,
+                amount_swapped,
+            )
+            .unwrap();
+        let destination = utils::unpack_token_account(&destination_account.data).unwrap();
+        assert_eq!(destination.amount, amount_swapped);
+
+        // balanced deposits mint pool tokens in pool token precision
+        let depositor_key = pubkey_rand();
+        let deposit_amounts = [1_000_000, 100_000_000, 1_000_000_000];
+        let (token_keys, mut token_accounts, pool_key, mut pool_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, &deposit_amounts, 0);
+        accounts
+            .deposit(
+                &depositor_key,
+                &token_keys,
+                &mut token_accounts,
+                &pool_key,
+                &mut pool_account,
+                &deposit_amounts,
+                2_900_000_000,
+            )
+            .unwrap();
+        let pool_account = utils::unpack_token_account(&pool_account.data).unwrap();
+        assert!(pool_account.amount < 3_000_000_000);
+    }
+
+    #[test]
+    fn test_multi_swap_host_fee() {
+        let user_key = pubkey_rand();
+        let trader_key = pubkey_rand();
+        let amp_factor = 85;
+        let token_amounts = [5000, 5000, 5000];
+        let fees = Fees {
+            host_fee_numerator: 1,
+            host_fee_denominator: 2,
+            ..DEFAULT_TEST_FEES
+        };
+        let mut accounts = MultiSwapAccountInfo::new(&user_key, amp_factor, &token_amounts, fees);
+        accounts.initialize_swap().unwrap();
+        let invariant = StableSwap::new(amp_factor, amp_factor, ZERO_TS, ZERO_TS, ZERO_TS);
+        let amount_in = 1000;
+
+        // host fee account for the wrong mint
+        {
+            let (mut token_keys, mut token_accounts, _pool_key, _pool_account) =
+                accounts.setup_token_accounts(&user_key, &trader_key, &[amount_in, 0, 0], 0);
+            let destination_key = token_keys.remove(2);
+            let mut destination_account = token_accounts.remove(2);
+            let host_key = token_keys.remove(1);
+            let host_account = token_accounts.remove(1);
+            accounts.host_fee_account = Some((host_key, host_account));
             assert_eq!(
-                old_swap_token_a.amount - swap_token_a.amount - expected_admin_fee,
-                U256::to_u64(expected_withdraw_one_amount).unwrap()
+                Err(SwapError::IncorrectMint.into()),
+                accounts.swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    2,
+                    amount_in,
+                    0,
+                )
             );
-            let admin_fee_key_a =
-                utils::unpack_token_account(&accounts.admin_fee_a_account.data).unwrap();
-            assert_eq!(admin_fee_key_a.amount, expected_admin_fee);
-            let swap_token_b = utils::unpack_token_account(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, old_swap_token_b.amount);
-            let pool_mint = Processor::unpack_mint(&accounts.pool_mint_account.data).unwrap();
-            assert_eq!(pool_mint.supply, old_pool_mint.supply - withdraw_amount);
+            accounts.host_fee_account = None;
         }
 
-        // pool is paused
+        // swap splits the admin fee with the host
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool,
-            );
-            // pause pool
-            accounts.pause().unwrap();
+            let (mut token_keys, mut token_accounts, _pool_key, _pool_account) =
+                accounts.setup_token_accounts(&user_key, &trader_key, &[amount_in, 0, 0], 0);
+            let destination_key = token_keys.remove(2);
+            let mut destination_account = token_accounts.remove(2);
+            let (mut host_keys, mut host_accounts, _, _) =
+                accounts.setup_token_accounts(&user_key, &trader_key, &[0, 0, 0], 0);
+            accounts.host_fee_account = Some((host_keys.remove(2), host_accounts.remove(2)));
+            let swap_amounts: Vec<U256> = token_amounts.iter().map(|a| U256::from(*a)).collect();
+            let result = invariant
+                .swap_to_n(0, 2, U256::from(amount_in), &swap_amounts, &fees)
+                .unwrap();
+            accounts
+                .swap(
+                    &trader_key,
+                    &token_keys[0],
+                    &mut token_accounts[0],
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    2,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
 
+            let (_, host_account) = accounts.host_fee_account.take().unwrap();
+            let host_fee = utils::unpack_token_account(&host_account.data).unwrap();
+            assert!(host_fee.amount > 0);
+            assert_eq!(U256::from(host_fee.amount), result.host_fee);
+            let swap_info = MultiSwapInfo::unpack(&accounts.swap_account.data).unwrap();
             assert_eq!(
-                Err(SwapError::IsPaused.into()),
-                accounts.withdraw_one(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount,
-                    minimum_amount,
-                )
+                U256::from(swap_info.admin_fees[2] + host_fee.amount),
+                result.admin_fee
             );
         }
     }
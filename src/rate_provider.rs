@@ -0,0 +1,88 @@
+//! Rate provider account format
+
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+
+/// Exchange rate published by a rate provider account, e.g. the value of a
+/// liquid staking token in its underlying token.
+/// Any program may own rate provider accounts as long as it writes them in
+/// this format; a pool only reads the account it was initialized with.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateInfo {
+    /// Initialized state
+    pub is_initialized: bool,
+    /// Value of one token B in token A, scaled by `curve::RATE_PRECISION`
+    pub rate: u64,
+    /// Unix timestamp of the last rate update
+    pub last_update_ts: i64,
+}
+
+impl Sealed for RateInfo {}
+impl IsInitialized for RateInfo {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for RateInfo {
+    const LEN: usize = 17;
+
+    /// Unpacks a byte buffer into a [RateInfo](struct.RateInfo.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 17];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, rate, last_update_ts) = array_refs![input, 1, 8, 8];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            rate: u64::from_le_bytes(*rate),
+            last_update_ts: i64::from_le_bytes(*last_update_ts),
+        })
+    }
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 17];
+        let (is_initialized, rate, last_update_ts) = mut_array_refs![output, 1, 8, 8];
+        is_initialized[0] = self.is_initialized as u8;
+        *rate = self.rate.to_le_bytes();
+        *last_update_ts = self.last_update_ts.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_info_packing() {
+        let rate: u64 = 1_050_000_000_000_000_000;
+        let last_update_ts: i64 = i64::MAX;
+        let rate_info = RateInfo {
+            is_initialized: true,
+            rate,
+            last_update_ts,
+        };
+
+        let mut packed = [0u8; RateInfo::LEN];
+        RateInfo::pack(rate_info, &mut packed).unwrap();
+        let unpacked = RateInfo::unpack(&packed).unwrap();
+        assert_eq!(rate_info, unpacked);
+
+        let mut packed = vec![1u8];
+        packed.extend_from_slice(&rate.to_le_bytes());
+        packed.extend_from_slice(&last_update_ts.to_le_bytes());
+        let unpacked = RateInfo::unpack(&packed).unwrap();
+        assert_eq!(rate_info, unpacked);
+
+        let packed = [0u8; RateInfo::LEN];
+        let err = RateInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+}
@@ -1,7 +1,12 @@
 //! State transition types
 
-use crate::fees::Fees;
+use crate::{
+    curve::{CurveType, RampType, MAX_N_COINS},
+    error::SwapError,
+    fees::Fees,
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use num_traits::FromPrimitive;
 use solana_program::{
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
@@ -60,6 +65,132 @@ pub struct SwapInfo {
     pub admin_fee_key_b: Pubkey,
     /// Fees
     pub fees: Fees,
+
+    /// Multiplier normalizing token A amounts to the pool token precision
+    pub token_a_precision_multiplier: u64,
+    /// Multiplier normalizing token B amounts to the pool token precision
+    pub token_b_precision_multiplier: u64,
+
+    /// Public key of the account publishing the rate of token B in token A,
+    /// or the default key if the tokens trade 1:1
+    pub rate_provider: Pubkey,
+    /// Program owning the rate provider account
+    pub rate_provider_program: Pubkey,
+    /// Maximum age of the rate provider's rate in seconds
+    pub max_rate_age: i64,
+
+    /// Curve the pool prices trades with
+    pub curve_type: CurveType,
+    /// Price of one token B in token A for constant price pools, scaled by
+    /// `curve::RATE_PRECISION`
+    pub token_b_price: u64,
+
+    /// Schedule A follows between `start_ramp_ts` and `stop_ramp_ts`
+    pub ramp_type: RampType,
+
+    /// Fees to be applied from future_fees_ts
+    pub future_fees: Fees,
+    /// Timestamp from which future_fees can be applied, or `ZERO_TS` if no
+    /// fee change is committed
+    pub future_fees_ts: i64,
+
+    /// Admin fees accrued in the token A account, not part of the pool
+    /// balances until swept by `WithdrawAdminFees`
+    pub admin_fees_a: u64,
+    /// Admin fees accrued in the token B account
+    pub admin_fees_b: u64,
+
+    /// Cumulative amount of token A swapped into the pool
+    pub total_volume_a: u128,
+    /// Cumulative amount of token B swapped into the pool
+    pub total_volume_b: u128,
+    /// Cumulative fees in token A left in the pool for liquidity providers
+    pub total_lp_fees_a: u128,
+    /// Cumulative fees in token B left in the pool for liquidity providers
+    pub total_lp_fees_b: u128,
+    /// Cumulative admin fees in token A, including the host fee share
+    pub total_admin_fees_a: u128,
+    /// Cumulative admin fees in token B, including the host fee share
+    pub total_admin_fees_b: u128,
+    /// Number of swaps through the pool
+    pub swap_count: u64,
+    /// Timestamp of the last swap, or `ZERO_TS` if there has been none
+    pub last_trade_ts: i64,
+}
+
+impl SwapInfo {
+    /// Precision multipliers of token A and token B
+    pub fn precision_multipliers(&self) -> [u64; 2] {
+        [
+            self.token_a_precision_multiplier,
+            self.token_b_precision_multiplier,
+        ]
+    }
+
+    /// Whether the pool reads the rate of token B from a rate provider
+    pub fn has_rate_provider(&self) -> bool {
+        self.rate_provider != Pubkey::default()
+    }
+
+    /// Balance of the pool in the swap token account `token`, i.e. the
+    /// account's `amount` less the admin fees accrued in it
+    pub fn pool_amount(&self, token: &Pubkey, amount: u64) -> Result<u64, SwapError> {
+        let admin_fees = if *token == self.token_a {
+            self.admin_fees_a
+        } else if *token == self.token_b {
+            self.admin_fees_b
+        } else {
+            return Err(SwapError::IncorrectSwapAccount);
+        };
+        amount
+            .checked_sub(admin_fees)
+            .ok_or(SwapError::CalculationFailure)
+    }
+
+    /// Accrue admin fees in the swap token account `token`
+    pub fn accrue_admin_fees(&mut self, token: &Pubkey, amount: u64) -> Result<(), SwapError> {
+        let admin_fees = if *token == self.token_a {
+            &mut self.admin_fees_a
+        } else if *token == self.token_b {
+            &mut self.admin_fees_b
+        } else {
+            return Err(SwapError::IncorrectSwapAccount);
+        };
+        *admin_fees = admin_fees
+            .checked_add(amount)
+            .ok_or(SwapError::CalculationFailure)?;
+        Ok(())
+    }
+
+    /// Add `volume` swapped into, and `lp_fees` and `admin_fees` charged in,
+    /// the swap token account `token` to the cumulative counters
+    pub fn record_trade(
+        &mut self,
+        token: &Pubkey,
+        volume: u64,
+        lp_fees: u64,
+        admin_fees: u64,
+    ) -> Result<(), SwapError> {
+        let (total_volume, total_lp_fees, total_admin_fees) = if *token == self.token_a {
+            (
+                &mut self.total_volume_a,
+                &mut self.total_lp_fees_a,
+                &mut self.total_admin_fees_a,
+            )
+        } else if *token == self.token_b {
+            (
+                &mut self.total_volume_b,
+                &mut self.total_lp_fees_b,
+                &mut self.total_admin_fees_b,
+            )
+        } else {
+            return Err(SwapError::IncorrectSwapAccount);
+        };
+        *total_volume = total_volume.saturating_add(volume.into());
+        *total_lp_fees = total_lp_fees.saturating_add(lp_fees.into());
+        *total_admin_fees = total_admin_fees.saturating_add(admin_fees.into());
+        Ok(())
+    }
 }
 
 impl Sealed for SwapInfo {}
@@ -70,11 +201,11 @@ impl IsInitialized for SwapInfo {
 }
 
 impl Pack for SwapInfo {
-    const LEN: usize = 395;
+    const LEN: usize = 741;
 
     /// Unpacks a byte buffer into a [SwapInfo](struct.SwapInfo.html).
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
-        let input = array_ref![input, 0, 395];
+        let input = array_ref![input, 0, 741];
         #[allow(clippy::ptr_offset_with_cast)]
         let (
             is_initialized,
@@ -95,7 +226,30 @@ impl Pack for SwapInfo {
             admin_fee_key_a,
             admin_fee_key_b,
             fees,
-        ) = array_refs![input, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 32, 32, 32, 64];
+            token_a_precision_multiplier,
+            token_b_precision_multiplier,
+            rate_provider,
+            rate_provider_program,
+            max_rate_age,
+            curve_type,
+            token_b_price,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees_a,
+            admin_fees_b,
+            total_volume_a,
+            total_volume_b,
+            total_lp_fees_a,
+            total_lp_fees_b,
+            total_admin_fees_a,
+            total_admin_fees_b,
+            swap_count,
+            last_trade_ts,
+        ) = array_refs![
+            input, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 32, 32, 32, 88, 8, 8, 32, 32, 8,
+            1, 8, 1, 88, 8, 8, 8, 16, 16, 16, 16, 16, 16, 8, 8
+        ];
         Ok(Self {
             is_initialized: match is_initialized {
                 [0] => false,
@@ -123,11 +277,32 @@ impl Pack for SwapInfo {
             admin_fee_key_a: Pubkey::new_from_array(*admin_fee_key_a),
             admin_fee_key_b: Pubkey::new_from_array(*admin_fee_key_b),
             fees: Fees::unpack_from_slice(fees)?,
+            token_a_precision_multiplier: u64::from_le_bytes(*token_a_precision_multiplier),
+            token_b_precision_multiplier: u64::from_le_bytes(*token_b_precision_multiplier),
+            rate_provider: Pubkey::new_from_array(*rate_provider),
+            rate_provider_program: Pubkey::new_from_array(*rate_provider_program),
+            max_rate_age: i64::from_le_bytes(*max_rate_age),
+            curve_type: CurveType::from_u8(curve_type[0])
+                .ok_or(ProgramError::InvalidAccountData)?,
+            token_b_price: u64::from_le_bytes(*token_b_price),
+            ramp_type: RampType::from_u8(ramp_type[0]).ok_or(ProgramError::InvalidAccountData)?,
+            future_fees: Fees::unpack_from_slice(future_fees)?,
+            future_fees_ts: i64::from_le_bytes(*future_fees_ts),
+            admin_fees_a: u64::from_le_bytes(*admin_fees_a),
+            admin_fees_b: u64::from_le_bytes(*admin_fees_b),
+            total_volume_a: u128::from_le_bytes(*total_volume_a),
+            total_volume_b: u128::from_le_bytes(*total_volume_b),
+            total_lp_fees_a: u128::from_le_bytes(*total_lp_fees_a),
+            total_lp_fees_b: u128::from_le_bytes(*total_lp_fees_b),
+            total_admin_fees_a: u128::from_le_bytes(*total_admin_fees_a),
+            total_admin_fees_b: u128::from_le_bytes(*total_admin_fees_b),
+            swap_count: u64::from_le_bytes(*swap_count),
+            last_trade_ts: i64::from_le_bytes(*last_trade_ts),
         })
     }
 
     fn pack_into_slice(&self, output: &mut [u8]) {
-        let output = array_mut_ref![output, 0, 395];
+        let output = array_mut_ref![output, 0, 741];
         let (
             is_initialized,
             is_paused,
@@ -147,7 +322,30 @@ impl Pack for SwapInfo {
             admin_fee_key_a,
             admin_fee_key_b,
             fees,
-        ) = mut_array_refs![output, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 32, 32, 32, 64];
+            token_a_precision_multiplier,
+            token_b_precision_multiplier,
+            rate_provider,
+            rate_provider_program,
+            max_rate_age,
+            curve_type,
+            token_b_price,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees_a,
+            admin_fees_b,
+            total_volume_a,
+            total_volume_b,
+            total_lp_fees_a,
+            total_lp_fees_b,
+            total_admin_fees_a,
+            total_admin_fees_b,
+            swap_count,
+            last_trade_ts,
+        ) = mut_array_refs![
+            output, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 32, 32, 32, 32, 32, 32, 88, 8, 8, 32, 32,
+            8, 1, 8, 1, 88, 8, 8, 8, 16, 16, 16, 16, 16, 16, 8, 8
+        ];
         is_initialized[0] = self.is_initialized as u8;
         is_paused[0] = self.is_paused as u8;
         nonce[0] = self.nonce;
@@ -166,6 +364,272 @@ impl Pack for SwapInfo {
         admin_fee_key_a.copy_from_slice(self.admin_fee_key_a.as_ref());
         admin_fee_key_b.copy_from_slice(self.admin_fee_key_b.as_ref());
         self.fees.pack_into_slice(&mut fees[..]);
+        *token_a_precision_multiplier = self.token_a_precision_multiplier.to_le_bytes();
+        *token_b_precision_multiplier = self.token_b_precision_multiplier.to_le_bytes();
+        rate_provider.copy_from_slice(self.rate_provider.as_ref());
+        rate_provider_program.copy_from_slice(self.rate_provider_program.as_ref());
+        *max_rate_age = self.max_rate_age.to_le_bytes();
+        curve_type[0] = self.curve_type as u8;
+        *token_b_price = self.token_b_price.to_le_bytes();
+        ramp_type[0] = self.ramp_type as u8;
+        self.future_fees.pack_into_slice(&mut future_fees[..]);
+        *future_fees_ts = self.future_fees_ts.to_le_bytes();
+        *admin_fees_a = self.admin_fees_a.to_le_bytes();
+        *admin_fees_b = self.admin_fees_b.to_le_bytes();
+        *total_volume_a = self.total_volume_a.to_le_bytes();
+        *total_volume_b = self.total_volume_b.to_le_bytes();
+        *total_lp_fees_a = self.total_lp_fees_a.to_le_bytes();
+        *total_lp_fees_b = self.total_lp_fees_b.to_le_bytes();
+        *total_admin_fees_a = self.total_admin_fees_a.to_le_bytes();
+        *total_admin_fees_b = self.total_admin_fees_b.to_le_bytes();
+        *swap_count = self.swap_count.to_le_bytes();
+        *last_trade_ts = self.last_trade_ts.to_le_bytes();
+    }
+}
+
+/// Program states for a pool holding more than two tokens.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MultiSwapInfo {
+    /// Initialized state
+    pub is_initialized: bool,
+
+    /// Paused state
+    pub is_paused: bool,
+
+    /// Nonce used in program address
+    /// The program address is created deterministically with the nonce,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token accounts and pool token mint.
+    pub nonce: u8,
+
+    /// Number of tokens in the pool
+    pub n_coins: u8,
+
+    /// Initial amplification coefficient (A)
+    pub initial_amp_factor: u64,
+    /// Target amplification coefficient (A)
+    pub target_amp_factor: u64,
+    /// Ramp A start timestamp
+    pub start_ramp_ts: i64,
+    /// Ramp A stop timestamp
+    pub stop_ramp_ts: i64,
+
+    /// Deadline to transfer admin control to future_admin_key
+    pub future_admin_deadline: i64,
+    /// Public key of the admin account to be applied
+    pub future_admin_key: Pubkey,
+    /// Public key of admin account to execute admin instructions
+    pub admin_key: Pubkey,
+
+    /// Pool tokens are issued when tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original tokens.
+    pub pool_mint: Pubkey,
+
+    /// Token accounts, only the first `n_coins` are set
+    pub tokens: [Pubkey; MAX_N_COINS],
+    /// Mint information for each token, only the first `n_coins` are set
+    pub token_mints: [Pubkey; MAX_N_COINS],
+    /// Public keys of the admin token accounts to receive trading and / or
+    /// withdrawal fees for each token, only the first `n_coins` are set
+    pub admin_fee_keys: [Pubkey; MAX_N_COINS],
+    /// Fees
+    pub fees: Fees,
+    /// Multipliers normalizing each token's amounts to the pool token's
+    /// precision, only the first `n_coins` are set
+    pub precision_multipliers: [u64; MAX_N_COINS],
+
+    /// Schedule A follows between `start_ramp_ts` and `stop_ramp_ts`
+    pub ramp_type: RampType,
+
+    /// Fees to be applied from future_fees_ts
+    pub future_fees: Fees,
+    /// Timestamp from which future_fees can be applied, or `ZERO_TS` if no
+    /// fee change is committed
+    pub future_fees_ts: i64,
+
+    /// Admin fees accrued in each swap token account, not part of the pool
+    /// balances until swept by `WithdrawAdminFees`
+    pub admin_fees: [u64; MAX_N_COINS],
+}
+
+impl MultiSwapInfo {
+    /// Index of the given pool token account, if it belongs to the pool
+    pub fn token_index(&self, token: &Pubkey) -> Option<usize> {
+        self.tokens[..self.n_coins as usize]
+            .iter()
+            .position(|key| key == token)
+    }
+
+    /// Balance of the pool in the `i`th swap token account, i.e. the
+    /// account's `amount` less the admin fees accrued in it
+    pub fn pool_amount(&self, i: usize, amount: u64) -> Result<u64, SwapError> {
+        amount
+            .checked_sub(self.admin_fees[i])
+            .ok_or(SwapError::CalculationFailure)
+    }
+
+    /// Accrue admin fees in the `i`th swap token account
+    pub fn accrue_admin_fees(&mut self, i: usize, amount: u64) -> Result<(), SwapError> {
+        self.admin_fees[i] = self.admin_fees[i]
+            .checked_add(amount)
+            .ok_or(SwapError::CalculationFailure)?;
+        Ok(())
+    }
+}
+
+impl Sealed for MultiSwapInfo {}
+impl IsInitialized for MultiSwapInfo {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for MultiSwapInfo {
+    const LEN: usize = 773;
+
+    /// Unpacks a byte buffer into a [MultiSwapInfo](struct.MultiSwapInfo.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 773];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            is_paused,
+            nonce,
+            n_coins,
+            initial_amp_factor,
+            target_amp_factor,
+            start_ramp_ts,
+            stop_ramp_ts,
+            future_admin_deadline,
+            future_admin_key,
+            admin_key,
+            pool_mint,
+            tokens,
+            token_mints,
+            admin_fee_keys,
+            fees,
+            precision_multipliers,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees,
+        ) = array_refs![
+            input, 1, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 128, 128, 128, 88, 32, 1, 88, 8, 32
+        ];
+        let n_coins = n_coins[0];
+        if n_coins as usize > MAX_N_COINS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            is_paused: match is_paused {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            n_coins,
+            initial_amp_factor: u64::from_le_bytes(*initial_amp_factor),
+            target_amp_factor: u64::from_le_bytes(*target_amp_factor),
+            start_ramp_ts: i64::from_le_bytes(*start_ramp_ts),
+            stop_ramp_ts: i64::from_le_bytes(*stop_ramp_ts),
+            future_admin_deadline: i64::from_le_bytes(*future_admin_deadline),
+            future_admin_key: Pubkey::new_from_array(*future_admin_key),
+            admin_key: Pubkey::new_from_array(*admin_key),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            tokens: unpack_pubkeys(tokens),
+            token_mints: unpack_pubkeys(token_mints),
+            admin_fee_keys: unpack_pubkeys(admin_fee_keys),
+            fees: Fees::unpack_from_slice(fees)?,
+            precision_multipliers: unpack_u64s(precision_multipliers),
+            ramp_type: RampType::from_u8(ramp_type[0]).ok_or(ProgramError::InvalidAccountData)?,
+            future_fees: Fees::unpack_from_slice(future_fees)?,
+            future_fees_ts: i64::from_le_bytes(*future_fees_ts),
+            admin_fees: unpack_u64s(admin_fees),
+        })
+    }
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 773];
+        let (
+            is_initialized,
+            is_paused,
+            nonce,
+            n_coins,
+            initial_amp_factor,
+            target_amp_factor,
+            start_ramp_ts,
+            stop_ramp_ts,
+            future_admin_deadline,
+            future_admin_key,
+            admin_key,
+            pool_mint,
+            tokens,
+            token_mints,
+            admin_fee_keys,
+            fees,
+            precision_multipliers,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees,
+        ) = mut_array_refs![
+            output, 1, 1, 1, 1, 8, 8, 8, 8, 8, 32, 32, 32, 128, 128, 128, 88, 32, 1, 88, 8, 32
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        is_paused[0] = self.is_paused as u8;
+        nonce[0] = self.nonce;
+        n_coins[0] = self.n_coins;
+        *initial_amp_factor = self.initial_amp_factor.to_le_bytes();
+        *target_amp_factor = self.target_amp_factor.to_le_bytes();
+        *start_ramp_ts = self.start_ramp_ts.to_le_bytes();
+        *stop_ramp_ts = self.stop_ramp_ts.to_le_bytes();
+        *future_admin_deadline = self.future_admin_deadline.to_le_bytes();
+        future_admin_key.copy_from_slice(self.future_admin_key.as_ref());
+        admin_key.copy_from_slice(self.admin_key.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        pack_pubkeys(&self.tokens, tokens);
+        pack_pubkeys(&self.token_mints, token_mints);
+        pack_pubkeys(&self.admin_fee_keys, admin_fee_keys);
+        self.fees.pack_into_slice(&mut fees[..]);
+        pack_u64s(&self.precision_multipliers, precision_multipliers);
+        ramp_type[0] = self.ramp_type as u8;
+        self.future_fees.pack_into_slice(&mut future_fees[..]);
+        *future_fees_ts = self.future_fees_ts.to_le_bytes();
+        pack_u64s(&self.admin_fees, admin_fees);
+    }
+}
+
+fn unpack_pubkeys(input: &[u8; 32 * MAX_N_COINS]) -> [Pubkey; MAX_N_COINS] {
+    let mut keys = [Pubkey::default(); MAX_N_COINS];
+    for (key, chunk) in keys.iter_mut().zip(input.chunks(32)) {
+        *key = Pubkey::new(chunk);
+    }
+    keys
+}
+
+fn pack_pubkeys(keys: &[Pubkey; MAX_N_COINS], output: &mut [u8; 32 * MAX_N_COINS]) {
+    for (key, chunk) in keys.iter().zip(output.chunks_mut(32)) {
+        chunk.copy_from_slice(key.as_ref());
+    }
+}
+
+fn unpack_u64s(input: &[u8; 8 * MAX_N_COINS]) -> [u64; MAX_N_COINS] {
+    let mut values = [0u64; MAX_N_COINS];
+    for (value, chunk) in values.iter_mut().zip(input.chunks(8)) {
+        *value = u64::from_le_bytes(*array_ref![chunk, 0, 8]);
+    }
+    values
+}
+
+fn pack_u64s(values: &[u64; MAX_N_COINS], output: &mut [u8; 8 * MAX_N_COINS]) {
+    for (value, chunk) in values.iter().zip(output.chunks_mut(8)) {
+        chunk.copy_from_slice(&value.to_le_bytes());
     }
 }
 
@@ -199,6 +663,11 @@ mod tests {
         let token_b_mint = Pubkey::new_from_array(token_b_mint_raw);
         let admin_fee_key_a = Pubkey::new_from_array(admin_fee_key_a_raw);
         let admin_fee_key_b = Pubkey::new_from_array(admin_fee_key_b_raw);
+        let rate_provider_raw = [10u8; 32];
+        let rate_provider_program_raw = [11u8; 32];
+        let rate_provider = Pubkey::new_from_array(rate_provider_raw);
+        let rate_provider_program = Pubkey::new_from_array(rate_provider_program_raw);
+        let max_rate_age: i64 = 3600;
         let admin_trade_fee_numerator = 1;
         let admin_trade_fee_denominator = 2;
         let admin_withdraw_fee_numerator = 3;
@@ -207,6 +676,9 @@ mod tests {
         let trade_fee_denominator = 6;
         let withdraw_fee_numerator = 7;
         let withdraw_fee_denominator = 8;
+        let offpeg_fee_multiplier = 9;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 11;
         let fees = Fees {
             admin_trade_fee_numerator,
             admin_trade_fee_denominator,
@@ -216,7 +688,31 @@ mod tests {
             trade_fee_denominator,
             withdraw_fee_numerator,
             withdraw_fee_denominator,
+            offpeg_fee_multiplier,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+        let token_a_precision_multiplier: u64 = 1;
+        let token_b_precision_multiplier: u64 = 100;
+        let curve_type = CurveType::ConstantPrice;
+        let token_b_price: u64 = 2_000_000_000_000_000_000;
+        let ramp_type = RampType::Geometric;
+        let future_fees = Fees {
+            trade_fee_numerator: 10,
+            trade_fee_denominator: 11,
+            ..fees
         };
+        let future_fees_ts: i64 = i64::MAX;
+        let admin_fees_a: u64 = 12;
+        let admin_fees_b: u64 = 13;
+        let total_volume_a: u128 = 14;
+        let total_volume_b: u128 = u128::MAX;
+        let total_lp_fees_a: u128 = 16;
+        let total_lp_fees_b: u128 = 17;
+        let total_admin_fees_a: u128 = 18;
+        let total_admin_fees_b: u128 = 19;
+        let swap_count: u64 = 20;
+        let last_trade_ts: i64 = 21;
 
         let is_initialized = true;
         let is_paused = false;
@@ -239,6 +735,26 @@ mod tests {
             admin_fee_key_a,
             admin_fee_key_b,
             fees,
+            token_a_precision_multiplier,
+            token_b_precision_multiplier,
+            rate_provider,
+            rate_provider_program,
+            max_rate_age,
+            curve_type,
+            token_b_price,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees_a,
+            admin_fees_b,
+            total_volume_a,
+            total_volume_b,
+            total_lp_fees_a,
+            total_lp_fees_b,
+            total_admin_fees_a,
+            total_admin_fees_b,
+            swap_count,
+            last_trade_ts,
         };
 
         let mut packed = [0u8; SwapInfo::LEN];
@@ -272,9 +788,45 @@ mod tests {
         packed.extend_from_slice(&trade_fee_denominator.to_le_bytes());
         packed.extend_from_slice(&withdraw_fee_numerator.to_le_bytes());
         packed.extend_from_slice(&withdraw_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&offpeg_fee_multiplier.to_le_bytes());
+        packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&token_a_precision_multiplier.to_le_bytes());
+        packed.extend_from_slice(&token_b_precision_multiplier.to_le_bytes());
+        packed.extend_from_slice(&rate_provider_raw);
+        packed.extend_from_slice(&rate_provider_program_raw);
+        packed.extend_from_slice(&max_rate_age.to_le_bytes());
+        let curve_type_offset = packed.len();
+        packed.push(2 as u8); // curve_type
+        packed.extend_from_slice(&token_b_price.to_le_bytes());
+        let ramp_type_offset = packed.len();
+        packed.push(1 as u8); // ramp_type
+        let mut future_fees_slice = [0u8; Fees::LEN];
+        future_fees.pack_into_slice(&mut future_fees_slice[..]);
+        packed.extend_from_slice(&future_fees_slice);
+        packed.extend_from_slice(&future_fees_ts.to_le_bytes());
+        packed.extend_from_slice(&admin_fees_a.to_le_bytes());
+        packed.extend_from_slice(&admin_fees_b.to_le_bytes());
+        packed.extend_from_slice(&total_volume_a.to_le_bytes());
+        packed.extend_from_slice(&total_volume_b.to_le_bytes());
+        packed.extend_from_slice(&total_lp_fees_a.to_le_bytes());
+        packed.extend_from_slice(&total_lp_fees_b.to_le_bytes());
+        packed.extend_from_slice(&total_admin_fees_a.to_le_bytes());
+        packed.extend_from_slice(&total_admin_fees_b.to_le_bytes());
+        packed.extend_from_slice(&swap_count.to_le_bytes());
+        packed.extend_from_slice(&last_trade_ts.to_le_bytes());
         let unpacked = SwapInfo::unpack(&packed).unwrap();
         assert_eq!(swap_info, unpacked);
 
+        packed[curve_type_offset] = 3;
+        let err = SwapInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+        packed[curve_type_offset] = 2;
+
+        packed[ramp_type_offset] = 2;
+        let err = SwapInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+
         let packed = [0u8; SwapInfo::LEN];
         let swap_info: SwapInfo = Default::default();
         let unpack_unchecked = SwapInfo::unpack_unchecked(&packed).unwrap();
@@ -282,4 +834,214 @@ mod tests {
         let err = SwapInfo::unpack(&packed).unwrap_err();
         assert_eq!(err, ProgramError::UninitializedAccount);
     }
+
+    #[test]
+    fn test_admin_fees() {
+        let mut swap_info = SwapInfo {
+            token_a: Pubkey::new_from_array([1u8; 32]),
+            token_b: Pubkey::new_from_array([2u8; 32]),
+            ..SwapInfo::default()
+        };
+        let token_a = swap_info.token_a;
+        let token_b = swap_info.token_b;
+        swap_info.accrue_admin_fees(&token_a, 10).unwrap();
+        swap_info.accrue_admin_fees(&token_b, 20).unwrap();
+        swap_info.accrue_admin_fees(&token_b, 5).unwrap();
+        assert_eq!(swap_info.admin_fees_a, 10);
+        assert_eq!(swap_info.admin_fees_b, 25);
+
+        assert_eq!(swap_info.pool_amount(&token_a, 100), Ok(90));
+        assert_eq!(swap_info.pool_amount(&token_b, 100), Ok(75));
+        assert_eq!(
+            swap_info.pool_amount(&token_b, 20),
+            Err(SwapError::CalculationFailure)
+        );
+        let other = Pubkey::new_from_array([3u8; 32]);
+        assert_eq!(
+            swap_info.pool_amount(&other, 100),
+            Err(SwapError::IncorrectSwapAccount)
+        );
+        assert_eq!(
+            swap_info.accrue_admin_fees(&other, 1),
+            Err(SwapError::IncorrectSwapAccount)
+        );
+        swap_info.admin_fees_a = u64::MAX;
+        assert_eq!(
+            swap_info.accrue_admin_fees(&token_a, 1),
+            Err(SwapError::CalculationFailure)
+        );
+    }
+
+    #[test]
+    fn test_record_trade() {
+        let mut swap_info = SwapInfo {
+            token_a: Pubkey::new_from_array([1u8; 32]),
+            token_b: Pubkey::new_from_array([2u8; 32]),
+            ..SwapInfo::default()
+        };
+        let token_a = swap_info.token_a;
+        let token_b = swap_info.token_b;
+        swap_info.record_trade(&token_a, 100, 0, 0).unwrap();
+        swap_info.record_trade(&token_b, 0, 3, 2).unwrap();
+        swap_info.record_trade(&token_b, 50, 1, 1).unwrap();
+        assert_eq!(swap_info.total_volume_a, 100);
+        assert_eq!(swap_info.total_volume_b, 50);
+        assert_eq!(swap_info.total_lp_fees_a, 0);
+        assert_eq!(swap_info.total_lp_fees_b, 4);
+        assert_eq!(swap_info.total_admin_fees_a, 0);
+        assert_eq!(swap_info.total_admin_fees_b, 3);
+
+        // counters saturate rather than fail trades
+        swap_info.total_volume_a = u128::MAX;
+        swap_info.record_trade(&token_a, 1, 0, 0).unwrap();
+        assert_eq!(swap_info.total_volume_a, u128::MAX);
+        assert_eq!(
+            swap_info.record_trade(&Pubkey::new_from_array([3u8; 32]), 1, 0, 0),
+            Err(SwapError::IncorrectSwapAccount)
+        );
+    }
+
+    #[test]
+    fn test_multi_swap_info_packing() {
+        let nonce = 255;
+        let n_coins = 3;
+        let initial_amp_factor: u64 = 1;
+        let target_amp_factor: u64 = 1;
+        let start_ramp_ts: i64 = i64::MAX;
+        let stop_ramp_ts: i64 = i64::MAX;
+        let future_admin_deadline: i64 = i64::MAX;
+        let future_admin_key_raw = [1u8; 32];
+        let admin_key_raw = [2u8; 32];
+        let pool_mint_raw = [3u8; 32];
+        let tokens_raw = [[4u8; 32], [5u8; 32], [6u8; 32], [0u8; 32]];
+        let token_mints_raw = [[7u8; 32], [8u8; 32], [9u8; 32], [0u8; 32]];
+        let admin_fee_keys_raw = [[10u8; 32], [11u8; 32], [12u8; 32], [0u8; 32]];
+        let precision_multipliers = [1_000, 10, 1, 0];
+        let ramp_type = RampType::Geometric;
+        let fees = Fees {
+            admin_trade_fee_numerator: 1,
+            admin_trade_fee_denominator: 2,
+            admin_withdraw_fee_numerator: 3,
+            admin_withdraw_fee_denominator: 4,
+            trade_fee_numerator: 5,
+            trade_fee_denominator: 6,
+            withdraw_fee_numerator: 7,
+            withdraw_fee_denominator: 8,
+            offpeg_fee_multiplier: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let future_fees = Fees {
+            trade_fee_numerator: 10,
+            trade_fee_denominator: 11,
+            ..fees
+        };
+        let future_fees_ts: i64 = i64::MAX;
+        let admin_fees = [13, 14, 15, 0];
+
+        let swap_info = MultiSwapInfo {
+            is_initialized: true,
+            is_paused: false,
+            nonce,
+            n_coins,
+            initial_amp_factor,
+            target_amp_factor,
+            start_ramp_ts,
+            stop_ramp_ts,
+            future_admin_deadline,
+            future_admin_key: Pubkey::new_from_array(future_admin_key_raw),
+            admin_key: Pubkey::new_from_array(admin_key_raw),
+            pool_mint: Pubkey::new_from_array(pool_mint_raw),
+            tokens: [
+                Pubkey::new_from_array(tokens_raw[0]),
+                Pubkey::new_from_array(tokens_raw[1]),
+                Pubkey::new_from_array(tokens_raw[2]),
+                Pubkey::default(),
+            ],
+            token_mints: [
+                Pubkey::new_from_array(token_mints_raw[0]),
+                Pubkey::new_from_array(token_mints_raw[1]),
+                Pubkey::new_from_array(token_mints_raw[2]),
+                Pubkey::default(),
+            ],
+            admin_fee_keys: [
+                Pubkey::new_from_array(admin_fee_keys_raw[0]),
+                Pubkey::new_from_array(admin_fee_keys_raw[1]),
+                Pubkey::new_from_array(admin_fee_keys_raw[2]),
+                Pubkey::default(),
+            ],
+            fees,
+            precision_multipliers,
+            ramp_type,
+            future_fees,
+            future_fees_ts,
+            admin_fees,
+        };
+
+        let mut packed = [0u8; MultiSwapInfo::LEN];
+        MultiSwapInfo::pack(swap_info, &mut packed).unwrap();
+        let unpacked = MultiSwapInfo::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+        assert_eq!(
+            unpacked.token_index(&Pubkey::new_from_array(tokens_raw[2])),
+            Some(2)
+        );
+        assert_eq!(unpacked.token_index(&Pubkey::default()), None);
+
+        let mut packed = vec![];
+        packed.push(1u8); // is_initialized
+        packed.push(0u8); // is_paused
+        packed.push(nonce);
+        packed.push(n_coins);
+        packed.extend_from_slice(&initial_amp_factor.to_le_bytes());
+        packed.extend_from_slice(&target_amp_factor.to_le_bytes());
+        packed.extend_from_slice(&start_ramp_ts.to_le_bytes());
+        packed.extend_from_slice(&stop_ramp_ts.to_le_bytes());
+        packed.extend_from_slice(&future_admin_deadline.to_le_bytes());
+        packed.extend_from_slice(&future_admin_key_raw);
+        packed.extend_from_slice(&admin_key_raw);
+        packed.extend_from_slice(&pool_mint_raw);
+        for raw in tokens_raw
+            .iter()
+            .chain(token_mints_raw.iter())
+            .chain(admin_fee_keys_raw.iter())
+        {
+            packed.extend_from_slice(raw);
+        }
+        let mut fees_slice = [0u8; Fees::LEN];
+        fees.pack_into_slice(&mut fees_slice[..]);
+        packed.extend_from_slice(&fees_slice);
+        for precision_multiplier in precision_multipliers.iter() {
+            packed.extend_from_slice(&precision_multiplier.to_le_bytes());
+        }
+        let ramp_type_offset = packed.len();
+        packed.push(1u8); // ramp_type
+        let mut future_fees_slice = [0u8; Fees::LEN];
+        future_fees.pack_into_slice(&mut future_fees_slice[..]);
+        packed.extend_from_slice(&future_fees_slice);
+        packed.extend_from_slice(&future_fees_ts.to_le_bytes());
+        for admin_fee in admin_fees.iter() {
+            packed.extend_from_slice(&admin_fee.to_le_bytes());
+        }
+        let unpacked = MultiSwapInfo::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        // Unknown ramp type
+        packed[ramp_type_offset] = 2;
+        let err = MultiSwapInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+        packed[ramp_type_offset] = 1;
+
+        // n_coins larger than the layout allows
+        packed[3] = MAX_N_COINS as u8 + 1;
+        let err = MultiSwapInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+
+        let packed = [0u8; MultiSwapInfo::LEN];
+        let swap_info: MultiSwapInfo = Default::default();
+        let unpack_unchecked = MultiSwapInfo::unpack_unchecked(&packed).unwrap();
+        assert_eq!(unpack_unchecked, swap_info);
+        let err = MultiSwapInfo::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
 }
@@ -1,9 +1,16 @@
 //! Utility methods
 
-use crate::error::SwapError;
+use crate::{
+    bn::U256,
+    curve::{CurveType, RATE_PRECISION},
+    error::SwapError,
+    processor::Processor,
+    rate_provider::RateInfo,
+    state::SwapInfo,
+};
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
-use spl_token::state::Account;
+use spl_token::state::{Account, Mint};
 
 /// Calculates the authority id by generating a program address.
 pub fn authority_id(program_id: &Pubkey, my_info: &Pubkey, nonce: u8) -> Result<Pubkey, SwapError> {
@@ -16,13 +23,141 @@ pub fn unpack_token_account(data: &[u8]) -> Result<Account, SwapError> {
     Account::unpack(data).map_err(|_| SwapError::ExpectedAccount)
 }
 
+/// Calculates the multiplier normalizing amounts of a token with
+/// `token_decimals` to the pool token's `pool_decimals`.
+pub fn precision_multiplier(token_decimals: u8, pool_decimals: u8) -> Result<u64, SwapError> {
+    pool_decimals
+        .checked_sub(token_decimals)
+        .and_then(|exponent| 10u64.checked_pow(exponent.into()))
+        .ok_or(SwapError::MismatchedDecimals)
+}
+
+/// Unpacks a rate provider `RateInfo`, which must hold a nonzero rate.
+pub fn unpack_rate_info(data: &[u8]) -> Result<RateInfo, SwapError> {
+    match RateInfo::unpack(data) {
+        Ok(rate_info) if rate_info.rate != 0 => Ok(rate_info),
+        _ => Err(SwapError::InvalidRateProvider),
+    }
+}
+
+/// Reads the virtual price of a swap's pool token at `current_ts` from its
+/// reserve token accounts, pool mint and rate provider if it has one, scaled
+/// by `curve::RATE_PRECISION`. Stale rates are rejected as in swaps.
+pub fn virtual_price(
+    token_swap: &SwapInfo,
+    token_a: &Account,
+    token_b: &Account,
+    pool_mint: &Mint,
+    rate_info: Option<&RateInfo>,
+    current_ts: i64,
+) -> Result<U256, SwapError> {
+    if token_a.mint != token_swap.token_a_mint || token_b.mint != token_swap.token_b_mint {
+        return Err(SwapError::IncorrectMint);
+    }
+    let token_b_rate = match (token_swap.has_rate_provider(), rate_info) {
+        (false, None) if token_swap.curve_type == CurveType::ConstantPrice => {
+            token_swap.token_b_price
+        }
+        (false, None) => RATE_PRECISION,
+        (true, Some(rate_info))
+            if current_ts.saturating_sub(rate_info.last_update_ts) > token_swap.max_rate_age =>
+        {
+            return Err(SwapError::StaleRate)
+        }
+        (true, Some(rate_info)) => rate_info.rate,
+        _ => return Err(SwapError::InvalidRateProvider),
+    };
+    if pool_mint.supply == 0 {
+        return Err(SwapError::EmptyPool);
+    }
+    Processor::calculator(
+        token_swap,
+        current_ts,
+        &token_swap.precision_multipliers(),
+        &[RATE_PRECISION, token_b_rate],
+    )
+    .compute_virtual_price(
+        U256::from(token_swap.pool_amount(&token_swap.token_a, token_a.amount)?),
+        U256::from(token_swap.pool_amount(&token_swap.token_b, token_b.amount)?),
+        U256::from(pool_mint.supply),
+    )
+    .map_err(SwapError::from)
+}
+
+/// Number of seconds in a year of 365 days
+pub const SECONDS_PER_YEAR: i64 = 31_536_000;
+
+/// Annualized rate of the LP fees a swap earned between two snapshots of its
+/// state, taken at the unix timestamps `earlier_ts` and `later_ts`, relative
+/// to the value of the pool at the `later` snapshot, scaled by
+/// `curve::RATE_PRECISION`. The pool is valued in token A from its reserve
+/// token accounts at that snapshot and the rate of token B in token A, see
+/// `Processor::token_b_rate`.
+pub fn fee_apr(
+    earlier: &SwapInfo,
+    earlier_ts: i64,
+    later: &SwapInfo,
+    later_ts: i64,
+    token_a: &Account,
+    token_b: &Account,
+    token_b_rate: u64,
+) -> Result<U256, SwapError> {
+    if earlier.pool_mint != later.pool_mint
+        || later.swap_count < earlier.swap_count
+        || later.total_lp_fees_a < earlier.total_lp_fees_a
+        || later.total_lp_fees_b < earlier.total_lp_fees_b
+    {
+        return Err(SwapError::InvalidInput);
+    }
+    if token_a.mint != later.token_a_mint || token_b.mint != later.token_b_mint {
+        return Err(SwapError::IncorrectMint);
+    }
+    let elapsed = later_ts
+        .checked_sub(earlier_ts)
+        .filter(|elapsed| *elapsed > 0)
+        .ok_or(SwapError::InvalidInput)?;
+    let value = |amount_a: U256, amount_b: U256| -> Option<U256> {
+        amount_a
+            .checked_mul(later.token_a_precision_multiplier.into())?
+            .checked_mul(RATE_PRECISION.into())?
+            .checked_add(
+                amount_b
+                    .checked_mul(later.token_b_precision_multiplier.into())?
+                    .checked_mul(token_b_rate.into())?,
+            )
+    };
+    let pool_value = value(
+        later.pool_amount(&later.token_a, token_a.amount)?.into(),
+        later.pool_amount(&later.token_b, token_b.amount)?.into(),
+    )
+    .ok_or(SwapError::CalculationFailure)?;
+    if pool_value.is_zero() {
+        return Err(SwapError::EmptyPool);
+    }
+    value(
+        (later.total_lp_fees_a - earlier.total_lp_fees_a).into(),
+        (later.total_lp_fees_b - earlier.total_lp_fees_b).into(),
+    )
+    .and_then(|fee_value| fee_value.checked_mul(SECONDS_PER_YEAR.into()))
+    .and_then(|fee_value| fee_value.checked_mul(RATE_PRECISION.into()))
+    .and_then(|fee_value| fee_value.checked_div(pool_value.checked_mul(elapsed.into())?))
+    .ok_or(SwapError::CalculationFailure)
+}
+
 #[cfg(test)]
 pub mod test_utils {
     use crate::{
-        curve::ZERO_TS, fees::Fees, instruction::*, processor::Processor, state::SwapInfo,
+        curve::{CurveType, RampType, RATE_PRECISION, ZERO_TS},
+        fees::Fees,
+        instruction::*,
+        processor::Processor,
+        rate_provider::RateInfo,
+        state::{MultiSwapInfo, SwapInfo},
     };
     use solana_program::{
-        account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+        account_info::AccountInfo,
+        entrypoint::ProgramResult,
+        instruction::{AccountMeta, Instruction},
         program_error::ProgramError,
     };
     use solana_program::{
@@ -50,6 +185,9 @@ pub mod test_utils {
         trade_fee_denominator: 100,
         withdraw_fee_numerator: 6,
         withdraw_fee_denominator: 100,
+        offpeg_fee_multiplier: 0,
+        host_fee_numerator: 0,
+        host_fee_denominator: 1,
     };
 
     /// Default token decimals
@@ -91,6 +229,12 @@ pub mod test_utils {
         pub admin_fee_b_key: Pubkey,
         pub admin_fee_b_account: Account,
         pub fees: Fees,
+        pub rate_provider: Option<(Pubkey, Account)>,
+        pub rate_provider_program: Pubkey,
+        pub max_rate_age: i64,
+        pub host_fee_account: Option<(Pubkey, Account)>,
+        pub curve_type: CurveType,
+        pub token_b_price: u64,
     }
 
     impl SwapAccountInfo {
@@ -100,62 +244,50 @@ pub mod test_utils {
             token_a_amount: u64,
             token_b_amount: u64,
             fees: Fees,
+        ) -> Self {
+            Self::new_with_decimals(
+                user_key,
+                amp_factor,
+                token_a_amount,
+                token_b_amount,
+                fees,
+                [DEFAULT_TOKEN_DECIMALS, DEFAULT_TOKEN_DECIMALS],
+                DEFAULT_TOKEN_DECIMALS,
+            )
+        }
+
+        pub fn new_with_decimals(
+            user_key: &Pubkey,
+            amp_factor: u64,
+            token_a_amount: u64,
+            token_b_amount: u64,
+            fees: Fees,
+            token_decimals: [u8; 2],
+            pool_decimals: u8,
         ) -> Self {
             let swap_key = pubkey_rand();
             let swap_account = Account::new(0, SwapInfo::get_packed_len(), &SWAP_PROGRAM_ID);
             let (authority_key, nonce) =
                 Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
 
-            let (pool_mint_key, mut pool_mint_account) = create_mint(
-                &TOKEN_PROGRAM_ID,
-                &authority_key,
-                DEFAULT_TOKEN_DECIMALS,
-                None,
-            );
-            let (pool_token_key, pool_token_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                &user_key,
-                0,
-            );
-            let (token_a_mint_key, mut token_a_mint_account) =
-                create_mint(&TOKEN_PROGRAM_ID, &user_key, DEFAULT_TOKEN_DECIMALS, None);
-            let (token_a_key, token_a_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &token_a_mint_key,
-                &mut token_a_mint_account,
-                &user_key,
-                &authority_key,
-                token_a_amount,
-            );
-            let (admin_fee_a_key, admin_fee_a_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &token_a_mint_key,
-                &mut token_a_mint_account,
-                &user_key,
-                &authority_key,
-                0,
-            );
-            let (token_b_mint_key, mut token_b_mint_account) =
-                create_mint(&TOKEN_PROGRAM_ID, &user_key, DEFAULT_TOKEN_DECIMALS, None);
-            let (token_b_key, token_b_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &token_b_mint_key,
-                &mut token_b_mint_account,
-                &user_key,
-                &authority_key,
-                token_b_amount,
-            );
-            let (admin_fee_b_key, admin_fee_b_account) = mint_token(
-                &TOKEN_PROGRAM_ID,
-                &token_b_mint_key,
-                &mut token_b_mint_account,
-                &user_key,
-                &authority_key,
-                0,
-            );
+            let (pool_mint_key, pool_mint_account, pool_token_key, pool_token_account) =
+                create_pool_mint(user_key, &authority_key, pool_decimals);
+            let SwapToken {
+                mint_key: token_a_mint_key,
+                mint_account: token_a_mint_account,
+                token_key: token_a_key,
+                token_account: token_a_account,
+                admin_fee_key: admin_fee_a_key,
+                admin_fee_account: admin_fee_a_account,
+            } = create_swap_token(user_key, &authority_key, token_a_amount, token_decimals[0]);
+            let SwapToken {
+                mint_key: token_b_mint_key,
+                mint_account: token_b_mint_account,
+                token_key: token_b_key,
+                token_account: token_b_account,
+                admin_fee_key: admin_fee_b_key,
+                admin_fee_account: admin_fee_b_account,
+            } = create_swap_token(user_key, &authority_key, token_b_amount, token_decimals[1]);
 
             let admin_account = Account::default();
 
@@ -185,11 +317,17 @@ pub mod test_utils {
                 admin_fee_b_key,
                 admin_fee_b_account,
                 fees,
+                rate_provider: None,
+                rate_provider_program: Pubkey::default(),
+                max_rate_age: 0,
+                host_fee_account: None,
+                curve_type: CurveType::StableSwap,
+                token_b_price: RATE_PRECISION,
             }
         }
 
         pub fn initialize_swap(&mut self) -> ProgramResult {
-            do_process_instruction(
+            do_process_instruction_with_optional_accounts(
                 initialize(
                     &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
@@ -207,6 +345,10 @@ pub mod test_utils {
                     self.nonce,
                     self.initial_amp_factor,
                     self.fees,
+                    &self.rate_provider_program,
+                    self.max_rate_age,
+                    self.curve_type,
+                    self.token_b_price,
                 )
                 .unwrap(),
                 vec![
@@ -223,6 +365,7 @@ pub mod test_utils {
                     &mut self.pool_token_account,
                     &mut Account::default(),
                 ],
+                vec![&mut self.rate_provider],
             )
         }
 
@@ -268,33 +411,18 @@ pub mod test_utils {
             )
         }
 
-        fn get_admin_fee_key(&self, account_key: &Pubkey) -> Pubkey {
-            if *account_key == self.token_a_key {
-                return self.admin_fee_a_key;
-            } else if *account_key == self.token_b_key {
-                return self.admin_fee_b_key;
-            }
-            panic!("Could not find matching admin fee account");
-        }
-
-        fn get_admin_fee_account(&self, account_key: &Pubkey) -> &Account {
-            if *account_key == self.admin_fee_a_key {
-                return &self.admin_fee_a_account;
-            } else if *account_key == self.admin_fee_b_key {
-                return &self.admin_fee_b_account;
-            }
-            panic!("Could not find matching admin fee account");
-        }
-
-        fn set_admin_fee_account_(&mut self, account_key: &Pubkey, account: Account) {
-            if *account_key == self.admin_fee_a_key {
-                self.admin_fee_a_account = account;
-                return;
-            } else if *account_key == self.admin_fee_b_key {
-                self.admin_fee_b_account = account;
-                return;
-            }
-            panic!("Could not find matching admin fee account");
+        pub fn pool_amounts(&self) -> (u64, u64) {
+            let token_swap = SwapInfo::unpack(&self.swap_account.data).unwrap();
+            let token_a = super::unpack_token_account(&self.token_a_account.data).unwrap();
+            let token_b = super::unpack_token_account(&self.token_b_account.data).unwrap();
+            (
+                token_swap
+                    .pool_amount(&self.token_a_key, token_a.amount)
+                    .unwrap(),
+                token_swap
+                    .pool_amount(&self.token_b_key, token_b.amount)
+                    .unwrap(),
+            )
         }
 
         fn get_token_account(&self, account_key: &Pubkey) -> &Account {
@@ -330,33 +458,78 @@ pub mod test_utils {
             minimum_amount_out: u64,
         ) -> ProgramResult {
             // approve moving from user source account
-            do_process_instruction(
-                approve(
+            approve_spend(
+                user_source_key,
+                user_source_account,
+                user_key,
+                &self.authority_key,
+                amount_in,
+            );
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+
+            // perform the swap
+            do_process_instruction_with_optional_accounts(
+                swap(
+                    &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
-                    &user_source_key,
+                    &self.swap_key,
                     &self.authority_key,
-                    &user_key,
-                    &[],
+                    &user_source_key,
+                    &swap_source_key,
+                    &swap_destination_key,
+                    &user_destination_key,
                     amount_in,
+                    minimum_amount_out,
                 )
                 .unwrap(),
                 vec![
-                    &mut user_source_account,
+                    &mut self.swap_account,
                     &mut Account::default(),
+                    &mut user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    &mut user_destination_account,
                     &mut Account::default(),
+                    &mut clock_account(ZERO_TS),
                 ],
-            )
-            .unwrap();
+                vec![&mut self.rate_provider, &mut self.host_fee_account],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+
+            Ok(())
+        }
+
+        pub fn swap_exact_out(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            mut user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            mut user_destination_account: &mut Account,
+            amount_out: u64,
+            maximum_amount_in: u64,
+        ) -> ProgramResult {
+            // approve moving from user source account
+            approve_spend(
+                user_source_key,
+                user_source_account,
+                user_key,
+                &self.authority_key,
+                maximum_amount_in,
+            );
 
-            let admin_destination_key = self.get_admin_fee_key(swap_destination_key);
-            let mut admin_destination_account =
-                self.get_admin_fee_account(&admin_destination_key).clone();
             let mut swap_source_account = self.get_token_account(swap_source_key).clone();
             let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
 
             // perform the swap
-            do_process_instruction(
-                swap(
+            do_process_instruction_with_optional_accounts(
+                swap_exact_out(
                     &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
                     &self.swap_key,
@@ -365,9 +538,8 @@ pub mod test_utils {
                     &swap_source_key,
                     &swap_destination_key,
                     &user_destination_key,
-                    &admin_destination_key,
-                    amount_in,
-                    minimum_amount_out,
+                    amount_out,
+                    maximum_amount_in,
                 )
                 .unwrap(),
                 vec![
@@ -377,13 +549,12 @@ pub mod test_utils {
                     &mut swap_source_account,
                     &mut swap_destination_account,
                     &mut user_destination_account,
-                    &mut admin_destination_account,
                     &mut Account::default(),
                     &mut clock_account(ZERO_TS),
                 ],
+                vec![&mut self.rate_provider],
             )?;
 
-            self.set_admin_fee_account_(&admin_destination_key, admin_destination_account);
             self.set_token_account(swap_source_key, swap_source_account);
             self.set_token_account(swap_destination_key, swap_destination_account);
 
@@ -402,6 +573,69 @@ pub mod test_utils {
             amount_a: u64,
             amount_b: u64,
             min_mint_amount: u64,
+        ) -> ProgramResult {
+            approve_spend(
+                depositor_token_a_key,
+                depositor_token_a_account,
+                depositor_key,
+                &self.authority_key,
+                amount_a,
+            );
+
+            approve_spend(
+                depositor_token_b_key,
+                depositor_token_b_account,
+                depositor_key,
+                &self.authority_key,
+                amount_b,
+            );
+
+            // perform deposit
+            do_process_instruction_with_optional_accounts(
+                deposit(
+                    &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &depositor_token_a_key,
+                    &depositor_token_b_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &depositor_pool_key,
+                    amount_a,
+                    amount_b,
+                    min_mint_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut depositor_token_a_account,
+                    &mut depositor_token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut depositor_pool_account,
+                    &mut Account::default(),
+                    &mut clock_account(ZERO_TS),
+                ],
+                vec![&mut self.rate_provider],
+            )
+        }
+
+        pub fn deposit_exact_lp(
+            &mut self,
+            depositor_key: &Pubkey,
+            depositor_token_a_key: &Pubkey,
+            mut depositor_token_a_account: &mut Account,
+            depositor_token_b_key: &Pubkey,
+            mut depositor_token_b_account: &mut Account,
+            depositor_pool_key: &Pubkey,
+            mut depositor_pool_account: &mut Account,
+            pool_token_amount: u64,
+            max_token_a: u64,
+            max_token_b: u64,
         ) -> ProgramResult {
             do_process_instruction(
                 approve(
@@ -410,7 +644,7 @@ pub mod test_utils {
                     &self.authority_key,
                     &depositor_key,
                     &[],
-                    amount_a,
+                    max_token_a,
                 )
                 .unwrap(),
                 vec![
@@ -428,7 +662,7 @@ pub mod test_utils {
                     &self.authority_key,
                     &depositor_key,
                     &[],
-                    amount_b,
+                    max_token_b,
                 )
                 .unwrap(),
                 vec![
@@ -439,9 +673,8 @@ pub mod test_utils {
             )
             .unwrap();
 
-            // perform deposit
             do_process_instruction(
-                deposit(
+                deposit_exact_lp(
                     &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
                     &self.swap_key,
@@ -452,9 +685,9 @@ pub mod test_utils {
                     &self.token_b_key,
                     &self.pool_mint_key,
                     &depositor_pool_key,
-                    amount_a,
-                    amount_b,
-                    min_mint_amount,
+                    pool_token_amount,
+                    max_token_a,
+                    max_token_b,
                 )
                 .unwrap(),
                 vec![
@@ -467,7 +700,6 @@ pub mod test_utils {
                     &mut self.pool_mint_account,
                     &mut depositor_pool_account,
                     &mut Account::default(),
-                    &mut clock_account(ZERO_TS),
                 ],
             )
         }
@@ -486,27 +718,73 @@ pub mod test_utils {
             minimum_b_amount: u64,
         ) -> ProgramResult {
             // approve swap program to take out pool tokens
+            approve_spend(
+                pool_key,
+                pool_account,
+                user_key,
+                &self.authority_key,
+                pool_amount,
+            );
+
+            // perform withraw
             do_process_instruction(
-                approve(
+                withdraw(
+                    &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
-                    &pool_key,
+                    &self.swap_key,
                     &self.authority_key,
-                    &user_key,
-                    &[],
+                    &self.pool_mint_key,
+                    &pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &token_a_key,
+                    &token_b_key,
                     pool_amount,
+                    minimum_a_amount,
+                    minimum_b_amount,
                 )
                 .unwrap(),
                 vec![
-                    &mut pool_account,
+                    &mut self.swap_account,
                     &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    &mut pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut token_a_account,
+                    &mut token_b_account,
                     &mut Account::default(),
                 ],
-            )
-            .unwrap();
+            )?;
+
+            Ok(())
+        }
+
+        pub fn withdraw_imbalance(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            mut pool_account: &mut Account,
+            token_a_key: &Pubkey,
+            mut token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            mut token_b_account: &mut Account,
+            token_a_amount: u64,
+            token_b_amount: u64,
+            max_burn_amount: u64,
+        ) -> ProgramResult {
+            // approve swap program to take out pool tokens
+            approve_spend(
+                pool_key,
+                pool_account,
+                user_key,
+                &self.authority_key,
+                max_burn_amount,
+            );
 
             // perform withraw
-            do_process_instruction(
-                withdraw(
+            do_process_instruction_with_optional_accounts(
+                withdraw_imbalance(
                     &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
                     &self.swap_key,
@@ -517,11 +795,9 @@ pub mod test_utils {
                     &self.token_b_key,
                     &token_a_key,
                     &token_b_key,
-                    &self.admin_fee_a_key,
-                    &self.admin_fee_b_key,
-                    pool_amount,
-                    minimum_a_amount,
-                    minimum_b_amount,
+                    token_a_amount,
+                    token_b_amount,
+                    max_burn_amount,
                 )
                 .unwrap(),
                 vec![
@@ -533,10 +809,10 @@ pub mod test_utils {
                     &mut self.token_b_account,
                     &mut token_a_account,
                     &mut token_b_account,
-                    &mut self.admin_fee_a_account,
-                    &mut self.admin_fee_b_account,
                     &mut Account::default(),
+                    &mut clock_account(ZERO_TS),
                 ],
+                vec![&mut self.rate_provider],
             )?;
 
             Ok(())
@@ -553,26 +829,16 @@ pub mod test_utils {
             minimum_amount: u64,
         ) -> ProgramResult {
             // approve swap program to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &TOKEN_PROGRAM_ID,
-                    &pool_key,
-                    &self.authority_key,
-                    &user_key,
-                    &[],
-                    pool_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+            approve_spend(
+                pool_key,
+                pool_account,
+                user_key,
+                &self.authority_key,
+                pool_amount,
+            );
 
             // perform withraw_one
-            do_process_instruction(
+            do_process_instruction_with_optional_accounts(
                 withdraw_one(
                     &SWAP_PROGRAM_ID,
                     &TOKEN_PROGRAM_ID,
@@ -583,7 +849,6 @@ pub mod test_utils {
                     &self.token_a_key,
                     &self.token_b_key,
                     &dest_token_key,
-                    &self.admin_fee_a_key,
                     pool_amount,
                     minimum_amount,
                 )
@@ -596,172 +861,591 @@ pub mod test_utils {
                     &mut self.token_a_account,
                     &mut self.token_b_account,
                     &mut dest_token_account,
-                    &mut self.admin_fee_a_account,
                     &mut Account::default(),
                     &mut clock_account(ZERO_TS),
                 ],
+                vec![&mut self.rate_provider, &mut self.host_fee_account],
             )
         }
+    }
+
+    /// Admin instructions, shared by the two-token and multi-token swap fixtures
+    pub trait SwapAdmin {
+        /// Keys of the swap state, swap authority and admin accounts
+        fn admin_keys(&self) -> (Pubkey, Pubkey, Pubkey);
+
+        /// Process an admin instruction against the swap state, swap authority
+        /// and admin accounts, followed by the given `accounts`
+        fn process_admin_instruction(
+            &mut self,
+            instruction: Instruction,
+            accounts: Vec<&mut Account>,
+        ) -> ProgramResult;
 
-        /** Admin functions **/
+        /// Sweep the admin fees accrued in the swap token accounts
+        fn withdraw_admin_fees(&mut self) -> ProgramResult;
 
-        pub fn ramp_a(
+        fn ramp_a(
             &mut self,
             target_amp: u64,
             current_ts: i64,
             stop_ramp_ts: i64,
+            ramp_type: RampType,
         ) -> ProgramResult {
-            do_process_instruction(
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
                 ramp_a(
                     &SWAP_PROGRAM_ID,
-                    &self.swap_key,
-                    &self.authority_key,
-                    &self.admin_key,
+                    &swap_key,
+                    &authority_key,
+                    &admin_key,
                     target_amp,
                     stop_ramp_ts,
+                    ramp_type,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                    &mut clock_account(current_ts),
-                ],
+                vec![&mut clock_account(current_ts)],
             )
         }
 
-        pub fn stop_ramp_a(&mut self, current_ts: i64) -> ProgramResult {
-            do_process_instruction(
-                stop_ramp_a(
+        fn stop_ramp_a(&mut self, current_ts: i64) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                stop_ramp_a(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![&mut clock_account(current_ts)],
+            )
+        }
+
+        fn pause(&mut self) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                pause(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![],
+            )
+        }
+
+        fn unpause(&mut self) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                unpause(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![],
+            )
+        }
+
+        fn set_admin_fee_account(
+            &mut self,
+            new_admin_fee_key: &Pubkey,
+            new_admin_fee_account: &Account,
+        ) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                set_fee_account(
                     &SWAP_PROGRAM_ID,
-                    &self.swap_key,
-                    &self.authority_key,
-                    &self.admin_key,
+                    &swap_key,
+                    &authority_key,
+                    &admin_key,
+                    new_admin_fee_key,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                    &mut clock_account(current_ts),
-                ],
+                vec![&mut new_admin_fee_account.clone()],
+            )
+        }
+
+        fn apply_new_admin(&mut self, current_ts: i64) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                apply_new_admin(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![&mut clock_account(current_ts)],
+            )
+        }
+
+        fn commit_new_admin(&mut self, new_admin_key: &Pubkey, current_ts: i64) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                commit_new_admin(
+                    &SWAP_PROGRAM_ID,
+                    &swap_key,
+                    &authority_key,
+                    &admin_key,
+                    new_admin_key,
+                )
+                .unwrap(),
+                vec![&mut Account::default(), &mut clock_account(current_ts)],
             )
         }
 
-        pub fn pause(&mut self) -> ProgramResult {
+        fn commit_new_fees(&mut self, new_fees: Fees, current_ts: i64) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                commit_new_fees(
+                    &SWAP_PROGRAM_ID,
+                    &swap_key,
+                    &authority_key,
+                    &admin_key,
+                    new_fees,
+                )
+                .unwrap(),
+                vec![&mut clock_account(current_ts)],
+            )
+        }
+
+        fn apply_new_fees(&mut self, current_ts: i64) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                apply_new_fees(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![&mut clock_account(current_ts)],
+            )
+        }
+
+        fn revert_new_fees(&mut self) -> ProgramResult {
+            let (swap_key, authority_key, admin_key) = self.admin_keys();
+            self.process_admin_instruction(
+                revert_new_fees(&SWAP_PROGRAM_ID, &swap_key, &authority_key, &admin_key).unwrap(),
+                vec![],
+            )
+        }
+    }
+
+    impl SwapAdmin for SwapAccountInfo {
+        fn admin_keys(&self) -> (Pubkey, Pubkey, Pubkey) {
+            (self.swap_key, self.authority_key, self.admin_key)
+        }
+
+        fn process_admin_instruction(
+            &mut self,
+            instruction: Instruction,
+            accounts: Vec<&mut Account>,
+        ) -> ProgramResult {
+            let mut authority_account = Account::default();
+            let mut admin_accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.admin_account,
+            ];
+            admin_accounts.extend(accounts);
+            do_process_instruction(instruction, admin_accounts)
+        }
+
+        fn withdraw_admin_fees(&mut self) -> ProgramResult {
             do_process_instruction(
-                pause(
+                withdraw_admin_fees(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
                     &self.admin_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.admin_fee_a_key,
+                    &self.admin_fee_b_key,
                 )
                 .unwrap(),
                 vec![
                     &mut self.swap_account,
                     &mut Account::default(),
                     &mut self.admin_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.admin_fee_a_account,
+                    &mut self.admin_fee_b_account,
+                    &mut Account::default(),
                 ],
             )
         }
+    }
 
-        pub fn unpause(&mut self) -> ProgramResult {
+    /// Accounts of a multi-token swap, with the per-token accounts in pool order
+    pub struct MultiSwapAccountInfo {
+        /// Nonce used to derive the swap authority
+        pub nonce: u8,
+        /// Swap authority
+        pub authority_key: Pubkey,
+        /// Amplification coefficient
+        pub amp_factor: u64,
+        /// Swap state account
+        pub swap_key: Pubkey,
+        pub swap_account: Account,
+        /// Pool token mint
+        pub pool_mint_key: Pubkey,
+        pub pool_mint_account: Account,
+        /// Pool token account receiving the initial liquidity
+        pub pool_token_key: Pubkey,
+        pub pool_token_account: Account,
+        /// Mints of the swap tokens
+        pub token_mint_keys: Vec<Pubkey>,
+        pub token_mint_accounts: Vec<Account>,
+        /// Swap token accounts
+        pub token_keys: Vec<Pubkey>,
+        pub token_accounts: Vec<Account>,
+        /// Swap admin
+        pub admin_key: Pubkey,
+        pub admin_account: Account,
+        /// Admin fee accounts
+        pub admin_fee_keys: Vec<Pubkey>,
+        pub admin_fee_accounts: Vec<Account>,
+        /// Fees the swap is initialized with
+        pub fees: Fees,
+        /// Host fee account passed to swaps, if any
+        pub host_fee_account: Option<(Pubkey, Account)>,
+    }
+
+    impl MultiSwapAccountInfo {
+        /// Create the accounts of a swap holding `token_amounts`, not yet initialized
+        pub fn new(user_key: &Pubkey, amp_factor: u64, token_amounts: &[u64], fees: Fees) -> Self {
+            Self::new_with_decimals(
+                user_key,
+                amp_factor,
+                token_amounts,
+                fees,
+                &vec![DEFAULT_TOKEN_DECIMALS; token_amounts.len()],
+                DEFAULT_TOKEN_DECIMALS,
+            )
+        }
+
+        /// Create the accounts of a swap holding `token_amounts`, with the
+        /// given token and pool token decimals, not yet initialized
+        pub fn new_with_decimals(
+            user_key: &Pubkey,
+            amp_factor: u64,
+            token_amounts: &[u64],
+            fees: Fees,
+            token_decimals: &[u8],
+            pool_decimals: u8,
+        ) -> Self {
+            let swap_key = pubkey_rand();
+            let swap_account = Account::new(0, MultiSwapInfo::get_packed_len(), &SWAP_PROGRAM_ID);
+            let (authority_key, nonce) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+
+            let (pool_mint_key, pool_mint_account, pool_token_key, pool_token_account) =
+                create_pool_mint(user_key, &authority_key, pool_decimals);
+
+            let mut token_mint_keys = vec![];
+            let mut token_mint_accounts = vec![];
+            let mut token_keys = vec![];
+            let mut token_accounts = vec![];
+            let mut admin_fee_keys = vec![];
+            let mut admin_fee_accounts = vec![];
+            for (token_amount, decimals) in token_amounts.iter().zip(token_decimals.iter()) {
+                let token = create_swap_token(user_key, &authority_key, *token_amount, *decimals);
+                token_mint_keys.push(token.mint_key);
+                token_mint_accounts.push(token.mint_account);
+                token_keys.push(token.token_key);
+                token_accounts.push(token.token_account);
+                admin_fee_keys.push(token.admin_fee_key);
+                admin_fee_accounts.push(token.admin_fee_account);
+            }
+
+            let admin_account = Account::default();
+
+            MultiSwapAccountInfo {
+                nonce,
+                authority_key,
+                amp_factor,
+                swap_key,
+                swap_account,
+                pool_mint_key,
+                pool_mint_account,
+                pool_token_key,
+                pool_token_account,
+                token_mint_keys,
+                token_mint_accounts,
+                token_keys,
+                token_accounts,
+                admin_key: admin_account.owner,
+                admin_account,
+                admin_fee_keys,
+                admin_fee_accounts,
+                fees,
+                host_fee_account: None,
+            }
+        }
+
+        /// Initialize the swap
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.admin_account,
+                &mut self.pool_mint_account,
+                &mut self.pool_token_account,
+                &mut token_program_account,
+            ];
+            accounts.extend(self.token_mint_accounts.iter_mut());
+            accounts.extend(self.token_accounts.iter_mut());
+            accounts.extend(self.admin_fee_accounts.iter_mut());
             do_process_instruction(
-                unpause(
+                initialize_multi(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
                     &self.admin_key,
+                    &self.pool_mint_key,
+                    &self.pool_token_key,
+                    &self.token_mint_keys,
+                    &self.token_keys,
+                    &self.admin_fee_keys,
+                    self.nonce,
+                    self.amp_factor,
+                    self.fees,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                ],
+                accounts,
             )
         }
 
-        pub fn set_admin_fee_account(
+        /// Mint `amounts` of each swap token and `pool_amount` pool tokens to new accounts owned by `account_owner`
+        pub fn setup_token_accounts(
             &mut self,
-            new_admin_fee_key: &Pubkey,
-            new_admin_fee_account: &Account,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            amounts: &[u64],
+            pool_amount: u64,
+        ) -> (Vec<Pubkey>, Vec<Account>, Pubkey, Account) {
+            let mut token_keys = vec![];
+            let mut token_accounts = vec![];
+            for (i, amount) in amounts.iter().enumerate() {
+                let (token_key, token_account) = mint_token(
+                    &TOKEN_PROGRAM_ID,
+                    &self.token_mint_keys[i],
+                    &mut self.token_mint_accounts[i],
+                    mint_owner,
+                    account_owner,
+                    *amount,
+                );
+                token_keys.push(token_key);
+                token_accounts.push(token_account);
+            }
+            let (pool_key, pool_account) = mint_token(
+                &TOKEN_PROGRAM_ID,
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                account_owner,
+                pool_amount,
+            );
+            (token_keys, token_accounts, pool_key, pool_account)
+        }
+
+        /// Pool balances of the swap token accounts, less the accrued admin fees
+        pub fn pool_amounts(&self) -> Vec<u64> {
+            let token_swap = MultiSwapInfo::unpack(&self.swap_account.data).unwrap();
+            self.token_accounts
+                .iter()
+                .enumerate()
+                .map(|(i, token_account)| {
+                    let token = super::unpack_token_account(&token_account.data).unwrap();
+                    token_swap.pool_amount(i, token.amount).unwrap()
+                })
+                .collect()
+        }
+
+        /// Swap `amount_in` of token `token_index_in` for token `token_index_out`
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            token_index_in: u8,
+            token_index_out: u8,
+            amount_in: u64,
+            minimum_amount_out: u64,
         ) -> ProgramResult {
-            do_process_instruction(
-                set_fee_account(
+            // approve moving from user source account
+            approve_spend(
+                user_source_key,
+                user_source_account,
+                user_key,
+                &self.authority_key,
+                amount_in,
+            );
+
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut clock = clock_account(ZERO_TS);
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                user_source_account,
+                user_destination_account,
+                &mut token_program_account,
+                &mut clock,
+            ];
+            accounts.extend(self.token_accounts.iter_mut());
+            do_process_instruction_with_optional_accounts(
+                swap_multi(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
-                    &self.admin_key,
-                    new_admin_fee_key,
+                    user_source_key,
+                    user_destination_key,
+                    &self.token_keys,
+                    token_index_in,
+                    token_index_out,
+                    amount_in,
+                    minimum_amount_out,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                    &mut new_admin_fee_account.clone(),
-                ],
+                accounts,
+                vec![&mut self.host_fee_account],
             )
         }
 
-        pub fn apply_new_admin(&mut self, current_ts: i64) -> ProgramResult {
+        /// Deposit `amounts` of each token into the swap
+        pub fn deposit(
+            &mut self,
+            depositor_key: &Pubkey,
+            depositor_token_keys: &[Pubkey],
+            depositor_token_accounts: &mut [Account],
+            depositor_pool_key: &Pubkey,
+            depositor_pool_account: &mut Account,
+            amounts: &[u64],
+            min_mint_amount: u64,
+        ) -> ProgramResult {
+            for ((depositor_token_key, depositor_token_account), amount) in depositor_token_keys
+                .iter()
+                .zip(depositor_token_accounts.iter_mut())
+                .zip(amounts.iter())
+            {
+                approve_spend(
+                    depositor_token_key,
+                    depositor_token_account,
+                    depositor_key,
+                    &self.authority_key,
+                    *amount,
+                );
+            }
+
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut clock = clock_account(ZERO_TS);
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.pool_mint_account,
+                depositor_pool_account,
+                &mut token_program_account,
+                &mut clock,
+            ];
+            accounts.extend(depositor_token_accounts.iter_mut());
+            accounts.extend(self.token_accounts.iter_mut());
             do_process_instruction(
-                apply_new_admin(
+                deposit_multi(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
-                    &self.admin_key,
+                    &self.pool_mint_key,
+                    depositor_pool_key,
+                    depositor_token_keys,
+                    &self.token_keys,
+                    amounts.to_vec(),
+                    min_mint_amount,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                    &mut clock_account(current_ts),
-                ],
+                accounts,
             )
         }
 
-        pub fn commit_new_admin(
+        /// Burn `pool_amount` pool tokens and withdraw each token in proportion
+        pub fn withdraw(
             &mut self,
-            new_admin_key: &Pubkey,
-            current_ts: i64,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            token_keys: &[Pubkey],
+            token_accounts: &mut [Account],
+            pool_amount: u64,
+            minimum_amounts: &[u64],
         ) -> ProgramResult {
+            // approve swap program to take out pool tokens
+            approve_spend(
+                pool_key,
+                pool_account,
+                user_key,
+                &self.authority_key,
+                pool_amount,
+            );
+
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.pool_mint_account,
+                pool_account,
+                &mut token_program_account,
+            ];
+            accounts.extend(self.token_accounts.iter_mut());
+            accounts.extend(token_accounts.iter_mut());
             do_process_instruction(
-                commit_new_admin(
+                withdraw_multi(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
-                    &self.admin_key,
-                    new_admin_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    &self.token_keys,
+                    token_keys,
+                    pool_amount,
+                    minimum_amounts.to_vec(),
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                    &mut Account::default(),
-                    &mut clock_account(current_ts),
-                ],
+                accounts,
             )
         }
+    }
 
-        pub fn set_new_fees(&mut self, new_fees: Fees) -> ProgramResult {
+    impl SwapAdmin for MultiSwapAccountInfo {
+        fn admin_keys(&self) -> (Pubkey, Pubkey, Pubkey) {
+            (self.swap_key, self.authority_key, self.admin_key)
+        }
+
+        fn process_admin_instruction(
+            &mut self,
+            instruction: Instruction,
+            accounts: Vec<&mut Account>,
+        ) -> ProgramResult {
+            let mut authority_account = Account::default();
+            let mut admin_accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.admin_account,
+            ];
+            admin_accounts.extend(accounts);
+            do_process_instruction(instruction, admin_accounts)
+        }
+
+        fn withdraw_admin_fees(&mut self) -> ProgramResult {
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.admin_account,
+            ];
+            accounts.extend(self.token_accounts.iter_mut());
+            accounts.extend(self.admin_fee_accounts.iter_mut());
+            accounts.push(&mut token_program_account);
             do_process_instruction(
-                set_new_fees(
+                withdraw_admin_fees_multi(
                     &SWAP_PROGRAM_ID,
+                    &TOKEN_PROGRAM_ID,
                     &self.swap_key,
                     &self.authority_key,
                     &self.admin_key,
-                    new_fees,
+                    &self.token_keys,
+                    &self.admin_fee_keys,
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.admin_account,
-                ],
+                accounts,
             )
         }
     }
@@ -816,6 +1500,20 @@ pub mod test_utils {
         });
     }
 
+    /// Process `instruction`, appending each of `optional_accounts` that is
+    /// present, e.g. the swap's rate provider
+    pub fn do_process_instruction_with_optional_accounts<'a>(
+        mut instruction: Instruction,
+        mut accounts: Vec<&'a mut Account>,
+        optional_accounts: Vec<&'a mut Option<(Pubkey, Account)>>,
+    ) -> ProgramResult {
+        for (key, account) in optional_accounts.into_iter().flatten() {
+            instruction.accounts.push(AccountMeta::new(*key, false));
+            accounts.push(account);
+        }
+        do_process_instruction(instruction, accounts)
+    }
+
     pub fn do_process_instruction(
         instruction: Instruction,
         accounts: Vec<&mut Account>,
@@ -863,6 +1561,103 @@ pub mod test_utils {
         res
     }
 
+    /// Token mint, swap token account and admin fee account of one swap token
+    struct SwapToken {
+        mint_key: Pubkey,
+        mint_account: Account,
+        token_key: Pubkey,
+        token_account: Account,
+        admin_fee_key: Pubkey,
+        admin_fee_account: Account,
+    }
+
+    /// Create a token mint along with the swap's token account, funded with
+    /// `amount`, and its empty admin fee account
+    fn create_swap_token(
+        user_key: &Pubkey,
+        authority_key: &Pubkey,
+        amount: u64,
+        decimals: u8,
+    ) -> SwapToken {
+        let (mint_key, mut mint_account) = create_mint(&TOKEN_PROGRAM_ID, user_key, decimals, None);
+        let (token_key, token_account) = mint_token(
+            &TOKEN_PROGRAM_ID,
+            &mint_key,
+            &mut mint_account,
+            user_key,
+            authority_key,
+            amount,
+        );
+        let (admin_fee_key, admin_fee_account) = mint_token(
+            &TOKEN_PROGRAM_ID,
+            &mint_key,
+            &mut mint_account,
+            user_key,
+            authority_key,
+            0,
+        );
+        SwapToken {
+            mint_key,
+            mint_account,
+            token_key,
+            token_account,
+            admin_fee_key,
+            admin_fee_account,
+        }
+    }
+
+    /// Create the pool mint, owned by the swap authority, and an empty pool
+    /// token account for the user
+    fn create_pool_mint(
+        user_key: &Pubkey,
+        authority_key: &Pubkey,
+        decimals: u8,
+    ) -> (Pubkey, Account, Pubkey, Account) {
+        let (pool_mint_key, mut pool_mint_account) =
+            create_mint(&TOKEN_PROGRAM_ID, authority_key, decimals, None);
+        let (pool_token_key, pool_token_account) = mint_token(
+            &TOKEN_PROGRAM_ID,
+            &pool_mint_key,
+            &mut pool_mint_account,
+            authority_key,
+            user_key,
+            0,
+        );
+        (
+            pool_mint_key,
+            pool_mint_account,
+            pool_token_key,
+            pool_token_account,
+        )
+    }
+
+    /// Approve `delegate_key` to transfer `amount` out of the source account
+    fn approve_spend(
+        source_key: &Pubkey,
+        source_account: &mut Account,
+        owner_key: &Pubkey,
+        delegate_key: &Pubkey,
+        amount: u64,
+    ) {
+        do_process_instruction(
+            approve(
+                &TOKEN_PROGRAM_ID,
+                source_key,
+                delegate_key,
+                owner_key,
+                &[],
+                amount,
+            )
+            .unwrap(),
+            vec![
+                source_account,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+    }
+
     fn mint_minimum_balance() -> u64 {
         Rent::default().minimum_balance(SplMint::get_packed_len())
     }
@@ -951,4 +1746,28 @@ pub mod test_utils {
 
         (mint_key, mint_account)
     }
+
+    /// Mock rate provider account owned by `owner`, publishing `rate` updated
+    /// at `ZERO_TS`
+    pub fn create_rate_provider(owner: &Pubkey, rate: u64) -> (Pubkey, Account) {
+        let mut rate_provider_account = Account::new(0, RateInfo::get_packed_len(), owner);
+        set_rate(&mut rate_provider_account, rate);
+        (pubkey_rand(), rate_provider_account)
+    }
+
+    /// Publishes `rate` updated at `ZERO_TS` to a mock rate provider account
+    pub fn set_rate(rate_provider_account: &mut Account, rate: u64) {
+        set_rate_at(rate_provider_account, rate, ZERO_TS);
+    }
+
+    /// Publishes `rate` updated at `last_update_ts` to a mock rate provider
+    /// account
+    pub fn set_rate_at(rate_provider_account: &mut Account, rate: u64, last_update_ts: i64) {
+        let rate_info = RateInfo {
+            is_initialized: true,
+            rate,
+            last_update_ts,
+        };
+        RateInfo::pack(rate_info, &mut rate_provider_account.data).unwrap();
+    }
 }